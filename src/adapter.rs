@@ -13,9 +13,213 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    sync::{Arc, RwLock},
+};
 
-/// Initializes an adapter using the provided Url
+/// Position of a paged listing (see Adapter::list_objects_paged)
+pub type Cursor = String;
+
+/// Creates a base adapter (the storage backend) from an Url
+pub type BaseAdapterFactory = Box<dyn Fn(&url::Url) -> Result<Box<dyn Adapter>> + Send + Sync>;
+
+/// Creates a layer adapter (for example compression) wrapping another adapter
+pub type LayerAdapterFactory =
+    Box<dyn Fn(Arc<RwLock<Box<dyn Adapter>>>, &url::Url) -> Result<Box<dyn Adapter>> + Send + Sync>;
+
+/// Assembles adapters from an Url whose scheme is a list of `+` separated names. The leftmost
+/// name selects the base adapter (the storage backend), subsequent names select the layers
+/// which are wrapped around it in order (for example `file+flate://` stores DEFLATE compressed
+/// objects in a folder). New adapters are made available by registering them with the builder.
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, adapter::AdapterBuilder, flate2adapter::Flate2Adapter};
+/// use std::sync::{Arc, RwLock};
+/// let mut builder = AdapterBuilder::new();
+/// builder.register_layer("deflate", Box::new(|backend, _| Ok(Box::new(Flate2Adapter::new(backend)))));
+/// let adapter = builder.build("memory+deflate://").unwrap();
+/// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+/// assert!(builder.build("memory+unknown://").is_err());
+/// assert!(builder.build("unknown://").is_err());
+/// assert!(builder.build("memory+flate+retry://?retries=5&backoff=50").is_ok());
+/// assert!(builder.build("memory+retry://?retries=many").is_err());
+/// ```
+pub struct AdapterBuilder {
+    bases: BTreeMap<String, BaseAdapterFactory>,
+    layers: BTreeMap<String, LayerAdapterFactory>,
+}
+
+impl AdapterBuilder {
+    /// Creates a builder with all the adapters provided by this crate registered
+    /// (memory, file, sqlite, solid and webdav base adapters, flate, brotli and retry layers). The retry
+    /// layer reads its policy from the `retries` (maximum number of attempts) and `backoff`
+    /// (initial delay in milliseconds) query parameters of the Url
+    pub fn new() -> Self {
+        let mut builder = AdapterBuilder::empty();
+        builder.register_base(
+            "memory",
+            Box::new(|_| Ok(Box::new(crate::memoryadapter::MemoryAdapter::new()))),
+        );
+        builder.register_base(
+            "file",
+            Box::new(|url| {
+                let adapter = crate::filesystemadapter::FilesystemAdapter::new(url.path())
+                    .map_err(|e| anyhow!("{}", e))?;
+                Ok(Box::new(adapter))
+            }),
+        );
+        #[cfg(feature = "solid")]
+        builder.register_base(
+            "solid",
+            Box::new(|url| {
+                let username = if url.username().is_empty() {
+                    None
+                } else {
+                    Some(url.username().to_string())
+                };
+                let password = url.password().map(|s| s.to_string());
+                let host = url.host().ok_or_else(|| anyhow!("missing_host"))?;
+                Ok(Box::new(crate::solidadapter::SolidAdapter::new(
+                    "https://".to_string() + &host.to_string(),
+                    url.path().to_string() + "/",
+                    username,
+                    password,
+                )?))
+            }),
+        );
+        #[cfg(feature = "webdav")]
+        builder.register_base(
+            "webdav",
+            Box::new(|url| {
+                let username = if url.username().is_empty() {
+                    None
+                } else {
+                    Some(url.username().to_string())
+                };
+                let password = url.password().map(|s| s.to_string());
+                let host = url.host().ok_or_else(|| anyhow!("missing_host"))?;
+                let port = url.port().map(|p| format!(":{}", p)).unwrap_or_default();
+                Ok(Box::new(crate::webdavadapter::WebDavAdapter::new(
+                    "https://".to_string() + &host.to_string() + &port + url.path(),
+                    username,
+                    password,
+                )?))
+            }),
+        );
+        #[cfg(feature = "sqlitedb")]
+        builder.register_base(
+            "sqlite",
+            Box::new(|url| {
+                if url.path().eq(":memory:") {
+                    Ok(Box::new(
+                        crate::sqliteadapter::SqliteAdapter::new_in_memory(),
+                    ))
+                } else {
+                    Ok(Box::new(crate::sqliteadapter::SqliteAdapter::new(
+                        url.path(),
+                    )))
+                }
+            }),
+        );
+        builder.register_layer(
+            "flate",
+            Box::new(|backend, _| Ok(Box::new(crate::flate2adapter::Flate2Adapter::new(backend)))),
+        );
+        #[cfg(feature = "brotliadapter")]
+        builder.register_layer(
+            "brotli",
+            Box::new(|backend, _| Ok(Box::new(crate::brotliadapter::BrotliAdapter::new(backend)))),
+        );
+        builder.register_layer(
+            "retry",
+            Box::new(|backend, url| {
+                let mut policy = crate::retryadapter::RetryPolicy::default();
+                for (k, v) in url.query_pairs() {
+                    match k.as_ref() {
+                        "retries" => {
+                            policy.max_attempts =
+                                v.parse().map_err(|_| anyhow!("invalid_retries: {}", v))?
+                        }
+                        "backoff" => {
+                            policy.initial_backoff = std::time::Duration::from_millis(
+                                v.parse().map_err(|_| anyhow!("invalid_backoff: {}", v))?,
+                            )
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Box::new(
+                    crate::retryadapter::RetryAdapter::new_with_policy(backend, policy),
+                ))
+            }),
+        );
+        builder
+    }
+
+    /// Creates a builder without any registered adapter
+    pub fn empty() -> Self {
+        AdapterBuilder {
+            bases: BTreeMap::new(),
+            layers: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a base adapter (replacing any adapter previously registered with the same name)
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the adapter in the Url scheme
+    /// * `factory` - The function creating the adapter from the Url
+    pub fn register_base(&mut self, name: &str, factory: BaseAdapterFactory) {
+        self.bases.insert(name.to_string(), factory);
+    }
+
+    /// Registers a layer adapter (replacing any adapter previously registered with the same name)
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the adapter in the Url scheme
+    /// * `factory` - The function wrapping the inner adapter
+    pub fn register_layer(&mut self, name: &str, factory: LayerAdapterFactory) {
+        self.layers.insert(name.to_string(), factory);
+    }
+
+    /// Builds the adapter described by the Url (fails if the scheme contains unknown adapters)
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - An Url for the adapter (username and passwords can be encoded)
+    pub fn build(&self, url: &str) -> Result<Box<dyn Adapter>> {
+        let url = url::Url::parse(url).map_err(|_| anyhow!("invalid_adapter_url"))?;
+        let mut names = url.scheme().split('+');
+        let base = names.next().unwrap_or_default();
+        let factory = self
+            .bases
+            .get(base)
+            .ok_or_else(|| anyhow!("unknown_adapter_scheme: {}", base))?;
+        let mut adapter = factory(&url)?;
+        for name in names {
+            let factory = self
+                .layers
+                .get(name)
+                .ok_or_else(|| anyhow!("unknown_adapter_scheme: {}", name))?;
+            adapter = factory(Arc::new(RwLock::new(adapter)), &url)?;
+        }
+        Ok(adapter)
+    }
+}
+
+impl Default for AdapterBuilder {
+    fn default() -> Self {
+        AdapterBuilder::new()
+    }
+}
+
+/// Initializes an adapter using the provided Url (see AdapterBuilder for the supported schemes)
 ///
 /// # Arguments
 ///
@@ -30,61 +234,7 @@ use anyhow::Result;
 /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
 /// ```
 pub fn get_adapter(url: &str) -> Result<Box<dyn Adapter>> {
-    let url = url::Url::parse(url).expect("invalid_url");
-    let username = if url.username().is_empty() {
-        None
-    } else {
-        Some(url.username().to_string())
-    };
-    let password = url.password().map(|s| s.to_string());
-    let mut adapter: Option<Box<dyn Adapter>> = None;
-    if url.scheme().starts_with("memory") {
-        adapter = Some(Box::new(crate::memoryadapter::MemoryAdapter::new()));
-    } else if url.scheme().starts_with("file") {
-        adapter = Some(Box::new(
-            crate::filesystemadapter::FilesystemAdapter::new(url.path())
-                .expect("cannot_initialize_adapter"),
-        ));
-    }
-    #[cfg(feature = "solid")]
-    if url.scheme().starts_with("solid") {
-        adapter = Some(Box::new(
-            crate::solidadapter::SolidAdapter::new(
-                "https://".to_string() + &url.host().unwrap().to_string(),
-                url.path().to_string() + "/",
-                username,
-                password,
-            )
-            .expect("cannot_initialize_adapter"),
-        ));
-    }
-    #[cfg(feature = "sqlitedb")]
-    if url.scheme().starts_with("sqlite") && !url.path().eq(":memory:") {
-        adapter = Some(Box::new(crate::sqliteadapter::SqliteAdapter::new(
-            url.path(),
-        )));
-    } else if url.scheme().starts_with("sqlite") {
-        adapter = Some(Box::new(
-            crate::sqliteadapter::SqliteAdapter::new_in_memory(),
-        ));
-    }
-    match adapter {
-        Some(adapter) => {
-            if url.scheme().ends_with("+flate") {
-                return Ok(Box::new(crate::flate2adapter::Flate2Adapter::new(
-                    std::sync::Arc::new(std::sync::RwLock::new(adapter)),
-                )));
-            }
-            #[cfg(feature = "brotli")]
-            if url.scheme().ends_with("+brotli") {
-                return Ok(Box::new(crate::brotliadapter::BrotliAdapter::new(
-                    std::sync::Arc::new(std::sync::RwLock::new(adapter)),
-                )));
-            }
-            Ok(adapter)
-        }
-        None => anyhow::bail!("invalid_adapter_url"),
-    }
+    AdapterBuilder::new().build(url)
 }
 
 /// An adapter implements a storage backend for delta states
@@ -100,6 +250,27 @@ pub trait Adapter: Send + Sync {
     ///
     fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>>;
 
+    /// Reads an object or a sub-object (see read_object) appending its content to the given
+    /// buffer, which allows callers to reuse the same buffer for several objects. The default
+    /// implementation appends the result of read_object
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    /// * `buf` - The buffer the content is appended to
+    fn read_object_into(
+        &self,
+        key: &str,
+        offset: usize,
+        length: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        buf.extend_from_slice(&self.read_object(key, offset, length)?);
+        Ok(())
+    }
+
     /// Writes an object to the storage
     ///
     /// # Arguments
@@ -108,10 +279,467 @@ pub trait Adapter: Send + Sync {
     /// * `data` - The content of the object
     fn write_object(&self, key: &str, data: &[u8]) -> Result<()>;
 
+    /// Writes an object to the storage reading its content from the given reader, so that
+    /// backends supporting incremental writes do not need to hold the whole content in memory.
+    /// The default implementation reads the whole content and writes it with write_object
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `reader` - The source of the content of the object
+    fn write_object_streaming(&self, key: &str, reader: &mut dyn Read) -> Result<()> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        self.write_object(key, &data)
+    }
+
+    /// Writes multiple objects to the storage as a single logical operation. The default
+    /// implementation writes each object with write_object (backends can override it to reduce
+    /// the number of round trips or to write all objects in a transaction)
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The keys associated with the objects along with their content
+    fn write_batch(&self, items: &[(&str, &[u8])]) -> Result<()> {
+        for (key, data) in items {
+            self.write_object(key, data)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes an object from the storage (deleting a missing object is not an error). The
+    /// default implementation fails, since objects are never deleted during normal operation
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        bail!("delete_not_supported: {}", key)
+    }
+
+    /// Deletes multiple objects from the storage, returning the result of the deletion of each
+    /// key (so that callers can proceed even if some deletions fail). The default implementation
+    /// deletes each object with delete_object (backends can override it to reduce the number of
+    /// round trips)
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keys associated with the objects
+    fn delete_objects(&self, keys: &[&str]) -> Result<Vec<(String, Result<()>)>> {
+        Ok(keys
+            .iter()
+            .map(|key| (key.to_string(), self.delete_object(key)))
+            .collect())
+    }
+
+    /// Makes all written objects durable (backends which buffer writes must override it). The
+    /// default implementation does nothing, since writes are expected to be durable once
+    /// write_object or write_batch return
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the size (in bytes) of an object in the storage. The default implementation reads
+    /// the object (backends should override it)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        Ok(self.read_object(key, 0, 0)?.len() as u64)
+    }
+
+    /// Returns the total size (in bytes) of the objects in the storage. The default
+    /// implementation reads all objects (backends should override it)
+    fn total_size(&self) -> Result<u64> {
+        let mut size = 0;
+        for key in self.list_objects("")? {
+            size += self.read_object(&key, 0, 0)?.len() as u64;
+        }
+        Ok(size)
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
     ///
     /// * `ext` - The extension (last part of the string) of the requested objects    
     fn list_objects(&self, ext: &str) -> Result<Vec<String>>;
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time. Keys are returned in
+    /// ascending order, at most limit keys are returned for each call. The returned cursor (if any) must
+    /// be passed to the next call to obtain the following page: when no cursor is returned all keys have
+    /// been listed. The default implementation fetches the full list and splits it into pages.
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        if limit == 0 {
+            bail!("invalid_page_limit");
+        }
+        let mut list = self.list_objects(ext)?;
+        list.sort();
+        let start = match cursor {
+            Some(c) => list.partition_point(|k| k.as_str() <= c),
+            None => 0,
+        };
+        let end = list.len().min(start + limit);
+        let page = list[start..end].to_vec();
+        let next = if end < list.len() {
+            page.last().cloned()
+        } else {
+            None
+        };
+        Ok((page, next))
+    }
+
+    /// Iterates over the keys of the objects whose key ends with ext (see list_objects), so that keys
+    /// can be processed while the listing is still in progress. The default implementation wraps
+    /// list_objects (hence the full list is fetched first).
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        match self.list_objects(ext) {
+            Ok(keys) => Box::new(keys.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+}
+
+/// Transforms the payloads of individual objects stored in data packs (see
+/// Melda::set_payload_codec), for example to compress values with a known shape or to
+/// redact them. Unlike an adapter, which only sees whole packs, a codec is applied to each
+/// object within a pack. Digests are computed before encoding, hence a codec changes the
+/// stored bytes but not the identifiers of objects: all replicas sharing the storage must
+/// use the same codec.
+pub trait PayloadCodec: Send + Sync {
+    /// Encodes the serialized (JSON) payload of an object before it is written to a pack
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The serialized payload
+    fn encode(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Decodes a payload read from a pack, returning the serialized (JSON) payload
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The encoded payload
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Iterator over the keys of the objects of an adapter, fetching them one page at a time
+/// (see Adapter::list_objects_paged)
+pub struct PagedObjects<'a> {
+    adapter: &'a dyn Adapter,
+    ext: String,
+    limit: usize,
+    page: std::vec::IntoIter<String>,
+    cursor: Option<Cursor>,
+    done: bool,
+}
+
+impl<'a> PagedObjects<'a> {
+    /// Creates an iterator over the keys of the objects whose key ends with ext
+    ///
+    /// # Arguments
+    ///
+    /// * `adapter` - The adapter
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `limit` - The maximum number of keys fetched for each page
+    pub fn new(adapter: &'a dyn Adapter, ext: &str, limit: usize) -> Self {
+        PagedObjects {
+            adapter,
+            ext: ext.to_string(),
+            limit,
+            page: Vec::new().into_iter(),
+            cursor: None,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for PagedObjects<'_> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(key) = self.page.next() {
+                return Some(Ok(key));
+            }
+            if self.done {
+                return None;
+            }
+            match self
+                .adapter
+                .list_objects_paged(&self.ext, self.cursor.as_deref(), self.limit)
+            {
+                Ok((page, next)) => {
+                    self.page = page.into_iter();
+                    self.done = next.is_none();
+                    self.cursor = next;
+                }
+                Err(e) => {
+                    // Stop listing after an error
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Lists one page of the keys of an adapter storing each object in a folder named after the
+/// first characters of its key (see Adapter::list_objects_paged): the folders are visited in
+/// order and only the folders needed for the page are listed. The cursor is made of the folder
+/// and of the last key of the page (separated by a slash).
+///
+/// # Arguments
+///
+/// * `folders` - The names of the folders
+/// * `list_folder` - Lists the keys of the requested objects within a folder
+/// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+/// * `limit` - The maximum number of keys in the page
+pub(crate) fn list_folders_paged<F>(
+    mut folders: Vec<String>,
+    mut list_folder: F,
+    cursor: Option<&str>,
+    limit: usize,
+) -> Result<(Vec<String>, Option<Cursor>)>
+where
+    F: FnMut(&str) -> Result<Vec<String>>,
+{
+    if limit == 0 {
+        bail!("invalid_page_limit");
+    }
+    let start = match cursor {
+        Some(c) => Some(c.split_once('/').ok_or_else(|| anyhow!("invalid_cursor"))?),
+        None => None,
+    };
+    folders.sort();
+    let mut page = vec![];
+    let mut last_folder = "";
+    for folder in &folders {
+        if matches!(start, Some((f, _)) if folder.as_str() < f) {
+            continue;
+        }
+        let mut keys = list_folder(folder)?;
+        keys.sort();
+        for key in keys {
+            if matches!(start, Some((f, k)) if folder == f && key.as_str() <= k) {
+                continue;
+            }
+            if page.len() == limit {
+                // There are more keys
+                let next = format!("{}/{}", last_folder, page.last().unwrap());
+                return Ok((page, Some(next)));
+            }
+            page.push(key);
+            last_folder = folder;
+        }
+    }
+    Ok((page, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use mktemp::Temp;
+
+    use crate::memoryadapter::MemoryAdapter;
+
+    use super::{list_folders_paged, Adapter, AdapterBuilder};
+
+    fn assert_round_trip(adapter: &dyn Adapter) {
+        assert!(adapter
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        let ro = adapter.read_object("somekey.delta", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "somedata");
+        assert!(adapter.list_objects(".delta").unwrap().len() == 1);
+    }
+
+    fn build_error(builder: &AdapterBuilder, url: &str) -> String {
+        match builder.build(url) {
+            Ok(_) => panic!("unexpected_adapter: {}", url),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_builder_memory() {
+        let adapter = AdapterBuilder::new().build("memory://").unwrap();
+        assert_round_trip(adapter.as_ref());
+    }
+
+    #[test]
+    fn test_builder_file() {
+        let temp = Temp::new_dir().unwrap();
+        let url = "file://".to_string() + temp.to_path_buf().to_str().unwrap();
+        let adapter = AdapterBuilder::new().build(&url).unwrap();
+        assert_round_trip(adapter.as_ref());
+        // Objects are stored in the folder
+        let reopened = AdapterBuilder::new().build(&url).unwrap();
+        assert!(reopened.list_objects(".delta").unwrap() == vec!["somekey"]);
+    }
+
+    #[cfg(feature = "sqlitedb")]
+    #[test]
+    fn test_builder_sqlite() {
+        let adapter = AdapterBuilder::new().build("sqlite::memory:").unwrap();
+        assert_round_trip(adapter.as_ref());
+        let temp = Temp::new_dir().unwrap();
+        let path = temp.to_path_buf().join("melda.db");
+        let url = "sqlite://".to_string() + path.to_str().unwrap();
+        let adapter = AdapterBuilder::new().build(&url).unwrap();
+        assert_round_trip(adapter.as_ref());
+        assert!(path.exists());
+    }
+
+    #[cfg(feature = "solid")]
+    #[test]
+    fn test_builder_solid() {
+        // The scheme is registered (the Url is checked before connecting to the pod)
+        let builder = AdapterBuilder::new();
+        assert!(build_error(&builder, "solid:///folder") == "missing_host");
+        assert!(build_error(&builder, "solid+flate:///folder") == "missing_host");
+    }
+
+    #[cfg(feature = "webdav")]
+    #[test]
+    fn test_builder_webdav() {
+        // The scheme is registered (the Url is checked before connecting to the server)
+        let builder = AdapterBuilder::new();
+        assert!(build_error(&builder, "webdav:///dav") == "missing_host");
+        assert!(build_error(&builder, "webdav+retry:///dav") == "missing_host");
+    }
+
+    #[test]
+    fn test_builder_flate() {
+        let adapter = AdapterBuilder::new().build("memory+flate://").unwrap();
+        assert_round_trip(adapter.as_ref());
+        // Objects are compressed
+        let data = vec![b'a'; 4096];
+        assert!(adapter.write_object("otherkey.pack", &data).is_ok());
+        assert!(adapter.read_object("otherkey.pack", 0, 0).unwrap() == data);
+        assert!(adapter.total_size().unwrap() < 4096);
+    }
+
+    #[cfg(feature = "brotliadapter")]
+    #[test]
+    fn test_builder_brotli() {
+        let adapter = AdapterBuilder::new().build("memory+brotli://").unwrap();
+        assert_round_trip(adapter.as_ref());
+        let data = vec![b'a'; 4096];
+        assert!(adapter.write_object("otherkey.pack", &data).is_ok());
+        assert!(adapter.read_object("otherkey.pack", 0, 0).unwrap() == data);
+        assert!(adapter.total_size().unwrap() < 4096);
+    }
+
+    #[test]
+    fn test_builder_retry() {
+        let builder = AdapterBuilder::new();
+        let adapter = builder.build("memory+retry://").unwrap();
+        assert_round_trip(adapter.as_ref());
+        let adapter = builder
+            .build("memory+retry://?retries=5&backoff=50")
+            .unwrap();
+        assert_round_trip(adapter.as_ref());
+        assert!(build_error(&builder, "memory+retry://?retries=many") == "invalid_retries: many");
+        assert!(build_error(&builder, "memory+retry://?backoff=-1") == "invalid_backoff: -1");
+    }
+
+    #[test]
+    fn test_builder_layers() {
+        // Layers are applied in order
+        let adapter = AdapterBuilder::new()
+            .build("memory+flate+retry://?retries=2")
+            .unwrap();
+        assert_round_trip(adapter.as_ref());
+        let data = vec![b'a'; 4096];
+        assert!(adapter.write_object("otherkey.pack", &data).is_ok());
+        assert!(adapter.total_size().unwrap() < 4096);
+    }
+
+    #[test]
+    fn test_builder_unknown_schemes() {
+        let builder = AdapterBuilder::new();
+        assert!(build_error(&builder, "unknown://") == "unknown_adapter_scheme: unknown");
+        assert!(build_error(&builder, "memory+unknown://") == "unknown_adapter_scheme: unknown");
+        assert!(build_error(&builder, "memory+://") == "unknown_adapter_scheme: ");
+        // Layers cannot be used as base adapters (and vice versa)
+        assert!(build_error(&builder, "flate+memory://") == "unknown_adapter_scheme: flate");
+        assert!(build_error(&builder, "memory+memory://") == "unknown_adapter_scheme: memory");
+        assert!(build_error(&builder, "not an url") == "invalid_adapter_url");
+        // Nothing is registered in an empty builder
+        let empty = AdapterBuilder::empty();
+        assert!(build_error(&empty, "memory://") == "unknown_adapter_scheme: memory");
+    }
+
+    #[test]
+    fn test_builder_register() {
+        let mut builder = AdapterBuilder::empty();
+        builder.register_base("mem", Box::new(|_| Ok(Box::new(MemoryAdapter::new()))));
+        builder.register_layer("same", Box::new(|backend, _| Ok(Box::new(Same(backend)))));
+        let adapter = builder.build("mem+same+same://").unwrap();
+        assert_round_trip(adapter.as_ref());
+        assert!(build_error(&builder, "memory://") == "unknown_adapter_scheme: memory");
+    }
+
+    // A layer which forwards all calls to the backend
+    struct Same(std::sync::Arc<std::sync::RwLock<Box<dyn Adapter>>>);
+
+    impl Adapter for Same {
+        fn read_object(&self, key: &str, offset: usize, length: usize) -> anyhow::Result<Vec<u8>> {
+            self.0.read().unwrap().read_object(key, offset, length)
+        }
+
+        fn write_object(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+            self.0.read().unwrap().write_object(key, data)
+        }
+
+        fn list_objects(&self, ext: &str) -> anyhow::Result<Vec<String>> {
+            self.0.read().unwrap().list_objects(ext)
+        }
+    }
+
+    #[test]
+    fn test_list_folders_paged() {
+        let folders = vec!["bb".to_string(), "aa".to_string(), "cc".to_string()];
+        let listed = std::cell::RefCell::new(vec![]);
+        let list_folder = |folder: &str| -> anyhow::Result<Vec<String>> {
+            listed.borrow_mut().push(folder.to_string());
+            Ok(match folder {
+                "aa" => vec!["aa2".to_string(), "aa1".to_string()],
+                "bb" => vec!["bb1".to_string()],
+                _ => vec![],
+            })
+        };
+        let (page, next) = list_folders_paged(folders.clone(), list_folder, None, 1).unwrap();
+        assert_eq!(page, vec!["aa1"]);
+        assert_eq!(next.as_deref(), Some("aa/aa1"));
+        // Only the folders needed for the page are listed
+        assert_eq!(*listed.borrow(), vec!["aa"]);
+        let (page, next) =
+            list_folders_paged(folders.clone(), list_folder, next.as_deref(), 1).unwrap();
+        assert_eq!(page, vec!["aa2"]);
+        let (page, next) =
+            list_folders_paged(folders.clone(), list_folder, next.as_deref(), 1).unwrap();
+        assert_eq!(page, vec!["bb1"]);
+        assert!(next.is_none());
+        let (page, next) = list_folders_paged(folders.clone(), list_folder, None, 10).unwrap();
+        assert_eq!(page, vec!["aa1", "aa2", "bb1"]);
+        assert!(next.is_none());
+        assert!(list_folders_paged(folders.clone(), list_folder, Some("aa1"), 1).is_err());
+        assert!(list_folders_paged(folders, list_folder, None, 0).is_err());
+    }
 }
@@ -0,0 +1,534 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2022 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::{Adapter, PagedObjects};
+use crate::constants::{AUDIT_EXTENSION, LIST_PAGE_SIZE};
+use crate::utils::{digest_bytes, digest_string, DigestWriter};
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+use std::{
+    io::{Read, Write},
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Operation recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOperation {
+    /// An object was written
+    Write,
+    /// An object was deleted
+    Delete,
+}
+
+impl AuditOperation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuditOperation::Write => "write",
+            AuditOperation::Delete => "delete",
+        }
+    }
+
+    fn parse(s: &str) -> Result<AuditOperation> {
+        match s {
+            "write" => Ok(AuditOperation::Write),
+            "delete" => Ok(AuditOperation::Delete),
+            _ => bail!("invalid_audit_operation: {}", s),
+        }
+    }
+}
+
+/// A record of the audit log. Each record contains the digest of the previous record, hence
+/// records cannot be altered, removed or reordered without breaking the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Position of the record in the log (the first record is 0)
+    pub sequence: u64,
+    /// Time of the operation (milliseconds since the Unix epoch)
+    pub timestamp: u64,
+    /// The operation
+    pub operation: AuditOperation,
+    /// Key of the object
+    pub key: String,
+    /// Length of the written content (in bytes, 0 for deletions)
+    pub length: usize,
+    /// Digest of the written content (empty for deletions)
+    pub digest: String,
+    /// Digest of the previous record (empty for the first record)
+    pub previous: String,
+}
+
+impl AuditRecord {
+    fn to_value(&self) -> Value {
+        json!({
+            "s": self.sequence,
+            "t": self.timestamp,
+            "o": self.operation.as_str(),
+            "k": self.key,
+            "l": self.length,
+            "d": self.digest,
+            "p": self.previous,
+        })
+    }
+
+    fn from_value(v: &Value) -> Result<AuditRecord> {
+        let field = |name: &str| v.get(name).ok_or_else(|| anyhow!("invalid_audit_record"));
+        let number = |name: &str| {
+            field(name)?
+                .as_u64()
+                .ok_or_else(|| anyhow!("invalid_audit_record"))
+        };
+        let string = |name: &str| {
+            field(name)?
+                .as_str()
+                .map(|s| s.to_string())
+                .ok_or_else(|| anyhow!("invalid_audit_record"))
+        };
+        Ok(AuditRecord {
+            sequence: number("s")?,
+            timestamp: number("t")?,
+            operation: AuditOperation::parse(&string("o")?)?,
+            key: string("k")?,
+            length: number("l")? as usize,
+            digest: string("d")?,
+            previous: string("p")?,
+        })
+    }
+}
+
+/// Wraps another adapter and appends a record (see AuditRecord) to an audit log for each
+/// object written to (or deleted from) it, while reads and listings are passed through. The
+/// log is stored in a separate adapter, so that it is not affected by operations on the
+/// store (such as garbage collection): each record is a distinct object (the log adapter is
+/// only ever appended to) whose key is made of the sequence number and the digest of the
+/// record. A record is appended only once the operation has succeeded. Multiple audit
+/// adapters should not share the same log concurrently.
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter, auditadapter::{AuditAdapter, AuditOperation}};
+/// use std::sync::{Arc, RwLock};
+/// use serde_json::json;
+/// let backend : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let log : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let log = Arc::new(RwLock::new(log));
+/// let audit = AuditAdapter::new(Arc::new(RwLock::new(backend)), log.clone()).unwrap();
+/// let adapter : Box<dyn Adapter> = Box::new(audit);
+/// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+/// replica.update(json!({ "somekey" : "somevalue" }).as_object().unwrap().clone()).unwrap();
+/// let block_id = replica.commit(None).unwrap().unwrap();
+/// // The log can be read by another audit adapter
+/// let backend : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let audit = AuditAdapter::new(Arc::new(RwLock::new(backend)), log).unwrap();
+/// let history = audit.history().unwrap();
+/// assert!(history.iter().all(|r| r.operation == AuditOperation::Write));
+/// assert!(history.iter().any(|r| r.key == block_id.clone() + ".delta"));
+/// assert!(history.iter().any(|r| r.key.ends_with(".pack")));
+/// ```
+pub struct AuditAdapter {
+    backend: Arc<RwLock<Box<dyn Adapter>>>,
+    log: Arc<RwLock<Box<dyn Adapter>>>,
+    // Sequence number and digest of the next record
+    last: Mutex<(u64, String)>,
+}
+
+impl AuditAdapter {
+    /// Creates a new adapter wrapping the specified adapter, appending records to the given
+    /// log (which might already contain records)
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The adapter to be wrapped
+    /// * `log` - The adapter storing the audit log
+    pub fn new(
+        backend: Arc<RwLock<Box<dyn Adapter>>>,
+        log: Arc<RwLock<Box<dyn Adapter>>>,
+    ) -> Result<Self> {
+        let keys = AuditAdapter::list_records(&log)?;
+        let last = match keys.last() {
+            Some(key) => {
+                let (sequence, digest) = AuditAdapter::parse_record_key(key)?;
+                (sequence + 1, digest.to_string())
+            }
+            None => (0, String::new()),
+        };
+        Ok(AuditAdapter {
+            backend,
+            log,
+            last: Mutex::new(last),
+        })
+    }
+
+    /// Returns the records of the audit log in chronological order, verifying that no record
+    /// has been altered, removed or reordered (otherwise a tampered_audit_log error is returned)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{adapter::Adapter, memoryadapter::MemoryAdapter, auditadapter::{AuditAdapter, AuditOperation}};
+    /// use std::sync::{Arc, RwLock};
+    /// let backend : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let log : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let log = Arc::new(RwLock::new(log));
+    /// let audit = AuditAdapter::new(Arc::new(RwLock::new(backend)), log.clone()).unwrap();
+    /// audit.write_object("alpha.delta", "somedata".as_bytes()).unwrap();
+    /// audit.delete_object("alpha.delta").unwrap();
+    /// let history = audit.history().unwrap();
+    /// assert_eq!(history.len(), 2);
+    /// assert_eq!(history[0].key, "alpha.delta");
+    /// assert_eq!(history[0].length, 8);
+    /// assert_eq!(history[1].operation, AuditOperation::Delete);
+    /// // Removing a record breaks the chain
+    /// let first = log.read().unwrap().list_objects(".audit").unwrap().into_iter().min().unwrap();
+    /// log.read().unwrap().delete_object(&(first + ".audit")).unwrap();
+    /// assert!(audit.history().is_err());
+    /// ```
+    pub fn history(&self) -> Result<Vec<AuditRecord>> {
+        let mut records = vec![];
+        let mut previous = String::new();
+        for (sequence, key) in AuditAdapter::list_records(&self.log)?.iter().enumerate() {
+            let (_, digest) = AuditAdapter::parse_record_key(key)?;
+            let data = self
+                .log
+                .read()
+                .expect("cannot_acquire_log_for_reading")
+                .read_object(&(key.clone() + AUDIT_EXTENSION), 0, 0)?;
+            let record = AuditRecord::from_value(&serde_json::from_slice(&data)?)?;
+            if digest_bytes(&data) != digest
+                || record.sequence != sequence as u64
+                || record.previous != previous
+            {
+                bail!("tampered_audit_log: {}", key);
+            }
+            previous = digest.to_string();
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    // Lists the keys of the records of the log (without extension) in chronological order
+    fn list_records(log: &Arc<RwLock<Box<dyn Adapter>>>) -> Result<Vec<String>> {
+        let mut keys = log
+            .read()
+            .expect("cannot_acquire_log_for_reading")
+            .list_objects(AUDIT_EXTENSION)?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    // Returns the sequence number and the digest of a record given its key
+    fn parse_record_key(key: &str) -> Result<(u64, &str)> {
+        key.split_once('-')
+            .and_then(|(s, d)| Some((s.parse::<u64>().ok()?, d)))
+            .ok_or_else(|| anyhow!("invalid_audit_record_key: {}", key))
+    }
+
+    // Appends a record to the log
+    fn append(
+        &self,
+        operation: AuditOperation,
+        key: &str,
+        length: usize,
+        digest: String,
+    ) -> Result<()> {
+        let mut last = self.last.lock().expect("cannot_acquire_log_state");
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let record = AuditRecord {
+            sequence: last.0,
+            timestamp,
+            operation,
+            key: key.to_string(),
+            length,
+            digest,
+            previous: last.1.clone(),
+        };
+        let content = record.to_value().to_string();
+        let record_digest = digest_string(&content);
+        let record_key = format!("{:020}-{}{}", last.0, record_digest, AUDIT_EXTENSION);
+        self.log
+            .read()
+            .expect("cannot_acquire_log_for_reading")
+            .write_object(&record_key, content.as_bytes())?;
+        *last = (last.0 + 1, record_digest);
+        Ok(())
+    }
+}
+
+/// Reader computing the digest and the length of the content being read
+struct AuditReader<'a> {
+    inner: &'a mut dyn Read,
+    digest: DigestWriter,
+    length: usize,
+}
+
+impl Read for AuditReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.digest.write_all(&buf[..n])?;
+        self.length += n;
+        Ok(n)
+    }
+}
+
+impl Adapter for AuditAdapter {
+    /// Reads an object or a sub-object from the backend storage. When offset and length are both 0
+    /// the full object is returned, otherwise the sub-object is returned
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    ///
+    fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .read_object(key, offset, length)
+    }
+
+    /// Reads an object or a sub-object from the backend storage, appending it to buf
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    /// * `buf` - The buffer the content is appended to
+    fn read_object_into(
+        &self,
+        key: &str,
+        offset: usize,
+        length: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .read_object_into(key, offset, length, buf)
+    }
+
+    /// Writes an object to the storage, then records the write
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .write_object(key, data)?;
+        self.append(AuditOperation::Write, key, data.len(), digest_bytes(data))
+    }
+
+    /// Writes an object to the storage reading its content from reader, then records the
+    /// write (the digest is computed while the content is streamed)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `reader` - The source of the content of the object
+    fn write_object_streaming(&self, key: &str, reader: &mut dyn Read) -> Result<()> {
+        let mut reader = AuditReader {
+            inner: reader,
+            digest: DigestWriter::default(),
+            length: 0,
+        };
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .write_object_streaming(key, &mut reader)?;
+        let length = reader.length;
+        self.append(AuditOperation::Write, key, length, reader.digest.finish())
+    }
+
+    /// Writes multiple objects to the storage, then records each write
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The keys associated with the objects along with their content
+    fn write_batch(&self, items: &[(&str, &[u8])]) -> Result<()> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .write_batch(items)?;
+        for (key, data) in items {
+            self.append(AuditOperation::Write, key, data.len(), digest_bytes(data))?;
+        }
+        Ok(())
+    }
+
+    /// Deletes an object from the storage, then records the deletion
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .delete_object(key)?;
+        self.append(AuditOperation::Delete, key, 0, String::new())
+    }
+
+    /// Flushes the backend storage and the log
+    fn flush(&self) -> Result<()> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .flush()?;
+        self.log
+            .read()
+            .expect("cannot_acquire_log_for_reading")
+            .flush()
+    }
+
+    /// Returns the size (in bytes) of an object in the backend storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .object_size(key)
+    }
+
+    /// Returns the total size (in bytes) of the backend storage (the log is not included)
+    fn total_size(&self) -> Result<u64> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .total_size()
+    }
+
+    /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .list_objects(ext)
+    }
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<crate::adapter::Cursor>)> {
+        self.backend
+            .read()
+            .expect("cannot_acquire_backend_for_reading")
+            .list_objects_paged(ext, cursor, limit)
+    }
+
+    /// Iterates over the keys of the objects whose key ends with ext, fetching them one page at
+    /// a time (see list_objects_paged)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        Box::new(PagedObjects::new(self, ext, LIST_PAGE_SIZE))
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::{
+        adapter::Adapter,
+        auditadapter::{AuditAdapter, AuditOperation},
+        memoryadapter::MemoryAdapter,
+        utils::digest_string,
+    };
+    #[allow(unused_imports)]
+    use std::sync::{Arc, RwLock};
+
+    #[allow(dead_code)]
+    fn memory() -> Arc<RwLock<Box<dyn Adapter>>> {
+        let adapter: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        Arc::new(RwLock::new(adapter))
+    }
+
+    #[test]
+    fn test_audit_records_writes() {
+        let backend = memory();
+        let log = memory();
+        let aa = AuditAdapter::new(backend.clone(), log.clone()).unwrap();
+        aa.write_object("alpha.delta", b"alpha").unwrap();
+        aa.write_batch(&[("beta.pack", b"beta"), ("gamma.delta", b"gamma")])
+            .unwrap();
+        aa.write_object_streaming("delta.pack", &mut "delta".as_bytes())
+            .unwrap();
+        assert!(aa.read_object("delta.pack", 0, 0).unwrap() == b"delta");
+        assert!(aa.list_objects(".delta").unwrap().len() == 2);
+        let history = aa.history().unwrap();
+        let keys: Vec<&str> = history.iter().map(|r| r.key.as_str()).collect();
+        assert!(keys == vec!["alpha.delta", "beta.pack", "gamma.delta", "delta.pack"]);
+        assert!(history[3].digest == digest_string("delta"));
+        assert!(history[3].length == 5);
+        assert!(history.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        // The log is kept separate from the store
+        assert!(backend
+            .read()
+            .unwrap()
+            .list_objects(".audit")
+            .unwrap()
+            .is_empty());
+        // Records are appended to an existing log
+        let aa = AuditAdapter::new(backend, log).unwrap();
+        aa.delete_object("alpha.delta").unwrap();
+        let history = aa.history().unwrap();
+        assert!(history.len() == 5);
+        assert!(history[4].operation == AuditOperation::Delete);
+        assert!(history[4].sequence == 4);
+    }
+
+    #[test]
+    fn test_audit_detects_tampering() {
+        let log = memory();
+        let aa = AuditAdapter::new(memory(), log.clone()).unwrap();
+        aa.write_object("alpha.delta", b"alpha").unwrap();
+        aa.write_object("beta.delta", b"beta").unwrap();
+        assert!(aa.history().unwrap().len() == 2);
+        // Replace the last record with an altered one
+        let mut keys = log.read().unwrap().list_objects(".audit").unwrap();
+        keys.sort();
+        let key = keys.last().unwrap().clone() + ".audit";
+        let content = log.read().unwrap().read_object(&key, 0, 0).unwrap();
+        let altered = String::from_utf8(content).unwrap().replace("beta", "gamma");
+        log.read().unwrap().delete_object(&key).unwrap();
+        log.read()
+            .unwrap()
+            .write_object(&key, altered.as_bytes())
+            .unwrap();
+        assert!(aa.history().is_err());
+    }
+}
@@ -13,7 +13,8 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use crate::adapter::Adapter;
+use crate::adapter::{Adapter, Cursor, PagedObjects};
+use crate::constants::LIST_PAGE_SIZE;
 use anyhow::Result;
 use std::{
     io::Read,
@@ -72,6 +73,72 @@ impl Adapter for BrotliAdapter {
         self.backend.write().unwrap().write_object(&key, &buffer)
     }
 
+    /// Writes multiple objects to the storage (compressed objects are written as a batch on the backend)
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The keys associated with the objects along with their content
+    fn write_batch(&self, items: &[(&str, &[u8])]) -> Result<()> {
+        let mut compressed_items = vec![];
+        for (key, data) in items {
+            let key = key.to_string() + ".brotli"; // Change key to avoid mismatching cache objects
+            let mut compressor = brotli::CompressorReader::new(*data, 4096, 11, 22);
+            let mut buffer = vec![];
+            compressor.read_to_end(&mut buffer)?;
+            compressed_items.push((key, buffer));
+        }
+        let batch: Vec<(&str, &[u8])> = compressed_items
+            .iter()
+            .map(|(k, d)| (k.as_str(), d.as_slice()))
+            .collect();
+        self.backend.write().unwrap().write_batch(&batch)
+    }
+
+    /// Deletes an object from the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let key = key.to_string() + ".brotli"; // Change key to avoid mismatching cache objects
+        self.backend.write().unwrap().delete_object(&key)
+    }
+
+    /// Deletes multiple objects from the storage (objects are deleted as a batch on the backend)
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keys associated with the objects
+    fn delete_objects(&self, keys: &[&str]) -> Result<Vec<(String, Result<()>)>> {
+        let backend_keys: Vec<String> = keys.iter().map(|k| k.to_string() + ".brotli").collect();
+        let batch: Vec<&str> = backend_keys.iter().map(|k| k.as_str()).collect();
+        let results = self.backend.write().unwrap().delete_objects(&batch)?;
+        Ok(results
+            .into_iter()
+            .map(|(k, r)| (k.strip_suffix(".brotli").unwrap_or(&k).to_string(), r))
+            .collect())
+    }
+
+    /// Flushes the backend storage
+    fn flush(&self) -> Result<()> {
+        self.backend.read().unwrap().flush()
+    }
+
+    /// Returns the size (in bytes) of the compressed object in the backend storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        let key = key.to_string() + ".brotli"; // Change key to avoid mismatching cache objects
+        self.backend.read().unwrap().object_size(&key)
+    }
+
+    /// Returns the total size (in bytes) of the backend storage (the size of the compressed objects)
+    fn total_size(&self) -> Result<u64> {
+        self.backend.read().unwrap().total_size()
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -85,6 +152,43 @@ impl Adapter for BrotliAdapter {
             .map(|k| k.trim_end_matches(".brotli").to_string())
             .collect())
     }
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time (the cursor of
+    /// the backend is passed through)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        let ext = ext.to_string() + ".brotli";
+        let (keys, next) = self
+            .backend
+            .read()
+            .unwrap()
+            .list_objects_paged(&ext, cursor, limit)?;
+        let keys = keys
+            .into_iter()
+            .map(|k| k.trim_end_matches(".brotli").to_string())
+            .collect();
+        Ok((keys, next))
+    }
+
+    /// Iterates over the keys of the objects whose key ends with ext, fetching them one page at
+    /// a time (see list_objects_paged)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        Box::new(PagedObjects::new(self, ext, LIST_PAGE_SIZE))
+    }
 }
 
 mod tests {
@@ -183,4 +287,32 @@ mod tests {
         assert!(sqa.list_objects(".pack").unwrap().len() == 1);
         assert!(sqa.list_objects("").unwrap().len() == 2);
     }
+
+    #[test]
+    fn test_list_objects_paged() {
+        let ma: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let sqa = BrotliAdapter::new(std::sync::Arc::new(std::sync::RwLock::new(ma)));
+        for k in ["a", "b", "c", "d", "e"] {
+            assert!(sqa
+                .write_object(&(k.to_string() + ".delta"), "somedata".as_bytes())
+                .is_ok());
+        }
+        assert!(sqa.write_object("c.pack", "otherdata".as_bytes()).is_ok());
+        let mut all = vec![];
+        let mut cursor = None;
+        loop {
+            let (page, next) = sqa
+                .list_objects_paged(".delta", cursor.as_deref(), 2)
+                .unwrap();
+            assert!(page.len() <= 2);
+            all.extend(page);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+        assert_eq!(all, vec!["a", "b", "c", "d", "e"]);
+        let keys: Vec<String> = sqa.iter_objects("").collect::<anyhow::Result<_>>().unwrap();
+        assert_eq!(keys.len(), 6);
+    }
 }
@@ -32,8 +32,64 @@ pub const PATCH_DELETE: &str = r#"d"#;
 pub const PACK_EXTENSION: &str = r#".pack"#;
 /// Delta block extension
 pub const DELTA_EXTENSION: &str = r#".delta"#;
+/// Binary blob extension
+pub const BLOB_EXTENSION: &str = r#".blob"#;
+/// Number of keys fetched at once when listing the objects in a storage
+pub const LIST_PAGE_SIZE: usize = 1024;
+/// Maximum number of keys deleted with a single statement by the SQLite adapter (below the
+/// default limit on the number of parameters of a statement)
+#[cfg(feature = "sqlitedb")]
+pub const SQLITE_MAX_DELETE_KEYS: usize = 500;
+/// Repack record extension (records which packs have been replaced by repacking)
+pub const REPACK_EXTENSION: &str = r#".repack"#;
+/// Replaced packs field (inside repack records)
+pub const REPLACED_FIELD: &str = r#"r"#;
 /// Data pack index extension
 pub const INDEX_EXTENSION: &str = r#".index"#;
+/// Pinned revisions record extension
+pub const PINS_EXTENSION: &str = r#".pins"#;
+/// Extension of the records of audit logs (see AuditAdapter)
+#[cfg(feature = "audit")]
+pub const AUDIT_EXTENSION: &str = r#".audit"#;
+/// Prefix for binary blob references (followed by the digest of the blob)
+pub const BLOB_REFERENCE_PREFIX: &str = "\u{25C9}";
+/// Header of portable archives (followed by the format version)
+pub const ARCHIVE_MAGIC: &[u8] = b"MELDAARC";
+/// Version of the portable archive format
+pub const ARCHIVE_VERSION: u8 = 1;
+/// Maximum length (in bytes) of the keys of the items of a portable archive
+pub const MAX_ARCHIVE_KEY_LENGTH: usize = 4096;
+/// Header of packs written with one object per line (see PackFormat::Lines), followed by
+/// the version of the format and a newline
+pub const PACK_LINES_HEADER: &[u8] = b"#MELDAPACK:jsonl:1\n";
+/// Separator between the namespace and the key of stored items (keys are also used as
+/// file names by some adapters, hence a path separator cannot be used)
+pub const NAMESPACE_SEPARATOR: &str = "~";
+/// Field of the root object containing the entries of a key-value store (see MeldaKv)
+pub const KV_ENTRIES_FIELD: &str = "entries\u{266D}";
+/// Value field (inside the entries of a key-value store)
+pub const KV_VALUE_FIELD: &str = "value";
+/// Default length of the tail of revisions (characters of the digest of the parent revision)
+pub const DEFAULT_REVISION_TAIL_LENGTH: usize = 7;
+/// Maximum length of the tail of revisions (the length of a digest)
+pub const MAX_REVISION_TAIL_LENGTH: usize = 64;
+/// Default maximum nesting depth of the objects reconstructed when reading a document
+pub const DEFAULT_MAX_READ_DEPTH: usize = 256;
+/// Maximum number of references to objects and array descriptors expanded when reading a
+/// document (shared objects are expanded at each reference)
+pub const MAX_READ_REFERENCES: usize = 1 << 20;
+/// Revision tail length field (inside delta blocks, only if it differs from the default)
+pub const TAIL_LENGTH_FIELD: &str = r#"t"#;
+/// Block format version field (inside delta blocks, only if the block requires a version
+/// newer than the first one)
+pub const BLOCK_VERSION_FIELD: &str = r#"v"#;
+/// Block format version introducing merge records (see Melda::resolve_merged)
+pub const MERGE_RECORDS_BLOCK_VERSION: u64 = 2;
+/// Block format version introducing non-default revision tail lengths (see
+/// MeldaConfig::revision_tail_length), the latest supported version
+pub const TAIL_LENGTH_BLOCK_VERSION: u64 = 3;
+/// Field of the root object containing the characters of a text (see MeldaText)
+pub const TEXT_CHARS_FIELD: &str = "text\u{266D}";
 /// Default root object identifier
 pub const ROOT_ID: &str = "\u{221A}";
 /// Parents field key (inside delta blocks)
@@ -50,9 +106,15 @@ pub const PACK_FIELD: &str = r#"k"#;
 pub const HASH_FIELD: &str = r#"#"#;
 /// Expected identifier field (inside objects)
 pub const ID_FIELD: &str = r#"_id"#;
+/// Deleted flag field (inside tombstones, see Melda::read_with_tombstones)
+pub const DELETED_FIELD: &str = r#"_deleted"#;
+/// Field of the deletion metadata of a renamed object (contains the new identifier)
+pub const RENAMED_TO_FIELD: &str = r#"_renamed_to"#;
 /// Hash for empty objects
 pub const EMPTY_HASH: &str = r#"e"#;
 /// Hash for deleted objects
 pub const DELETED_HASH: &str = r#"d"#;
+/// Hash prefix for deleted objects with metadata (followed by the digest of the metadata)
+pub const DELETED_META_PREFIX: &str = r#"dm"#;
 /// Hash for resolved revisions
 pub const RESOLVED_HASH: &str = r#"r"#;
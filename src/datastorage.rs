@@ -13,44 +13,529 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use crate::adapter::Adapter;
-use crate::constants::{HASH_FIELD, INDEX_EXTENSION, PACK_EXTENSION};
+use crate::adapter::{Adapter, Cursor, PagedObjects, PayloadCodec};
+use crate::constants::{
+    BLOB_EXTENSION, DELTA_EXTENSION, HASH_FIELD, INDEX_EXTENSION, LIST_PAGE_SIZE,
+    NAMESPACE_SEPARATOR, PACK_EXTENSION, PACK_FIELD, PACK_LINES_HEADER, PINS_EXTENSION,
+    REPACK_EXTENSION, REPLACED_FIELD,
+};
+use crate::melda::PackFormat;
 use crate::revision::Revision;
-use crate::utils::digest_bytes;
+use crate::utils::{digest_bytes, serialized_size, DigestWriter};
 use anyhow::{anyhow, bail, Result};
 use lru::LruCache;
 use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
 use std::num::NonZeroUsize;
-use std::sync::{Arc, Mutex, RwLock};
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Computes the digest, offset and length of each object in the raw pack data, according to
+/// the format of the pack (see PackFormat)
+fn pack_entries(data: &[u8]) -> Vec<(String, usize, usize)> {
+    match data.strip_prefix(PACK_LINES_HEADER) {
+        Some(lines) => pack_line_entries(lines, PACK_LINES_HEADER.len()),
+        None => pack_array_entries(data),
+    }
+}
+
+/// Computes the entries of a pack with one object per line (empty lines are ignored), the
+/// offsets are relative to the start of the pack (lines start at the given offset)
+fn pack_line_entries(data: &[u8], start: usize) -> Vec<(String, usize, usize)> {
+    let mut entries = vec![];
+    let mut offset = start;
+    for line in data.split(|c| *c == b'\n') {
+        if !line.is_empty() {
+            entries.push((digest_bytes(line), offset, line.len()));
+        }
+        offset += line.len() + 1;
+    }
+    entries
+}
+
+/// Computes the entries of a pack containing a JSON array of objects (unbalanced closing
+/// braces are ignored)
+fn pack_array_entries(data: &[u8]) -> Vec<(String, usize, usize)> {
+    let mut entries = vec![];
+    let mut flag: usize = 0;
+    let mut obj_start = 0;
+    for (offset, c) in data.iter().enumerate() {
+        if *c == b'{' {
+            if flag == 0 {
+                obj_start = offset;
+            };
+            flag += 1;
+        } else if *c == b'}' && flag > 0 {
+            flag -= 1;
+            if flag == 0 {
+                let digest = digest_bytes(&data[obj_start..offset + 1]);
+                let count = offset + 1 - obj_start;
+                entries.push((digest, obj_start, count));
+            };
+        }
+    }
+    entries
+}
 
 pub struct DataStorage {
     adapter: Arc<RwLock<Box<dyn Adapter>>>,
-    stage: HashMap<String, Value>,
+    // Sorted by digest, so that packs of the same objects are identical
+    stage: BTreeMap<String, Value>,
     values: HashMap<String, (String, usize, usize)>,
     loaded_packs: BTreeSet<String>,
-    cache: Mutex<LruCache<String, Map<String, Value>>>,
+    replaced_packs: HashMap<String, BTreeSet<String>>,
+    // Pins records read (or written) by this replica, which are replaced by write_pins
+    pins_records: HashSet<String>,
+    cache: Mutex<ObjectCache>,
+    index_threshold: usize,
+    stream_threshold: usize,
+    pack_format: PackFormat,
+    strict_hashes: bool,
+    codec: Option<Arc<dyn PayloadCodec>>,
+    concurrency: ConcurrencyLimit,
+}
+
+/// Limits the number of concurrent operations on the adapter (a counting semaphore, the
+/// number of operations is unbounded if there is no limit)
+#[derive(Default)]
+struct ConcurrencyLimit {
+    // Number of operations in progress and limit
+    state: Mutex<(usize, Option<usize>)>,
+    released: Condvar,
+}
+
+impl ConcurrencyLimit {
+    /// Waits until an operation can start, the returned permit ends the operation when dropped
+    fn acquire(&self) -> Permit<'_> {
+        let mut state = self.state.lock().unwrap();
+        while state.1.is_some_and(|limit| state.0 >= limit) {
+            state = self.released.wait(state).unwrap();
+        }
+        state.0 += 1;
+        Permit(self)
+    }
+
+    fn set_limit(&self, limit: Option<usize>) {
+        self.state.lock().unwrap().1 = limit;
+        // Waiting operations might be able to start with the new limit
+        self.released.notify_all();
+    }
+}
+
+/// An operation in progress (see ConcurrencyLimit)
+struct Permit<'a>(&'a ConcurrencyLimit);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().0 -= 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// A guard of the adapter lock which holds a permit (see ConcurrencyLimit): the lock is
+/// released before the permit
+struct Throttled<'a, G> {
+    guard: G,
+    _permit: Permit<'a>,
+}
+
+impl<G: Deref> Deref for Throttled<'_, G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+/// Returns true if the digest represents a simple character (a short hexadecimal digest provided
+/// through HASH_FIELD), whose content is not stored
+fn is_simple_character(digest: &str) -> bool {
+    digest.len() <= 8 && u32::from_str_radix(digest, 16).is_ok()
+}
+
+/// Produces the content of a pack (in the given format) from the staged values, serializing
+/// (and encoding) one value at a time, and records the position of each payload
+struct PackReader<'a> {
+    values: std::collections::btree_map::Iter<'a, String, Value>,
+    codec: Option<&'a dyn PayloadCodec>,
+    format: PackFormat,
+    buf: Vec<u8>,
+    pos: usize,
+    // Number of bytes produced so far (including buf)
+    offset: usize,
+    finished: bool,
+    index: Map<String, Value>,
+}
+
+impl<'a> PackReader<'a> {
+    fn new(
+        stage: &'a BTreeMap<String, Value>,
+        codec: Option<&'a dyn PayloadCodec>,
+        format: PackFormat,
+    ) -> Self {
+        PackReader {
+            values: stage.iter(),
+            codec,
+            format,
+            buf: vec![],
+            pos: 0,
+            offset: 0,
+            finished: false,
+            index: Map::new(),
+        }
+    }
+
+    /// Serializes the next payload into the buffer, returns false at the end of the pack
+    fn fill(&mut self) -> bool {
+        self.buf.clear();
+        self.pos = 0;
+        match self.values.next() {
+            Some((digest, v)) => {
+                match self.format {
+                    PackFormat::Array => self.buf.push(if self.offset == 0 { b'[' } else { b',' }),
+                    PackFormat::Lines if self.offset == 0 => {
+                        self.buf.extend_from_slice(PACK_LINES_HEADER)
+                    }
+                    PackFormat::Lines => {}
+                }
+                let content = serde_json::to_vec(v).unwrap();
+                let bytes = match self.codec {
+                    Some(codec) => codec.encode(&content),
+                    None => content,
+                };
+                self.index.insert(
+                    digest.clone(),
+                    json!([self.offset + self.buf.len(), bytes.len()]),
+                );
+                self.buf.extend_from_slice(&bytes);
+                if self.format == PackFormat::Lines {
+                    self.buf.push(b'\n');
+                }
+            }
+            None if !self.finished => {
+                match self.format {
+                    PackFormat::Array if self.offset == 0 => self.buf.extend_from_slice(b"[]"),
+                    PackFormat::Array => self.buf.push(b']'),
+                    PackFormat::Lines if self.offset == 0 => {
+                        self.buf.extend_from_slice(PACK_LINES_HEADER)
+                    }
+                    PackFormat::Lines => {}
+                }
+                self.finished = true;
+            }
+            None => return false,
+        }
+        self.offset += self.buf.len();
+        true
+    }
+}
+
+impl Read for PackReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if !self.fill() {
+                return Ok(0);
+            }
+        }
+        let count = out.len().min(self.buf.len() - self.pos);
+        out[..count].copy_from_slice(&self.buf[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+/// Computes the digest of a pack, keeping its content only while it does not exceed the limit
+struct PackSink {
+    digest: DigestWriter,
+    content: Option<Vec<u8>>,
+    limit: usize,
+}
+
+impl Write for PackSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.digest.write_all(buf)?;
+        if let Some(content) = &mut self.content {
+            if content.len() + buf.len() > self.limit {
+                self.content = None;
+            } else {
+                content.extend_from_slice(buf);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Cache of the objects read from packs, bounded by the number of objects and optionally by
+/// the total size (in bytes, as serialized) of the cached objects
+struct ObjectCache {
+    objects: LruCache<String, (Arc<Map<String, Value>>, usize)>,
+    budget: Option<usize>,
+    // Part of the budget used by other caches (see DataStorage::reserve_cache_bytes)
+    reserved: usize,
+    bytes: usize,
+    evictions: usize,
+}
+
+impl ObjectCache {
+    fn new(capacity: NonZeroUsize) -> ObjectCache {
+        ObjectCache {
+            objects: LruCache::new(capacity),
+            budget: None,
+            reserved: 0,
+            bytes: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Returns the size of the object (only measured if a budget is set)
+    fn measure(&self, object: &Map<String, Value>) -> usize {
+        if self.budget.is_none() {
+            return 0;
+        }
+        serialized_size(object)
+    }
+
+    fn get(&mut self, digest: &str) -> Option<Arc<Map<String, Value>>> {
+        self.objects.get(digest).map(|(object, _)| object.clone())
+    }
+
+    fn contains(&self, digest: &str) -> bool {
+        self.objects.contains(digest)
+    }
+
+    fn put(&mut self, digest: String, object: Arc<Map<String, Value>>) {
+        let size = self.measure(&object);
+        self.bytes += size;
+        if let Some((old, (_, old_size))) = self.objects.push(digest.clone(), (object, size)) {
+            self.bytes -= old_size;
+            if old != digest {
+                self.evictions += 1;
+            }
+        }
+        self.enforce_budget();
+    }
+
+    /// Evicts the least recently used objects until the cached objects fit the budget (minus
+    /// the reserved part)
+    fn enforce_budget(&mut self) {
+        if let Some(budget) = self.budget {
+            while self.bytes > budget.saturating_sub(self.reserved) {
+                match self.objects.pop_lru() {
+                    Some((_, (_, size))) => {
+                        self.bytes -= size;
+                        self.evictions += 1;
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    fn set_budget(&mut self, budget: Option<usize>) {
+        self.budget = budget;
+        // Sizes are only measured while a budget is set
+        let mut bytes = 0;
+        let digests: Vec<String> = self.objects.iter().map(|(d, _)| d.clone()).collect();
+        for digest in digests {
+            let size = match self.objects.peek(&digest) {
+                Some((object, _)) => self.measure(object),
+                None => continue,
+            };
+            if let Some(entry) = self.objects.peek_mut(&digest) {
+                entry.1 = size;
+            }
+            bytes += size;
+        }
+        self.bytes = bytes;
+        self.enforce_budget();
+    }
+
+    fn clear(&mut self) {
+        self.objects.clear();
+        self.bytes = 0;
+    }
+}
+
+/// Prefixes the keys of all items with a namespace, so that multiple documents can share the same adapter
+struct NamespaceAdapter {
+    backend: Arc<RwLock<Box<dyn Adapter>>>,
+    prefix: String,
+}
+
+impl NamespaceAdapter {
+    fn strip(&self, keys: Vec<String>) -> Vec<String> {
+        keys.into_iter()
+            .filter_map(|k| k.strip_prefix(&self.prefix).map(|k| k.to_string()))
+            .collect()
+    }
+}
+
+impl Adapter for NamespaceAdapter {
+    fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        let key = self.prefix.clone() + key;
+        self.backend
+            .read()
+            .unwrap()
+            .read_object(&key, offset, length)
+    }
+
+    fn read_object_into(
+        &self,
+        key: &str,
+        offset: usize,
+        length: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        let key = self.prefix.clone() + key;
+        self.backend
+            .read()
+            .unwrap()
+            .read_object_into(&key, offset, length, buf)
+    }
+
+    fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let key = self.prefix.clone() + key;
+        self.backend.write().unwrap().write_object(&key, data)
+    }
+
+    fn write_object_streaming(&self, key: &str, reader: &mut dyn Read) -> Result<()> {
+        let key = self.prefix.clone() + key;
+        self.backend
+            .write()
+            .unwrap()
+            .write_object_streaming(&key, reader)
+    }
+
+    fn write_batch(&self, items: &[(&str, &[u8])]) -> Result<()> {
+        let keys: Vec<String> = items.iter().map(|(k, _)| self.prefix.clone() + k).collect();
+        let batch: Vec<(&str, &[u8])> = keys
+            .iter()
+            .zip(items)
+            .map(|(k, (_, d))| (k.as_str(), *d))
+            .collect();
+        self.backend.write().unwrap().write_batch(&batch)
+    }
+
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let key = self.prefix.clone() + key;
+        self.backend.write().unwrap().delete_object(&key)
+    }
+
+    fn delete_objects(&self, keys: &[&str]) -> Result<Vec<(String, Result<()>)>> {
+        let keys: Vec<String> = keys.iter().map(|k| self.prefix.clone() + k).collect();
+        let batch: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+        let results = self.backend.write().unwrap().delete_objects(&batch)?;
+        Ok(results
+            .into_iter()
+            .map(|(k, r)| (k.strip_prefix(&self.prefix).unwrap_or(&k).to_string(), r))
+            .collect())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.backend.read().unwrap().flush()
+    }
+
+    fn object_size(&self, key: &str) -> Result<u64> {
+        let key = self.prefix.clone() + key;
+        self.backend.read().unwrap().object_size(&key)
+    }
+
+    // Only the objects within the namespace are counted
+    fn total_size(&self) -> Result<u64> {
+        let backend = self.backend.read().unwrap();
+        let mut size = 0;
+        for key in backend.list_objects("")? {
+            if key.starts_with(&self.prefix) {
+                size += backend.object_size(&key)?;
+            }
+        }
+        Ok(size)
+    }
+
+    fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+        let keys = self.backend.read().unwrap().list_objects(ext)?;
+        Ok(self.strip(keys))
+    }
+
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        // The cursor of the backend is passed through (pages can be shorter than limit)
+        let (keys, next) = self
+            .backend
+            .read()
+            .unwrap()
+            .list_objects_paged(ext, cursor, limit)?;
+        Ok((self.strip(keys), next))
+    }
+
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        Box::new(PagedObjects::new(self, ext, LIST_PAGE_SIZE))
+    }
 }
 
 impl DataStorage {
-    /// Constructs a new Data storage based on the provided adapter
-    pub fn new(adapter: Arc<RwLock<Box<dyn Adapter>>>) -> DataStorage {
-        let cache_size = std::env::var("MELDA_DATA_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
-        DataStorage {
+    /// Constructs a new Data storage based on the provided adapter, with the given cache
+    /// capacity, index threshold (bytes per object above which an index is written) and
+    /// stream threshold (size in bytes above which packs are streamed, see pack_with), using
+    /// the given format for new packs.
+    /// If a namespace is provided, only the items within the namespace are visible.
+    /// If strict_hashes is true, objects providing their own digest (see digest_object) are
+    /// rejected when they would alias a different object
+    pub fn new(
+        adapter: Arc<RwLock<Box<dyn Adapter>>>,
+        cache_size: usize,
+        index_threshold: usize,
+        stream_threshold: usize,
+        pack_format: PackFormat,
+        namespace: Option<&str>,
+        strict_hashes: bool,
+    ) -> Result<DataStorage> {
+        let cache_size =
+            NonZeroUsize::new(cache_size).ok_or_else(|| anyhow!("invalid_data_cache_capacity"))?;
+        let adapter = match namespace {
+            Some(namespace) => {
+                if namespace.is_empty()
+                    || !namespace
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    bail!("invalid_namespace");
+                }
+                let namespaced: Box<dyn Adapter> = Box::new(NamespaceAdapter {
+                    backend: adapter,
+                    prefix: namespace.to_string() + NAMESPACE_SEPARATOR,
+                });
+                Arc::new(RwLock::new(namespaced))
+            }
+            None => adapter,
+        };
+        Ok(DataStorage {
             adapter,
-            stage: HashMap::<String, Value>::new(),
+            stage: BTreeMap::<String, Value>::new(),
             values: HashMap::<String, (String, usize, usize)>::new(),
             loaded_packs: BTreeSet::new(),
-            cache: Mutex::new(LruCache::<String, Map<String, Value>>::new(
-                NonZeroUsize::new(cache_size).unwrap(),
-            )),
-        }
+            replaced_packs: HashMap::new(),
+            pins_records: HashSet::new(),
+            cache: Mutex::new(ObjectCache::new(cache_size)),
+            concurrency: ConcurrencyLimit::default(),
+            index_threshold,
+            stream_threshold,
+            pack_format,
+            strict_hashes,
+            codec: None,
+        })
     }
 
     /// Merges another DataStorage into this one
@@ -79,33 +564,15 @@ impl DataStorage {
     /// Loads a pack file (and rebuilds the index)
     fn load_pack(&mut self, pack: &str) -> Result<()> {
         let object = pack.to_string() + PACK_EXTENSION;
-        let data = self
-            .adapter
-            .read()
-            .unwrap()
-            .read_object(object.as_str(), 0, 0)?;
+        let data = self.read_adapter().read_object(object.as_str(), 0, 0)?;
         self.load_pack_data(pack, &data)
     }
 
     /// Data is the raw string (we need to compute the offset and length of the object)
     fn load_pack_data(&mut self, name: &str, data: &[u8]) -> Result<()> {
-        let mut flag = 0;
-        let mut obj_start = 0;
-        for (offset, c) in data.iter().enumerate() {
-            if *c == b'{' {
-                if flag == 0 {
-                    obj_start = offset;
-                };
-                flag += 1;
-            } else if *c == b'}' {
-                flag -= 1;
-                if flag == 0 {
-                    let digest = digest_bytes(&data[obj_start..offset + 1]);
-                    let count = offset + 1 - obj_start;
-                    self.values
-                        .insert(digest, (name.to_string(), obj_start, count));
-                };
-            }
+        for (digest, offset, count) in pack_entries(data) {
+            self.values
+                .insert(digest, (name.to_string(), offset, count));
         }
         Ok(())
     }
@@ -113,9 +580,19 @@ impl DataStorage {
     /// Loads an index object
     fn load_index_object(&mut self, index: &str, obj: &Map<String, Value>) -> Result<()> {
         for (k, v) in obj {
-            let d = v.as_array().unwrap();
-            let offset = d[0].as_i64().unwrap() as usize;
-            let count = d[1].as_i64().unwrap() as usize;
+            let (offset, count) = match v.as_array().map(|d| d.as_slice()) {
+                Some([offset, count]) => (
+                    offset
+                        .as_u64()
+                        .ok_or_else(|| anyhow!("invalid_index_entry"))?
+                        as usize,
+                    count
+                        .as_u64()
+                        .ok_or_else(|| anyhow!("invalid_index_entry"))?
+                        as usize,
+                ),
+                _ => bail!("invalid_index_entry"),
+            };
             self.values
                 .insert(k.clone(), (index.to_string(), offset, count));
         }
@@ -125,11 +602,7 @@ impl DataStorage {
     /// Loads an index file
     fn load_index(&mut self, index: &str) -> Result<()> {
         let object = index.to_string() + INDEX_EXTENSION;
-        let data = self
-            .adapter
-            .read()
-            .unwrap()
-            .read_object(object.as_str(), 0, 0)?;
+        let data = self.read_adapter().read_object(object.as_str(), 0, 0)?;
         let json = std::str::from_utf8(&data)?;
         let json: Value = serde_json::from_str(json)?;
         if json.is_object() {
@@ -139,42 +612,261 @@ impl DataStorage {
         }
     }
 
-    /// Reloads the storage
+    /// Limits the number of concurrent operations on the adapter (None removes the limit).
+    /// Operations exceeding the limit wait until other operations complete.
+    pub fn set_max_concurrency(&self, max: Option<usize>) {
+        self.concurrency.set_limit(max);
+    }
+
+    /// Locks the adapter for reading, once the concurrency limit allows a new operation
+    fn read_adapter(&self) -> Throttled<'_, RwLockReadGuard<'_, Box<dyn Adapter>>> {
+        let permit = self.concurrency.acquire();
+        Throttled {
+            guard: self.adapter.read().unwrap(),
+            _permit: permit,
+        }
+    }
+
+    /// Locks the adapter for writing, once the concurrency limit allows a new operation
+    fn write_adapter(&self) -> Throttled<'_, RwLockWriteGuard<'_, Box<dyn Adapter>>> {
+        let permit = self.concurrency.acquire();
+        Throttled {
+            guard: self.adapter.write().unwrap(),
+            _permit: permit,
+        }
+    }
+
+    /// Reloads the storage, listing the packs one page at a time. Returns the number of loaded
+    /// packs.
     /// TODO: This can be partially replaced by a call to refresh
-    pub fn reload(&mut self) -> Result<Vec<String>> {
+    pub fn reload(&mut self) -> Result<usize> {
         if !self.stage.is_empty() {
             bail!("non_empty_data_stage");
         }
         self.loaded_packs.clear();
+        self.replaced_packs.clear();
         self.values.clear();
-        let pack_list = self.adapter.read().unwrap().list_objects(PACK_EXTENSION)?;
-        let index_list = self.adapter.read().unwrap().list_objects(INDEX_EXTENSION)?;
-        let index_set = index_list.into_iter().collect::<HashSet<_>>();
-        if !pack_list.is_empty() {
-            for i in &pack_list {
-                if index_set.contains(i) {
-                    self.load_index(i)?;
+        self.load_repack_records()?;
+        let mut count = 0;
+        let mut cursor = None;
+        loop {
+            let (page, next) =
+                self.list_raw_items_paged(PACK_EXTENSION, cursor.as_deref(), LIST_PAGE_SIZE)?;
+            for i in page {
+                // Replaced packs are kept until the next repack (see repack)
+                if self.replaced_packs.contains_key(&i) {
+                    continue;
+                }
+                if self.has_index(&i) {
+                    self.load_index(&i)?;
                 } else {
-                    self.load_pack(i)?;
+                    self.load_pack(&i)?;
                 }
-                self.loaded_packs.insert(i.clone());
+                self.loaded_packs.insert(i);
+                count += 1;
+            }
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns true if the pack has an index (looked up for each pack, so that the indexes do
+    /// not need to be listed)
+    fn has_index(&self, pack: &str) -> bool {
+        self.read_adapter()
+            .object_size(&(pack.to_string() + INDEX_EXTENSION))
+            .is_ok()
+    }
+
+    fn load_repack_records(&mut self) -> Result<()> {
+        for record in self.list_all_raw_items(REPACK_EXTENSION)? {
+            let key = record + REPACK_EXTENSION;
+            let data = self.read_adapter().read_object(&key, 0, 0)?;
+            let record: Value = serde_json::from_slice(&data)?;
+            let packs = |field: &str| -> Result<BTreeSet<String>> {
+                record
+                    .get(field)
+                    .and_then(|v| v.as_array())
+                    .ok_or_else(|| anyhow!("invalid_repack_record"))?
+                    .iter()
+                    .map(|p| {
+                        p.as_str()
+                            .map(|p| p.to_string())
+                            .ok_or_else(|| anyhow!("pack_not_a_string"))
+                    })
+                    .collect()
+            };
+            let replacements = packs(PACK_FIELD)?;
+            for old in packs(REPLACED_FIELD)? {
+                self.loaded_packs.remove(&old);
+                self.replaced_packs.insert(old, replacements.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the pinned revisions of each object (the records written by several replicas
+    /// are merged)
+    pub fn read_pins(&mut self) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        let mut pins: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut records = HashSet::new();
+        for record in self.list_all_raw_items(PINS_EXTENSION)? {
+            let key = record.clone() + PINS_EXTENSION;
+            let data = self.read_adapter().read_object(&key, 0, 0)?;
+            let content: BTreeMap<String, BTreeSet<String>> =
+                serde_json::from_slice(&data).map_err(|_| anyhow!("invalid_pins_record"))?;
+            for (uuid, revisions) in content {
+                pins.entry(uuid).or_default().extend(revisions);
             }
+            records.insert(record);
         }
-        Ok(pack_list)
+        self.pins_records = records;
+        Ok(pins)
+    }
+
+    /// Writes a record with the pinned revisions of each object, replacing the records
+    /// previously read (or written) by this replica: records written in the meantime by
+    /// other replicas are kept (and merged by read_pins)
+    pub fn write_pins(&mut self, pins: &BTreeMap<String, BTreeSet<String>>) -> Result<()> {
+        let record = serde_json::to_string(pins)?;
+        let digest = digest_bytes(record.as_bytes());
+        let adapter = self.write_adapter();
+        adapter.write_object(&(digest.clone() + PINS_EXTENSION), record.as_bytes())?;
+        for old in self.pins_records.iter().filter(|r| **r != digest) {
+            adapter.delete_object(&(old.clone() + PINS_EXTENSION))?;
+        }
+        drop(adapter);
+        self.pins_records = HashSet::from([digest]);
+        Ok(())
     }
 
-    pub fn get_loaded_packs(&self) -> &BTreeSet<String> {
-        &self.loaded_packs
+    /// Returns the packs containing the objects of the given pack: the pack itself, unless
+    /// it has been replaced by repacking
+    pub fn resolve_pack(&self, pack: &str) -> BTreeSet<String> {
+        let mut result = BTreeSet::new();
+        let mut pending = vec![pack.to_string()];
+        let mut visited = HashSet::new();
+        while let Some(p) = pending.pop() {
+            if !visited.insert(p.clone()) {
+                continue;
+            }
+            match self.replaced_packs.get(&p) {
+                Some(replacements) => pending.extend(replacements.iter().cloned()),
+                None => {
+                    result.insert(p);
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns true if the objects of the pack are available (the pack, or the packs
+    /// which replaced it, have been loaded)
+    pub fn is_available_pack(&self, pack: &str) -> bool {
+        self.resolve_pack(pack)
+            .iter()
+            .all(|p| self.loaded_packs.contains(p))
+    }
+
+    /// Rewrites the objects stored in packs smaller than target_size into new packs of
+    /// roughly target_size bytes (with indexes). A repack record is written so that references
+    /// to the replaced packs (in delta blocks) remain valid. The replaced packs are deleted by
+    /// the next repack (packs replaced by earlier repacks are deleted now). Returns the number
+    /// of loaded packs before and after repacking.
+    pub fn repack(&mut self, target_size: usize) -> Result<(usize, usize)> {
+        if !self.stage.is_empty() {
+            bail!("non_empty_data_stage");
+        }
+        // Load the packs (and repack records) written by other replicas, so that no value
+        // refers to a pack which has been replaced
+        self.refresh()?;
+        // Packs written by this replica are not yet part of the loaded packs
+        let written: Vec<String> = self.values.values().map(|(p, _, _)| p.clone()).collect();
+        self.loaded_packs.extend(written);
+        let before = self.loaded_packs.len();
+        // Determine the size of the live objects of each pack
+        let mut sizes: HashMap<&String, usize> = self.loaded_packs.iter().map(|p| (p, 0)).collect();
+        for (pack, _, length) in self.values.values() {
+            if let Some(size) = sizes.get_mut(pack) {
+                *size += length;
+            }
+        }
+        let candidates: BTreeSet<String> = sizes
+            .into_iter()
+            .filter(|(_, size)| *size < target_size)
+            .map(|(p, _)| p.clone())
+            .collect();
+        if candidates.len() < 2 {
+            return Ok((before, before));
+        }
+        let mut objects: Vec<(String, usize, String)> = self
+            .values
+            .iter()
+            .filter(|(_, (pack, _, _))| candidates.contains(pack))
+            .map(|(digest, (pack, offset, _))| (pack.clone(), *offset, digest.clone()))
+            .collect();
+        objects.sort();
+        // Write the new packs
+        let mut new_packs = BTreeSet::new();
+        let mut current_size = 0;
+        let count = objects.len();
+        for (i, (_, _, digest)) in objects.into_iter().enumerate() {
+            let value = self.read_raw_value(&digest)?;
+            current_size += serde_json::to_string(&value)?.len();
+            self.stage.insert(digest, value);
+            if current_size >= target_size || i + 1 == count {
+                if let Some((pack, _)) = self.pack_with(|_| Ok(vec![]))? {
+                    new_packs.insert(pack);
+                }
+                current_size = 0;
+            }
+        }
+        let replaced: BTreeSet<String> = candidates.difference(&new_packs).cloned().collect();
+        // Packs replaced by earlier repacks are deleted now, whereas the packs replaced by this
+        // repack are kept until the next one, since other replicas may still read them
+        let stored = self.list_all_raw_items(PACK_EXTENSION)?;
+        let obsolete: Vec<String> = self
+            .replaced_packs
+            .keys()
+            .filter(|p| stored.contains(*p))
+            .cloned()
+            .collect();
+        // Record the replacement before deleting the old packs
+        let mut record = Map::new();
+        record.insert(REPLACED_FIELD.to_string(), json!(replaced));
+        record.insert(PACK_FIELD.to_string(), json!(new_packs));
+        let record = serde_json::to_string(&record)?;
+        let key = digest_bytes(record.as_bytes()) + REPACK_EXTENSION;
+        self.write_adapter().write_object(&key, record.as_bytes())?;
+        for pack in &new_packs {
+            self.loaded_packs.insert(pack.clone());
+        }
+        for pack in replaced {
+            self.loaded_packs.remove(&pack);
+            self.replaced_packs.insert(pack, new_packs.clone());
+        }
+        let adapter = self.write_adapter();
+        for pack in obsolete {
+            adapter.delete_object(&(pack.clone() + INDEX_EXTENSION))?;
+            adapter.delete_object(&(pack + PACK_EXTENSION))?;
+        }
+        drop(adapter);
+        Ok((before, self.loaded_packs.len()))
     }
 
     pub fn refresh(&mut self) -> Result<Vec<String>> {
-        let pack_list = self.adapter.read().unwrap().list_objects(PACK_EXTENSION)?;
-        let index_list = self.adapter.read().unwrap().list_objects(INDEX_EXTENSION)?;
+        self.load_repack_records()?;
+        let pack_list = self.read_adapter().list_objects(PACK_EXTENSION)?;
+        let index_list = self.read_adapter().list_objects(INDEX_EXTENSION)?;
         let index_set = index_list.into_iter().collect::<HashSet<_>>();
         let mut new_packs = vec![];
         if !pack_list.is_empty() {
             for i in &pack_list {
-                if self.loaded_packs.contains(i) {
+                // Replaced packs are kept until the next repack (see repack)
+                if self.loaded_packs.contains(i) || self.replaced_packs.contains_key(i) {
                     continue;
                 }
                 if index_set.contains(i) {
@@ -194,10 +886,42 @@ impl DataStorage {
         Ok(())
     }
 
-    /// Returns true if the pack is readable and valid (digest matches)
+    /// Removes a single value from the stage (if it is staged)
+    pub fn unstage_value(&mut self, digest: &str) {
+        self.stage.remove(digest);
+    }
+
+    /// Clears the state of the storage (staged and loaded values, pins records, cache),
+    /// optionally deleting all items from the adapter
+    pub fn clear(&mut self, purge: bool) -> Result<()> {
+        if purge {
+            let adapter = self.write_adapter();
+            for key in adapter.list_objects("")? {
+                adapter.delete_object(&key)?;
+            }
+        }
+        self.stage.clear();
+        self.values.clear();
+        self.loaded_packs.clear();
+        self.replaced_packs.clear();
+        self.pins_records.clear();
+        self.cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Returns true if the pack is readable and valid (digest matches), if the pack has
+    /// been replaced by repacking the packs which replaced it are checked
     pub fn is_readable_and_valid_pack(&self, pack: &str) -> Result<bool> {
+        if self.replaced_packs.contains_key(pack) {
+            for p in self.resolve_pack(pack) {
+                if !self.is_readable_and_valid_pack(&p)? {
+                    return Ok(false);
+                }
+            }
+            return Ok(true);
+        }
         let pack_name = pack.to_string() + PACK_EXTENSION;
-        match self.adapter.read().unwrap().read_object(&pack_name, 0, 0) {
+        match self.read_adapter().read_object(&pack_name, 0, 0) {
             Ok(data) => {
                 let d = digest_bytes(data.as_slice());
                 Ok(d.eq(pack))
@@ -216,47 +940,260 @@ impl DataStorage {
         Ok(())
     }
 
-    /// Writes an object associating it with the given revision (digest)
+    /// Returns the digest of the payload of the revision stored in a pack (special revisions
+    /// and simple characters are not stored, deleted revisions can reference a metadata object)
+    pub fn payload_digest(rev: &Revision) -> Option<&str> {
+        if let Some(meta) = rev.get_deletion_meta_digest() {
+            Some(meta)
+        } else if rev.is_resolved()
+            || rev.is_deleted()
+            || rev.is_empty()
+            || is_simple_character(&rev.digest)
+        {
+            None
+        } else {
+            Some(&rev.digest)
+        }
+    }
+
+    /// Returns true if the content of a raw item matches its key: packs, delta blocks, blobs,
+    /// repack and pins records are identified by the digest of their content, indexes (identified by the
+    /// digest of the pack they refer to) must be valid JSON objects. Keys must consist of a digest
+    /// followed by one of these extensions (other keys are never valid, since they could refer to
+    /// locations outside the storage)
+    pub fn is_valid_raw_item(key: &str, data: &[u8]) -> bool {
+        let is_digest = |d: &str| {
+            d.len() == 64
+                && d.chars()
+                    .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+        };
+        if let Some(pack) = key.strip_suffix(INDEX_EXTENSION) {
+            is_digest(pack) && serde_json::from_slice::<Map<String, Value>>(data).is_ok()
+        } else {
+            [
+                PACK_EXTENSION,
+                DELTA_EXTENSION,
+                BLOB_EXTENSION,
+                REPACK_EXTENSION,
+                PINS_EXTENSION,
+            ]
+            .iter()
+            .filter_map(|ext| key.strip_suffix(ext))
+            .any(|digest| is_digest(digest) && digest_bytes(data) == digest)
+        }
+    }
+
+    /// Returns true if the value of the revision is stored in a pack
+    pub fn is_stored(rev: &Revision) -> bool {
+        DataStorage::payload_digest(rev).is_some()
+    }
+
+    /// Reads and verifies all packs in the storage (without loading them). Returns the set
+    /// of corrupt packs (unreadable or whose digest does not match) and the digests of the
+    /// objects found in valid packs (associated with the pack identifier)
+    pub fn scan_packs(&self) -> Result<(BTreeSet<String>, HashMap<String, String>)> {
+        let pack_list = self.read_adapter().list_objects(PACK_EXTENSION)?;
+        let index_list = self.read_adapter().list_objects(INDEX_EXTENSION)?;
+        let index_set = index_list.into_iter().collect::<HashSet<_>>();
+        let mut corrupt = BTreeSet::new();
+        let mut objects = HashMap::new();
+        for p in pack_list {
+            let key = p.clone() + PACK_EXTENSION;
+            let data = match self.read_adapter().read_object(&key, 0, 0) {
+                Ok(data) if digest_bytes(&data).eq(&p) => data,
+                _ => {
+                    corrupt.insert(p);
+                    continue;
+                }
+            };
+            if index_set.contains(&p) {
+                let key = p.clone() + INDEX_EXTENSION;
+                let index = self
+                    .read_adapter()
+                    .read_object(&key, 0, 0)
+                    .ok()
+                    .and_then(|i| serde_json::from_slice::<Value>(&i).ok());
+                match index.as_ref().and_then(|i| i.as_object()) {
+                    Some(index) => index.keys().for_each(|digest| {
+                        objects.insert(digest.clone(), p.clone());
+                    }),
+                    None => {
+                        corrupt.insert(p);
+                    }
+                }
+            } else {
+                for (digest, _, _) in pack_entries(&data) {
+                    objects.insert(digest, p.clone());
+                }
+            }
+        }
+        Ok((corrupt, objects))
+    }
+
+    /// Returns true if strict mode is enabled (see check_hash)
+    pub fn strict_hashes(&self) -> bool {
+        self.strict_hashes
+    }
+
+    /// Fails if an object providing its own digest (HASH_FIELD) would alias a different object:
+    /// either a different object with the same digest is staged, or the object is not stored
+    /// (simple characters) but contains other fields (which would be lost)
+    pub fn check_hash(&self, digest: &str, obj: &Map<String, Value>) -> Result<()> {
+        if !obj.contains_key(HASH_FIELD) {
+            return Ok(());
+        }
+        let aliased = if is_simple_character(digest) {
+            obj.len() > 1
+        } else {
+            self.stage
+                .get(digest)
+                .is_some_and(|v| v.as_object() != Some(obj))
+        };
+        if aliased {
+            bail!("hash_collision: {}", digest);
+        }
+        Ok(())
+    }
+
+    /// Writes an object associating it with the given revision (digest). In strict mode, objects
+    /// providing their own digest are checked first (see check_hash)
     pub fn write_object(&mut self, rev: &Revision, obj: Map<String, Value>) -> Result<()> {
-        if rev.is_resolved() || rev.is_deleted() || rev.is_empty() {
+        if self.strict_hashes {
+            self.check_hash(&rev.digest, &obj)?;
+        }
+        if let Some(meta) = rev.get_deletion_meta_digest() {
+            // Metadata of a deleted object (stored according to its content)
+            self.write_raw_value(meta, obj.into())
+        } else if !DataStorage::is_stored(rev) {
             Ok(())
         } else {
             // Otherwise store according to the object digest
-            if rev.digest.len() <= 8 && u32::from_str_radix(&rev.digest, 16).is_ok() {
-                Ok(())
-            } else {
-                self.write_raw_value(&rev.digest, obj.clone().into())?;
-                {
-                    let mut cache = self.cache.lock().unwrap();
-                    cache.put(rev.digest.to_string(), obj); // Only cache the full object
-                }
-                Ok(())
+            self.write_raw_value(&rev.digest, obj.clone().into())?;
+            {
+                let mut cache = self.cache.lock().unwrap();
+                cache.put(rev.digest.to_string(), Arc::new(obj)); // Only cache the full object
             }
+            Ok(())
+        }
+    }
+
+    /// Returns the capacity of the cache (number of objects)
+    pub fn cache_capacity(&self) -> usize {
+        self.cache.lock().unwrap().objects.cap().get()
+    }
+
+    /// Sets (or removes) the codec applied to the payloads stored in packs (see PayloadCodec)
+    pub fn set_codec(&mut self, codec: Option<Arc<dyn PayloadCodec>>) {
+        self.codec = codec;
+    }
+
+    /// Limits the total size (in bytes) of the cached objects, None removes the limit
+    pub fn set_cache_budget(&self, budget: Option<usize>) {
+        self.cache.lock().unwrap().set_budget(budget);
+    }
+
+    /// Reserves part of the budget of the cache for other caches (evicting objects if needed),
+    /// the cached objects are then bounded by the remaining part
+    pub fn reserve_cache_bytes(&self, bytes: usize) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.reserved = bytes;
+        cache.enforce_budget();
+    }
+
+    /// Returns the number of cached objects, their total size in bytes (only measured while
+    /// a budget is set), the budget and the number of evicted objects
+    pub fn cache_stats(&self) -> (usize, usize, Option<usize>, usize) {
+        let cache = self.cache.lock().unwrap();
+        (
+            cache.objects.len(),
+            cache.bytes,
+            cache.budget,
+            cache.evictions,
+        )
+    }
+
+    /// Returns the length (in bytes) of the payload with the given digest in its pack, None if
+    /// the payload has not been committed
+    pub fn stored_length(&self, digest: &str) -> Option<usize> {
+        self.values.get(digest).map(|(_, _, length)| *length)
+    }
+
+    /// Reads the object at the given revision into the cache (if its value is stored in a pack)
+    pub fn warm_object(&self, revision: &Revision) -> Result<()> {
+        if revision.get_deletion_meta_digest().is_some()
+            || !DataStorage::is_stored(revision)
+            || self.cache.lock().unwrap().contains(&revision.digest)
+        {
+            return Ok(());
         }
+        let value = self.read_raw_value(&revision.digest)?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow!("expecting_an_object"))?;
+        self.cache
+            .lock()
+            .unwrap()
+            .put(revision.digest.clone(), Arc::new(object.clone()));
+        Ok(())
     }
 
     /// Reads an object at the given revision
     pub fn read_object(&self, revision: &Revision) -> Result<Map<String, Value>> {
-        if revision.is_deleted() {
+        if let Some(meta) = revision.get_deletion_meta_digest() {
+            // Special case, deleted object with metadata
+            let value = self.read_raw_value(meta)?;
+            let mut object = value
+                .as_object()
+                .ok_or_else(|| anyhow!("expecting_an_object"))?
+                .clone();
+            object.insert("_deleted".to_string(), Value::from(true));
+            Ok(object)
+        } else if revision.is_deleted() {
             // Special case, deleted object
             Ok(json!({"_deleted":true}).as_object().unwrap().clone())
         } else if revision.is_resolved() {
             // Special case, resolved object
             Ok(json!({"_resolved":true}).as_object().unwrap().clone())
-        } else if revision.digest.len() <= 8 && u32::from_str_radix(&revision.digest, 16).is_ok() {
+        } else if revision.is_empty() {
+            // Special case, empty object
+            Ok(Map::new())
+        } else if is_simple_character(&revision.digest) {
             // Special case, simple character
             let mut o = Map::<String, Value>::new();
             o.insert(HASH_FIELD.to_string(), Value::from(revision.digest.clone()));
             Ok(o)
         } else if let Some(object) = self.cache.lock().unwrap().get(&revision.digest) {
-            Ok(object.clone())
+            Ok(object.as_ref().clone())
         } else {
             let value = self.read_raw_value(&revision.digest)?;
-            let object = value.as_object().expect("expecting_an_object");
+            let object = value
+                .as_object()
+                .ok_or_else(|| anyhow!("expecting_an_object"))?;
             Ok(object.clone())
         }
     }
 
+    /// Reads an object at the given revision (see read_object) without copying it: objects
+    /// stored in packs are shared with the cache (and are put into the cache if missing)
+    pub fn read_object_ref(&self, revision: &Revision) -> Result<Arc<Map<String, Value>>> {
+        if revision.get_deletion_meta_digest().is_some() || !DataStorage::is_stored(revision) {
+            return Ok(Arc::new(self.read_object(revision)?));
+        }
+        if let Some(object) = self.cache.lock().unwrap().get(&revision.digest) {
+            return Ok(object.clone());
+        }
+        let value = self.read_raw_value(&revision.digest)?;
+        let object = match value {
+            Value::Object(object) => Arc::new(object),
+            _ => bail!("expecting_an_object"),
+        };
+        self.cache
+            .lock()
+            .unwrap()
+            .put(revision.digest.clone(), object.clone());
+        Ok(object)
+    }
+
     /// Writes the given (JSON) value into the temporary pack (if not already there)
     pub fn write_raw_value(&mut self, digest: &str, obj: Value) -> Result<()> {
         if !self.values.contains_key(digest) && !self.stage.contains_key(digest) {
@@ -265,18 +1202,21 @@ impl DataStorage {
         Ok(())
     }
 
-    /// Reads a JSON value given its digest
+    /// Reads a JSON value given its digest. If the pack containing the value cannot be read
+    /// (for example because it has been removed from the adapter after the storage was loaded)
+    /// a missing_pack error is returned
     pub fn read_raw_value(&self, digest: &str) -> Result<Value> {
         if let Some(value) = self.values.get(digest) {
             let (pack, offset, length) = value;
             let key = pack.clone() + PACK_EXTENSION;
             let data = self
-                .adapter
-                .read()
-                .unwrap()
-                .read_object(&key, *offset, *length)?;
-            let json = std::str::from_utf8(&data)?;
-            let json: Value = serde_json::from_str(json)?;
+                .read_adapter()
+                .read_object(&key, *offset, *length)
+                .map_err(|e| anyhow!("missing_pack: {} ({})", pack, e))?;
+            let json: Value = match &self.codec {
+                Some(codec) => serde_json::from_slice(&codec.decode(&data)?)?,
+                None => serde_json::from_slice(&data)?,
+            };
             Ok(json)
         } else if let Some(value) = self.stage.get(digest) {
             Ok(value.clone())
@@ -285,46 +1225,65 @@ impl DataStorage {
         }
     }
 
-    /// Packs temporary data into a new pack with an index (committing to the adapter)
-    /// Returns the identifier or the pack (digest of its contents)
-    pub fn pack(&mut self) -> Result<Option<String>> {
+    /// Packs temporary data into a new pack with an index (committing to the adapter), the objects
+    /// returned by extra (which receives the identifier of the pack, if any) are written to the
+    /// adapter in the same batch. Packs larger than the stream threshold are instead streamed to
+    /// the adapter (see Adapter::write_object_streaming) before the batch, so that at most one
+    /// serialized object is held in memory. Returns the identifier or the pack (digest of its contents) and its size in bytes
+    pub fn pack_with<F>(&mut self, extra: F) -> Result<Option<(String, usize)>>
+    where
+        F: FnOnce(Option<&str>) -> Result<Vec<(String, Vec<u8>)>>,
+    {
         if self.stage.is_empty() {
+            let items = extra(None)?;
+            self.write_raw_batch(&items)?;
             return Ok(None);
         }
-        let mut index_map = Map::<String, Value>::new();
-        let mut buf = Vec::<u8>::new();
-        let mut start: usize = 1;
-        buf.push(b'[');
-        let mut remaining = self.stage.len();
-        for (digest, v) in &self.stage {
-            let content = serde_json::to_string(&v).unwrap();
-            let bytes = content.as_bytes();
-            buf.extend_from_slice(bytes);
-            index_map.insert(digest.clone(), json!([start, bytes.len()]));
-            remaining -= 1;
-            if remaining > 0 {
-                buf.push(b',');
-                start = buf.len();
+        // Compute the digest of the pack (serializing one value at a time), keeping its content
+        // only if it is small enough to be written along with the other items
+        let codec = self.codec.as_deref();
+        let mut reader = PackReader::new(&self.stage, codec, self.pack_format);
+        let mut sink = PackSink {
+            digest: DigestWriter::default(),
+            content: Some(vec![]),
+            limit: self.stream_threshold,
+        };
+        std::io::copy(&mut reader, &mut sink)?;
+        let index_map = reader.index;
+        let pack_size = reader.offset;
+        let pack_digest = sink.digest.finish();
+        let pack_key = pack_digest.clone() + PACK_EXTENSION;
+        let mut items = vec![];
+        match sink.content {
+            Some(content) => items.push((pack_key, content)),
+            None => {
+                // Large pack: serialize it again while streaming it to the adapter, before
+                // the other items (so that they never refer to a missing pack)
+                let mut reader = PackReader::new(&self.stage, codec, self.pack_format);
+                self.write_adapter()
+                    .write_object_streaming(&pack_key, &mut reader)?;
             }
         }
-        buf.push(b']');
-        let pack_digest = digest_bytes(buf.as_slice());
-        let pack_key = pack_digest.clone() + PACK_EXTENSION;
-        let adapter = self.adapter.write().unwrap();
-        adapter.write_object(&pack_key, buf.as_slice())?;
-        drop(adapter);
-        if buf.len() > 800 * index_map.len() {
-            // 80 bytes is the estimated size of an index entry, use index only if the size is 10 times bigger
+        // Encoded payloads can only be located through the index
+        if self.codec.is_some() || pack_size > self.index_threshold * index_map.len() {
+            // 80 bytes is the estimated size of an index entry, by default use index only if the size is 10 times bigger
             // Only write the index if worth it
             let index_key = pack_digest.clone() + INDEX_EXTENSION;
             let index_map_contents = serde_json::to_string(&index_map).unwrap();
-            let adapter = self.adapter.write().unwrap();
-            adapter.write_object(&index_key, index_map_contents.as_bytes())?;
-            drop(adapter);
+            items.push((index_key, index_map_contents.into_bytes()));
         }
+        items.extend(extra(Some(&pack_digest))?);
+        self.write_raw_batch(&items)?;
         self.load_index_object(&pack_digest, &index_map)?;
         self.stage.clear();
-        Ok(Some(pack_digest))
+        Ok(Some((pack_digest, pack_size)))
+    }
+
+    /// Returns the number of staged values along with the total length (in bytes) of their
+    /// serialization, without cloning them
+    pub fn stage_size(&self) -> (usize, usize) {
+        let bytes = self.stage.values().map(serialized_size).sum();
+        (self.stage.len(), bytes)
     }
 
     pub fn stage(&self) -> Result<Value> {
@@ -349,18 +1308,127 @@ impl DataStorage {
         }
     }
 
+    /// Writes a binary blob (if not already there), returns the digest of the blob
+    pub fn write_blob(&mut self, data: &[u8]) -> Result<String> {
+        let digest = digest_bytes(data);
+        let key = digest.clone() + BLOB_EXTENSION;
+        self.write_adapter().write_object(&key, data)?;
+        Ok(digest)
+    }
+
+    /// Reads a binary blob given its digest (and verifies its content)
+    pub fn read_blob(&self, digest: &str) -> Result<Vec<u8>> {
+        let key = digest.to_string() + BLOB_EXTENSION;
+        let data = self.read_adapter().read_object(&key, 0, 0)?;
+        if !digest_bytes(&data).eq(digest) {
+            bail!("mismatching_blob_hash");
+        }
+        Ok(data)
+    }
+
     pub fn read_raw_bytes(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
-        self.adapter
-            .read()
-            .unwrap()
-            .read_object(key, offset, length)
+        self.read_adapter().read_object(key, offset, length)
+    }
+
+    /// Reads raw bytes appending them to the given buffer
+    pub fn read_raw_bytes_into(
+        &self,
+        key: &str,
+        offset: usize,
+        length: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        self.read_adapter()
+            .read_object_into(key, offset, length, buf)
+    }
+
+    /// Flushes the adapter (see Adapter::flush)
+    pub fn flush(&self) -> Result<()> {
+        self.read_adapter().flush()
+    }
+
+    /// Returns the total size (in bytes) of the items in the adapter
+    pub fn total_size(&self) -> Result<u64> {
+        self.read_adapter().total_size()
     }
 
     pub fn write_raw_bytes(&mut self, key: &str, data: &[u8]) -> Result<()> {
-        self.adapter.write().unwrap().write_object(key, data)
+        self.write_adapter().write_object(key, data)
+    }
+
+    pub fn write_raw_batch(&mut self, items: &[(String, Vec<u8>)]) -> Result<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        let batch: Vec<(&str, &[u8])> = items
+            .iter()
+            .map(|(k, d)| (k.as_str(), d.as_slice()))
+            .collect();
+        self.write_adapter().write_batch(&batch)
+    }
+
+    /// Deletes raw items (see Adapter::delete_objects), returning the result for each key
+    pub fn delete_raw_items(&mut self, keys: &[&str]) -> Result<Vec<(String, Result<()>)>> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        self.write_adapter().delete_objects(keys)
     }
 
     pub fn list_raw_items(&self, ext: &str) -> Result<Vec<String>> {
-        self.adapter.read().unwrap().list_objects(ext)
+        self.read_adapter().list_objects(ext)
+    }
+
+    /// Returns the stored size (in bytes) of an item (see Adapter::object_size)
+    pub fn raw_item_size(&self, key: &str) -> Result<u64> {
+        self.read_adapter().object_size(key)
+    }
+
+    /// Reads the items whose key ends with ext while they are being listed (see
+    /// Adapter::iter_objects), calling f with the key and the content of each item (or the
+    /// read error). Items whose stored size exceeds max_size are not read, f receives an
+    /// item_too_large error instead. Listing stops if f returns an error, which is returned.
+    /// The adapter is locked while listing, hence f must not access the storage.
+    pub fn for_each_raw_item<F>(&self, ext: &str, max_size: usize, mut f: F) -> Result<()>
+    where
+        F: FnMut(String, Result<Vec<u8>>) -> Result<()>,
+    {
+        let adapter = self.read_adapter();
+        for key in adapter.iter_objects(ext) {
+            let key = key?;
+            let object = key.clone() + ext;
+            let content = match adapter.object_size(&object) {
+                Ok(size) if size > max_size as u64 => Err(anyhow!("item_too_large")),
+                Ok(_) => adapter.read_object(&object, 0, 0),
+                Err(e) => Err(e),
+            };
+            f(key, content)?;
+        }
+        Ok(())
+    }
+
+    /// Lists one page of items whose key ends with ext (see Adapter::list_objects_paged)
+    pub fn list_raw_items_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        self.read_adapter().list_objects_paged(ext, cursor, limit)
+    }
+
+    /// Collects all items whose key ends with ext, fetching the list page by page
+    fn list_all_raw_items(&self, ext: &str) -> Result<HashSet<String>> {
+        let mut items = HashSet::new();
+        let mut cursor = None;
+        loop {
+            let (page, next) = self.list_raw_items_paged(ext, cursor.as_deref(), LIST_PAGE_SIZE)?;
+            items.extend(page);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+        Ok(items)
     }
 }
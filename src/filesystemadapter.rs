@@ -13,15 +13,22 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use crate::adapter::Adapter;
+use crate::adapter::{list_folders_paged, Adapter, Cursor};
 use anyhow::{bail, Result};
 use std::{
     convert::TryInto,
-    fs::{create_dir_all, metadata, read_dir, File},
-    io::{Read, Seek, SeekFrom, Write},
+    fs::{create_dir_all, metadata, read_dir, remove_file, rename, File},
+    io::{copy, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
+/// Prefix of temporary files (which are ignored when listing objects)
+const TEMPORARY_PREFIX: &str = ".";
+
+/// Counter used to generate unique temporary file names
+static TEMPORARY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
 /// Implements storage in a folder on the filesystem
 pub struct FilesystemAdapter {
     path: PathBuf,
@@ -53,6 +60,22 @@ impl FilesystemAdapter {
         Ok((prefix.to_string(), subdirectory))
     }
 
+    /// Returns the path of a new temporary file (in the same directory as the target file)
+    fn get_temporary_path(&self, target: &Path) -> Result<PathBuf> {
+        let fname = target
+            .file_name()
+            .and_then(|f| f.to_str())
+            .ok_or_else(|| anyhow::anyhow!("invalid_file_name"))?;
+        let tmpname = format!(
+            "{}{}.{}.{}.tmp",
+            TEMPORARY_PREFIX,
+            fname,
+            std::process::id(),
+            TEMPORARY_COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        Ok(target.with_file_name(tmpname))
+    }
+
     fn ensure_container_exists(&self, key: &str) -> Result<(String, PathBuf)> {
         let (prefix, target) = self.get_object_path(key)?;
         let path = target
@@ -101,6 +124,43 @@ impl Adapter for FilesystemAdapter {
         }
     }
 
+    /// Reads an object or a sub-object appending its content to the given buffer (the file
+    /// is read directly into the buffer)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    /// * `buf` - The buffer the content is appended to
+    fn read_object_into(
+        &self,
+        key: &str,
+        offset: usize,
+        length: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        let (_, filepath) = self.get_object_path(key)?;
+        let mut f = File::open(&filepath)?;
+        let start = buf.len();
+        let result = if length == 0 {
+            f.read_to_end(buf).map(|_| ())
+        } else {
+            let metadata = metadata(&filepath)?;
+            if metadata.len() < (offset + length).try_into().unwrap() {
+                bail!("out_of_bounds")
+            }
+            f.seek(SeekFrom::Start(offset.try_into().unwrap()))?;
+            buf.resize(start + length, 0);
+            f.read_exact(&mut buf[start..])
+        };
+        // Partially read content is not left in the buffer
+        if result.is_err() {
+            buf.truncate(start);
+        }
+        Ok(result?)
+    }
+
     /// Writes an object to the storage
     ///
     /// # Arguments
@@ -108,59 +168,168 @@ impl Adapter for FilesystemAdapter {
     /// * `key` - The key associated with the object
     /// * `data` - The content of the object    
     fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.write_object_streaming(key, &mut &data[..])
+    }
+
+    /// Writes an object to the storage, copying the content from the reader to the file
+    /// as it is read
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `reader` - The source of the content of the object
+    fn write_object_streaming(&self, key: &str, reader: &mut dyn Read) -> Result<()> {
         let (_, filepath) = self.ensure_container_exists(key)?;
         if !filepath.exists() {
-            let mut f = File::create(filepath)?;
-            f.write_all(data)?;
-            f.flush()?;
+            // Write to a temporary file first, then rename it (atomically) so that
+            // a partially written file is never visible
+            let tmppath = self.get_temporary_path(&filepath)?;
+            let mut f = File::create(&tmppath)?;
+            if let Err(e) = copy(reader, &mut f).and_then(|_| f.sync_all()) {
+                drop(f);
+                let _ = remove_file(&tmppath);
+                return Err(e.into());
+            }
+            drop(f);
+            if filepath.exists() {
+                // Written concurrently, do not overwrite
+                remove_file(&tmppath)?;
+            } else if let Err(e) = rename(&tmppath, &filepath) {
+                let _ = remove_file(&tmppath);
+                return Err(e.into());
+            }
         }
         Ok(())
     }
 
+    /// Deletes an object from the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let (_, filepath) = self.get_object_path(key)?;
+        match remove_file(&filepath) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the size (in bytes) of an object in the storage (obtained from the metadata of
+    /// the file)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        let (_, filepath) = self.get_object_path(key)?;
+        Ok(metadata(&filepath)?.len())
+    }
+
+    /// Returns the total size (in bytes) of the objects in the storage (temporary files are
+    /// not included)
+    fn total_size(&self) -> Result<u64> {
+        let mut size = 0;
+        for de in read_dir(self.path.clone())?.flatten() {
+            if !de.path().is_dir() {
+                continue;
+            }
+            for subde in read_dir(de.path())?.flatten() {
+                let dp = subde.path();
+                let temporary = dp
+                    .file_name()
+                    .and_then(|f| f.to_str())
+                    .is_none_or(|f| f.starts_with(TEMPORARY_PREFIX));
+                if dp.is_file() && !temporary {
+                    size += metadata(&dp)?.len();
+                }
+            }
+        }
+        Ok(size)
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
     ///
     /// * `ext` - The extension (last part of the string) of the requested objects     
     fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
-        let content = read_dir(self.path.clone())?;
-        let mut result = vec![];
-        for sd in content {
-            match sd {
-                Ok(de) => {
-                    // Recursively list process contents
-                    let subcontent = read_dir(de.path())?;
-                    for f in subcontent {
-                        match f {
-                            Ok(subde) => {
-                                let dp = subde.path();
-                                if dp.is_file() {
-                                    let fname =
-                                        dp.file_name().unwrap().to_str().unwrap().to_string();
-                                    if fname.ends_with(ext) {
-                                        let fname = fname.strip_suffix(ext).unwrap().to_string();
-                                        result.push(fname);
-                                    }
-                                }
-                            }
-                            Err(_) => continue,
-                        }
-                    }
+        self.iter_objects(ext).collect()
+    }
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time (only the
+    /// folders needed for the page are read)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        let folders = read_dir(&self.path)?
+            .flatten()
+            .filter(|de| de.path().is_dir())
+            .filter_map(|de| de.file_name().to_str().map(|f| f.to_string()))
+            .collect();
+        let list_folder = |folder: &str| -> Result<Vec<String>> {
+            Ok(read_dir(self.path.join(folder))?
+                .flatten()
+                .filter(|de| de.path().is_file())
+                .filter_map(|de| de.file_name().to_str().map(|f| f.to_string()))
+                .filter(|fname| !fname.starts_with(TEMPORARY_PREFIX))
+                .filter_map(|fname| fname.strip_suffix(ext).map(|f| f.to_string()))
+                .collect())
+        };
+        list_folders_paged(folders, list_folder, cursor, limit)
+    }
+
+    /// Iterates over the keys of all objects whose key ends with ext, reading the content of the
+    /// folders while iterating
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        let content = match read_dir(self.path.clone()) {
+            Ok(content) => content,
+            Err(e) => return Box::new(std::iter::once(Err(e.into()))),
+        };
+        let ext = ext.to_string();
+        Box::new(content.flatten().flat_map(move |de| {
+            // Recursively list process contents
+            let subcontent = match read_dir(de.path()) {
+                Ok(subcontent) => subcontent,
+                Err(e) => {
+                    return Box::new(std::iter::once(Err(e.into())))
+                        as Box<dyn Iterator<Item = Result<String>>>
                 }
-                Err(_) => continue,
-            }
-        }
-        if result.is_empty() {
-            Ok(vec![])
-        } else {
-            Ok(result)
-        }
+            };
+            let ext = ext.clone();
+            Box::new(subcontent.flatten().filter_map(move |subde| {
+                let dp = subde.path();
+                if !dp.is_file() {
+                    return None;
+                }
+                let fname = dp.file_name().unwrap().to_str().unwrap().to_string();
+                if fname.starts_with(TEMPORARY_PREFIX) {
+                    // Skip temporary (possibly partial) files
+                    return None;
+                }
+                fname.strip_suffix(ext.as_str()).map(|f| Ok(f.to_string()))
+            }))
+        }))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use mktemp::Temp;
+    use std::io::Read;
 
     use crate::{adapter::Adapter, flate2adapter::Flate2Adapter};
 
@@ -363,4 +532,164 @@ mod tests {
         assert!(sqa.list_objects(".pack").unwrap().len() == 1);
         assert!(sqa.list_objects("").unwrap().len() == 2);
     }
+
+    #[test]
+    fn test_filesystem_interrupted_write() {
+        let temp = Temp::new_dir().unwrap();
+        let path_buf = temp.to_path_buf();
+        let sqa = FilesystemAdapter::new(path_buf.to_str().unwrap()).unwrap();
+        assert!(sqa
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        // Simulate an interrupted write (temporary file written but not renamed)
+        let (_, target) = sqa.ensure_container_exists("somekey.pack").unwrap();
+        let tmppath = sqa.get_temporary_path(&target).unwrap();
+        let mut f = std::fs::File::create(&tmppath).unwrap();
+        std::io::Write::write_all(&mut f, "partial".as_bytes()).unwrap();
+        drop(f);
+        assert!(tmppath.exists());
+        assert!(!target.exists());
+        assert!(sqa.list_objects(".pack").unwrap().is_empty());
+        assert!(sqa.list_objects("").unwrap().len() == 1);
+        assert!(sqa.read_object("somekey.pack", 0, 0).is_err());
+        // A subsequent write succeeds
+        assert!(sqa
+            .write_object("somekey.pack", "otherdata".as_bytes())
+            .is_ok());
+        assert!(sqa.list_objects(".pack").unwrap().len() == 1);
+        let ro = String::from_utf8(sqa.read_object("somekey.pack", 0, 0).unwrap()).unwrap();
+        assert!(ro == "otherdata");
+    }
+
+    #[test]
+    fn test_filesystem_write_object_streaming() {
+        let temp = Temp::new_dir().unwrap();
+        let path_buf = temp.to_path_buf();
+        let sqa = FilesystemAdapter::new(path_buf.to_str().unwrap()).unwrap();
+        let mut reader = "some".as_bytes().chain("data".as_bytes());
+        assert!(sqa
+            .write_object_streaming("somekey.pack", &mut reader)
+            .is_ok());
+        let ro = String::from_utf8(sqa.read_object("somekey.pack", 0, 0).unwrap()).unwrap();
+        assert!(ro == "somedata");
+        // A failing reader leaves no object (nor temporary file) behind
+        struct Failing;
+        impl std::io::Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("failed"))
+            }
+        }
+        let mut reader = "partial".as_bytes().chain(Failing);
+        assert!(sqa
+            .write_object_streaming("otherkey.pack", &mut reader)
+            .is_err());
+        assert!(sqa.read_object("otherkey.pack", 0, 0).is_err());
+        assert!(std::fs::read_dir(path_buf.join("ot")).unwrap().count() == 0);
+    }
+
+    #[test]
+    fn test_filesystem_read_object_into() {
+        let temp = Temp::new_dir().unwrap();
+        let path_buf = temp.to_path_buf();
+        let sqa = FilesystemAdapter::new(path_buf.to_str().unwrap()).unwrap();
+        assert!(sqa
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        let mut buf = "prefix".as_bytes().to_vec();
+        assert!(sqa
+            .read_object_into("somekey.delta", 0, 0, &mut buf)
+            .is_ok());
+        assert!(String::from_utf8(buf.clone()).unwrap() == "prefixsomedata");
+        buf.clear();
+        assert!(sqa
+            .read_object_into("somekey.delta", 1, 2, &mut buf)
+            .is_ok());
+        assert!(String::from_utf8(buf.clone()).unwrap() == "om");
+        assert!(sqa
+            .read_object_into("somekey.delta", 7, 2, &mut buf)
+            .is_err());
+        // Failed reads leave the buffer unchanged
+        let (_, target) = sqa.ensure_container_exists("dirkey.delta").unwrap();
+        std::fs::create_dir(&target).unwrap();
+        assert!(sqa
+            .read_object_into("dirkey.delta", 0, 0, &mut buf)
+            .is_err());
+        assert!(String::from_utf8(buf.clone()).unwrap() == "om");
+    }
+
+    #[test]
+    fn test_filesystem_total_size() {
+        let temp = Temp::new_dir().unwrap();
+        let path_buf = temp.to_path_buf();
+        let sqa = FilesystemAdapter::new(path_buf.to_str().unwrap()).unwrap();
+        assert!(sqa.total_size().unwrap() == 0);
+        assert!(sqa
+            .write_object("somekey.pack", "somedata".as_bytes())
+            .is_ok());
+        assert!(sqa
+            .write_object("otherkey.delta", "otherdata".as_bytes())
+            .is_ok());
+        // Temporary files are not included
+        let (_, target) = sqa.ensure_container_exists("thirdkey.pack").unwrap();
+        let tmppath = sqa.get_temporary_path(&target).unwrap();
+        std::fs::write(&tmppath, "partial".as_bytes()).unwrap();
+        assert!(sqa.total_size().unwrap() == 17);
+        assert!(sqa.object_size("otherkey.delta").unwrap() == 9);
+        assert!(sqa.object_size("thirdkey.pack").is_err());
+    }
+
+    #[test]
+    fn test_filesystem_iter_objects() {
+        let temp = Temp::new_dir().unwrap();
+        let path_buf = temp.to_path_buf();
+        let sqa = FilesystemAdapter::new(path_buf.to_str().unwrap()).unwrap();
+        assert!(sqa.iter_objects(".delta").next().is_none());
+        for k in ["a", "b", "c"] {
+            assert!(sqa
+                .write_object(&(k.to_string() + ".delta"), "somedata".as_bytes())
+                .is_ok());
+        }
+        assert!(sqa.write_object("c.pack", "otherdata".as_bytes()).is_ok());
+        let mut all: Vec<String> = sqa
+            .iter_objects(".delta")
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        all.sort();
+        assert!(all == vec!["a", "b", "c"]);
+        assert!(sqa.iter_objects("").count() == 4);
+    }
+
+    #[test]
+    fn test_filesystem_list_objects_paged() {
+        let temp = Temp::new_dir().unwrap();
+        let path_buf = temp.to_path_buf();
+        let sqa = FilesystemAdapter::new(path_buf.to_str().unwrap()).unwrap();
+        let (page, cursor) = sqa.list_objects_paged(".delta", None, 2).unwrap();
+        assert!(page.is_empty());
+        assert!(cursor.is_none());
+        for k in ["aa1", "aa2", "ab1", "ba1", "bb1"] {
+            assert!(sqa
+                .write_object(&(k.to_string() + ".delta"), "somedata".as_bytes())
+                .is_ok());
+        }
+        assert!(sqa.write_object("ab2.pack", "otherdata".as_bytes()).is_ok());
+        let mut all = vec![];
+        let mut cursor = None;
+        loop {
+            let (page, next) = sqa
+                .list_objects_paged(".delta", cursor.as_deref(), 2)
+                .unwrap();
+            assert!(page.len() <= 2);
+            all.extend(page);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+        assert_eq!(all, vec!["aa1", "aa2", "ab1", "ba1", "bb1"]);
+        let (page, cursor) = sqa.list_objects_paged("", None, 10).unwrap();
+        assert!(page.len() == 6);
+        assert!(cursor.is_none());
+        assert!(sqa.list_objects_paged(".delta", None, 0).is_err());
+    }
 }
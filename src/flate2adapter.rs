@@ -13,7 +13,8 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use crate::adapter::Adapter;
+use crate::adapter::{Adapter, Cursor, PagedObjects};
+use crate::constants::LIST_PAGE_SIZE;
 use anyhow::Result;
 use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
 use std::{
@@ -24,6 +25,7 @@ use std::{
 /// Implements compressed storage (using DEFLATE) on other adapters
 pub struct Flate2Adapter {
     backend: Arc<RwLock<Box<dyn Adapter>>>,
+    level: Compression,
 }
 
 impl Flate2Adapter {
@@ -33,7 +35,24 @@ impl Flate2Adapter {
     ///
     /// * `backend` - The adapter to be wrapped
     pub fn new(backend: Arc<RwLock<Box<dyn Adapter>>>) -> Self {
-        Flate2Adapter { backend }
+        Flate2Adapter {
+            backend,
+            level: Compression::default(),
+        }
+    }
+
+    /// Creates a new adapter wrapping the specified adapter, using the given compression level
+    /// when writing objects. Objects can be read regardless of the level they were written with.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The adapter to be wrapped
+    /// * `level` - The compression level (from 0, no compression, to 9, best compression)
+    pub fn new_with_level(backend: Arc<RwLock<Box<dyn Adapter>>>, level: u32) -> Self {
+        Flate2Adapter {
+            backend,
+            level: Compression::new(level),
+        }
     }
 }
 
@@ -68,7 +87,7 @@ impl Adapter for Flate2Adapter {
     /// * `data` - The content of the object    
     fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
         let key = key.to_string() + ".flate"; // Change key to avoid mismatching cache objects
-        let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
+        let mut e = DeflateEncoder::new(Vec::new(), self.level);
         e.write_all(data)?;
         let compressed = e.finish().unwrap();
         self.backend
@@ -77,6 +96,71 @@ impl Adapter for Flate2Adapter {
             .write_object(&key, compressed.as_slice())
     }
 
+    /// Writes multiple objects to the storage (compressed objects are written as a batch on the backend)
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The keys associated with the objects along with their content
+    fn write_batch(&self, items: &[(&str, &[u8])]) -> Result<()> {
+        let mut compressed_items = vec![];
+        for (key, data) in items {
+            let key = key.to_string() + ".flate"; // Change key to avoid mismatching cache objects
+            let mut e = DeflateEncoder::new(Vec::new(), self.level);
+            e.write_all(data)?;
+            compressed_items.push((key, e.finish()?));
+        }
+        let batch: Vec<(&str, &[u8])> = compressed_items
+            .iter()
+            .map(|(k, d)| (k.as_str(), d.as_slice()))
+            .collect();
+        self.backend.write().unwrap().write_batch(&batch)
+    }
+
+    /// Deletes an object from the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let key = key.to_string() + ".flate"; // Change key to avoid mismatching cache objects
+        self.backend.write().unwrap().delete_object(&key)
+    }
+
+    /// Deletes multiple objects from the storage (objects are deleted as a batch on the backend)
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keys associated with the objects
+    fn delete_objects(&self, keys: &[&str]) -> Result<Vec<(String, Result<()>)>> {
+        let backend_keys: Vec<String> = keys.iter().map(|k| k.to_string() + ".flate").collect();
+        let batch: Vec<&str> = backend_keys.iter().map(|k| k.as_str()).collect();
+        let results = self.backend.write().unwrap().delete_objects(&batch)?;
+        Ok(results
+            .into_iter()
+            .map(|(k, r)| (k.strip_suffix(".flate").unwrap_or(&k).to_string(), r))
+            .collect())
+    }
+
+    /// Flushes the backend storage
+    fn flush(&self) -> Result<()> {
+        self.backend.read().unwrap().flush()
+    }
+
+    /// Returns the size (in bytes) of the compressed object in the backend storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        let key = key.to_string() + ".flate"; // Change key to avoid mismatching cache objects
+        self.backend.read().unwrap().object_size(&key)
+    }
+
+    /// Returns the total size (in bytes) of the backend storage (the size of the compressed objects)
+    fn total_size(&self) -> Result<u64> {
+        self.backend.read().unwrap().total_size()
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -90,6 +174,43 @@ impl Adapter for Flate2Adapter {
             .map(|k| k.trim_end_matches(".flate").to_string())
             .collect())
     }
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time (the cursor of
+    /// the backend is passed through)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        let ext = ext.to_string() + ".flate";
+        let (keys, next) = self
+            .backend
+            .read()
+            .unwrap()
+            .list_objects_paged(&ext, cursor, limit)?;
+        let keys = keys
+            .into_iter()
+            .map(|k| k.trim_end_matches(".flate").to_string())
+            .collect();
+        Ok((keys, next))
+    }
+
+    /// Iterates over the keys of the objects whose key ends with ext, fetching them one page at
+    /// a time (see list_objects_paged)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        Box::new(PagedObjects::new(self, ext, LIST_PAGE_SIZE))
+    }
 }
 
 mod tests {
@@ -188,4 +309,61 @@ mod tests {
         assert!(sqa.list_objects(".pack").unwrap().len() == 1);
         assert!(sqa.list_objects("").unwrap().len() == 2);
     }
+
+    #[test]
+    fn test_compression_levels() {
+        let ma: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let ma = std::sync::Arc::new(std::sync::RwLock::new(ma));
+        let fast = Flate2Adapter::new_with_level(ma.clone(), 0);
+        let best = Flate2Adapter::new_with_level(ma.clone(), 9);
+        let data = "somedata".repeat(100);
+        assert!(fast.write_object("fast.pack", data.as_bytes()).is_ok());
+        assert!(best.write_object("best.pack", data.as_bytes()).is_ok());
+        let stored_fast = ma
+            .read()
+            .unwrap()
+            .read_object("fast.pack.flate", 0, 0)
+            .unwrap();
+        let stored_best = ma
+            .read()
+            .unwrap()
+            .read_object("best.pack.flate", 0, 0)
+            .unwrap();
+        assert!(stored_best.len() < stored_fast.len());
+        let default = Flate2Adapter::new(ma);
+        for adapter in [&fast, &best, &default] {
+            let ro = adapter.read_object("fast.pack", 0, 0).unwrap();
+            assert!(String::from_utf8(ro).unwrap() == data);
+            let ro = adapter.read_object("best.pack", 0, 0).unwrap();
+            assert!(String::from_utf8(ro).unwrap() == data);
+        }
+    }
+
+    #[test]
+    fn test_list_objects_paged() {
+        let ma: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let sqa = Flate2Adapter::new(std::sync::Arc::new(std::sync::RwLock::new(ma)));
+        for k in ["a", "b", "c", "d", "e"] {
+            assert!(sqa
+                .write_object(&(k.to_string() + ".delta"), "somedata".as_bytes())
+                .is_ok());
+        }
+        assert!(sqa.write_object("c.pack", "otherdata".as_bytes()).is_ok());
+        let mut all = vec![];
+        let mut cursor = None;
+        loop {
+            let (page, next) = sqa
+                .list_objects_paged(".delta", cursor.as_deref(), 2)
+                .unwrap();
+            assert!(page.len() <= 2);
+            all.extend(page);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+        assert_eq!(all, vec!["a", "b", "c", "d", "e"]);
+        let keys: Vec<String> = sqa.iter_objects("").collect::<anyhow::Result<_>>().unwrap();
+        assert_eq!(keys.len(), 6);
+    }
 }
@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 pub mod adapter;
+#[cfg(feature = "audit")]
+pub mod auditadapter;
 #[cfg(feature = "brotliadapter")]
 pub mod brotliadapter;
 mod constants;
@@ -21,11 +23,16 @@ mod datastorage;
 pub mod filesystemadapter;
 pub mod flate2adapter;
 pub mod melda;
+pub mod meldakv;
 pub mod memoryadapter;
-mod revision;
+pub mod retryadapter;
+pub mod revision;
 mod revisiontree;
 #[cfg(feature = "solid")]
 pub mod solidadapter;
 #[cfg(feature = "sqlitedb")]
 pub mod sqliteadapter;
+pub mod text;
 mod utils;
+#[cfg(feature = "webdav")]
+pub mod webdavadapter;
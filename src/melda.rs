@@ -13,38 +13,601 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use crate::adapter::Adapter;
+use crate::adapter::{Adapter, PayloadCodec};
 use crate::constants::{
-    ARRAY_DESCRIPTOR_DELTA_ORDER_FIELD, ARRAY_DESCRIPTOR_ORDER_FIELD, CHANGESETS_FIELD,
-    DELTA_EXTENSION, ID_FIELD, INFORMATION_FIELD, OBJECTS_FIELD, PACK_FIELD, PARENTS_FIELD,
-    ROOT_ID,
+    ARCHIVE_MAGIC, ARCHIVE_VERSION, ARRAY_DESCRIPTOR_DELTA_ORDER_FIELD,
+    ARRAY_DESCRIPTOR_ORDER_FIELD, BLOB_REFERENCE_PREFIX, BLOCK_VERSION_FIELD, CHANGESETS_FIELD,
+    DEFAULT_MAX_READ_DEPTH, DEFAULT_REVISION_TAIL_LENGTH, DELETED_FIELD, DELETED_HASH,
+    DELETED_META_PREFIX, DELTA_EXTENSION, HASH_FIELD, ID_FIELD, INDEX_EXTENSION, INFORMATION_FIELD,
+    LIST_PAGE_SIZE, MAX_ARCHIVE_KEY_LENGTH, MAX_REVISION_TAIL_LENGTH, MERGE_RECORDS_BLOCK_VERSION,
+    OBJECTS_FIELD, PACK_EXTENSION, PACK_FIELD, PARENTS_FIELD, PINS_EXTENSION, RENAMED_TO_FIELD,
+    REPACK_EXTENSION, RESOLVED_HASH, ROOT_ID, TAIL_LENGTH_BLOCK_VERSION, TAIL_LENGTH_FIELD,
 };
 use crate::datastorage::DataStorage;
 use crate::revision::Revision;
 use crate::revisiontree::RevisionTree;
 use crate::utils::{
     apply_diff_patch, digest_bytes, digest_object, digest_string, flatten, is_array_descriptor,
-    make_diff_patch, merge_arrays, unflatten,
+    is_flattened_field, make_diff_patch, merge_arrays, merge_arrays_by, serialized_size, unflatten,
+    unflatten_with, validate_object, Instant,
 };
 use anyhow::{anyhow, bail, Result};
 use lru::LruCache;
 use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{Map, Value};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
 use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex, RwLock};
+use std::thread::ThreadId;
+use std::time::Duration;
 
 /// Change triple (used for storing block changesets)
 #[derive(PartialEq, Clone)]
 struct Change(String, Revision, Option<Revision>);
 
+impl Change {
+    /// Returns true if the revision of the change can be derived from its parent (that is, if
+    /// the change can be recorded as a creation or update record)
+    fn is_derived(&self, tail_length: usize) -> bool {
+        let Change(_, rev, prev) = self;
+        match prev {
+            None => Revision::new(1, rev.digest.clone(), None) == *rev,
+            Some(prev) => {
+                Revision::new_updated_with_tail_length(rev.digest.clone(), prev, tail_length)
+                    == *rev
+            }
+        }
+    }
+
+    /// Returns the changeset record of the change: a creation record [uuid, digest], an
+    /// update record [uuid, parent, digest] or, if the revision cannot be derived from its
+    /// parent (a merged revision, see resolve_merged), a merge record [uuid, parent, digest,
+    /// revision]. Blocks containing merge records are marked with MERGE_RECORDS_BLOCK_VERSION.
+    fn to_record(&self, tail_length: usize) -> Value {
+        let derived = self.is_derived(tail_length);
+        let Change(uuid, rev, prev) = self;
+        match prev {
+            // Creation record
+            None => Value::from(vec![uuid.clone(), rev.digest.clone()]),
+            // Update record
+            Some(prev) if derived => {
+                Value::from(vec![uuid.clone(), prev.to_string(), rev.digest.clone()])
+            }
+            // Merge record
+            Some(prev) => Value::from(vec![
+                uuid.clone(),
+                prev.to_string(),
+                rev.digest.clone(),
+                rev.to_string(),
+            ]),
+        }
+    }
+
+    /// Parses a changeset record (see to_record), merge records are only accepted if
+    /// merge_records is true
+    fn from_record(record: &[Value], tail_length: usize, merge_records: bool) -> Result<Change> {
+        let uuid = record
+            .first()
+            .and_then(|u| u.as_str())
+            .ok_or_else(|| anyhow!("expecting_uuid_string"))?;
+        if record.len() == 2 {
+            // Creation record
+            let digest = record[1]
+                .as_str()
+                .ok_or_else(|| anyhow!("expecting_digest_string"))?;
+            let r = Revision::new(1, digest.to_string(), None);
+            Ok(Change(uuid.to_string(), r, None))
+        } else if record.len() == 3 || (record.len() == 4 && merge_records) {
+            // Update (or merge) record
+            let prev = record[1]
+                .as_str()
+                .ok_or_else(|| anyhow!("expecting_revision_string"))?;
+            let digest = record[2]
+                .as_str()
+                .ok_or_else(|| anyhow!("expecting_digest_string"))?;
+            let prev = Revision::from(prev)?;
+            let r = if record.len() == 4 {
+                let r = Revision::from(
+                    record[3]
+                        .as_str()
+                        .ok_or_else(|| anyhow!("expecting_revision_string"))?,
+                )?;
+                if r.digest != digest || r.index <= prev.index {
+                    bail!("invalid_merge_record");
+                }
+                r
+            } else {
+                Revision::new_updated_with_tail_length(digest, &prev, tail_length)
+            };
+            Ok(Change(uuid.to_string(), r, Some(prev)))
+        } else {
+            bail!("invalid_changes_record")
+        }
+    }
+}
+
+/// Format of the data packs written on commit. Packs of both formats can always be read,
+/// hence replicas using different formats can share the same storage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PackFormat {
+    /// A JSON array of objects (the default)
+    #[default]
+    Array,
+    /// A header line followed by one object per line (JSON Lines), which is easier to
+    /// inspect, diff and search with line-oriented tools
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig, PackFormat}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let config = MeldaConfig { pack_format: PackFormat::Lines, ..MeldaConfig::default() };
+    /// let replica = Melda::new_with_config(adapter.clone(), config).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone());
+    /// replica.commit(None).unwrap();
+    /// let pack = adapter.read().unwrap().list_objects(".pack").unwrap().pop().unwrap();
+    /// let content = adapter.read().unwrap().read_object(&(pack + ".pack"), 0, 0).unwrap();
+    /// let content = String::from_utf8(content).unwrap();
+    /// assert!(content.starts_with("#MELDAPACK:jsonl:1\n"));
+    /// assert!(content.lines().any(|l| l == r#"{"key":"alpha"}"#));
+    /// // Packs of both formats are read by any replica
+    /// let array = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert_eq!(array.read().unwrap(), replica.read().unwrap());
+    /// array.update(json!({ "items\u{266D}" : [ { "_id" : "1", "key" : "gamma" } ] }).as_object().unwrap().clone());
+    /// array.commit(None).unwrap();
+    /// replica.refresh().unwrap();
+    /// assert_eq!(replica.read().unwrap(), array.read().unwrap());
+    /// ```
+    Lines,
+}
+
+/// Strategy used to generate the identifier of objects without an explicit identifier (_id)
+/// within flattened fields
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum IdStrategy {
+    /// The identifier is the digest of the path of the object (the default): objects at
+    /// different paths are different objects, even if their content is identical
+    #[default]
+    PathBased,
+    /// The identifier is the digest of the content of the object: identical objects (at any
+    /// path, or twice in the same array) become the same object, hence they are stored only
+    /// once, and changes made to the object itself (for example through update_object) are
+    /// reflected in all references. Editing an object through update changes its content,
+    /// hence the edited object becomes a new object (the previous one is deleted if it is no
+    /// longer referenced): concurrent edits of the same object on different replicas are
+    /// therefore merged as different objects, rather than as conflicting revisions.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{IdStrategy, Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let object = json!({ "first\u{266D}" : { "a" : 1 }, "second\u{266D}" : { "a" : 1 }, "third\u{266D}" : { "a" : 2 } }).as_object().unwrap().clone();
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(object.clone()).unwrap();
+    /// assert_eq!(replica.user_objects().len(), 4);
+    /// let config = MeldaConfig { id_strategy: IdStrategy::ContentBased, ..MeldaConfig::default() };
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new_with_config(Arc::new(RwLock::new(adapter)), config).expect("cannot_initialize_crdt");
+    /// replica.update(object.clone()).unwrap();
+    /// assert_eq!(replica.user_objects().len(), 3);
+    /// let readback = replica.read().unwrap();
+    /// assert_eq!(readback["first\u{266D}"], readback["second\u{266D}"]);
+    /// assert_eq!(readback["second\u{266D}"]["a"], json!(1));
+    /// ```
+    ContentBased,
+}
+
+/// Limits enforced when parsing delta blocks read from the adapter, so that blocks crafted by
+/// untrusted peers cannot exhaust memory: blocks exceeding any limit are treated as invalid.
+/// Local commits which would produce such blocks fail before anything is written.
+/// The nesting depth of blocks is bounded by the JSON parser itself.
+///
+/// # Example
+/// ```
+/// use melda::{melda::{BlockLimits, Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+/// use std::sync::{Arc, Mutex, RwLock, atomic::{AtomicUsize, Ordering}};
+/// use serde_json::{Map, Value,json};
+/// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let adapter = Arc::new(RwLock::new(adapter));
+/// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+/// let items : Vec<Value> = (0..10).map(|i| json!({ "_id" : i.to_string() })).collect();
+/// replica.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone());
+/// let block = replica.commit(None).unwrap().unwrap();
+/// let limits = BlockLimits { max_changesets: 5, ..BlockLimits::default() };
+/// let config = MeldaConfig { block_limits: limits, ..MeldaConfig::default() };
+/// let limited = Melda::new_with_config(adapter.clone(), config).expect("cannot_initialize_crdt");
+/// assert!(limited.get_block(&block).unwrap().is_none());
+/// assert!(limited.get_all_objects().is_empty());
+/// let reloaded = Melda::new(adapter).expect("cannot_initialize_crdt");
+/// assert!(reloaded.get_block(&block).unwrap().is_some());
+/// // Local commits exceeding the limits fail without writing anything (the stage is kept)
+/// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let adapter = Arc::new(RwLock::new(adapter));
+/// let limits = BlockLimits { max_changesets: 5, max_info_bytes: 16, ..BlockLimits::default() };
+/// let config = MeldaConfig { block_limits: limits, ..MeldaConfig::default() };
+/// let limited = Melda::new_with_config(adapter.clone(), config).expect("cannot_initialize_crdt");
+/// limited.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone()).unwrap();
+/// assert_eq!(limited.commit(None).unwrap_err().to_string(), "too_many_changesets");
+/// assert!(adapter.read().unwrap().list_objects("").unwrap().is_empty());
+/// assert!(limited.stage().unwrap().is_some());
+/// limited.unstage().unwrap();
+/// limited.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+/// let info = json!({ "author" : "Some user with a long name" }).as_object().unwrap().clone();
+/// assert_eq!(limited.commit(Some(info)).unwrap_err().to_string(), "info_too_large");
+/// assert!(limited.commit(None).unwrap().is_some());
+/// // Blocks whose stored size exceeds max_block_bytes are rejected without being read
+/// struct CountingAdapter(MemoryAdapter, Arc<AtomicUsize>);
+/// impl Adapter for CountingAdapter {
+///     fn read_object(&self, key: &str, offset: usize, length: usize) -> anyhow::Result<Vec<u8>> {
+///         if key.ends_with(".delta") {
+///             self.1.fetch_add(1, Ordering::SeqCst);
+///         }
+///         self.0.read_object(key, offset, length)
+///     }
+///     fn write_object(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+///         self.0.write_object(key, data)
+///     }
+///     fn object_size(&self, key: &str) -> anyhow::Result<u64> {
+///         self.0.object_size(key)
+///     }
+///     fn list_objects(&self, ext: &str) -> anyhow::Result<Vec<String>> {
+///         self.0.list_objects(ext)
+///     }
+/// }
+/// let reads = Arc::new(AtomicUsize::new(0));
+/// let adapter : Box<dyn Adapter> = Box::new(CountingAdapter(MemoryAdapter::new(), reads.clone()));
+/// let adapter = Arc::new(RwLock::new(adapter));
+/// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+/// replica.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone()).unwrap();
+/// let block = replica.commit(None).unwrap().unwrap();
+/// reads.store(0, Ordering::SeqCst);
+/// let limits = BlockLimits { max_block_bytes: 64, ..BlockLimits::default() };
+/// let config = MeldaConfig { block_limits: limits, ..MeldaConfig::default() };
+/// let limited = Melda::new_with_config(adapter, config).expect("cannot_initialize_crdt");
+/// assert!(limited.get_block(&block).unwrap().is_none());
+/// assert_eq!(reads.load(Ordering::SeqCst), 0);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockLimits {
+    /// Maximum size (in bytes) of the content of a block (blocks whose stored size exceeds it
+    /// are not fetched)
+    pub max_block_bytes: usize,
+    /// Maximum number of changeset records in a block
+    pub max_changesets: usize,
+    /// Maximum number of parent blocks
+    pub max_parents: usize,
+    /// Maximum size (in bytes) of the serialized information object
+    pub max_info_bytes: usize,
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        BlockLimits {
+            max_block_bytes: 1 << 28,
+            max_changesets: 1 << 22,
+            max_parents: 1 << 16,
+            max_info_bytes: 1 << 20,
+        }
+    }
+}
+
+/// Configuration of a Melda instance
+///
+/// # Example
+/// ```
+/// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+/// use std::sync::{Arc, Mutex, RwLock};
+/// use serde_json::{Map, Value,json};
+/// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+/// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let adapter = Arc::new(RwLock::new(adapter));
+/// // Stream every pack to the adapter
+/// let config = MeldaConfig { stream_threshold: 0, ..MeldaConfig::default() };
+/// let replica = Melda::new_with_config(adapter.clone(), config).expect("cannot_initialize_crdt");
+/// replica.update(object.clone());
+/// replica.commit(None).unwrap();
+/// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let adapter2 = Arc::new(RwLock::new(adapter2));
+/// let buffered = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+/// buffered.update(object);
+/// buffered.commit(None).unwrap();
+/// // Streamed and buffered packs are identical
+/// let packs = adapter.read().unwrap().list_objects(".pack").unwrap();
+/// assert_eq!(packs, adapter2.read().unwrap().list_objects(".pack").unwrap());
+/// let reloaded = Melda::new(adapter).expect("cannot_initialize_crdt");
+/// assert_eq!(reloaded.read().unwrap(), buffered.read().unwrap());
+/// ```
+#[derive(Clone, Debug)]
+pub struct MeldaConfig {
+    /// Capacity of the array descriptors cache (number of descriptors)
+    pub array_descriptor_cache: usize,
+    /// Capacity of the data cache (number of objects)
+    pub data_cache: usize,
+    /// An index is written along with a pack only if the pack is larger than
+    /// index_threshold bytes per object
+    pub index_threshold: usize,
+    /// Packs larger than stream_threshold bytes are not buffered in memory on commit: they
+    /// are streamed to the adapter (see Adapter::write_object_streaming) one object at a time,
+    /// before (rather than in the same batch as) their index and block
+    pub stream_threshold: usize,
+    /// Format of the packs written on commit (see PackFormat)
+    pub pack_format: PackFormat,
+    /// If set, the keys of all stored items are prefixed with the namespace (followed by
+    /// a separator) and items of other namespaces are ignored: this allows multiple
+    /// documents to share the same adapter. Only ASCII letters, digits, - and _ are allowed.
+    pub namespace: Option<String>,
+    /// If true, the changes of each block are kept in memory after the block has been applied
+    /// (by default they are dropped to save memory and are fetched again from the adapter when
+    /// needed, see get_block_changes). Retaining changes avoids re-fetching and re-parsing
+    /// blocks, at the cost of memory proportional to the whole history. Changes are never
+    /// retained while a memory budget is set (see Melda::set_memory_budget).
+    pub retain_block_changes: bool,
+    /// Strategy used to generate the identifier of objects without an explicit identifier
+    /// (see IdStrategy). All replicas of a document must use the same strategy.
+    pub id_strategy: IdStrategy,
+    /// If true, objects providing their own digest through the hash field (see the hash
+    /// shortcut in the documentation of update) are rejected with a hash_collision error when
+    /// they would alias a different staged object, instead of being silently deduplicated
+    pub strict_hashes: bool,
+    /// Limits enforced when parsing delta blocks (see BlockLimits)
+    pub block_limits: BlockLimits,
+    /// If true, the adapter is flushed at the end of each commit (see Melda::sync), so that
+    /// committed changes are durable even if the adapter buffers writes
+    pub flush_on_commit: bool,
+    /// Number of characters of the digest of the parent revision used as the tail of new
+    /// revisions (between 7, the default, and 64). Longer tails make it less likely that
+    /// revisions with the same digest but different parents collide. The length is recorded
+    /// in the delta blocks (unless it is the default): all replicas of a document must use
+    /// the same length, blocks committed with a different length are rejected. Such blocks are
+    /// marked with a newer format version, hence readers which do not support it reject them
+    /// instead of deriving different revisions.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use sha2::{Digest, Sha256};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let config = MeldaConfig { revision_tail_length: 16, ..MeldaConfig::default() };
+    /// let replica = Melda::new_with_config(adapter.clone(), config.clone()).expect("cannot_initialize_crdt");
+    /// replica.create_object("myobject", json!({ "a" : 1 }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.update_object("myobject", json!({ "a" : 2 }).as_object().unwrap().clone()).unwrap();
+    /// let block = replica.commit(None).unwrap().unwrap();
+    /// let raw: Value = serde_json::from_slice(&replica.raw_read(&(block + ".delta")).unwrap()).unwrap();
+    /// assert_eq!(raw.get("t"), Some(&json!(16)));
+    /// assert_eq!(raw.get("v"), Some(&json!(3)));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!(winner.split('_').nth(1).unwrap().len(), 16);
+    /// // Replicas using the same length read the blocks
+    /// let same = Melda::new_with_config(adapter.clone(), config.clone()).expect("cannot_initialize_crdt");
+    /// assert_eq!(same.get_winner("myobject").unwrap(), winner);
+    /// // Replicas using a different length reject them
+    /// let other = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert!(other.get_winner("myobject").is_err());
+    /// // Blocks with an unknown format version are rejected
+    /// for (uuid, version) in [("known", 3), ("unknown", 4)] {
+    ///     let block = json!({ "c" : [ [ uuid, "0".repeat(64) ] ], "t" : 16, "v" : version }).to_string();
+    ///     let id = hex::encode(Sha256::digest(block.as_bytes()));
+    ///     adapter.write().unwrap().write_object(&(id + ".delta"), block.as_bytes()).unwrap();
+    /// }
+    /// replica.refresh().unwrap();
+    /// assert!(replica.get_winner("known").is_ok());
+    /// assert!(replica.get_winner("unknown").is_err());
+    /// let invalid = MeldaConfig { revision_tail_length: 3, ..MeldaConfig::default() };
+    /// assert!(Melda::new_with_config(adapter, invalid).is_err());
+    /// ```
+    pub revision_tail_length: usize,
+    /// Maximum nesting depth of the objects (and arrays) reconstructed when reading the document
+    /// (see read, read_lazy and read_roots): reading fails with a max_depth_exceeded error
+    /// instead of overflowing the stack when references are nested deeper (for example in a
+    /// document melded from an untrusted replica). References forming a cycle are rejected
+    /// with a reference_cycle error.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut nested = json!({ "leaf" : true });
+    /// for _ in 0..300 {
+    ///     nested = json!({ "child\u{266D}" : nested });
+    /// }
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// replica.update(nested.as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// // The document is nested deeper than the default maximum depth
+    /// assert!(replica.read().is_err());
+    /// assert!(replica.read_lazy().is_err());
+    /// let config = MeldaConfig { max_read_depth: 512, ..MeldaConfig::default() };
+    /// let replica = Melda::new_with_config(adapter, config).expect("cannot_initialize_crdt");
+    /// let mut value = Value::from(replica.read().unwrap());
+    /// for _ in 0..300 {
+    ///     value = value["child\u{266D}"].take();
+    /// }
+    /// assert_eq!(value["leaf"], json!(true));
+    /// ```
+    pub max_read_depth: usize,
+}
+
+impl Default for MeldaConfig {
+    /// Returns the default configuration, cache capacities are read from the
+    /// MELDA_ARRAYDESCRIPTORS_CACHE_CAP and MELDA_DATA_CACHE_CAP environment variables (if set)
+    fn default() -> Self {
+        let array_descriptor_cache = std::env::var("MELDA_ARRAYDESCRIPTORS_CACHE_CAP")
+            .unwrap_or_else(|_| "16".to_string())
+            .parse::<u32>()
+            .unwrap() as usize;
+        let data_cache = std::env::var("MELDA_DATA_CACHE_CAP")
+            .unwrap_or_else(|_| "16".to_string())
+            .parse::<u32>()
+            .unwrap() as usize;
+        MeldaConfig {
+            array_descriptor_cache,
+            data_cache,
+            index_threshold: 800,
+            stream_threshold: 1 << 24,
+            pack_format: PackFormat::default(),
+            namespace: None,
+            retain_block_changes: false,
+            id_strategy: IdStrategy::default(),
+            strict_hashes: false,
+            block_limits: BlockLimits::default(),
+            flush_on_commit: false,
+            revision_tail_length: DEFAULT_REVISION_TAIL_LENGTH,
+            max_read_depth: DEFAULT_MAX_READ_DEPTH,
+        }
+    }
+}
+
+/// Receives timings of the main operations of a Melda instance (see Melda::set_metrics).
+/// All methods have an empty default implementation. On wasm32-unknown-unknown, which has no
+/// clock, all durations are zero.
+///
+/// # Example
+/// ```
+/// use melda::{melda::{Melda, Metrics}, adapter::Adapter, memoryadapter::MemoryAdapter};
+/// use std::sync::{Arc, Mutex, RwLock};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::time::Duration;
+/// use serde_json::{Map, Value,json};
+/// #[derive(Default)]
+/// struct Commits(AtomicUsize);
+/// impl Metrics for Commits {
+///     fn on_commit(&self, duration: Duration, block_count: usize, pack_bytes: usize) {
+///         self.0.fetch_add(block_count, Ordering::SeqCst);
+///     }
+/// }
+/// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+/// let commits = Arc::new(Commits::default());
+/// replica.set_metrics(Some(commits.clone()));
+/// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+/// replica.update(object);
+/// replica.commit(None);
+/// replica.read();
+/// assert_eq!(commits.0.load(Ordering::SeqCst), 1);
+/// ```
+pub trait Metrics: Send + Sync {
+    /// Called after a successful commit
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - The duration of the commit
+    /// * `block_count` - The number of written delta blocks
+    /// * `pack_bytes` - The size of the written data pack (0 if no pack was written)
+    fn on_commit(&self, _duration: Duration, _block_count: usize, _pack_bytes: usize) {}
+
+    /// Called after a successful reload
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - The duration of the reload
+    /// * `block_count` - The number of loaded delta blocks
+    fn on_reload(&self, _duration: Duration, _block_count: usize) {}
+
+    /// Called after the data structure has been successfully read
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - The duration of the read
+    /// * `object_count` - The number of objects which have been read
+    fn on_read(&self, _duration: Duration, _object_count: usize) {}
+}
+
+/// Long running operation whose progress is reported (see ProgressReporter)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressOperation {
+    /// Delta blocks being parsed during reload
+    LoadBlocks,
+    /// Delta blocks being applied during reload
+    ApplyBlocks,
+    /// Items being copied by meld (or meld_verified)
+    Meld,
+    /// Delta blocks being checked by verify
+    Verify,
+}
+
+/// Receives the progress of long running operations (see Melda::set_progress_reporter)
+pub trait ProgressReporter: Send + Sync {
+    /// Called after each processed item
+    ///
+    /// # Arguments
+    ///
+    /// * `operation` - The operation in progress
+    /// * `done` - The number of processed items
+    /// * `total` - The total number of items to be processed
+    fn on_progress(&self, operation: ProgressOperation, done: usize, total: usize);
+}
+
+/// Generator of object identifiers (see Melda::set_id_generator)
+pub type IdGenerator = Box<dyn FnMut() -> String + Send>;
+
+/// Generator of the information object of automatic commits (see AutoCommitConfig)
+pub type InformationGenerator = Arc<dyn Fn() -> Option<Map<String, Value>> + Send + Sync>;
+
+/// Configuration of the automatic commit mode (see Melda::set_autocommit)
+#[derive(Clone, Default)]
+pub struct AutoCommitConfig {
+    /// Generator of the information object passed to each automatic commit (see
+    /// Melda::commit), if None commits have no information object
+    pub information: Option<InformationGenerator>,
+}
+
+/// Identity of array elements used when merging concurrent versions of an array (see
+/// Melda::set_element_identity)
+pub type ElementIdentity = Arc<dyn Fn(&Value) -> String + Send + Sync>;
+
+/// Last value supplied by update for each object, with the winning revision it produced
+type InputCache = HashMap<String, (Revision, Map<String, Value>)>;
+
 /// Melda is a Delta-State CRDT for arbitrary JSON documents.
 pub struct Melda {
     documents: RwLock<BTreeMap<String, RwLock<RevisionTree>>>,
     data: RwLock<DataStorage>,
     stage: RwLock<Vec<Change>>,
     blocks: RwLock<BTreeMap<String, RwLock<Block>>>,
-    array_descriptors_cache: Mutex<LruCache<Revision, ArrayDescriptor>>,
+    array_descriptors_cache: Mutex<OrderCache>,
+    digest_cache: Mutex<Option<HashMap<String, (Revision, String)>>>,
+    input_cache: Mutex<Option<InputCache>>,
+    array_descriptor_bases: Mutex<HashMap<Revision, Vec<Value>>>,
+    pruned_revisions: Mutex<HashMap<String, HashSet<Revision>>>,
+    pinned_revisions: Mutex<BTreeMap<String, BTreeSet<String>>>,
+    inconsistent_revisions: Mutex<BTreeMap<String, BTreeSet<String>>>,
+    applied_children: Mutex<HashMap<String, usize>>,
+    shallow: bool,
+    retired_blocks: RwLock<HashSet<String>>,
+    retain_block_changes: bool,
+    memory_budget: Mutex<Option<usize>>,
+    id_strategy: IdStrategy,
+    block_limits: BlockLimits,
+    flush_on_commit: bool,
+    revision_tail_length: usize,
+    max_read_depth: usize,
+    subscriptions: Mutex<Subscriptions>,
+    metrics: RwLock<Option<Arc<dyn Metrics>>>,
+    progress: RwLock<Option<Arc<dyn ProgressReporter>>>,
+    cancellation: RwLock<Option<Arc<AtomicBool>>>,
+    id_generator: Mutex<Option<IdGenerator>>,
+    element_identity: RwLock<Option<ElementIdentity>>,
+    autocommit: RwLock<Option<AutoCommitConfig>>,
+    // Nesting depth of the calls in automatic commit mode, for each thread
+    autocommit_depth: Mutex<HashMap<ThreadId, usize>>,
+    generated_ids: AtomicUsize,
+    // Digest of the current anchors (see generate_id), reset whenever the anchors change
+    anchors_digest: Mutex<Option<String>>,
 }
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -57,6 +620,9 @@ enum Status {
     Invalid,
 }
 
+/// A change recorded in a block: (object, revision, parent revision)
+pub type BlockChange = (String, String, Option<String>);
+
 /// Block is a public structure representing a block. It is used to represent a block that has been correctly parsed.
 
 #[derive(Clone)]
@@ -69,6 +635,116 @@ pub struct Block {
     status: Status,
 }
 
+/// Result of the verification of the whole storage (see Melda::verify)
+#[derive(Clone, Debug, Default)]
+pub struct VerifyReport {
+    /// Packs that cannot be read or whose digest does not match their identifier
+    pub corrupt_packs: BTreeSet<String>,
+    /// Blocks that cannot be read, parsed, or whose digest does not match their identifier
+    pub corrupt_blocks: BTreeSet<String>,
+    /// Pairs of (block, reference) where the reference is a pack, a parent block or
+    /// a revision (whose payload is missing) which is not available or not valid
+    pub dangling_references: BTreeSet<(String, String)>,
+    /// Digests of the objects stored in packs which are not referenced by any block
+    pub orphaned_objects: BTreeSet<String>,
+}
+
+impl VerifyReport {
+    /// Returns true if no corruption or dangling reference was found (orphaned objects
+    /// do not affect the integrity of the storage)
+    pub fn is_valid(&self) -> bool {
+        self.corrupt_packs.is_empty()
+            && self.corrupt_blocks.is_empty()
+            && self.dangling_references.is_empty()
+    }
+}
+
+/// Node of the revision graph of an object (see Melda::revision_graph)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevisionNode {
+    /// The revision
+    pub revision: String,
+    /// The parent revision (None for the first revision of the object)
+    pub parent: Option<String>,
+    /// True if the revision has not been committed yet
+    pub is_staging: bool,
+    /// True if the revision records a deletion
+    pub is_deleted: bool,
+    /// True if the revision records a conflict resolution
+    pub is_resolved: bool,
+    /// True if the revision is the winning revision
+    pub is_winner: bool,
+}
+
+/// Change of the winning revision of an object (see Melda::subscribe)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevisionChange {
+    /// The identifier of the object
+    pub uuid: String,
+    /// The previous winning revision (None if the object was unknown)
+    pub previous: Option<String>,
+    /// The current winning revision (None if the object is not known anymore)
+    pub current: Option<String>,
+}
+
+/// Result of the compaction of the data packs (see Melda::repack)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepackStats {
+    /// Number of loaded packs before repacking
+    pub packs_before: usize,
+    /// Number of loaded packs after repacking
+    pub packs_after: usize,
+}
+
+/// Size of the uncommitted changes (see Melda::staging_stats)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StagingStats {
+    /// Number of staged revisions (across all objects)
+    pub object_count: usize,
+    /// Estimated size (in bytes) of the staged values, as they would be stored in a pack
+    pub byte_estimate: usize,
+}
+
+/// Usage of the caches of a replica (see Melda::memory_stats)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Number of objects in the data cache
+    pub cached_objects: usize,
+    /// Total size (in bytes, as serialized) of the cached objects, only measured while a
+    /// memory budget is set
+    pub cached_bytes: usize,
+    /// Number of materialized orders of array descriptors in the cache
+    pub cached_orders: usize,
+    /// Total size (in bytes, as serialized) of the cached orders, only measured while a
+    /// memory budget is set
+    pub order_bytes: usize,
+    /// The memory budget (see Melda::set_memory_budget)
+    pub budget: Option<usize>,
+    /// Number of objects and orders evicted from the caches so far
+    pub evictions: usize,
+    /// Number of delta blocks which retain their changes in memory
+    pub retained_blocks: usize,
+}
+
+/// Deduplication of the committed payloads (see Melda::dedup_stats)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// Number of distinct payloads referenced by the revisions of all objects
+    pub unique_objects: usize,
+    /// Number of revisions (of all objects) referencing a payload
+    pub total_references: usize,
+    /// Size (in bytes) which would have been needed to store a separate payload for each
+    /// reference, minus the size of the distinct payloads
+    pub bytes_saved: usize,
+}
+
+// Subscriptions to changes of winning revisions, along with the last notified winners
+#[derive(Default)]
+struct Subscriptions {
+    senders: Vec<(Option<String>, Sender<RevisionChange>)>,
+    winners: HashMap<String, Option<String>>,
+}
+
 // Array descriptor represents an array descriptor. It is used to support reconstruction of delta descriptors
 #[derive(Clone)]
 struct ArrayDescriptor {
@@ -145,6 +821,98 @@ impl ArrayDescriptor {
     }
 }
 
+// Cache of the materialized orders of array descriptors, bounded by the number of orders and
+// (while a memory budget is set) by their total size (in bytes, as serialized)
+struct OrderCache {
+    descriptors: LruCache<Revision, (ArrayDescriptor, usize)>,
+    measured: bool,
+    bytes: usize,
+    evictions: usize,
+}
+
+impl OrderCache {
+    fn new(capacity: NonZeroUsize) -> OrderCache {
+        OrderCache {
+            descriptors: LruCache::new(capacity),
+            measured: false,
+            bytes: 0,
+            evictions: 0,
+        }
+    }
+
+    // Returns the size of the order of the descriptor (only measured while a budget is set)
+    fn measure(&self, descriptor: &ArrayDescriptor) -> usize {
+        if self.measured {
+            serialized_size(descriptor.get_order())
+        } else {
+            0
+        }
+    }
+
+    fn get(&mut self, revision: &Revision) -> Option<&ArrayDescriptor> {
+        self.descriptors
+            .get(revision)
+            .map(|(descriptor, _)| descriptor)
+    }
+
+    fn contains(&self, revision: &Revision) -> bool {
+        self.descriptors.contains(revision)
+    }
+
+    fn cap(&self) -> NonZeroUsize {
+        self.descriptors.cap()
+    }
+
+    fn put(&mut self, revision: Revision, descriptor: ArrayDescriptor) {
+        let size = self.measure(&descriptor);
+        self.bytes += size;
+        if let Some((old, (_, old_size))) =
+            self.descriptors.push(revision.clone(), (descriptor, size))
+        {
+            self.bytes -= old_size;
+            if old != revision {
+                self.evictions += 1;
+            }
+        }
+    }
+
+    // Evicts the least recently used orders until their total size fits the limit
+    fn shrink_to(&mut self, limit: usize) {
+        while self.bytes > limit {
+            match self.descriptors.pop_lru() {
+                Some((_, (_, size))) => {
+                    self.bytes -= size;
+                    self.evictions += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    // Starts (or stops) measuring the orders, sizes are only measured while a budget is set
+    fn set_measured(&mut self, measured: bool) {
+        self.measured = measured;
+        let mut bytes = 0;
+        let revisions: Vec<Revision> = self.descriptors.iter().map(|(r, _)| r.clone()).collect();
+        for revision in revisions {
+            let size = match self.descriptors.peek(&revision) {
+                Some((descriptor, _)) => self.measure(descriptor),
+                None => continue,
+            };
+            if let Some(entry) = self.descriptors.peek_mut(&revision) {
+                entry.1 = size;
+            }
+            bytes += size;
+        }
+        self.bytes = bytes;
+    }
+
+    fn clear(&mut self) {
+        self.descriptors.clear();
+        self.bytes = 0;
+    }
+}
+
 impl Melda {
     /// Initializes a new Melda data structure using the provided adapter
     ///
@@ -161,19 +929,118 @@ impl Melda {
     /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// ```
     pub fn new(adapter: Arc<RwLock<Box<dyn Adapter>>>) -> Result<Melda> {
-        let cache_size = std::env::var("MELDA_ARRAYDESCRIPTORS_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
-        let dc = Melda {
-            documents: RwLock::new(BTreeMap::<String, RwLock<RevisionTree>>::new()),
-            data: RwLock::new(DataStorage::new(adapter.clone())),
-            stage: RwLock::new(Vec::<Change>::new()),
-            blocks: RwLock::new(BTreeMap::new()),
-            array_descriptors_cache: Mutex::new(LruCache::<Revision, ArrayDescriptor>::new(
-                NonZeroUsize::new(cache_size).unwrap(),
-            )),
-        };
+        Melda::new_with_config(adapter, MeldaConfig::default())
+    }
+
+    /// Initializes a new Melda data structure using the provided adapter and configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `adapter` - The backend adapter used to persist the data on commit
+    /// * `config` - The configuration (cache capacities, index threshold and namespace)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let config = MeldaConfig { array_descriptor_cache: 4, data_cache: 1024, ..MeldaConfig::default() };
+    /// let mut replica = Melda::new_with_config(Arc::new(RwLock::new(adapter)), config).expect("cannot_initialize_crdt");
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let config = MeldaConfig { data_cache: 0, ..MeldaConfig::default() };
+    /// assert!(Melda::new_with_config(Arc::new(RwLock::new(adapter)), config).is_err());
+    /// // Two documents sharing the same adapter
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let config = MeldaConfig { namespace: Some("first".to_string()), ..MeldaConfig::default() };
+    /// let first = Melda::new_with_config(adapter.clone(), config.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : "alpha" }).as_object().unwrap().clone();
+    /// first.update(object);
+    /// first.commit(None);
+    /// let second_config = MeldaConfig { namespace: Some("second".to_string()), ..MeldaConfig::default() };
+    /// let second = Melda::new_with_config(adapter.clone(), second_config).expect("cannot_initialize_crdt");
+    /// assert!(second.get_all_objects().is_empty());
+    /// let object = json!({ "somekey" : "beta" }).as_object().unwrap().clone();
+    /// second.update(object);
+    /// second.commit(None);
+    /// first.reload();
+    /// assert_eq!(first.read().unwrap().get("somekey"), Some(&json!("alpha")));
+    /// second.reload();
+    /// assert_eq!(second.read().unwrap().get("somekey"), Some(&json!("beta")));
+    /// assert!(adapter.read().unwrap().list_objects("").unwrap().iter().all(|k| k.starts_with("first~") || k.starts_with("second~")));
+    /// let config = MeldaConfig { namespace: Some("not/valid".to_string()), ..MeldaConfig::default() };
+    /// assert!(Melda::new_with_config(adapter, config).is_err());
+    /// ```
+    pub fn new_with_config(
+        adapter: Arc<RwLock<Box<dyn Adapter>>>,
+        config: MeldaConfig,
+    ) -> Result<Melda> {
+        let dc = Melda::initialize(adapter, &config)?;
+        dc.reload()?;
+        Ok(dc)
+    }
+
+    /// Initializes a new data structure in shallow mode: only the headers of the blocks
+    /// (parents and packs) are loaded, then the changes of the blocks reachable from the
+    /// anchors are fetched one block at a time while they are applied (so that the current
+    /// state is read correctly). Only the anchor blocks (and the headers of blocks which have
+    /// not been applied yet) are retained, whereas only the identifiers of the other blocks are
+    /// kept. This reduces memory usage for replicas which only need the current state: refresh
+    /// and commit keep working incrementally (retiring the blocks which are no longer anchors).
+    /// APIs which traverse the history of blocks (such as get_block, commit_log,
+    /// commit_anchors, merge_base and changed_since) only see the retained blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `adapter` - The storage adapter
+    /// * `config` - The configuration (see MeldaConfig)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let mut blocks = vec![];
+    /// for i in 0..3 {
+    ///     replica.update(json!({ "somekey" : i }).as_object().unwrap().clone()).unwrap();
+    ///     blocks.push(replica.commit(None).unwrap().unwrap());
+    /// }
+    /// let first = &blocks[0];
+    /// let shallow = Melda::open_shallow(adapter.clone(), MeldaConfig::default()).expect("cannot_initialize_crdt");
+    /// assert_eq!(shallow.read().unwrap(), replica.read().unwrap());
+    /// assert_eq!(shallow.get_anchors(), replica.get_anchors());
+    /// assert!(shallow.get_block(first).unwrap().is_none());
+    /// assert_eq!(shallow.commit_log().len(), 1);
+    /// // Refresh only loads new blocks
+    /// replica.update(json!({ "somekey" : 3 }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// assert!(shallow.has_new_blocks().unwrap());
+    /// shallow.refresh().unwrap();
+    /// assert!(!shallow.has_new_blocks().unwrap());
+    /// assert_eq!(shallow.read().unwrap(), replica.read().unwrap());
+    /// assert_eq!(shallow.commit_log().len(), 1);
+    /// // Changes are only tracked from the retained blocks
+    /// let processed = shallow.get_anchors();
+    /// assert!(shallow.changed_since(&processed).unwrap().is_empty());
+    /// let root = shallow.get_value_until("\u{221A}", &processed, None).unwrap();
+    /// assert_eq!(root.get("somekey"), Some(&json!(3)));
+    /// // Commits are based on the current anchors
+    /// shallow.update(json!({ "somekey" : 4 }).as_object().unwrap().clone()).unwrap();
+    /// let block = shallow.commit(None).unwrap().unwrap();
+    /// assert_eq!(shallow.get_block(&block).unwrap().unwrap().parents, Some(replica.get_anchors()));
+    /// replica.refresh().unwrap();
+    /// assert_eq!(shallow.read().unwrap(), replica.read().unwrap());
+    /// ```
+    pub fn open_shallow(
+        adapter: Arc<RwLock<Box<dyn Adapter>>>,
+        config: MeldaConfig,
+    ) -> Result<Melda> {
+        let mut dc = Melda::initialize(adapter, &config)?;
+        dc.shallow = true;
         dc.reload()?;
         Ok(dc)
     }
@@ -192,22 +1059,8 @@ impl Melda {
     /// let mut replica = Melda::new_from_url("memory+flate://").expect("cannot_initialize_crdt");
     /// ```
     pub fn new_from_url(url: &str) -> Result<Melda> {
-        let cache_size = std::env::var("MELDA_ARRAYDESCRIPTORS_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
         let adapter = Arc::new(RwLock::new(crate::adapter::get_adapter(url).unwrap()));
-        let dc = Melda {
-            documents: RwLock::new(BTreeMap::<String, RwLock<RevisionTree>>::new()),
-            data: RwLock::new(DataStorage::new(adapter.clone())),
-            stage: RwLock::new(Vec::<Change>::new()),
-            blocks: RwLock::new(BTreeMap::new()),
-            array_descriptors_cache: Mutex::new(LruCache::<Revision, ArrayDescriptor>::new(
-                NonZeroUsize::new(cache_size).unwrap(),
-            )),
-        };
-        dc.reload()?;
-        Ok(dc)
+        Melda::new(adapter)
     }
 
     /// Initializes a new Melda data structure using the provided adapter and loads until the given block
@@ -245,19 +1098,7 @@ impl Melda {
     /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
     /// ```
     pub fn new_until(adapter: Arc<RwLock<Box<dyn Adapter>>>, block: &str) -> Result<Melda> {
-        let cache_size = std::env::var("MELDA_ARRAYDESCRIPTORS_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
-        let dc = Melda {
-            documents: RwLock::new(BTreeMap::<String, RwLock<RevisionTree>>::new()),
-            data: RwLock::new(DataStorage::new(adapter.clone())),
-            stage: RwLock::new(Vec::<Change>::new()),
-            blocks: RwLock::new(BTreeMap::new()),
-            array_descriptors_cache: Mutex::new(LruCache::<Revision, ArrayDescriptor>::new(
-                NonZeroUsize::new(cache_size).unwrap(),
-            )),
-        };
+        let dc = Melda::initialize(adapter, &MeldaConfig::default())?;
         dc.reload_until(block)?;
         Ok(dc)
     }
@@ -271,22 +1112,60 @@ impl Melda {
     ///
     /// ```
     pub fn new_from_url_until(url: &str, block: &str) -> Result<Melda> {
-        let cache_size = std::env::var("MELDA_ARRAYDESCRIPTORS_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
         let adapter = Arc::new(RwLock::new(crate::adapter::get_adapter(url).unwrap()));
-        let dc = Melda {
+        Melda::new_until(adapter, block)
+    }
+
+    // Constructs the data structure (without loading any block)
+    fn initialize(adapter: Arc<RwLock<Box<dyn Adapter>>>, config: &MeldaConfig) -> Result<Melda> {
+        let array_descriptor_cache = NonZeroUsize::new(config.array_descriptor_cache)
+            .ok_or_else(|| anyhow!("invalid_array_descriptor_cache_capacity"))?;
+        if !(DEFAULT_REVISION_TAIL_LENGTH..=MAX_REVISION_TAIL_LENGTH)
+            .contains(&config.revision_tail_length)
+        {
+            bail!("invalid_revision_tail_length");
+        }
+        Ok(Melda {
             documents: RwLock::new(BTreeMap::<String, RwLock<RevisionTree>>::new()),
-            data: RwLock::new(DataStorage::new(adapter.clone())),
+            data: RwLock::new(DataStorage::new(
+                adapter,
+                config.data_cache,
+                config.index_threshold,
+                config.stream_threshold,
+                config.pack_format,
+                config.namespace.as_deref(),
+                config.strict_hashes,
+            )?),
             stage: RwLock::new(Vec::<Change>::new()),
             blocks: RwLock::new(BTreeMap::new()),
-            array_descriptors_cache: Mutex::new(LruCache::<Revision, ArrayDescriptor>::new(
-                NonZeroUsize::new(cache_size).unwrap(),
-            )),
-        };
-        dc.reload_until(block)?;
-        Ok(dc)
+            array_descriptors_cache: Mutex::new(OrderCache::new(array_descriptor_cache)),
+            digest_cache: Mutex::new(None),
+            input_cache: Mutex::new(None),
+            array_descriptor_bases: Mutex::new(HashMap::new()),
+            pruned_revisions: Mutex::new(HashMap::new()),
+            pinned_revisions: Mutex::new(BTreeMap::new()),
+            inconsistent_revisions: Mutex::new(BTreeMap::new()),
+            applied_children: Mutex::new(HashMap::new()),
+            shallow: false,
+            retired_blocks: RwLock::new(HashSet::new()),
+            retain_block_changes: config.retain_block_changes,
+            memory_budget: Mutex::new(None),
+            id_strategy: config.id_strategy,
+            block_limits: config.block_limits.clone(),
+            flush_on_commit: config.flush_on_commit,
+            revision_tail_length: config.revision_tail_length,
+            max_read_depth: config.max_read_depth,
+            subscriptions: Mutex::new(Subscriptions::default()),
+            metrics: RwLock::new(None),
+            progress: RwLock::new(None),
+            cancellation: RwLock::new(None),
+            id_generator: Mutex::new(None),
+            element_identity: RwLock::new(None),
+            autocommit: RwLock::new(None),
+            autocommit_depth: Mutex::new(HashMap::new()),
+            generated_ids: AtomicUsize::new(0),
+            anchors_digest: Mutex::new(None),
+        })
     }
 
     /// Records the creation of an object
@@ -307,6 +1186,16 @@ impl Melda {
     /// assert!(replica.create_object("myobject", object).is_ok())
     /// ```
     pub fn create_object(&self, uuid: &str, obj: Map<String, Value>) -> Result<()> {
+        self.autocommit(|| self.staged(|stage| self.create_object_impl(uuid, obj, stage)))
+    }
+
+    // Records the creation of an object (see create_object) in the given stage
+    fn create_object_impl(
+        &self,
+        uuid: &str,
+        obj: Map<String, Value>,
+        stage: &RwLock<Vec<Change>>,
+    ) -> Result<Option<Revision>> {
         // Create initial revision
         let rev = Revision::new(
             1u32,
@@ -314,26 +1203,16 @@ impl Melda {
             None,
         );
         let mut data_w = self.data.write().expect("cannot_acquire_data_for_writing");
-        data_w.write_object(&rev, obj).expect("cannot_write_object");
+        data_w.write_object(&rev, obj)?;
         drop(data_w);
-        // Obtain the revision tree (either an existing one of a new one)
-        let mut docs_w = self
-            .documents
-            .write()
-            .expect("cannot_acquire_documents_for_writing");
-        let mut rt_w = docs_w
-            .entry(uuid.to_string())
-            .or_insert_with(|| RwLock::new(RevisionTree::new()))
-            .write()
-            .expect("cannot_acquire_revision_tree_for_writing");
-        rt_w.add(rev.clone(), None);
-        drop(rt_w);
-        drop(docs_w);
-        self.stage
+        if let Some(cache) = self.digest_cache.lock().unwrap().as_mut() {
+            cache.insert(uuid.to_string(), (rev.clone(), rev.digest.clone()));
+        }
+        stage
             .write()
             .expect("cannot_acquire_stage_for_writing")
-            .push(Change(uuid.to_owned(), rev, None));
-        Ok(())
+            .push(Change(uuid.to_owned(), rev.clone(), None));
+        Ok(Some(rev))
     }
 
     /// Records the update of an object
@@ -356,75 +1235,21 @@ impl Melda {
     /// assert!(replica.update_object("myobject", object).is_ok());
     /// ```
     pub fn update_object(&self, uuid: &str, obj: Map<String, Value>) -> Result<()> {
-        // Obtain the revision tree (either an existing one of a new one)
-        let docs_r = self
-            .documents
-            .read()
-            .expect("cannot_acquire_documents_for_reading");
-        if let Some(rt) = docs_r.get(uuid) {
-            // Existing object
-            let mut rt_w = rt
-                .write()
-                .expect("cannot_acquire_revision_tree_for_writing");
-            if let Some(winning_revision) = rt_w.get_winner() {
-                // If its an array descriptor first need to compute the delta
-                // If create_delta_array_descriptor returns None it means that there are
-                // no differences between the current array and the new one
-                let object = if is_array_descriptor(uuid) {
-                    self.create_delta_array_descriptor(obj, &rt_w).unwrap()
-                } else {
-                    Some(obj)
-                };
-                // Now compute the digest to see if the object has changed
-                // An object can be None if its an "empty" delta array descriptor
-                if let Some(object) = object {
-                    let digest = digest_object(&object).unwrap(); // Digest of the current object
-                    if digest.ne(&winning_revision.digest) {
-                        // Digest is different, there was an update
-                        let rev = Revision::new_updated(digest, winning_revision);
-                        let winning_revision = winning_revision.clone();
-                        rt_w.add(rev.clone(), Some(winning_revision.clone()));
-                        let mut data_w =
-                            self.data.write().expect("cannot_acquire_data_for_writing");
-                        data_w.write_object(&rev, object).unwrap();
-                        drop(data_w);
-                        self.stage.write().unwrap().push(Change(
-                            uuid.to_string(),
-                            rev,
-                            Some(winning_revision),
-                        ));
-                    }
-                }
-            }
-            Ok(())
-        } else {
-            // Newly created object
-            drop(docs_r);
-            // No winning revision, assume that its a new object
-            self.create_object(uuid, obj)
-        }
-    }
-
-    fn read_object(&self, uuid: &str, rt: &RevisionTree) -> Result<Map<String, Value>> {
-        let winner = rt.get_winner().expect("object_has_no_winner");
-        if is_array_descriptor(uuid) {
-            let order = self.get_merged_order(rt).expect("cannot_get_merged_order");
-            Ok(ArrayDescriptor::new_from_order(order).to_json_object())
-        } else {
-            Ok(self
-                .data
-                .read()
-                .expect("cannot_acquire_data_for_reading")
-                .read_object(winner)
-                .expect("cannot_read_object"))
-        }
+        self.autocommit(|| {
+            self.staged(|stage| self.update_object_with_optional_digest(uuid, obj, None, stage))
+        })
     }
 
-    /// Records the deletion of an object
+    /// Records the update of an object whose digest is already known by the caller
+    ///
+    /// The digest must correspond to the value that digest_object would compute on the object.
+    /// If the digest matches the current state the update is skipped without serializing the object.
     ///
     /// # Arguments
     ///
     /// * `uuid` - The unique identifier of the object
+    /// * `obj` - The JSON object
+    /// * `digest` - The precomputed digest of the JSON object
     ///
     /// # Example
     /// ```
@@ -433,58 +1258,78 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1, 2, 3, 4 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);
-    /// assert!(replica.get_all_objects().contains("myobject"));    
-    /// replica.delete_object("myobject");
-    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// assert!(replica.create_object("myobject", object.clone()).is_ok());
     /// let winner = replica.get_winner("myobject").unwrap();
-    /// let value = replica.get_value("myobject", &winner);
-    /// assert!(value.is_ok());
-    /// assert!(value.unwrap().contains_key("_deleted"));
+    /// let digest = "e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196";
+    /// assert!(replica.update_object_with_digest("myobject", object, digest).is_ok());
+    /// assert_eq!(winner, replica.get_winner("myobject").unwrap());
+    /// ```
+    pub fn update_object_with_digest(
+        &self,
+        uuid: &str,
+        obj: Map<String, Value>,
+        digest: &str,
+    ) -> Result<()> {
+        self.autocommit(|| {
+            self.staged(|stage| {
+                self.update_object_with_optional_digest(uuid, obj, Some(digest.to_string()), stage)
+            })
+        })
+    }
+
+    /// Enables or disables the digest cache (disabled by default)
+    ///
+    /// When enabled, the digest of the last object passed to update_object is remembered
+    /// together with the winning revision it produced, so that updating an object with
+    /// unchanged content can be skipped (for array descriptors this avoids diffing). An entry
+    /// is only used while the winning revision of the object is the one it was recorded with.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - True to enable the cache, false to disable it (and drop its content)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey\u{266D}" : { "_id": "1", "key" : "alpha" }}).as_object().unwrap().clone();
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.set_digest_cache(true);
+    /// let object = json!({ "somekey\u{266D}" : [ "a", "b", "c" ] }).as_object().unwrap().clone();
     /// replica.update(object.clone());
-    /// let readback = replica.read().unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":{\"_id\":\"1\",\"key\":\"alpha\"}}", content);
-    /// replica.delete_object("1");
-    /// let readback = replica.read().unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":null}", content);
+    /// replica.commit(None);
+    /// replica.update(object.clone());
+    /// assert!(replica.stage().unwrap().is_none());
+    /// let object = json!({ "somekey\u{266D}" : [ "a", "c" ] }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// assert!(replica.stage().unwrap().is_some());
     /// ```
-    pub fn delete_object(&self, uuid: &str) -> Result<()> {
-        let docs_r = self
-            .documents
-            .read()
-            .expect("cannot_acquire_documents_for_reading");
-        if let Some(rt) = docs_r.get(uuid) {
-            let mut rt_w = rt
-                .write()
-                .expect("cannot_acquire_revision_tree_for_writing");
-            if let Some(winning_revision) = rt_w.get_winner() {
-                if !winning_revision.is_deleted() && !winning_revision.is_resolved() {
-                    let rev = Revision::new_deleted(winning_revision);
-                    let winning_revision = winning_revision.clone();
-                    rt_w.add(rev.clone(), Some(winning_revision.clone()));
-                    self.stage.write().unwrap().push(Change(
-                        uuid.to_string(),
-                        rev,
-                        Some(winning_revision),
-                    ));
-                }
+    pub fn set_digest_cache(&self, enabled: bool) {
+        let mut cache = self.digest_cache.lock().unwrap();
+        if enabled {
+            if cache.is_none() {
+                *cache = Some(HashMap::new());
             }
+        } else {
+            *cache = None;
         }
-        Ok(())
     }
 
-    /// Commits changes to the backend adapter
+    /// Enables or disables the input cache (disabled by default)
+    ///
+    /// When enabled, update remembers the flattened value supplied for each object together
+    /// with the winning revision it produced: objects whose value is identical to the one
+    /// supplied by the previous update are skipped (without computing their digest or, for
+    /// array descriptors, diffing their order) as long as their winning revision has not
+    /// changed. Values are compared by value (not by digest), hence the cache holds a copy of
+    /// the last input. The cache is cleared when the state is changed externally (by refresh,
+    /// reload, reload_until, unstage and clear).
     ///
     /// # Arguments
     ///
-    /// * `information` - Optional JSON object for recording additional commit information
+    /// * `enabled` - True to enable the cache, false to disable it (and drop its content)
     ///
     /// # Example
     /// ```
@@ -493,365 +1338,662 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));  
+    /// replica.set_input_cache(true);
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "k" : 1 }, { "_id" : "2", "k" : 2 } ] }).as_object().unwrap().clone();
+    /// replica.update(object.clone()).unwrap();
     /// replica.commit(None);
-    /// replica.delete_object("myobject");
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", winner);
-    /// let value = replica.get_value("myobject", &winner);
-    /// assert!(value.is_ok());
-    /// assert!(value.unwrap().contains_key("_deleted"));
-    /// let info = json!({ "author" : "Some user", "date" : "2022-05-23 13:47:00CET" }).as_object().unwrap().clone();
-    /// replica.commit(Some(info));
+    /// replica.update(object.clone()).unwrap();
+    /// assert!(replica.stage().unwrap().is_none());
+    /// // Changes made outside update are not hidden by the cache
+    /// replica.update_object("1", json!({ "k" : 3 }).as_object().unwrap().clone()).unwrap();
+    /// replica.update(object.clone()).unwrap();
+    /// assert_eq!(replica.read().unwrap(), object.clone().into_iter().chain([("_id".to_string(), json!("\u{221A}"))]).collect());
+    /// replica.commit(None);
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "2", "k" : 2 } ] }).as_object().unwrap().clone();
+    /// replica.update(object.clone()).unwrap();
+    /// assert_eq!(replica.read().unwrap().get("items\u{266D}").unwrap(), &json!([ { "_id" : "2", "k" : 2 } ]));
     /// ```
-    pub fn commit(&self, information: Option<Map<String, Value>>) -> Result<Option<String>> {
-        let stage = self.stage.read().unwrap();
-        if stage.is_empty() {
-            return Ok(None);
+    pub fn set_input_cache(&self, enabled: bool) {
+        let mut cache = self.input_cache.lock().unwrap();
+        if enabled {
+            if cache.is_none() {
+                *cache = Some(HashMap::new());
+            }
+        } else {
+            *cache = None;
         }
-        drop(stage);
-        let mut block = Map::<String, Value>::new();
-        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
-        let _packid = data.pack()?;
-        // Process stage
-        let mut changes = Vec::<Value>::new();
-        for Change(uuid, rev, prev) in self.stage.read().unwrap().iter() {
-            if prev.is_none() {
-                // Creation record
-                let tuple = vec![uuid.clone(), rev.digest.clone()];
-                changes.push(Value::from(tuple));
-            } else {
-                // Update record
-                let triple = vec![
-                    uuid.clone(),
-                    prev.as_ref().unwrap().to_string(),
-                    rev.digest.clone(),
-                ];
-                changes.push(Value::from(triple));
-            }
-        }
-        block.insert(CHANGESETS_FIELD.to_string(), Value::from(changes));
-        // Insert information object
-        if let Some(information) = information {
-            block.insert(INFORMATION_FIELD.to_string(), Value::from(information));
-        }
-        // Insert anchors
-        let anchors_blocks = self.get_anchors();
-        if !anchors_blocks.is_empty() {
-            let anchors_blocks: Vec<String> =
-                anchors_blocks.iter().map(|bid| bid.to_string()).collect();
-            block.insert(PARENTS_FIELD.to_string(), Value::from(anchors_blocks));
-        }
-        // Insert pack indentifer
-        if _packid.is_some() {
-            let packs = vec![_packid.unwrap()];
-            block.insert(PACK_FIELD.to_string(), Value::from(packs));
-        }
-        let blockstr = serde_json::to_string(&block).unwrap();
-        let block_hash = digest_string(&blockstr);
-        let blockid = block_hash.clone() + DELTA_EXTENSION;
-        data.write_raw_bytes(&blockid, blockstr.as_bytes())?;
-        // Clears the stage
-        self.stage.write().unwrap().clear();
-        // Load the block
-        drop(data);
-        let mut b = self.parse_raw_block(block_hash.clone(), block).unwrap();
-        b.status = Status::ValidAndApplied;
-        self.blocks
-            .write()
-            .unwrap()
-            .insert(block_hash.clone(), RwLock::new(b));
-        Ok(Some(block_hash))
     }
 
-    /// Returns a set of the identifier of all objects
+    // Drops the content of the input cache (if enabled)
+    fn clear_input_cache(&self) {
+        if let Some(cache) = self.input_cache.lock().unwrap().as_mut() {
+            cache.clear();
+        }
+    }
+
+    /// Sets (or removes) the generator used by generate_id
+    ///
+    /// # Arguments
+    ///
+    /// * `generator` - The function returning a new identifier at each call, None to restore
+    ///   the default (deterministic) generator
+    pub fn set_id_generator(&self, generator: Option<IdGenerator>) {
+        *self.id_generator.lock().unwrap() = generator;
+    }
+
+    /// Sets (or removes) the function deciding whether two elements of concurrent versions of
+    /// an array are the same element when they are merged (by default elements are the same
+    /// if they are equal). Two elements are the same if the function returns the same string:
+    /// only one of them is kept in the merged array, the one of the winning revision (see
+    /// get_winner). Elements of the other versions which are the same as an element of the
+    /// winning revision are therefore dropped, even if they are not equal to it.
+    ///
+    /// The function receives the flattened elements: objects are replaced by their identifier
+    /// (hence objects are always compared by identifier, which is why keyed objects never need
+    /// an identity function) and strings are escaped (prefixed with an exclamation mark). Only the order of merged
+    /// arrays is affected (see read), and all replicas should use the same function, otherwise
+    /// they might read different values.
+    ///
+    /// # Arguments
+    ///
+    /// * `identity` - The function returning the identity of an element, None to restore
+    ///   the default (full-value equality)
     ///
     /// # Example
     /// ```
     /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
-    /// use std::collections::BTreeSet;
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1.0f32, 2.0f32, 3.0f32, 4.0f32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);
-    /// let object = json!({ "somekey" : [ "somedata", 1.0f32, 2.0f32, 3.0f32, 4.0f32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("another", object);
-    /// assert_eq!(replica.get_all_objects(), BTreeSet::from(["another".to_string(),"myobject".to_string()]));
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "tags\u{266D}" : [ "red" ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica).unwrap();
+    /// replica2.refresh().unwrap();
+    /// // Both replicas concurrently add the same tag (with a different case)
+    /// replica.update(json!({ "tags\u{266D}" : [ "red", "Blue" ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica2.update(json!({ "tags\u{266D}" : [ "red", "blue" ] }).as_object().unwrap().clone()).unwrap();
+    /// replica2.commit(None).unwrap();
+    /// replica.meld(&replica2).unwrap();
+    /// replica.refresh().unwrap();
+    /// let tags = replica.read().unwrap().get("tags\u{266D}").unwrap().as_array().unwrap().len();
+    /// assert_eq!(tags, 3);
+    /// // Tags are compared without considering the case
+    /// replica.set_element_identity(Some(Arc::new(|v: &Value| v.to_string().to_lowercase())));
+    /// let tags = replica.read().unwrap().get("tags\u{266D}").unwrap().as_array().unwrap().len();
+    /// assert_eq!(tags, 2);
     /// ```
-    pub fn get_all_objects(&self) -> BTreeSet<String> {
-        self.documents
-            .read()
-            .unwrap()
-            .iter()
-            .map(|(k, _)| k.clone())
-            .collect()
+    pub fn set_element_identity(&self, identity: Option<ElementIdentity>) {
+        *self
+            .element_identity
+            .write()
+            .expect("cannot_acquire_element_identity_for_writing") = identity;
     }
 
-    /// Returns a the value associated with the given revision
+    /// Enables (or disables) the automatic commit mode: when enabled, each call to a method
+    /// recording changes (create_object, update_object, update_object_with_digest,
+    /// delete_object, delete_object_with_meta, rename_object, update, update_typed, import,
+    /// resolve_as and resolve_merged) commits the recorded changes (see commit) before
+    /// returning, so that the stage never needs to be managed. Calls made within another call
+    /// are part of the outer commit: for example update creates a single delta block for all
+    /// the objects it changes, and update_streaming keeps committing every commit_every
+    /// objects. If the commit fails, the error is returned and the changes remain staged.
+    /// Nesting is tracked separately for each thread: calls made concurrently on different
+    /// threads are all outermost calls, each of them commits before returning (and the commit
+    /// includes the changes staged so far by the other threads).
+    ///
+    /// Each commit writes a delta block (and usually a pack), and blocks are loaded one by one
+    /// when a replica is loaded or refreshed: frequent small commits make the history (hence
+    /// the storage and the time spent loading it) grow much faster than batched commits.
     ///
     /// # Arguments
     ///
-    /// * `uuid` - The identifier of the object
-    /// * `revision`- The revision which we want to obtain the value for
+    /// * `config` - The configuration of the automatic commits, None to disable them (changes
+    ///   are then committed explicitly, as by default)
     ///
     /// # Example
     /// ```
-    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use melda::{melda::{Melda, AutoCommitConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
-    /// use std::collections::BTreeSet;
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1.0f32, 2.0f32, 3.0f32, 4.0f32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object.clone());
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// let value = replica.get_value("myobject", &winner).unwrap();
-    /// assert_eq!(value, object);
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let information = Arc::new(|| Some(json!({ "author" : "someone" }).as_object().unwrap().clone()));
+    /// replica.set_autocommit(Some(AutoCommitConfig { information: Some(information) }));
+    /// // A single block for all the objects changed by update
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "1" }, { "_id" : "2" } ] }).as_object().unwrap().clone()).unwrap();
+    /// assert!(!replica.has_staging());
+    /// assert_eq!(replica.commit_log().len(), 1);
+    /// replica.delete_object("1").unwrap();
+    /// assert!(!replica.has_staging());
+    /// let log = replica.commit_log();
+    /// assert_eq!(log.len(), 2);
+    /// assert!(log.iter().all(|(_, info)| info.as_ref().unwrap().get("author") == Some(&json!("someone"))));
+    /// // Manual control
+    /// replica.set_autocommit(None);
+    /// replica.delete_object("2").unwrap();
+    /// assert!(replica.has_staging());
+    /// replica.commit(None).unwrap();
+    /// let reloaded = Melda::new(adapter).expect("cannot_initialize_crdt");
+    /// assert_eq!(reloaded.read().unwrap(), replica.read().unwrap());
+    /// // A call on another thread is committed even while update_streaming is running
+    /// replica.set_autocommit(Some(AutoCommitConfig { information: None }));
+    /// let (started, wait_started) = std::sync::mpsc::channel();
+    /// let (done, wait_done) = std::sync::mpsc::channel();
+    /// let replica = &replica;
+    /// std::thread::scope(|s| {
+    ///     s.spawn(move || {
+    ///         let objects = (0..2u32).map(|i| {
+    ///             if i == 0 {
+    ///                 started.send(()).unwrap();
+    ///                 wait_done.recv().unwrap();
+    ///             }
+    ///             json!({ "count" : i }).as_object().unwrap().clone()
+    ///         });
+    ///         replica.update_streaming(objects, 10, |_| None).unwrap();
+    ///     });
+    ///     wait_started.recv().unwrap();
+    ///     replica.create_object("3", json!({}).as_object().unwrap().clone()).unwrap();
+    ///     assert!(!replica.has_staging());
+    ///     done.send(()).unwrap();
+    /// });
+    /// assert!(!replica.has_staging());
+    /// assert_eq!(replica.read().unwrap().get("count"), Some(&json!(1)));
     /// ```
-    pub fn get_value(&self, uuid: &str, revision: &str) -> Result<Map<String, Value>> {
-        let revision = Revision::from(revision).expect("invalid_revision_string");
-        match self
-            .documents
+    pub fn set_autocommit(&self, config: Option<AutoCommitConfig>) {
+        *self
+            .autocommit
+            .write()
+            .expect("cannot_acquire_autocommit_for_writing") = config;
+    }
+
+    // Runs a method recording changes, then commits them if the automatic commit mode is
+    // enabled (only for the outermost call, see set_autocommit)
+    pub(crate) fn autocommit<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        let config = self
+            .autocommit
             .read()
-            .expect("failed_to_acquire_documents_for_reading")
-            .get(uuid)
-        {
-            Some(_) => self
-                .data
-                .read()
-                .expect("cannot_acquire_data_for_reading")
-                .read_object(&revision),
-            None => Err(anyhow!("invalid object uuid")),
+            .expect("cannot_acquire_autocommit_for_reading")
+            .clone();
+        let config = match config {
+            Some(config) => config,
+            None => return f(),
+        };
+        let thread = std::thread::current().id();
+        *self
+            .autocommit_depth
+            .lock()
+            .unwrap()
+            .entry(thread)
+            .or_insert(0) += 1;
+        let result = f();
+        let outermost = {
+            let mut depths = self.autocommit_depth.lock().unwrap();
+            let depth = depths.entry(thread).or_insert(1);
+            *depth -= 1;
+            if *depth == 0 {
+                depths.remove(&thread);
+                true
+            } else {
+                false
+            }
+        };
+        let result = result?;
+        if outermost {
+            self.commit(config.information.as_ref().and_then(|generate| generate()))?;
         }
+        Ok(result)
     }
 
-    /// Returns a set of the current anchor blocks (blocks that have not been referenced as parents)
+    /// Returns a new identifier, which can be used as the identifier (_id) of new objects
+    /// instead of random identifiers (which make documents non-reproducible). Melda itself
+    /// never generates random identifiers: objects without an identifier receive one derived
+    /// from their path or content (see IdStrategy), and the identifiers of revisions, packs and
+    /// delta blocks are digests of their content (staged changes are sorted before being
+    /// committed), hence replicas which receive the same inputs (including the information
+    /// objects passed to commit) produce the same documents and the same blocks.
+    ///
+    /// Identifiers are produced by the generator set with set_id_generator or, by default,
+    /// derived from the current anchors and the number of identifiers generated so far by
+    /// this instance: the default identifiers are reproducible, but two replicas with the same
+    /// state generate the same identifiers, hence a generator of random identifiers should be
+    /// set if identifiers must be unique across replicas.
     ///
     /// # Example
     /// ```
     /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
-    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);
-    /// let anchors = replica.get_anchors();
-    /// assert!(anchors.is_empty());
-    /// let block = replica.commit(None).unwrap().unwrap();
-    /// let anchors = replica.get_anchors();
-    /// assert!(anchors.len() == 1);
-    /// assert!(anchors.contains(&block));
+    /// let replicas: Vec<Melda> = (0..2).map(|_| {
+    ///     let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    ///     Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt")
+    /// }).collect();
+    /// for replica in &replicas {
+    ///     let items: Vec<Value> = (0..3).map(|_| json!({ "_id" : replica.generate_id() })).collect();
+    ///     replica.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone());
+    ///     replica.commit(None);
+    /// }
+    /// // Replicas with the same state generate the same identifiers
+    /// assert_eq!(replicas[0].get_anchors(), replicas[1].get_anchors());
+    /// assert_eq!(replicas[0].generate_id(), replicas[1].generate_id());
+    /// assert_ne!(replicas[0].generate_id(), replicas[0].generate_id());
+    /// let mut next = 0;
+    /// replicas[0].set_id_generator(Some(Box::new(move || { next += 1; format!("item{}", next) })));
+    /// assert_eq!(replicas[0].generate_id(), "item1");
+    /// assert_eq!(replicas[0].generate_id(), "item2");
     /// ```
-    pub fn get_anchors(&self) -> BTreeSet<String> {
-        let blocks_r = self.blocks.read().unwrap();
-        // Return the identifiers of all blocks which are not referenced as parents
-        let mut anchors: BTreeSet<String> = blocks_r
-            .iter()
-            .filter(|(_, block)| block.read().unwrap().status == Status::ValidAndApplied)
-            .map(|(k, _)| k.clone())
-            .collect();
-        blocks_r
-            .iter()
-            .filter(|(_, block)| block.read().unwrap().status == Status::ValidAndApplied)
-            .for_each(|(_, b)| {
-                let block_r = b.read().unwrap();
-                if let Some(pr) = &block_r.parents {
-                    for p in pr {
-                        anchors.remove(p);
-                    }
-                }
-            });
-        anchors
+    pub fn generate_id(&self) -> String {
+        self.generate_id_for("")
     }
 
-    /// Reloads the CRDT (reloads all delta blocks)
+    // Returns a new identifier (see generate_id), the default identifiers are also derived from
+    // the given content
+    pub(crate) fn generate_id_for(&self, content: &str) -> String {
+        if let Some(generator) = self.id_generator.lock().unwrap().as_mut() {
+            return generator();
+        }
+        let count = self.generated_ids.fetch_add(1, Ordering::SeqCst);
+        // The anchors are computed without holding the lock, since blocks are locked before it
+        let cached = self.anchors_digest.lock().unwrap().clone();
+        let anchors = match cached {
+            Some(anchors) => anchors,
+            None => {
+                let anchors: Vec<String> = self.get_anchors().into_iter().collect();
+                let anchors = digest_string(&anchors.join(","));
+                *self.anchors_digest.lock().unwrap() = Some(anchors.clone());
+                anchors
+            }
+        };
+        digest_string(&format!("{}:{}:{}", anchors, count, content))
+    }
+
+    /// Sets (or removes) the codec applied to the payload of each object stored in the data
+    /// packs (see PayloadCodec). The codec is used for packs written (and values read) after
+    /// it has been set, hence it should be set right after the replica has been created. Since
+    /// digests are computed before encoding, all replicas sharing the storage (or melding with
+    /// this replica) must use the same codec.
+    ///
+    /// # Arguments
+    ///
+    /// * `codec` - The codec, None to store payloads as they are
     ///
     /// # Example
     /// ```
-    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use melda::{melda::Melda, adapter::{Adapter, PayloadCodec}, memoryadapter::MemoryAdapter};
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
+    /// struct Reversed;
+    /// impl PayloadCodec for Reversed {
+    ///     fn encode(&self, data: &[u8]) -> Vec<u8> {
+    ///         data.iter().rev().cloned().collect()
+    ///     }
+    ///     fn decode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    ///         Ok(data.iter().rev().cloned().collect())
+    ///     }
+    /// }
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block = replica.commit(None).unwrap().unwrap();
-    /// let anchors = replica.get_anchors();
-    /// assert!(anchors.len() == 1);
-    /// assert!(anchors.contains(&block));
-    /// replica.reload();
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// ```    
-    pub fn reload(&self) -> Result<()> {
-        // Check that stage is empty, otherwise fail (user must unstage explicity if necessary)
-        if !self.stage.read().unwrap().is_empty() {
-            bail!("stage_not_empty")
-        }
-        // Clear the documents
-        self.documents
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// replica.set_payload_codec(Some(Arc::new(Reversed)));
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "key" : "somevalue" } ] }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// replica.commit(None);
+    /// let packs = adapter.read().unwrap().list_objects(".pack").unwrap();
+    /// let pack = adapter.read().unwrap().read_object(&(packs[0].clone() + ".pack"), 0, 0).unwrap();
+    /// assert!(String::from_utf8_lossy(&pack).contains("eulavemos"));
+    /// let other = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// other.set_payload_codec(Some(Arc::new(Reversed)));
+    /// assert_eq!(other.read().unwrap(), replica.read().unwrap());
+    /// assert!(other.verify().unwrap().is_valid());
+    /// ```
+    pub fn set_payload_codec(&self, codec: Option<Arc<dyn PayloadCodec>>) {
+        self.data
             .write()
-            .expect("failed_to_acquire_documents_for_writing")
-            .clear();
-        // Read block list
-        let data = self.data.read().expect("cannot_acquire_data_for_reading");
-        let list_str = data.list_raw_items(DELTA_EXTENSION)?;
-        drop(data);
-        self.blocks.write().unwrap().clear();
-        // Reload data storage
-        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
-        data.reload()?;
-        drop(data);
-        // Clear the blocks
-        self.blocks.write().unwrap().clear();
-        // Fetch and parse blocks
-        if !list_str.is_empty() {
-            for i in &list_str {
-                if let Ok(block) = self.fetch_raw_block(i) {
-                    if let Ok(block) = self.parse_raw_block(i.to_string(), block) {
-                        self.blocks
-                            .write()
-                            .unwrap()
-                            .insert(i.to_string(), RwLock::new(block));
-                    }
-                }
-            }
+            .expect("cannot_acquire_data_for_writing")
+            .set_codec(codec);
+    }
+
+    /// Limits the number of concurrent operations on the adapter (None, the default, removes
+    /// the limit): parallel sections (for example in reload and read) wait until fewer than max
+    /// operations are in progress, which avoids exhausting file descriptors or being throttled
+    /// by remote storages. A limit of 0 is rejected with an invalid_max_concurrency error.
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - The maximum number of concurrent operations
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use serde_json::{Map, Value,json};
+    /// // Records the highest number of concurrent reads
+    /// struct Counting {
+    ///     backend: MemoryAdapter,
+    ///     reading: AtomicUsize,
+    ///     highest: Arc<AtomicUsize>,
+    /// }
+    /// impl Adapter for Counting {
+    ///     fn read_object(&self, key: &str, offset: usize, length: usize) -> anyhow::Result<Vec<u8>> {
+    ///         let reading = self.reading.fetch_add(1, Ordering::SeqCst) + 1;
+    ///         self.highest.fetch_max(reading, Ordering::SeqCst);
+    ///         std::thread::sleep(std::time::Duration::from_millis(1));
+    ///         let result = self.backend.read_object(key, offset, length);
+    ///         self.reading.fetch_sub(1, Ordering::SeqCst);
+    ///         result
+    ///     }
+    ///     fn write_object(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+    ///         self.backend.write_object(key, data)
+    ///     }
+    ///     fn list_objects(&self, ext: &str) -> anyhow::Result<Vec<String>> {
+    ///         self.backend.list_objects(ext)
+    ///     }
+    /// }
+    /// let highest = Arc::new(AtomicUsize::new(0));
+    /// let adapter : Box<dyn Adapter> = Box::new(Counting { backend: MemoryAdapter::new(), reading: AtomicUsize::new(0), highest: highest.clone() });
+    /// // Objects are read one at a time from the adapter (through the index of each pack)
+    /// let config = MeldaConfig { index_threshold: 0, data_cache: 1, ..MeldaConfig::default() };
+    /// let replica = Melda::new_with_config(Arc::new(RwLock::new(adapter)), config).expect("cannot_initialize_crdt");
+    /// assert!(replica.set_max_concurrency(Some(0)).is_err());
+    /// replica.set_max_concurrency(Some(2)).unwrap();
+    /// for i in 0..8 {
+    ///     let items: Vec<Value> = (0..16).map(|j| json!({ "_id" : format!("{}-{}", i, j), "value" : j })).collect();
+    ///     replica.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// replica.reload().unwrap();
+    /// assert_eq!(replica.read().unwrap()["items\u{266D}"].as_array().unwrap().len(), 16);
+    /// assert!(highest.load(Ordering::SeqCst) <= 2);
+    /// replica.set_max_concurrency(None).unwrap();
+    /// ```
+    pub fn set_max_concurrency(&self, max: Option<usize>) -> Result<()> {
+        if max == Some(0) {
+            bail!("invalid_max_concurrency");
         }
-        // Mark valid blocks
-        self.mark_valid_blocks();
-        // Apply all valid blocks
-        self.blocks.read().unwrap().iter().for_each(|(_, block)| {
-            let status = block.read().unwrap().status;
-            if status == Status::Valid {
-                let block_r = block.read().unwrap();
-                if self.apply_block(&block_r).is_ok() {
-                    drop(block_r);
-                    let mut block_w = block.write().unwrap();
-                    block_w.status = Status::ValidAndApplied;
-                    // We can drop the changes vector
-                    block_w.changes = None;
-                }
-            }
-        });
+        self.data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .set_max_concurrency(max);
         Ok(())
     }
 
-    /// Loads newly available blocks
+    /// Sets (or removes) the receiver of operation timings (see Metrics)
+    ///
+    /// # Arguments
+    ///
+    /// * `metrics` - The receiver of the timings, None to stop measuring
+    pub fn set_metrics(&self, metrics: Option<Arc<dyn Metrics>>) {
+        *self
+            .metrics
+            .write()
+            .expect("cannot_acquire_metrics_for_writing") = metrics;
+    }
+
+    /// Sets (or removes) the receiver of the progress of reload, meld and verify (see
+    /// ProgressReporter)
+    ///
+    /// # Arguments
+    ///
+    /// * `reporter` - The receiver of the progress, None to stop reporting
     ///
     /// # Example
     /// ```
-    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use melda::{melda::{Melda, ProgressOperation, ProgressReporter}, adapter::Adapter, memoryadapter::MemoryAdapter};
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
+    /// #[derive(Default)]
+    /// struct Progress(Mutex<Vec<(ProgressOperation, usize, usize)>>);
+    /// impl ProgressReporter for Progress {
+    ///     fn on_progress(&self, operation: ProgressOperation, done: usize, total: usize) {
+    ///         self.0.lock().unwrap().push((operation, done, total));
+    ///     }
+    /// }
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block = replica.commit(None).unwrap().unwrap();
-    /// let anchors = replica.get_anchors();
-    /// assert!(anchors.len() == 1);
-    /// assert!(anchors.contains(&block));
-    /// replica.refresh();
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// ```    
-    pub fn refresh(&self) -> Result<()> {
-        // 1. Save stage
-        let stage = self.stage()?;
-        // 2. Unstage
-        self.unstage()?;
-        // 3. Get new list of blocks
-        let data_r = self.data.read().expect("cannot_acquire_data_for_writing");
-        let list_str = data_r.list_raw_items(DELTA_EXTENSION)?;
-        drop(data_r);
-        // 4. Refresh data storage
-        let mut data_w = self.data.write().expect("cannot_acquire_data_for_writing");
-        data_w.refresh()?;
-        drop(data_w);
-        // 5. Load new blocks
-        if !list_str.is_empty() {
-            for i in &list_str {
-                let is_new_block = !self
-                    .blocks
-                    .read()
-                    .expect("cannot_acquire_blocks_for_reading")
-                    .contains_key(i);
-                if is_new_block {
-                    if let Ok(block) = self.fetch_raw_block(i) {
-                        if let Ok(block) = self.parse_raw_block(i.to_string(), block) {
-                            self.blocks
-                                .write()
-                                .expect("cannot_acquire_blocks_for_writing")
-                                .insert(i.to_string(), RwLock::new(block));
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// for i in 0..3 {
+    ///     replica.update(json!({ "somekey" : i }).as_object().unwrap().clone());
+    ///     replica.commit(None);
+    /// }
+    /// let progress = Arc::new(Progress::default());
+    /// replica.set_progress_reporter(Some(progress.clone()));
+    /// replica.reload().unwrap();
+    /// let reported = progress.0.lock().unwrap().clone();
+    /// assert!(reported.contains(&(ProgressOperation::LoadBlocks, 3, 3)));
+    /// assert!(reported.contains(&(ProgressOperation::ApplyBlocks, 3, 3)));
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica2.set_progress_reporter(Some(progress.clone()));
+    /// let copied = replica2.meld(&replica).unwrap();
+    /// assert_eq!(progress.0.lock().unwrap().last(), Some(&(ProgressOperation::Meld, copied.len(), copied.len())));
+    /// replica2.set_progress_reporter(None);
+    /// replica2.verify().unwrap();
+    /// assert_eq!(progress.0.lock().unwrap().last().unwrap().0, ProgressOperation::Meld);
+    /// ```
+    pub fn set_progress_reporter(&self, reporter: Option<Arc<dyn ProgressReporter>>) {
+        *self
+            .progress
+            .write()
+            .expect("cannot_acquire_progress_for_writing") = reporter;
+    }
+
+    // Returns the progress reporter (if any)
+    fn progress_reporter(&self) -> Option<Arc<dyn ProgressReporter>> {
+        self.progress
+            .read()
+            .expect("cannot_acquire_progress_for_reading")
+            .clone()
+    }
+
+    /// Sets (or removes) the flag used to interrupt reload, reload_until, meld and
+    /// meld_verified: once the flag is set, the operation in progress stops before processing
+    /// the next block (or item) and returns a cancelled error. The flag is not reset, hence
+    /// further operations are cancelled as well until it is cleared.
+    ///
+    /// A cancelled reload (or reload_until) leaves the instance empty, as after clear(false):
+    /// delta blocks, objects and pins are dropped (the stored data is untouched), reload must be
+    /// called again to restore the state. A cancelled meld (or meld_verified) leaves the
+    /// instance untouched: the items copied so far remain in the adapter, and are loaded by a
+    /// subsequent refresh (blocks whose packs were not copied yet are skipped until then).
+    ///
+    /// # Arguments
+    ///
+    /// * `flag` - The cancellation flag, None to disable cancellation
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, ProgressOperation, ProgressReporter}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock, atomic::{AtomicBool, Ordering}};
+    /// use serde_json::{Map, Value,json};
+    /// // Requests the cancellation as soon as the first block has been loaded
+    /// struct Shutdown(Arc<AtomicBool>);
+    /// impl ProgressReporter for Shutdown {
+    ///     fn on_progress(&self, operation: ProgressOperation, done: usize, total: usize) {
+    ///         self.0.store(true, Ordering::SeqCst);
+    ///     }
+    /// }
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// for i in 0..3 {
+    ///     replica.update(json!({ "somekey" : i }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// let expected = replica.read().unwrap();
+    /// let flag = Arc::new(AtomicBool::new(false));
+    /// replica.set_cancellation_flag(Some(flag.clone()));
+    /// replica.set_progress_reporter(Some(Arc::new(Shutdown(flag.clone()))));
+    /// assert_eq!(replica.reload().unwrap_err().to_string(), "cancelled");
+    /// assert!(replica.get_all_objects().is_empty());
+    /// assert!(replica.get_anchors().is_empty());
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// replica2.set_cancellation_flag(Some(flag.clone()));
+    /// assert!(replica2.meld(&replica).is_err());
+    /// // Clear the flag to reload again
+    /// replica.set_progress_reporter(None);
+    /// flag.store(false, Ordering::SeqCst);
+    /// replica.reload().unwrap();
+    /// assert_eq!(replica.read().unwrap(), expected);
+    /// replica2.meld(&replica).unwrap();
+    /// replica2.refresh().unwrap();
+    /// assert_eq!(replica2.read().unwrap(), expected);
+    /// ```
+    pub fn set_cancellation_flag(&self, flag: Option<Arc<AtomicBool>>) {
+        *self
+            .cancellation
+            .write()
+            .expect("cannot_acquire_cancellation_for_writing") = flag;
+    }
+
+    // Returns true if the cancellation flag is set
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .read()
+            .expect("cannot_acquire_cancellation_for_reading")
+            .as_ref()
+            .is_some_and(|f| f.load(Ordering::SeqCst))
+    }
+
+    // Drops the partially loaded state of a cancelled reload and returns a cancelled error
+    fn cancel_reload(&self) -> Result<()> {
+        self.clear(false)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!("reload cancelled");
+        bail!("cancelled")
+    }
+
+    // Drops the state of pruned histories (see prune_history), whenever the revision trees are
+    // rebuilt with the full history
+    fn clear_pruned(&self) {
+        self.pruned_revisions.lock().unwrap().clear();
+        self.array_descriptor_bases.lock().unwrap().clear();
+    }
+
+    // Starts timing an operation (only if metrics are enabled)
+    fn start_timer(&self) -> Option<(Arc<dyn Metrics>, Instant)> {
+        self.metrics
+            .read()
+            .expect("cannot_acquire_metrics_for_reading")
+            .clone()
+            .map(|metrics| (metrics, Instant::now()))
+    }
+
+    // Records the update of an object (see update_object) in the given stage, returns the
+    // recorded revision (if the object has changed)
+    fn update_object_with_optional_digest(
+        &self,
+        uuid: &str,
+        obj: Map<String, Value>,
+        digest: Option<String>,
+        stage: &RwLock<Vec<Change>>,
+    ) -> Result<Option<Revision>> {
+        // Obtain the revision tree (either an existing one of a new one)
+        let docs_r = self
+            .documents
+            .read()
+            .expect("cannot_acquire_documents_for_reading");
+        if let Some(rt) = docs_r.get(uuid) {
+            // Existing object
+            let rt_r = rt.read().expect("cannot_acquire_revision_tree_for_reading");
+            let mut recorded = None;
+            if let Some(winning_revision) = rt_r.get_winner() {
+                // Digest of the input object (only computed if it can be used)
+                let cache_enabled = self.digest_cache.lock().unwrap().is_some();
+                let input_digest = match digest {
+                    Some(d) => Some(d),
+                    None if cache_enabled => Some(digest_object(&obj)?),
+                    None => None,
+                };
+                if let Some(input_digest) = &input_digest {
+                    // Short-circuit if the content has not changed since the last update
+                    // (the entry is only valid if the winning revision has not changed)
+                    if let Some(cache) = self.digest_cache.lock().unwrap().as_ref() {
+                        if let Some((rev, d)) = cache.get(uuid) {
+                            if rev == winning_revision && d == input_digest {
+                                return Ok(None);
+                            }
                         }
                     }
+                    if !is_array_descriptor(uuid) && input_digest.eq(&winning_revision.digest) {
+                        return Ok(None);
+                    }
+                }
+                // If its an array descriptor first need to compute the delta
+                // If create_delta_array_descriptor returns None it means that there are
+                // no differences between the current array and the new one
+                let object = if is_array_descriptor(uuid) {
+                    self.create_delta_array_descriptor(obj, &rt_r).unwrap()
+                } else {
+                    Some(obj)
+                };
+                // Now compute the digest to see if the object has changed
+                // An object can be None if its an "empty" delta array descriptor
+                if let Some(object) = object {
+                    let digest = match &input_digest {
+                        Some(d) if !is_array_descriptor(uuid) => d.clone(),
+                        _ => digest_object(&object).unwrap(), // Digest of the current object
+                    };
+                    if digest.ne(&winning_revision.digest) {
+                        // Digest is different, there was an update
+                        let rev = Revision::new_updated_with_tail_length(
+                            digest,
+                            winning_revision,
+                            self.revision_tail_length,
+                        );
+                        let winning_revision = winning_revision.clone();
+                        let mut data_w =
+                            self.data.write().expect("cannot_acquire_data_for_writing");
+                        data_w.write_object(&rev, object)?;
+                        drop(data_w);
+                        stage.write().unwrap().push(Change(
+                            uuid.to_string(),
+                            rev.clone(),
+                            Some(winning_revision),
+                        ));
+                        recorded = Some(rev);
+                    }
+                }
+                if let Some(input_digest) = input_digest {
+                    if let Some(cache) = self.digest_cache.lock().unwrap().as_mut() {
+                        let winner = match &recorded {
+                            Some(rev) => rev.clone(),
+                            None => rt_r.get_winner().expect("object_has_no_winner").clone(),
+                        };
+                        cache.insert(uuid.to_string(), (winner, input_digest));
+                    }
                 }
             }
+            Ok(recorded)
+        } else {
+            // Newly created object
+            drop(docs_r);
+            // No winning revision, assume that its a new object
+            self.create_object_impl(uuid, obj, stage)
         }
-        // 6. Turn invalid blocks into unknown status blocks
-        let blocks_r = self
-            .blocks
-            .read()
-            .expect("cannot_acquire_blocks_for_reading");
-        blocks_r.par_iter().for_each(|(_, block)| {
-            let status = block
-                .read()
-                .expect("cannot_acquire_block_for_reading")
-                .status;
-            if status == Status::Invalid {
-                block
-                    .write()
-                    .expect("cannot_acquire_block_for_writing")
-                    .status = Status::Unknown;
-            }
-        });
-        drop(blocks_r);
-        // 7. Mark valid blocks
-        self.mark_valid_blocks();
-        // 8. Apply all valid blocks
-        let blocks_r = self
-            .blocks
-            .read()
-            .expect("cannot_acquire_blocks_for_reading");
-        blocks_r.iter().for_each(|(_, block)| {
-            let block_r = block.read().expect("cannot_acquire_block_for_reading");
-            let status = block
+    }
+
+    fn read_object(&self, uuid: &str, rt: &RevisionTree) -> Result<Map<String, Value>> {
+        let winner = rt.get_winner().expect("object_has_no_winner");
+        if is_array_descriptor(uuid) {
+            let order = self.get_merged_order(rt)?;
+            Ok(ArrayDescriptor::new_from_order(order).to_json_object())
+        } else {
+            self.data
                 .read()
-                .expect("cannot_acquire_block_for_reading")
-                .status;
-            if status == Status::Valid && self.apply_block(&block_r).is_ok() {
-                drop(block_r);
-                let mut block_w = block.write().expect("cannot_acquire_block_for_writing");
-                block_w.status = Status::ValidAndApplied;
-                // We can drop the changes vector
-                block_w.changes = None;
-            }
-        });
-        drop(blocks_r);
-        // 9. Re-apply stage
-        self.replay_stage(&stage)?;
-        Ok(())
+                .expect("cannot_acquire_data_for_reading")
+                .read_object(winner)
+        }
     }
 
-    /// Reloads the CRDT until the given block
+    /// Records the deletion of an object
     ///
     /// # Arguments
     ///
-    /// * `block` - Block identifier
+    /// * `uuid` - The unique identifier of the object
     ///
     /// # Example
     /// ```
@@ -859,109 +2001,4668 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1, 2, 3, 4 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// assert!(replica.get_all_objects().contains("myobject"));    
     /// replica.delete_object("myobject");
     /// assert!(replica.get_all_objects().contains("myobject"));
     /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", winner);
     /// let value = replica.get_value("myobject", &winner);
     /// assert!(value.is_ok());
     /// assert!(value.unwrap().contains_key("_deleted"));
-    /// let info = json!({ "author" : "Some user", "date" : "2022-05-23 13:47:00CET" }).as_object().unwrap().clone();
-    /// replica.commit(Some(info));
-    /// replica.reload_until(&block_id);
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey\u{266D}" : { "_id": "1", "key" : "alpha" }}).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// let readback = replica.read().unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":{\"_id\":\"1\",\"key\":\"alpha\"}}", content);
+    /// replica.delete_object("1");
+    /// let readback = replica.read().unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\"}", content);
     /// ```
-    pub fn reload_until(&self, block_id: &str) -> Result<()> {
-        let stage_r = self.stage.read().expect("cannot_acquire_stage_for_reading");
-        let mut documents_w = self
-            .documents
-            .write()
-            .expect("cannot_acquire_documents_for_writing");
-        // Ensure that the stage is empty
-        if !stage_r.is_empty() {
-            bail!("stage_not_empty")
+    pub fn delete_object(&self, uuid: &str) -> Result<()> {
+        self.autocommit(|| {
+            self.staged(|stage| self.delete_object_with_optional_meta(uuid, None, stage))
+        })
+    }
+
+    /// Records the deletion of an object, along with a metadata object (for example the reason
+    /// of the deletion). The metadata is stored with the deleted revision and is returned
+    /// by get_value (along with the _deleted field)
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The unique identifier of the object
+    /// * `meta` - The metadata JSON object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1, 2, 3, 4 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// replica.commit(None);
+    /// let meta = json!({ "reason" : "merged into another" }).as_object().unwrap().clone();
+    /// replica.delete_object_with_meta("myobject", meta);
+    /// replica.commit(None);
+    /// let replica = Melda::new(adapter).expect("cannot_initialize_crdt");
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// let value = replica.get_value("myobject", &winner).unwrap();
+    /// assert_eq!(value.get("_deleted"), Some(&json!(true)));
+    /// assert_eq!(value.get("reason"), Some(&json!("merged into another")));
+    /// ```
+    pub fn delete_object_with_meta(&self, uuid: &str, meta: Map<String, Value>) -> Result<()> {
+        self.autocommit(|| {
+            self.staged(|stage| self.delete_object_with_optional_meta(uuid, Some(meta), stage))
+        })
+    }
+
+    // Records the deletion of an object (see delete_object_with_meta) in the given stage
+    fn delete_object_with_optional_meta(
+        &self,
+        uuid: &str,
+        meta: Option<Map<String, Value>>,
+        stage: &RwLock<Vec<Change>>,
+    ) -> Result<()> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("cannot_acquire_documents_for_reading");
+        if let Some(rt) = docs_r.get(uuid) {
+            let rt_r = rt.read().expect("cannot_acquire_revision_tree_for_reading");
+            if let Some(winning_revision) = rt_r.get_winner() {
+                if !winning_revision.is_deleted() && !winning_revision.is_resolved() {
+                    let rev = match meta {
+                        Some(meta) => {
+                            let content = serde_json::to_string(&meta)?;
+                            let rev = Revision::new_updated_with_tail_length(
+                                DELETED_META_PREFIX.to_string() + &digest_string(&content),
+                                winning_revision,
+                                self.revision_tail_length,
+                            );
+                            self.data
+                                .write()
+                                .expect("cannot_acquire_data_for_writing")
+                                .write_object(&rev, meta)?;
+                            rev
+                        }
+                        None => Revision::new_updated_with_tail_length(
+                            DELETED_HASH,
+                            winning_revision,
+                            self.revision_tail_length,
+                        ),
+                    };
+                    let winning_revision = winning_revision.clone();
+                    stage.write().unwrap().push(Change(
+                        uuid.to_string(),
+                        rev,
+                        Some(winning_revision),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Renames an object, preserving its history. The revisions of the object are recorded
+    /// under the new identifier (so that the rename is replicated along with the history), while
+    /// the old identifier is deleted with a metadata object whose _renamed_to field contains the
+    /// new identifier (forwarding tombstone). References to the old identifier (for example in
+    /// parent objects) are not rewritten: they must be updated by the caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `old_uuid` - The current identifier of the object
+    /// * `new_uuid` - The new identifier of the object (must not exist)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "key" : "alpha" }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// replica.commit(None);
+    /// let object = json!({ "key" : "beta" }).as_object().unwrap().clone();
+    /// replica.update_object("myobject", object);
+    /// replica.commit(None);
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// // Unstaging the rename restores the old identifier
+    /// assert!(replica.rename_object("myobject", "renamed").is_ok());
+    /// assert!(replica.get_all_objects().contains("renamed"));
+    /// replica.unstage().unwrap();
+    /// assert!(!replica.has_staging());
+    /// assert!(!replica.get_all_objects().contains("renamed"));
+    /// assert_eq!(winner, replica.get_winner("myobject").unwrap());
+    /// assert_eq!(replica.get_value("myobject", &winner).unwrap().get("key"), Some(&json!("beta")));
+    /// assert!(replica.rename_object("myobject", "renamed").is_ok());
+    /// assert!(replica.rename_object("myobject", "other").is_err());
+    /// assert!(replica.rename_object("renamed", "myobject").is_err());
+    /// replica.commit(None);
+    /// let replica = Melda::new(adapter).expect("cannot_initialize_crdt");
+    /// assert_eq!(winner, replica.get_winner("renamed").unwrap());
+    /// let parent = replica.get_parent_revision("renamed", &winner).unwrap().unwrap();
+    /// assert_eq!(replica.get_value("renamed", &parent).unwrap().get("key"), Some(&json!("alpha")));
+    /// let tombstone = replica.get_winner("myobject").unwrap();
+    /// let value = replica.get_value("myobject", &tombstone).unwrap();
+    /// assert_eq!(value.get("_renamed_to"), Some(&json!("renamed")));
+    /// ```
+    pub fn rename_object(&self, old_uuid: &str, new_uuid: &str) -> Result<()> {
+        self.autocommit(|| self.rename_object_impl(old_uuid, new_uuid))
+    }
+
+    // Renames an object (see rename_object)
+    fn rename_object_impl(&self, old_uuid: &str, new_uuid: &str) -> Result<()> {
+        if is_array_descriptor(old_uuid) || is_array_descriptor(new_uuid) {
+            bail!("cannot_rename_array_descriptor");
+        }
+        let docs_r = self
+            .documents
+            .read()
+            .expect("cannot_acquire_documents_for_reading");
+        if docs_r.contains_key(new_uuid) {
+            bail!("object_already_exists");
+        }
+        let history: Vec<Change> = {
+            let rt_r = docs_r
+                .get(old_uuid)
+                .ok_or_else(|| anyhow!("unknown_object"))?
+                .read()
+                .expect("cannot_acquire_revision_tree_for_reading");
+            match rt_r.get_winner() {
+                Some(winner) if !winner.is_deleted() => {}
+                _ => bail!("object_deleted"),
+            }
+            rt_r.get_revisions()
+                .iter()
+                .map(|(rev, prev)| Change(new_uuid.to_string(), rev.clone(), prev.clone()))
+                .collect()
+        };
+        drop(docs_r);
+        let mut meta = Map::new();
+        meta.insert(
+            RENAMED_TO_FIELD.to_string(),
+            Value::from(new_uuid.to_string()),
+        );
+        // The history under the new identifier and the forwarding tombstone are staged together
+        // (hence unstage restores the old identifier)
+        self.staged(|stage| {
+            stage.write().unwrap().extend(history);
+            self.delete_object_with_optional_meta(old_uuid, Some(meta), stage)
+        })
+    }
+
+    /// Prunes the history of an object which is not in conflict, keeping only the last keep_last
+    /// revisions (including the winning revision) of the revision tree. Revisions which are not
+    /// ancestors of the winning revision are also removed. Pinned revisions (see pin_revision)
+    /// are never removed, hence the history is kept back to the oldest pinned revision. For array descriptors the order
+    /// corresponding to the newest removed revision is materialized, so that the current order can
+    /// still be rebuilt. Pruning only affects the in-memory state: the payloads remain in the data
+    /// packs (which are immutable and may be shared with other objects) and the full history is
+    /// restored by reload. Returns the number of removed revisions.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    /// * `keep_last` - The number of revisions to keep (at least one)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// for i in 0..5 {
+    ///     let items: Vec<Value> = (0..=i).map(|j| json!({ "_id" : j.to_string() })).collect();
+    ///     let object = json!({ "items\u{266D}" : items }).as_object().unwrap().clone();
+    ///     replica.update(object);
+    ///     replica.commit(None);
+    /// }
+    /// let before = replica.read().unwrap();
+    /// let descriptor = replica.dump_flat().unwrap().get("\u{221A}").unwrap().get("items\u{266D}").unwrap().as_str().unwrap().to_string();
+    /// let winner = replica.get_winner(&descriptor).unwrap();
+    /// assert_eq!(replica.prune_history(&descriptor, 2).unwrap(), 3);
+    /// assert_eq!(replica.prune_history(&descriptor, 2).unwrap(), 0);
+    /// assert!(replica.prune_history(&descriptor, 0).is_err());
+    /// assert_eq!(winner, replica.get_winner(&descriptor).unwrap());
+    /// assert_eq!(before, replica.read().unwrap());
+    /// let items: Vec<Value> = (0..6).map(|j| json!({ "_id" : j.to_string() })).collect();
+    /// let object = json!({ "items\u{266D}" : items }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// let readback = replica.read().unwrap();
+    /// assert_eq!(readback.get("items\u{266D}").unwrap().as_array().unwrap().len(), 6);
+    /// // Reloading restores the full history (the pruning state is dropped)
+    /// replica.unstage().unwrap();
+    /// replica.reload().unwrap();
+    /// assert_eq!(before, replica.read().unwrap());
+    /// assert!(replica.missing_revisions().is_empty());
+    /// assert_eq!(replica.prune_history(&descriptor, 2).unwrap(), 3);
+    /// ```
+    pub fn prune_history(&self, uuid: &str, keep_last: usize) -> Result<usize> {
+        if keep_last == 0 {
+            bail!("invalid_keep_last");
+        }
+        let docs_r = self
+            .documents
+            .read()
+            .expect("cannot_acquire_documents_for_reading");
+        let rt = docs_r.get(uuid).ok_or_else(|| anyhow!("unknown_object"))?;
+        let mut rt_w = rt
+            .write()
+            .expect("cannot_acquire_revision_tree_for_writing");
+        if rt_w.get_leafs().len() > 1 {
+            bail!("object_in_conflict");
+        }
+        // Determine the revisions to keep (the last ones in the history of the winner)
+        let winner = rt_w
+            .get_winner()
+            .ok_or_else(|| anyhow!("no_winner"))?
+            .clone();
+        // Pinned revisions (and the revisions between them and the winner) are also kept
+        let revisions = rt_w.get_all_revs();
+        let mut pins: HashSet<Revision> = self
+            .pinned_revisions
+            .lock()
+            .unwrap()
+            .get(uuid)
+            .map(|p| {
+                p.iter()
+                    .filter_map(|r| Revision::from(r).ok())
+                    .filter(|r| revisions.contains(r))
+                    .collect()
+            })
+            .unwrap_or_default();
+        drop(revisions);
+        let mut history = vec![];
+        let mut current = Some(&winner);
+        while let Some(revision) = current {
+            history.push(revision.clone());
+            current = rt_w.get_parent(revision);
+        }
+        let mut keep = keep_last.min(history.len());
+        for pin in &pins {
+            // Pinned revisions which are not ancestors of the winner are kept along with
+            // the revisions between them and the history of the winner
+            let mut revision = Some(pin);
+            while let Some(r) = revision {
+                if let Some(position) = history.iter().position(|h| h == r) {
+                    keep = keep.max(position + 1);
+                    break;
+                }
+                revision = rt_w.get_parent(r);
+            }
+        }
+        let mut kept: HashSet<Revision> = history[..keep].iter().cloned().collect();
+        for pin in pins.drain() {
+            let mut revision = Some(&pin);
+            while let Some(r) = revision.filter(|r| !kept.contains(*r)) {
+                kept.insert(r.clone());
+                revision = rt_w.get_parent(r);
+            }
+        }
+        let current = history.get(keep);
+        let removed: Vec<(Revision, Option<Revision>)> = rt_w
+            .get_revisions()
+            .iter()
+            .filter(|(revision, _)| !kept.contains(revision))
+            .cloned()
+            .collect();
+        if removed.is_empty() {
+            return Ok(0);
+        }
+        if is_array_descriptor(uuid) {
+            // Materialize the base order (used instead of the removed history)
+            if let Some(base) = current {
+                let order = self.rebuild_array_order(base, &rt_w)?;
+                self.array_descriptor_bases
+                    .lock()
+                    .unwrap()
+                    .insert(base.clone(), order);
+            }
+        }
+        let mut pruned = self.pruned_revisions.lock().unwrap();
+        let pruned = pruned.entry(uuid.to_string()).or_default();
+        for (revision, parent) in &removed {
+            rt_w.remove(revision.clone(), parent.clone());
+            pruned.insert(revision.clone());
+        }
+        Ok(removed.len())
+    }
+
+    /// Pins a revision of an object, so that it is treated as live by prune_history
+    /// (the revision is never removed). Repacking never reclaims the
+    /// payloads of the revisions. The set of pinned revisions is stored immediately in the
+    /// adapter (it does not need to be committed) and survives reloads. Pins are transferred
+    /// by meld: the pins of several replicas are merged.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    /// * `revision` - The revision to pin
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let mut revisions = vec![];
+    /// for i in 0..5u32 {
+    ///     let object = json!({ "value" : i }).as_object().unwrap().clone();
+    ///     replica.update_object("myobject", object);
+    ///     revisions.push(replica.get_winner("myobject").unwrap());
+    ///     replica.commit(None);
+    /// }
+    /// replica.pin_revision("myobject", &revisions[1]).unwrap();
+    /// assert!(replica.pin_revision("myobject", "1-notarevision").is_err());
+    /// assert!(replica.pin_revision("unknown", &revisions[1]).is_err());
+    /// assert_eq!(replica.prune_history("myobject", 1).unwrap(), 1);
+    /// let value = replica.get_value("myobject", &revisions[1]).unwrap();
+    /// assert_eq!(value.get("value").unwrap(), 1);
+    /// let reloaded = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert!(reloaded.pinned_revisions().get("myobject").unwrap().contains(&revisions[1]));
+    /// assert!(replica.unpin_revision("myobject", &revisions[1]).unwrap());
+    /// assert!(!replica.unpin_revision("myobject", &revisions[1]).unwrap());
+    /// assert_eq!(replica.prune_history("myobject", 1).unwrap(), 3);
+    /// let reloaded = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert!(reloaded.pinned_revisions().is_empty());
+    /// // Pins written concurrently by replicas sharing the adapter are merged
+    /// let other = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// other.pin_revision("myobject", &revisions[4]).unwrap();
+    /// reloaded.pin_revision("myobject", &revisions[3]).unwrap();
+    /// let merged = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert_eq!(merged.pinned_revisions().get("myobject").unwrap().len(), 2);
+    /// ```
+    pub fn pin_revision(&self, uuid: &str, revision: &str) -> Result<()> {
+        let rev = Revision::from(revision)?;
+        let docs_r = self
+            .documents
+            .read()
+            .expect("cannot_acquire_documents_for_reading");
+        let rt = docs_r.get(uuid).ok_or_else(|| anyhow!("unknown_object"))?;
+        if !rt
+            .read()
+            .expect("cannot_acquire_revision_tree_for_reading")
+            .get_all_revs()
+            .contains(&rev)
+        {
+            bail!("unknown_revision");
+        }
+        drop(docs_r);
+        let mut pins = self.pinned_revisions.lock().unwrap();
+        if pins
+            .entry(uuid.to_string())
+            .or_default()
+            .insert(revision.to_string())
+        {
+            self.store_pins(&pins)?;
+        }
+        Ok(())
+    }
+
+    /// Unpins a revision of an object (see pin_revision). Returns true if the revision was pinned.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    /// * `revision` - The revision to unpin
+    pub fn unpin_revision(&self, uuid: &str, revision: &str) -> Result<bool> {
+        let mut pins = self.pinned_revisions.lock().unwrap();
+        let removed = match pins.get_mut(uuid) {
+            Some(revisions) => {
+                let removed = revisions.remove(revision);
+                if revisions.is_empty() {
+                    pins.remove(uuid);
+                }
+                removed
+            }
+            None => false,
+        };
+        if removed {
+            self.store_pins(&pins)?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns the pinned revisions of each object (see pin_revision)
+    pub fn pinned_revisions(&self) -> BTreeMap<String, BTreeSet<String>> {
+        self.pinned_revisions.lock().unwrap().clone()
+    }
+
+    /// Writes the set of pinned revisions to the adapter
+    fn store_pins(&self, pins: &BTreeMap<String, BTreeSet<String>>) -> Result<()> {
+        self.data
+            .write()
+            .expect("cannot_acquire_data_for_writing")
+            .write_pins(pins)
+    }
+
+    /// Loads the set of pinned revisions from the adapter
+    fn load_pins(&self) -> Result<()> {
+        let pins = self
+            .data
+            .write()
+            .expect("cannot_acquire_data_for_writing")
+            .read_pins()?;
+        *self.pinned_revisions.lock().unwrap() = pins;
+        Ok(())
+    }
+
+    /// Commits changes to the backend adapter
+    ///
+    /// # Arguments
+    ///
+    /// * `information` - Optional JSON object for recording additional commit information
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));  
+    /// replica.commit(None);
+    /// replica.delete_object("myobject");
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", winner);
+    /// let value = replica.get_value("myobject", &winner);
+    /// assert!(value.is_ok());
+    /// assert!(value.unwrap().contains_key("_deleted"));
+    /// let info = json!({ "author" : "Some user", "date" : "2022-05-23 13:47:00CET" }).as_object().unwrap().clone();
+    /// replica.commit(Some(info));
+    /// // The same changes on different replicas result in identical delta blocks
+    /// let items : Vec<Value> = (0..32).map(|i| json!({ "_id" : format!("item{}", i), "value" : i })).collect();
+    /// let object = json!({ "items\u{266D}" : items }).as_object().unwrap().clone();
+    /// let adapter1 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter1 = Arc::new(RwLock::new(adapter1));
+    /// let replica1 = Melda::new(adapter1.clone()).expect("cannot_initialize_crdt");
+    /// replica1.update(object.clone());
+    /// let block1 = replica1.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// replica2.update(object);
+    /// let block2 = replica2.commit(None).unwrap().unwrap();
+    /// assert_eq!(block1, block2);
+    /// let keys = adapter1.read().unwrap().list_objects("").unwrap();
+    /// assert_eq!(keys, adapter2.read().unwrap().list_objects("").unwrap());
+    /// for key in keys {
+    ///     assert_eq!(adapter1.read().unwrap().read_object(&key, 0, 0).unwrap(), adapter2.read().unwrap().read_object(&key, 0, 0).unwrap());
+    /// }
+    /// ```
+    pub fn commit(&self, information: Option<Map<String, Value>>) -> Result<Option<String>> {
+        Ok(self.commit_block(information)?.map(|b| b.id))
+    }
+
+    /// Commits the stage (see commit), returning the anchors after the commit along with the
+    /// block which has been created (None if there were no changes to be committed)
+    ///
+    /// # Arguments
+    ///
+    /// * `information` - Optional information about the commit
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(replica.commit_returning_block(None).unwrap().is_none());
+    /// replica.update(json!({ "somekey" : "somevalue" }).as_object().unwrap().clone());
+    /// let info = json!({ "author" : "Some user" }).as_object().unwrap().clone();
+    /// let (anchors, block) = replica.commit_returning_block(Some(info.clone())).unwrap().unwrap();
+    /// assert_eq!(anchors, replica.get_anchors());
+    /// assert!(anchors.contains(&block.id));
+    /// assert_eq!(block.info, Some(info));
+    /// assert!(block.parents.is_none());
+    /// assert_eq!(block.packs.as_ref().unwrap().len(), 1);
+    /// replica.update(json!({ "somekey" : "othervalue" }).as_object().unwrap().clone());
+    /// let (_, second) = replica.commit_returning_block(None).unwrap().unwrap();
+    /// assert!(second.parents.unwrap().contains(&block.id));
+    /// ```
+    pub fn commit_returning_block(
+        &self,
+        information: Option<Map<String, Value>>,
+    ) -> Result<Option<(BTreeSet<String>, Block)>> {
+        match self.commit_block(information)? {
+            Some(block) => Ok(Some((self.get_anchors(), block))),
+            None => Ok(None),
+        }
+    }
+
+    // Commits the stage, returning (a copy of) the created block
+    fn commit_block(&self, information: Option<Map<String, Value>>) -> Result<Option<Block>> {
+        let stage = self.stage.read().unwrap();
+        if stage.is_empty() {
+            return Ok(None);
+        }
+        // Blocks exceeding the limits would be rejected when parsed: check the limits before
+        // writing anything (the stage is kept)
+        if stage.len() > self.block_limits.max_changesets {
+            bail!("too_many_changesets");
+        }
+        drop(stage);
+        if let Some(information) = &information {
+            if Value::from(information.clone()).to_string().len() > self.block_limits.max_info_bytes
+            {
+                bail!("info_too_large");
+            }
+        }
+        if self.get_anchors().len() > self.block_limits.max_parents {
+            bail!("too_many_parents");
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("commit").entered();
+        #[cfg(feature = "tracing")]
+        let started = Instant::now();
+        let timer = self.start_timer();
+        let mut block = Map::<String, Value>::new();
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        let mut block_hash = String::new();
+        // The pack, its index and the block are written as a single batch
+        let pack = data.pack_with(|packid| {
+            // Process stage
+            let changes = self.stage_records();
+            // Merge records require a newer block format
+            if changes
+                .iter()
+                .any(|c| c.as_array().is_some_and(|c| c.len() == 4))
+            {
+                block.insert(
+                    BLOCK_VERSION_FIELD.to_string(),
+                    Value::from(MERGE_RECORDS_BLOCK_VERSION),
+                );
+            }
+            block.insert(CHANGESETS_FIELD.to_string(), Value::from(changes));
+            // Insert information object
+            if let Some(information) = information {
+                block.insert(INFORMATION_FIELD.to_string(), Value::from(information));
+            }
+            // Insert anchors
+            let anchors_blocks = self.get_anchors();
+            if !anchors_blocks.is_empty() {
+                let anchors_blocks: Vec<String> =
+                    anchors_blocks.iter().map(|bid| bid.to_string()).collect();
+                block.insert(PARENTS_FIELD.to_string(), Value::from(anchors_blocks));
+            }
+            // Insert the revision tail length (only if it is not the default), which requires
+            // a newer block format (revisions would be derived differently by older readers)
+            if self.revision_tail_length != DEFAULT_REVISION_TAIL_LENGTH {
+                block.insert(
+                    TAIL_LENGTH_FIELD.to_string(),
+                    Value::from(self.revision_tail_length),
+                );
+                block.insert(
+                    BLOCK_VERSION_FIELD.to_string(),
+                    Value::from(TAIL_LENGTH_BLOCK_VERSION),
+                );
+            }
+            // Insert pack indentifer
+            if let Some(packid) = packid {
+                let packs = vec![packid.to_string()];
+                block.insert(PACK_FIELD.to_string(), Value::from(packs));
+            }
+            let blockstr = serde_json::to_string(&block).unwrap();
+            if blockstr.len() > self.block_limits.max_block_bytes {
+                bail!("block_too_large");
+            }
+            block_hash = digest_string(&blockstr);
+            let blockid = block_hash.clone() + DELTA_EXTENSION;
+            Ok(vec![(blockid, blockstr.into_bytes())])
+        })?;
+        let pack_bytes = pack.map(|(_, size)| size).unwrap_or(0);
+        if self.flush_on_commit {
+            data.flush()?;
+        }
+        // Clears the stage
+        self.stage.write().unwrap().clear();
+        // Load the block
+        drop(data);
+        let mut b = self.parse_raw_block(block_hash.clone(), block)?;
+        self.set_applied(&mut b);
+        let committed = b.clone();
+        if !self.retains_block_changes() {
+            b.changes = None;
+        }
+        self.blocks
+            .write()
+            .unwrap()
+            .insert(block_hash, RwLock::new(b));
+        self.retire_blocks();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            block = %committed.id,
+            changes = committed.changes.as_ref().map_or(0, |c| c.len()),
+            pack_bytes,
+            elapsed = ?started.elapsed(),
+            "committed block"
+        );
+        if let Some((metrics, start)) = timer {
+            metrics.on_commit(start.elapsed(), 1, pack_bytes);
+        }
+        self.notify_subscribers();
+        Ok(Some(committed))
+    }
+
+    /// Flushes the adapter, making all committed changes durable even if the adapter buffers
+    /// writes (see Adapter::flush and MeldaConfig::flush_on_commit)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use serde_json::{Map, Value,json};
+    /// struct Buffered(MemoryAdapter, Arc<AtomicUsize>);
+    /// impl Adapter for Buffered {
+    ///     fn read_object(&self, key: &str, offset: usize, length: usize) -> anyhow::Result<Vec<u8>> {
+    ///         self.0.read_object(key, offset, length)
+    ///     }
+    ///     fn write_object(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+    ///         self.0.write_object(key, data)
+    ///     }
+    ///     fn list_objects(&self, ext: &str) -> anyhow::Result<Vec<String>> {
+    ///         self.0.list_objects(ext)
+    ///     }
+    ///     fn flush(&self) -> anyhow::Result<()> {
+    ///         self.1.fetch_add(1, Ordering::SeqCst);
+    ///         Ok(())
+    ///     }
+    /// }
+    /// let flushes = Arc::new(AtomicUsize::new(0));
+    /// let adapter : Box<dyn Adapter> = Box::new(Buffered(MemoryAdapter::new(), flushes.clone()));
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "somekey" : "somevalue" }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// assert_eq!(flushes.load(Ordering::SeqCst), 0);
+    /// replica.sync().unwrap();
+    /// assert_eq!(flushes.load(Ordering::SeqCst), 1);
+    /// let config = MeldaConfig { flush_on_commit: true, ..MeldaConfig::default() };
+    /// let adapter : Box<dyn Adapter> = Box::new(Buffered(MemoryAdapter::new(), flushes.clone()));
+    /// let replica = Melda::new_with_config(Arc::new(RwLock::new(adapter)), config).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "somekey" : "somevalue" }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// assert_eq!(flushes.load(Ordering::SeqCst), 2);
+    /// ```
+    pub fn sync(&self) -> Result<()> {
+        self.data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .flush()
+    }
+
+    /// Lists the keys of the raw items stored in the adapter whose key ends with ext (the
+    /// extension is removed from the returned keys, unless ext is an empty string). This is a
+    /// low-level function meant for repair and migration tools.
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension of the requested items (for example .delta or .pack)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "somekey" : "somevalue" }).as_object().unwrap().clone());
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// assert_eq!(replica.raw_list(".delta").unwrap(), vec![block_id.clone()]);
+    /// assert!(replica.raw_list("").unwrap().contains(&(block_id + ".delta")));
+    /// ```
+    pub fn raw_list(&self, ext: &str) -> Result<Vec<String>> {
+        self.data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .list_raw_items(ext)
+    }
+
+    /// Reads the content of a raw item stored in the adapter (the key must include the
+    /// extension). This is a low-level function meant for repair and migration tools: the
+    /// content is returned as stored (packs are not decoded, see set_payload_codec).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the item
+    pub fn raw_read(&self, key: &str) -> Result<Vec<u8>> {
+        self.data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .read_raw_bytes(key, 0, 0)
+    }
+
+    /// Writes a raw item to the adapter (the key must include the extension). The content
+    /// must match the key (packs, delta blocks, blobs, repack and pins records are identified
+    /// by the digest of their content, indexes must be valid JSON objects), otherwise an
+    /// invalid_raw_item error is returned. Existing items are not overwritten.
+    /// Written items are not loaded: call refresh or reload afterwards.
+    ///
+    /// This is a low-level function meant for repair and migration tools: writing items that
+    /// are valid but inconsistent (for example a block referencing missing packs, or a pack
+    /// encoded with a different codec) might leave the store in a state that other replicas
+    /// cannot read.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the item
+    /// * `data` - The content of the item
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "somekey" : "somevalue" }).as_object().unwrap().clone());
+    /// replica.commit(None).unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// // Copy all items
+    /// for key in replica.raw_list("").unwrap() {
+    ///     replica2.raw_write(&key, &replica.raw_read(&key).unwrap()).unwrap();
+    /// }
+    /// replica2.refresh().unwrap();
+    /// assert_eq!(replica2.read().unwrap(), replica.read().unwrap());
+    /// assert!(replica2.raw_write("abc.delta", b"{}").is_err());
+    /// ```
+    pub fn raw_write(&self, key: &str, data: &[u8]) -> Result<()> {
+        if !DataStorage::is_valid_raw_item(key, data) {
+            bail!("invalid_raw_item: {}", key);
+        }
+        self.data
+            .write()
+            .expect("cannot_acquire_data_for_writing")
+            .write_raw_bytes(key, data)
+    }
+
+    /// Deletes raw items from the adapter (the keys must include the extension), returning the
+    /// result of the deletion of each key, so that the caller can proceed even if some deletions
+    /// fail. An error is returned only if the whole operation fails. Deleted items are not
+    /// unloaded: call reload afterwards.
+    ///
+    /// This is a low-level function meant for cleanup tools: deleting items which are still
+    /// referenced (for example the packs of a block) leaves the store in a state that other
+    /// replicas cannot read.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keys of the items
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "somekey" : "somevalue" }).as_object().unwrap().clone());
+    /// replica.commit(None).unwrap();
+    /// let keys = replica.raw_list("").unwrap();
+    /// let keys : Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+    /// let results = replica.delete_raw(&keys).unwrap();
+    /// assert_eq!(results.len(), keys.len());
+    /// assert!(results.iter().all(|(_, r)| r.is_ok()));
+    /// assert!(replica.raw_list("").unwrap().is_empty());
+    /// replica.reload().unwrap();
+    /// assert!(replica.get_all_objects().is_empty());
+    /// ```
+    pub fn delete_raw(&self, keys: &[&str]) -> Result<Vec<(String, Result<()>)>> {
+        self.data
+            .write()
+            .expect("cannot_acquire_data_for_writing")
+            .delete_raw_items(keys)
+    }
+
+    // Returns the changeset records of the stage in canonical order (sorted by uuid, then by
+    // revision), so that the same logical changes always result in the same delta block
+    fn stage_records(&self) -> Vec<Value> {
+        let stage = self.stage.read().unwrap();
+        let mut changes: Vec<&Change> = stage.iter().collect();
+        changes.sort_by(|Change(u1, r1, p1), Change(u2, r2, p2)| (u1, r1, p1).cmp(&(u2, r2, p2)));
+        changes
+            .iter()
+            .map(|c| c.to_record(self.revision_tail_length))
+            .collect()
+    }
+
+    /// Subscribes to the changes of the winning revision of an object. Changes are notified
+    /// when they become visible through commit (local changes), refresh, reload, reload_until,
+    /// resolve_as, resolve_merged, unstage and clear. The subscription is removed once the
+    /// receiver is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The identifier of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let receiver = replica.subscribe("myobject");
+    /// let object = json!({ "somekey" : "somevalue" }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// replica.create_object("another", json!({}).as_object().unwrap().clone());
+    /// assert!(receiver.try_recv().is_err());
+    /// replica.commit(None);
+    /// let change = receiver.try_recv().unwrap();
+    /// assert_eq!(change.uuid, "myobject");
+    /// assert_eq!(change.previous, None);
+    /// assert_eq!(change.current, Some(replica.get_winner("myobject").unwrap()));
+    /// assert!(receiver.try_recv().is_err());
+    /// // Subscribing again after dropping the receiver only notifies later changes
+    /// drop(receiver);
+    /// for value in ["othervalue", "lastvalue"] {
+    ///     let object = json!({ "somekey" : value }).as_object().unwrap().clone();
+    ///     replica.update_object("myobject", object);
+    ///     replica.commit(None);
+    /// }
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// let receiver = replica.subscribe("myobject");
+    /// replica.create_object("third", json!({}).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// assert!(receiver.try_recv().is_err());
+    /// replica.delete_object("myobject");
+    /// replica.commit(None);
+    /// let change = receiver.try_recv().unwrap();
+    /// assert_eq!(change.previous, Some(winner));
+    /// assert_eq!(change.current, Some(replica.get_winner("myobject").unwrap()));
+    /// ```
+    pub fn subscribe(&self, uuid: &str) -> Receiver<RevisionChange> {
+        self.add_subscription(Some(uuid.to_string()))
+    }
+
+    /// Subscribes to the changes of the winning revision of all objects (see subscribe)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica2 = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let receiver = replica2.subscribe_all();
+    /// replica.update(json!({ "somekey" : "somevalue" }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// replica2.refresh();
+    /// let change = receiver.try_recv().unwrap();
+    /// assert_eq!(change.uuid, "\u{221A}");
+    /// assert_eq!(change.current, Some(replica.get_winner("\u{221A}").unwrap()));
+    /// drop(receiver);
+    /// replica.update(json!({ "somekey" : "othervalue" }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// replica2.refresh();
+    /// ```
+    pub fn subscribe_all(&self) -> Receiver<RevisionChange> {
+        self.add_subscription(None)
+    }
+
+    fn add_subscription(&self, uuid: Option<String>) -> Receiver<RevisionChange> {
+        let (sender, receiver) = channel();
+        let winners = self.current_winners(uuid.as_deref());
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        for (k, w) in winners {
+            match w {
+                Some(w) => subscriptions.winners.insert(k, Some(w)),
+                None => subscriptions.winners.remove(&k),
+            };
+        }
+        subscriptions.senders.push((uuid, sender));
+        receiver
+    }
+
+    // Returns the winning revision of the given object (or of all objects)
+    fn current_winners(&self, uuid: Option<&str>) -> HashMap<String, Option<String>> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let winner = |rt: &RwLock<RevisionTree>| {
+            rt.read()
+                .expect("failed_to_acquire_revision_tree_for_reading")
+                .get_winner()
+                .map(|w| w.to_string())
+        };
+        match uuid {
+            Some(uuid) => HashMap::from([(uuid.to_string(), docs_r.get(uuid).and_then(winner))]),
+            None => docs_r
+                .iter()
+                .map(|(k, rt)| (k.clone(), winner(rt)))
+                .collect(),
+        }
+    }
+
+    // Notifies subscribers of the objects whose winning revision changed since the last
+    // notification (subscriptions whose receiver has been dropped are removed, along with the
+    // winners which are not tracked by any subscription anymore)
+    fn notify_subscribers(&self) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        if subscriptions.senders.is_empty() {
+            subscriptions.winners.clear();
+            return;
+        }
+        let mut current = if subscriptions.senders.iter().any(|(u, _)| u.is_none()) {
+            self.current_winners(None)
+        } else {
+            let mut winners = HashMap::new();
+            for (u, _) in &subscriptions.senders {
+                winners.extend(self.current_winners(u.as_deref()));
+            }
+            winners
+        };
+        // Objects which are not known anymore
+        for uuid in subscriptions.winners.keys() {
+            current.entry(uuid.clone()).or_insert(None);
+        }
+        let mut changes = vec![];
+        for (uuid, winner) in current {
+            let previous = subscriptions.winners.get(&uuid).cloned().flatten();
+            if previous != winner {
+                changes.push(RevisionChange {
+                    uuid: uuid.clone(),
+                    previous,
+                    current: winner.clone(),
+                });
+                // Objects without a winner are not tracked (a missing entry means no winner)
+                match winner {
+                    Some(winner) => subscriptions.winners.insert(uuid, Some(winner)),
+                    None => subscriptions.winners.remove(&uuid),
+                };
+            }
+        }
+        changes.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        subscriptions.senders.retain(|(uuid, sender)| {
+            changes
+                .iter()
+                .filter(|c| uuid.as_ref().is_none_or(|u| u == &c.uuid))
+                .all(|c| sender.send(c.clone()).is_ok())
+        });
+        if subscriptions.senders.iter().all(|(u, _)| u.is_some()) {
+            let Subscriptions { senders, winners } = &mut *subscriptions;
+            winners.retain(|uuid, _| senders.iter().any(|(u, _)| u.as_ref() == Some(uuid)));
+        }
+    }
+
+    /// Returns a set of the identifier of all objects
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1.0f32, 2.0f32, 3.0f32, 4.0f32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// let object = json!({ "somekey" : [ "somedata", 1.0f32, 2.0f32, 3.0f32, 4.0f32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("another", object);
+    /// assert_eq!(replica.get_all_objects(), BTreeSet::from(["another".to_string(),"myobject".to_string()]));
+    /// ```
+    pub fn get_all_objects(&self) -> BTreeSet<String> {
+        self.documents
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(k, _)| k.clone())
+            .collect()
+    }
+
+    /// Returns a set of the identifiers of the objects matching the given predicate
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Returns true for the identifiers to be included
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.create_object("todo_1", json!({}).as_object().unwrap().clone());
+    /// replica.create_object("todo_2", json!({}).as_object().unwrap().clone());
+    /// replica.create_object("note_1", json!({}).as_object().unwrap().clone());
+    /// let todos = replica.objects_matching(|uuid| uuid.starts_with("todo_"));
+    /// assert_eq!(todos, BTreeSet::from(["todo_1".to_string(), "todo_2".to_string()]));
+    /// ```
+    pub fn objects_matching(&self, predicate: impl Fn(&str) -> bool) -> BTreeSet<String> {
+        self.documents
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|k| predicate(k))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns a set of the identifiers of all objects, excluding array descriptors
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1" } ] }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// assert_eq!(replica.get_all_objects().len(), 3);
+    /// assert_eq!(replica.user_objects(), BTreeSet::from(["1".to_string(), "\u{221A}".to_string()]));
+    /// ```
+    pub fn user_objects(&self) -> BTreeSet<String> {
+        self.objects_matching(|uuid| !is_array_descriptor(uuid))
+    }
+
+    /// Returns a set of the identifiers of the objects whose winning revision index (the
+    /// number of revisions in its history, including the first one) is within the given range
+    ///
+    /// # Arguments
+    ///
+    /// * `min` - The minimum index (inclusive)
+    /// * `max` - The maximum index (inclusive)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.create_object("once", json!({}).as_object().unwrap().clone());
+    /// for i in 0..5 {
+    ///     replica.update_object("often", json!({ "value" : i }).as_object().unwrap().clone());
+    /// }
+    /// assert_eq!(replica.objects_by_index(5, u32::MAX), BTreeSet::from(["often".to_string()]));
+    /// assert_eq!(replica.objects_by_index(1, 1), BTreeSet::from(["once".to_string()]));
+    /// assert!(replica.objects_by_index(2, 4).is_empty());
+    /// ```
+    pub fn objects_by_index(&self, min: u32, max: u32) -> BTreeSet<String> {
+        self.documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .iter()
+            .filter(|(_, rt)| {
+                rt.read()
+                    .expect("failed_to_acquire_revision_tree_for_reading")
+                    .get_winner()
+                    .is_some_and(|winner| (min..=max).contains(&winner.index))
+            })
+            .map(|(uuid, _)| uuid.clone())
+            .collect()
+    }
+
+    /// Returns the total size (in bytes) occupied by the replica in the storage backend (only
+    /// the items of the namespace, if one is set)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert_eq!(replica.storage_size().unwrap(), 0);
+    /// let object = json!({ "somekey" : "somevalue" }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// replica.commit(None);
+    /// let size = replica.storage_size().unwrap();
+    /// assert!(size > 0);
+    /// // Namespaces sharing the same adapter only count their own items
+    /// let config = MeldaConfig { namespace: Some("other".to_string()), ..MeldaConfig::default() };
+    /// let other = Melda::new_with_config(adapter.clone(), config).expect("cannot_initialize_crdt");
+    /// assert_eq!(other.storage_size().unwrap(), 0);
+    /// let object = json!({ "otherkey" : "othervalue" }).as_object().unwrap().clone();
+    /// other.update(object);
+    /// other.commit(None);
+    /// assert!(other.storage_size().unwrap() > 0);
+    /// assert_eq!(adapter.read().unwrap().total_size().unwrap(), size + other.storage_size().unwrap());
+    /// ```
+    pub fn storage_size(&self) -> Result<u64> {
+        self.data.read().unwrap().total_size()
+    }
+
+    /// Returns a the value associated with the given revision. The value of a revision of an
+    /// array descriptor is its materialized order (the delta patches are applied), as if the
+    /// revision was the only leaf of the array (see get_conflicting_values)
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The identifier of the object
+    /// * `revision`- The revision which we want to obtain the value for
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1.0f32, 2.0f32, 3.0f32, 4.0f32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object.clone());
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// let value = replica.get_value("myobject", &winner).unwrap();
+    /// assert_eq!(value, object);
+    /// ```
+    pub fn get_value(&self, uuid: &str, revision: &str) -> Result<Map<String, Value>> {
+        let revision = Revision::from(revision).expect("invalid_revision_string");
+        match self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .get(uuid)
+        {
+            Some(rt) => {
+                let rt_r = rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                self.read_value(uuid, &rt_r, &revision)
+            }
+            None => Err(anyhow!("invalid object uuid")),
+        }
+    }
+
+    /// Returns the values of the conflicting revisions of the given object (see
+    /// get_conflicting), associated with their revision. Like get_value, the values of the
+    /// revisions of array descriptors are their materialized orders.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The identifier of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "items\u{266D}" : [ 1, 2, 3 ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica).unwrap();
+    /// replica2.refresh().unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [ 1, 2, 3, 4 ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica2.update(json!({ "items\u{266D}" : [ 0, 1, 2, 3 ] }).as_object().unwrap().clone()).unwrap();
+    /// replica2.commit(None).unwrap();
+    /// replica.meld(&replica2).unwrap();
+    /// replica.refresh().unwrap();
+    /// let array = replica.in_conflict().into_iter().find(|uuid| uuid.starts_with('^')).unwrap();
+    /// let values = replica.get_conflicting_values(&array).unwrap();
+    /// assert_eq!(values.len(), 1);
+    /// let winner = replica.get_value(&array, &replica.get_winner(&array).unwrap()).unwrap();
+    /// let orders = [winner.get("A").unwrap().clone(), values.values().next().unwrap().get("A").unwrap().clone()];
+    /// assert!(orders.contains(&json!([1, 2, 3, 4])));
+    /// assert!(orders.contains(&json!([0, 1, 2, 3])));
+    /// assert!(replica.get_conflicting_values("unknown").is_err());
+    /// ```
+    pub fn get_conflicting_values<T>(&self, uuid: T) -> Result<BTreeMap<String, Map<String, Value>>>
+    where
+        T: AsRef<str>,
+    {
+        let uuid = uuid.as_ref();
+        self.get_conflicting(uuid)?
+            .into_iter()
+            .map(|r| {
+                let value = self.get_value(uuid, &r)?;
+                Ok((r, value))
+            })
+            .collect()
+    }
+
+    // Reads the value of a revision of an object, materializing the order of array descriptors
+    fn read_value(
+        &self,
+        uuid: &str,
+        rt: &RevisionTree,
+        revision: &Revision,
+    ) -> Result<Map<String, Value>> {
+        if is_array_descriptor(uuid) && !revision.is_deleted() && DataStorage::is_stored(revision) {
+            let order = self.rebuild_array_order(revision, rt)?;
+            Ok(ArrayDescriptor::new_from_order(order).to_json_object())
+        } else {
+            self.data
+                .read()
+                .expect("cannot_acquire_data_for_reading")
+                .read_object(revision)
+        }
+    }
+
+    /// Returns the value associated with the given revision (see get_value) without copying it:
+    /// repeated reads of the same value share the same allocation while the value is cached
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The identifier of the object
+    /// * `revision`- The revision which we want to obtain the value for
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1, 2, 3, 4 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object.clone());
+    /// replica.commit(None);
+    /// let reloaded = Melda::new(adapter).expect("cannot_initialize_crdt");
+    /// let winner = reloaded.get_winner("myobject").unwrap();
+    /// let first = reloaded.get_value_arc("myobject", &winner).unwrap();
+    /// let second = reloaded.get_value_arc("myobject", &winner).unwrap();
+    /// assert_eq!(*first, object);
+    /// assert!(Arc::ptr_eq(&first, &second));
+    /// assert!(reloaded.get_value_arc("unknown", &winner).is_err());
+    /// ```
+    pub fn get_value_arc(&self, uuid: &str, revision: &str) -> Result<Arc<Map<String, Value>>> {
+        let revision = Revision::from(revision).expect("invalid_revision_string");
+        match self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .get(uuid)
+        {
+            // Array descriptors are materialized (see get_value)
+            Some(rt) if is_array_descriptor(uuid) => {
+                let rt_r = rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                Ok(Arc::new(self.read_value(uuid, &rt_r, &revision)?))
+            }
+            Some(_) => self
+                .data
+                .read()
+                .expect("cannot_acquire_data_for_reading")
+                .read_object_ref(&revision),
+            None => Err(anyhow!("invalid object uuid")),
+        }
+    }
+
+    /// Returns the value of an object as of the given anchor blocks, considering only the changes
+    /// recorded in the blocks reachable from the anchors (the current state is not modified and
+    /// no reload is performed). If a revision is given its value is returned, provided that it is
+    /// known at that point, otherwise the value of the winning revision is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The identifier of the object
+    /// * `anchors` - The anchor blocks (see get_anchors and commit_anchors)
+    /// * `revision` - An optional revision of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : "first" }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object.clone());
+    /// let first = replica.commit(None).unwrap().unwrap();
+    /// let first_revision = replica.get_winner("myobject").unwrap();
+    /// let updated = json!({ "somekey" : "second" }).as_object().unwrap().clone();
+    /// replica.update_object("myobject", updated.clone());
+    /// let second = replica.commit(None).unwrap().unwrap();
+    /// let second_revision = replica.get_winner("myobject").unwrap();
+    /// assert_eq!(replica.get_value_until("myobject", &BTreeSet::from([first.clone()]), None).unwrap(), object);
+    /// assert_eq!(replica.get_value_until("myobject", &BTreeSet::from([second.clone()]), None).unwrap(), updated);
+    /// assert_eq!(replica.get_value_until("myobject", &BTreeSet::from([second]), Some(&first_revision)).unwrap(), object);
+    /// assert!(replica.get_value_until("myobject", &BTreeSet::from([first.clone()]), Some(&second_revision)).is_err());
+    /// assert!(replica.get_value_until("otherobject", &BTreeSet::from([first]), None).is_err());
+    /// assert!(replica.get_value_until("myobject", &BTreeSet::from(["unknown".to_string()]), None).is_err());
+    /// ```
+    pub fn get_value_until(
+        &self,
+        uuid: &str,
+        anchors: &BTreeSet<String>,
+        revision: Option<&str>,
+    ) -> Result<Map<String, Value>> {
+        // Replay the changes of the object into a scratch revision tree
+        let mut rt = RevisionTree::new();
+        for bid in self.reachable_blocks(anchors)? {
+            for Change(id, r, prev) in self.block_changes(&bid)?.unwrap_or_default() {
+                if id == uuid {
+                    rt.add(r, prev);
+                }
+            }
+        }
+        if rt.is_empty() {
+            bail!("unknown_object");
+        }
+        match revision {
+            Some(revision) => {
+                let revision = Revision::from(revision)?;
+                if !rt.get_all_revs().contains(&revision) {
+                    bail!("unknown_revision");
+                }
+                self.data
+                    .read()
+                    .expect("cannot_acquire_data_for_reading")
+                    .read_object(&revision)
+            }
+            None => self.read_object(uuid, &rt),
+        }
+    }
+
+    /// Returns the identifiers of the objects changed by the applied blocks which are not reachable
+    /// from the given anchor blocks, that is the objects whose revisions have been introduced
+    /// since the state described by the anchors (staged changes are not considered)
+    ///
+    /// # Arguments
+    ///
+    /// * `anchors` - The anchor blocks (see get_anchors)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// replica.create_object("first", json!({ "a" : 1 }).as_object().unwrap().clone());
+    /// replica.create_object("second", json!({ "b" : 1 }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// let processed = replica.get_anchors();
+    /// assert!(replica.changed_since(&processed).unwrap().is_empty());
+    /// assert_eq!(replica.changed_since(&BTreeSet::new()).unwrap().len(), 2);
+    /// // Changes made on another replica
+    /// let other = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// other.update_object("second", json!({ "b" : 2 }).as_object().unwrap().clone());
+    /// other.create_object("third", json!({ "c" : 1 }).as_object().unwrap().clone());
+    /// other.commit(None);
+    /// replica.refresh();
+    /// replica.update_object("first", json!({ "a" : 2 }).as_object().unwrap().clone());
+    /// assert_eq!(replica.changed_since(&processed).unwrap(), BTreeSet::from(["second".to_string(), "third".to_string()]));
+    /// assert!(replica.changed_since(&BTreeSet::from(["unknown".to_string()])).is_err());
+    /// ```
+    pub fn changed_since(&self, anchors: &BTreeSet<String>) -> Result<BTreeSet<String>> {
+        let reachable = self.reachable_blocks(anchors)?;
+        let mut changed = BTreeSet::new();
+        for bid in self.applied_blocks() {
+            if !reachable.contains(&bid) {
+                for Change(uuid, _, _) in self.block_changes(&bid)?.unwrap_or_default() {
+                    changed.insert(uuid);
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    // Returns the blocks reachable (through their parents) from the given anchors
+    fn reachable_blocks(&self, anchors: &BTreeSet<String>) -> Result<BTreeSet<String>> {
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        let retired_r = self
+            .retired_blocks
+            .read()
+            .expect("cannot_acquire_retired_blocks_for_reading");
+        let mut reachable = BTreeSet::new();
+        let mut to_visit: Vec<String> = anchors.iter().cloned().collect();
+        while let Some(bid) = to_visit.pop() {
+            // Retired blocks (shallow mode) are only known by their identifiers
+            if reachable.contains(&bid) || retired_r.contains(&bid) {
+                continue;
+            }
+            let block = blocks_r
+                .get(&bid)
+                .ok_or_else(|| anyhow!("unknown_block: {}", bid))?
+                .read()
+                .expect("cannot_acquire_block_for_reading");
+            if let Some(parents) = &block.parents {
+                to_visit.extend(parents.iter().cloned());
+            }
+            drop(block);
+            reachable.insert(bid);
+        }
+        Ok(reachable)
+    }
+
+    /// Returns a set of the current anchor blocks (blocks that have not been referenced as parents)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// let anchors = replica.get_anchors();
+    /// assert!(anchors.is_empty());
+    /// let block = replica.commit(None).unwrap().unwrap();
+    /// let anchors = replica.get_anchors();
+    /// assert!(anchors.len() == 1);
+    /// assert!(anchors.contains(&block));
+    /// ```
+    pub fn get_anchors(&self) -> BTreeSet<String> {
+        let blocks_r = self.blocks.read().unwrap();
+        // Return the identifiers of all blocks which are not referenced as parents
+        let mut anchors: BTreeSet<String> = blocks_r
+            .iter()
+            .filter(|(_, block)| block.read().unwrap().status == Status::ValidAndApplied)
+            .map(|(k, _)| k.clone())
+            .collect();
+        blocks_r
+            .iter()
+            .filter(|(_, block)| block.read().unwrap().status == Status::ValidAndApplied)
+            .for_each(|(_, b)| {
+                let block_r = b.read().unwrap();
+                if let Some(pr) = &block_r.parents {
+                    for p in pr {
+                        anchors.remove(p);
+                    }
+                }
+            });
+        anchors
+    }
+
+    /// Returns true if the block is an anchor, that is if it has been applied and no applied
+    /// block refers to it as a parent (see get_anchors). Each check takes constant time, since
+    /// the number of applied children of each block is kept up to date.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_id` - The identifier of the block
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "somekey" : 1 }).as_object().unwrap().clone());
+    /// let first = replica.commit(None).unwrap().unwrap();
+    /// assert!(replica.is_anchor(&first));
+    /// replica.update(json!({ "somekey" : 2 }).as_object().unwrap().clone());
+    /// let second = replica.commit(None).unwrap().unwrap();
+    /// assert!(!replica.is_anchor(&first));
+    /// assert!(replica.is_anchor(&second));
+    /// assert!(!replica.is_anchor("unknown"));
+    /// let reloaded = Melda::new(adapter).expect("cannot_initialize_crdt");
+    /// assert!(!reloaded.is_anchor(&first));
+    /// assert!(reloaded.is_anchor(&second));
+    /// ```
+    pub fn is_anchor(&self, block_id: &str) -> bool {
+        let applied = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading")
+            .get(block_id)
+            .is_some_and(|b| b.read().unwrap().status == Status::ValidAndApplied);
+        applied
+            && self
+                .applied_children
+                .lock()
+                .unwrap()
+                .get(block_id)
+                .is_none_or(|c| *c == 0)
+    }
+
+    // Marks the block as applied, counting it as a child of its parents (see is_anchor)
+    fn set_applied(&self, block: &mut Block) {
+        block.status = Status::ValidAndApplied;
+        self.anchors_digest.lock().unwrap().take();
+        if let Some(parents) = &block.parents {
+            let mut children = self.applied_children.lock().unwrap();
+            for p in parents {
+                *children.entry(p.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    // Stops counting an applied block as a child of its parents (see set_applied)
+    fn unset_applied(&self, block: &Block) {
+        self.anchors_digest.lock().unwrap().take();
+        if let Some(parents) = &block.parents {
+            let mut children = self.applied_children.lock().unwrap();
+            for p in parents {
+                if let Some(c) = children.get_mut(p) {
+                    *c = c.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    // In shallow mode, drops the applied blocks which are not anchors, keeping only their
+    // identifiers (so that they are neither reloaded by refresh nor considered missing parents)
+    fn retire_blocks(&self) {
+        if !self.shallow {
+            return;
+        }
+        let anchors = self.get_anchors();
+        let mut blocks_w = self
+            .blocks
+            .write()
+            .expect("cannot_acquire_blocks_for_writing");
+        let mut retired_w = self.retired_blocks.write().unwrap();
+        blocks_w.retain(|bid, block| {
+            let applied = block.read().unwrap().status == Status::ValidAndApplied;
+            if applied && !anchors.contains(bid) {
+                retired_w.insert(bid.clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Returns the history of the anchor set: for each applied block, in topological order
+    /// (parents before children, ties broken by block identifier), the set of anchors as it stood
+    /// after the block was added (the block itself plus the previous anchors which are not
+    /// its parents). Each set can be used as a restore point (see new_until and reload_until).
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(replica.commit_anchors().is_empty());
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// let first = replica.commit(None).unwrap().unwrap();
+    /// replica.delete_object("myobject");
+    /// let second = replica.commit(None).unwrap().unwrap();
+    /// let history = replica.commit_anchors();
+    /// assert_eq!(history, vec![BTreeSet::from([first]), BTreeSet::from([second])]);
+    /// assert_eq!(history.last().unwrap(), &replica.get_anchors());
+    /// ```
+    pub fn commit_anchors(&self) -> Vec<BTreeSet<String>> {
+        let mut history = vec![];
+        let mut anchors = BTreeSet::new();
+        for (bid, parents) in self.applied_blocks_in_order() {
+            anchors.retain(|a| !parents.contains(a));
+            anchors.insert(bid);
+            history.push(anchors.clone());
+        }
+        history
+    }
+
+    /// Returns the commit log: the identifier and the information object (see commit) of each
+    /// applied block, in topological order (parents before children, ties broken by block
+    /// identifier). Blocks committed without information are included with None.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(replica.commit_log().is_empty());
+    /// replica.update(json!({ "somekey" : 1 }).as_object().unwrap().clone());
+    /// let info = json!({ "author" : "Some user" }).as_object().unwrap().clone();
+    /// let first = replica.commit(Some(info.clone())).unwrap().unwrap();
+    /// replica.update(json!({ "somekey" : 2 }).as_object().unwrap().clone());
+    /// let second = replica.commit(None).unwrap().unwrap();
+    /// assert_eq!(replica.commit_log(), vec![(first, Some(info)), (second, None)]);
+    /// ```
+    pub fn commit_log(&self) -> Vec<(String, Option<Map<String, Value>>)> {
+        let order = self.applied_blocks_in_order();
+        let blocks_r = self.blocks.read().unwrap();
+        order
+            .into_iter()
+            .filter_map(|(bid, _)| {
+                let info = blocks_r.get(&bid)?.read().unwrap().info.clone();
+                Some((bid, info))
+            })
+            .collect()
+    }
+
+    /// Returns the merge base of two anchor sets: the most recent blocks reachable (through their
+    /// parents) from both sets, that is the common ancestors which are not parents of other
+    /// common ancestors. The result is empty if the two sets share no history. Unknown blocks are
+    /// ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - The first anchor set
+    /// * `b` - The second anchor set
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "somekey" : 1 }).as_object().unwrap().clone());
+    /// let base = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica).unwrap();
+    /// replica2.refresh().unwrap();
+    /// replica.update(json!({ "somekey" : 2 }).as_object().unwrap().clone());
+    /// let first = BTreeSet::from([replica.commit(None).unwrap().unwrap()]);
+    /// replica2.update(json!({ "somekey" : 3 }).as_object().unwrap().clone());
+    /// let second = BTreeSet::from([replica2.commit(None).unwrap().unwrap()]);
+    /// replica.meld(&replica2).unwrap();
+    /// replica.refresh().unwrap();
+    /// assert_eq!(replica.merge_base(&first, &second), BTreeSet::from([base.clone()]));
+    /// assert_eq!(replica.merge_base(&first, &BTreeSet::from([base.clone()])), BTreeSet::from([base]));
+    /// assert_eq!(replica.merge_base(&first, &first), first);
+    /// assert!(replica.merge_base(&first, &BTreeSet::new()).is_empty());
+    /// ```
+    pub fn merge_base(&self, a: &BTreeSet<String>, b: &BTreeSet<String>) -> BTreeSet<String> {
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        let parents_of = |bid: &String| -> BTreeSet<String> {
+            blocks_r
+                .get(bid)
+                .and_then(|b| {
+                    b.read()
+                        .expect("cannot_acquire_block_for_reading")
+                        .parents
+                        .clone()
+                })
+                .unwrap_or_default()
+        };
+        let ancestors = |anchors: &BTreeSet<String>| -> BTreeSet<String> {
+            let mut reachable = BTreeSet::new();
+            let mut to_visit: Vec<String> = anchors.iter().cloned().collect();
+            while let Some(bid) = to_visit.pop() {
+                if !blocks_r.contains_key(&bid) || reachable.contains(&bid) {
+                    continue;
+                }
+                to_visit.extend(parents_of(&bid));
+                reachable.insert(bid);
+            }
+            reachable
+        };
+        let common: BTreeSet<String> = ancestors(a).intersection(&ancestors(b)).cloned().collect();
+        // Keep only the common ancestors which are not parents of other common ancestors
+        let mut base = common.clone();
+        for bid in &common {
+            for p in parents_of(bid) {
+                base.remove(&p);
+            }
+        }
+        base
+    }
+
+    /// Returns true if the ancestries (through the parents) of the two anchor sets have at least
+    /// one block in common. Anchor sets which do not share history belong to unrelated documents
+    /// (for example documents melded into the same store), hence comparing them (see merge_base)
+    /// is meaningless. Unknown blocks are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - The first anchor set
+    /// * `b` - The second anchor set
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "somekey" : 1 }).as_object().unwrap().clone());
+    /// let base = BTreeSet::from([replica.commit(None).unwrap().unwrap()]);
+    /// replica.update(json!({ "somekey" : 2 }).as_object().unwrap().clone());
+    /// let first = BTreeSet::from([replica.commit(None).unwrap().unwrap()]);
+    /// // An unrelated document
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// replica2.update(json!({ "otherkey" : 1 }).as_object().unwrap().clone());
+    /// let unrelated = BTreeSet::from([replica2.commit(None).unwrap().unwrap()]);
+    /// replica.meld(&replica2).unwrap();
+    /// replica.refresh().unwrap();
+    /// assert!(replica.share_history(&first, &base));
+    /// assert!(replica.share_history(&first, &first));
+    /// assert!(!replica.share_history(&first, &unrelated));
+    /// assert!(!replica.share_history(&first, &BTreeSet::new()));
+    /// ```
+    pub fn share_history(&self, a: &BTreeSet<String>, b: &BTreeSet<String>) -> bool {
+        // The merge base is empty if and only if there are no common ancestors
+        !self.merge_base(a, b).is_empty()
+    }
+
+    // Returns the applied blocks (with their applied parents) in topological order (parents
+    // before children, ties broken by block identifier)
+    fn applied_blocks_in_order(&self) -> Vec<(String, BTreeSet<String>)> {
+        let blocks_r = self.blocks.read().unwrap();
+        // Collect the parents of the applied blocks
+        let mut pending: BTreeMap<String, BTreeSet<String>> = blocks_r
+            .iter()
+            .filter(|(_, block)| block.read().unwrap().status == Status::ValidAndApplied)
+            .map(|(k, block)| {
+                let parents = block.read().unwrap().parents.clone().unwrap_or_default();
+                (k.clone(), parents)
+            })
+            .collect();
+        drop(blocks_r);
+        // Parents which are not applied blocks are ignored
+        let applied: BTreeSet<String> = pending.keys().cloned().collect();
+        for parents in pending.values_mut() {
+            parents.retain(|p| applied.contains(p));
+        }
+        // Kahn's algorithm (the DAG is walked once), the smallest ready identifier comes first
+        let mut children: HashMap<&String, Vec<&String>> = HashMap::new();
+        let mut missing: HashMap<&String, usize> = HashMap::new();
+        let mut ready = BTreeSet::new();
+        for (bid, parents) in &pending {
+            for p in parents {
+                children.entry(p).or_default().push(bid);
+            }
+            missing.insert(bid, parents.len());
+            if parents.is_empty() {
+                ready.insert(bid);
+            }
+        }
+        let mut order = Vec::with_capacity(pending.len());
+        while let Some(bid) = ready.pop_first() {
+            for child in children.get(bid).into_iter().flatten() {
+                let count = missing.get_mut(child).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert(*child);
+                }
+            }
+            order.push((bid.clone(), pending[bid].clone()));
+        }
+        order
+    }
+
+    /// Resets the replica to an empty state (objects, blocks, staged changes and caches are
+    /// dropped), as if it had been created on an empty adapter. If purge is true all items
+    /// are also deleted from the adapter (only the items of the namespace, if one is set),
+    /// otherwise they can be loaded again using reload.
+    ///
+    /// # Arguments
+    ///
+    /// * `purge` - True to delete all items from the adapter
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : "somevalue" }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// replica.commit(None);
+    /// replica.update(json!({ "somekey" : "othervalue" }).as_object().unwrap().clone());
+    /// replica.clear(false).unwrap();
+    /// assert!(replica.get_all_objects().is_empty());
+    /// assert!(replica.get_anchors().is_empty());
+    /// assert!(!replica.has_staging());
+    /// replica.reload().unwrap();
+    /// let mut readback = replica.read().unwrap();
+    /// readback.remove("_id");
+    /// assert_eq!(readback, object);
+    /// replica.clear(true).unwrap();
+    /// assert!(replica.get_all_objects().is_empty());
+    /// assert!(adapter.read().unwrap().list_objects("").unwrap().is_empty());
+    /// replica.update(object.clone());
+    /// assert!(replica.commit(None).unwrap().is_some());
+    /// ```
+    pub fn clear(&self, purge: bool) -> Result<()> {
+        self.data
+            .write()
+            .expect("cannot_acquire_data_for_writing")
+            .clear(purge)?;
+        self.documents
+            .write()
+            .expect("failed_to_acquire_documents_for_writing")
+            .clear();
+        self.blocks.write().unwrap().clear();
+        self.applied_children.lock().unwrap().clear();
+        self.anchors_digest.lock().unwrap().take();
+        self.retired_blocks.write().unwrap().clear();
+        self.stage.write().unwrap().clear();
+        let mut orders = self.array_descriptors_cache.lock().unwrap();
+        orders.clear();
+        self.enforce_memory_budget(&mut orders);
+        drop(orders);
+        if let Some(cache) = self.digest_cache.lock().unwrap().as_mut() {
+            cache.clear();
+        }
+        self.clear_input_cache();
+        self.clear_pruned();
+        self.pinned_revisions.lock().unwrap().clear();
+        self.inconsistent_revisions.lock().unwrap().clear();
+        self.notify_subscribers();
+        Ok(())
+    }
+
+    /// Reloads the CRDT (reloads all delta blocks)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block = replica.commit(None).unwrap().unwrap();
+    /// let anchors = replica.get_anchors();
+    /// assert!(anchors.len() == 1);
+    /// assert!(anchors.contains(&block));
+    /// replica.reload();
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// ```    
+    pub fn reload(&self) -> Result<()> {
+        // Check that stage is empty, otherwise fail (user must unstage explicity if necessary)
+        if !self.stage.read().unwrap().is_empty() {
+            bail!("stage_not_empty")
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("reload").entered();
+        #[cfg(feature = "tracing")]
+        let started = Instant::now();
+        let timer = self.start_timer();
+        // Clear the documents
+        self.documents
+            .write()
+            .expect("failed_to_acquire_documents_for_writing")
+            .clear();
+        self.clear_input_cache();
+        self.clear_pruned();
+        self.inconsistent_revisions.lock().unwrap().clear();
+        if self.is_cancelled() {
+            return self.cancel_reload();
+        }
+        // Reload data storage
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        data.reload()?;
+        drop(data);
+        self.load_pins()?;
+        // Clear the blocks
+        self.blocks.write().unwrap().clear();
+        self.applied_children.lock().unwrap().clear();
+        self.anchors_digest.lock().unwrap().take();
+        self.retired_blocks.write().unwrap().clear();
+        // Parse blocks as they are listed, so that only one raw block is in memory at a time.
+        // Packs cannot be checked while listing (the adapter is locked), they are checked below
+        let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        let max_size = self.block_limits.max_block_bytes;
+        let listed = data.for_each_raw_item(DELTA_EXTENSION, max_size, |i, content| {
+            // Stop listing as soon as the reload is cancelled
+            if self.is_cancelled() {
+                bail!("cancelled");
+            }
+            match content
+                .and_then(|c| self.decode_raw_block(&i, &c))
+                .and_then(|block| self.parse_block(i.clone(), block, false))
+            {
+                Ok(mut block) => {
+                    // In shallow mode changes are fetched when the block is applied
+                    if self.shallow {
+                        block.changes = None;
+                    }
+                    self.blocks.write().unwrap().insert(i, RwLock::new(block));
+                }
+                #[cfg(feature = "tracing")]
+                Err(e) => tracing::debug!(block = %i, error = %e, "cannot load block"),
+                #[cfg(not(feature = "tracing"))]
+                Err(_) => {}
+            }
+            Ok(())
+        });
+        drop(data);
+        if self.is_cancelled() {
+            return self.cancel_reload();
+        }
+        listed?;
+        // Discard the blocks referencing packs which are missing or invalid
+        let progress = self.progress_reporter();
+        let block_ids: Vec<String> = self.blocks.read().unwrap().keys().cloned().collect();
+        let total = block_ids.len();
+        for (done, i) in block_ids.into_iter().enumerate() {
+            if self.is_cancelled() {
+                return self.cancel_reload();
+            }
+            let packs = self.blocks.read().unwrap()[&i]
+                .read()
+                .unwrap()
+                .packs
+                .clone();
+            if !self.are_valid_packs(packs.iter().flatten().map(|p| p.as_str())) {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(block = %i, error = "missing_packs", "cannot load block");
+                self.blocks.write().unwrap().remove(&i);
+            }
+            if let Some(progress) = &progress {
+                progress.on_progress(ProgressOperation::LoadBlocks, done + 1, total);
+            }
+        }
+        // Load packs written while blocks were being listed
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        data.refresh()?;
+        drop(data);
+        // Mark valid blocks
+        self.mark_valid_blocks();
+        // Apply all valid blocks
+        let blocks_r = self.blocks.read().unwrap();
+        let total = blocks_r.len();
+        for (done, (_, block)) in blocks_r.iter().enumerate() {
+            if self.is_cancelled() {
+                drop(blocks_r);
+                return self.cancel_reload();
+            }
+            let status = block.read().unwrap().status;
+            if status == Status::Valid {
+                let block_r = block.read().unwrap();
+                if self.apply_block(&block_r).is_ok() {
+                    drop(block_r);
+                    let mut block_w = block.write().unwrap();
+                    self.set_applied(&mut block_w);
+                    // We can drop the changes vector
+                    if !self.retains_block_changes() {
+                        block_w.changes = None;
+                    }
+                }
+            }
+            if let Some(progress) = &progress {
+                progress.on_progress(ProgressOperation::ApplyBlocks, done + 1, total);
+            }
+        }
+        drop(blocks_r);
+        self.retire_blocks();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            blocks = self.blocks.read().unwrap().len(),
+            elapsed = ?started.elapsed(),
+            "reloaded blocks"
+        );
+        if let Some((metrics, start)) = timer {
+            metrics.on_reload(start.elapsed(), self.blocks.read().unwrap().len());
+        }
+        self.notify_subscribers();
+        Ok(())
+    }
+
+    /// Loads newly available blocks
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block = replica.commit(None).unwrap().unwrap();
+    /// let anchors = replica.get_anchors();
+    /// assert!(anchors.len() == 1);
+    /// assert!(anchors.contains(&block));
+    /// replica.refresh();
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// ```    
+    pub fn refresh(&self) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("refresh").entered();
+        #[cfg(feature = "tracing")]
+        let started = Instant::now();
+        self.clear_input_cache();
+        // 1. Save stage
+        let stage = self.stage()?;
+        // 2. Unstage
+        self.unstage()?;
+        // 3. Get new list of blocks
+        let data_r = self.data.read().expect("cannot_acquire_data_for_writing");
+        let list_str = data_r.list_raw_items(DELTA_EXTENSION)?;
+        drop(data_r);
+        // 4. Refresh data storage
+        let mut data_w = self.data.write().expect("cannot_acquire_data_for_writing");
+        data_w.refresh()?;
+        drop(data_w);
+        // 5. Load new blocks
+        if !list_str.is_empty() {
+            for i in &list_str {
+                let is_new_block = !self
+                    .blocks
+                    .read()
+                    .expect("cannot_acquire_blocks_for_reading")
+                    .contains_key(i)
+                    && !self.retired_blocks.read().unwrap().contains(i);
+                if is_new_block {
+                    match self
+                        .fetch_raw_block(i)
+                        .and_then(|block| self.parse_raw_block(i.to_string(), block))
+                    {
+                        Ok(mut block) => {
+                            if self.shallow {
+                                block.changes = None;
+                            }
+                            self.blocks
+                                .write()
+                                .expect("cannot_acquire_blocks_for_writing")
+                                .insert(i.to_string(), RwLock::new(block));
+                        }
+                        #[cfg(feature = "tracing")]
+                        Err(e) => tracing::debug!(block = %i, error = %e, "cannot load block"),
+                        #[cfg(not(feature = "tracing"))]
+                        Err(_) => {}
+                    }
+                }
+            }
+        }
+        // 6. Turn invalid blocks into unknown status blocks
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        blocks_r.par_iter().for_each(|(_, block)| {
+            let status = block
+                .read()
+                .expect("cannot_acquire_block_for_reading")
+                .status;
+            if status == Status::Invalid {
+                block
+                    .write()
+                    .expect("cannot_acquire_block_for_writing")
+                    .status = Status::Unknown;
+            }
+        });
+        drop(blocks_r);
+        // 7. Mark valid blocks
+        self.mark_valid_blocks();
+        // 8. Apply all valid blocks
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        blocks_r.iter().for_each(|(_, block)| {
+            let block_r = block.read().expect("cannot_acquire_block_for_reading");
+            let status = block
+                .read()
+                .expect("cannot_acquire_block_for_reading")
+                .status;
+            if status == Status::Valid && self.apply_block(&block_r).is_ok() {
+                drop(block_r);
+                let mut block_w = block.write().expect("cannot_acquire_block_for_writing");
+                self.set_applied(&mut block_w);
+                // We can drop the changes vector
+                if !self.retains_block_changes() {
+                    block_w.changes = None;
+                }
+            }
+        });
+        drop(blocks_r);
+        self.retire_blocks();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            blocks = self.blocks.read().unwrap().len(),
+            elapsed = ?started.elapsed(),
+            "refreshed blocks"
+        );
+        // 9. Re-apply stage
+        self.replay_stage(&stage)?;
+        self.notify_subscribers();
+        Ok(())
+    }
+
+    /// Returns true if the adapter contains delta blocks which have not been loaded yet, that
+    /// is if refresh may change the state of the replica. Only the list of blocks is read (no
+    /// block is fetched or parsed) and no write lock is acquired, hence this is a cheap probe
+    /// before refreshing. Blocks which could not be loaded by a previous refresh (for example
+    /// because their packs were not available yet) are reported as new.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let other = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert!(!other.has_new_blocks().unwrap());
+    /// replica.update(json!({ "somekey" : 1 }).as_object().unwrap().clone());
+    /// replica.commit(None).unwrap();
+    /// assert!(!replica.has_new_blocks().unwrap());
+    /// assert!(other.has_new_blocks().unwrap());
+    /// other.refresh().unwrap();
+    /// assert!(!other.has_new_blocks().unwrap());
+    /// ```
+    pub fn has_new_blocks(&self) -> Result<bool> {
+        let list_str = self
+            .data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .list_raw_items(DELTA_EXTENSION)?;
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        let retired_r = self.retired_blocks.read().unwrap();
+        Ok(list_str
+            .iter()
+            .any(|b| !blocks_r.contains_key(b) && !retired_r.contains(b)))
+    }
+
+    /// Reloads the CRDT until the given block
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - Block identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// replica.delete_object("myobject");
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", winner);
+    /// let value = replica.get_value("myobject", &winner);
+    /// assert!(value.is_ok());
+    /// assert!(value.unwrap().contains_key("_deleted"));
+    /// let info = json!({ "author" : "Some user", "date" : "2022-05-23 13:47:00CET" }).as_object().unwrap().clone();
+    /// replica.commit(Some(info));
+    /// replica.reload_until(&block_id);
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// ```
+    pub fn reload_until(&self, block_id: &str) -> Result<()> {
+        let stage_r = self.stage.read().expect("cannot_acquire_stage_for_reading");
+        let mut documents_w = self
+            .documents
+            .write()
+            .expect("cannot_acquire_documents_for_writing");
+        // Ensure that the stage is empty
+        if !stage_r.is_empty() {
+            bail!("stage_not_empty")
+        }
+        // Clear the documents
+        documents_w.clear();
+        drop(documents_w);
+        self.clear_input_cache();
+        self.clear_pruned();
+        self.inconsistent_revisions.lock().unwrap().clear();
+        // Read block list
+        let data_r = self.data.write().expect("cannot_acquire_data_for_writing");
+        let list_str = data_r.list_raw_items(DELTA_EXTENSION)?;
+        drop(data_r);
+        // Reload data storage
+        let mut data_w = self.data.write().expect("cannot_acquire_data_for_writing");
+        data_w.reload()?;
+        drop(data_w);
+        self.load_pins()?;
+        // Clear the blocks
+        let mut blocks_w = self
+            .blocks
+            .write()
+            .expect("cannot_acquire_blocks_for_writing");
+        blocks_w.clear();
+        self.applied_children.lock().unwrap().clear();
+        self.anchors_digest.lock().unwrap().take();
+        self.retired_blocks.write().unwrap().clear();
+        // Fetch and parse blocks
+        if !list_str.is_empty() {
+            for i in &list_str {
+                if self.is_cancelled() {
+                    drop(blocks_w);
+                    drop(stage_r);
+                    return self.cancel_reload();
+                }
+                if let Ok(block) = self.fetch_raw_block(i) {
+                    if let Ok(block) = self.parse_raw_block(i.to_string(), block) {
+                        blocks_w.insert(i.to_string(), RwLock::new(block));
+                    }
+                }
+            }
+        }
+        drop(blocks_w);
+        // Mark valid blocks
+        self.mark_valid_blocks();
+        // Check if block is valid
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        if !blocks_r.contains_key(block_id) {
+            bail!(
+                "reload_until_interrupted_block_not_found: {} {:?}",
+                block_id,
+                blocks_r.keys()
+            );
+        }
+        if blocks_r.get(block_id).unwrap().read().unwrap().status != Status::Valid {
+            bail!("reload_until_interrupted_invalid_block: {}", block_id);
+        }
+        // Apply block and parents
+        let mut to_apply = VecDeque::new();
+        to_apply.push_back(block_id.to_string());
+        while !to_apply.is_empty() {
+            if self.is_cancelled() {
+                drop(blocks_r);
+                drop(stage_r);
+                return self.cancel_reload();
+            }
+            let bid = to_apply.pop_front().unwrap();
+            let block_item = blocks_r.get(&bid).unwrap();
+            let block_r = block_item.read().expect("cannot_acquire_block_for_reading");
+            let status = block_r.status;
+            if status == Status::Valid && self.apply_block(&block_r).is_ok() {
+                if let Some(parents) = &block_r.parents {
+                    for b in parents {
+                        to_apply.push_back(b.to_string());
+                    }
+                }
+                drop(block_r);
+                let mut block_w = block_item
+                    .write()
+                    .expect("cannot_acquire_block_for_writing");
+                self.set_applied(&mut block_w);
+                // We can drop the changes vector
+                if !self.retains_block_changes() {
+                    block_w.changes = None;
+                }
+            }
+        }
+        drop(blocks_r);
+        self.retire_blocks();
+        self.notify_subscribers();
+        Ok(())
+    }
+
+    /// Rebuilds the revision trees of all objects from the applied blocks, as reload would but
+    /// without reading the blocks from the adapter again: the changes of the blocks must be
+    /// retained in memory (see MeldaConfig::retain_block_changes), otherwise the method fails
+    /// (blocks retired in shallow mode are not retained either). The trees are rebuilt
+    /// separately and replace the current ones only on success, hence on failure the current
+    /// state is left untouched. Pruned histories (see prune_history) are restored. Fails if the
+    /// stage is not empty.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let config = MeldaConfig { retain_block_changes: true, ..MeldaConfig::default() };
+    /// let replica = Melda::new_with_config(adapter.clone(), config).expect("cannot_initialize_crdt");
+    /// for i in 0..3 {
+    ///     replica.update(json!({ "somekey" : i }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// let expected = replica.read().unwrap();
+    /// assert_eq!(replica.prune_history("\u{221A}", 1).unwrap(), 2);
+    /// replica.rebuild_from_blocks().unwrap();
+    /// assert_eq!(replica.revision_graph("\u{221A}").unwrap().len(), 3);
+    /// assert_eq!(replica.read().unwrap(), expected);
+    /// replica.update(json!({ "somekey" : 4 }).as_object().unwrap().clone()).unwrap();
+    /// assert!(replica.rebuild_from_blocks().is_err());
+    /// // Without retained changes the blocks would have to be read again
+    /// let other = Melda::new(adapter).expect("cannot_initialize_crdt");
+    /// assert_eq!(other.rebuild_from_blocks().unwrap_err().to_string(), "block_changes_not_retained");
+    /// ```
+    pub fn rebuild_from_blocks(&self) -> Result<()> {
+        if self.has_staging() {
+            bail!("stage_not_empty")
+        }
+        if !self.retired_blocks.read().unwrap().is_empty() {
+            bail!("block_changes_not_retained");
+        }
+        // Rebuild the trees from the retained changes of the applied blocks
+        let mut documents = BTreeMap::new();
+        let mut inconsistent = BTreeMap::new();
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        for block in blocks_r.values() {
+            let block_r = block.read().expect("cannot_acquire_block_for_reading");
+            if block_r.status != Status::ValidAndApplied {
+                continue;
+            }
+            match &block_r.changes {
+                Some(changes) => Self::apply_changes(
+                    &block_r.id,
+                    changes,
+                    self.revision_tail_length,
+                    &mut documents,
+                    &mut inconsistent,
+                ),
+                None => bail!("block_changes_not_retained"),
+            }
+        }
+        drop(blocks_r);
+        // Replace the current trees
+        *self
+            .documents
+            .write()
+            .expect("failed_to_acquire_documents_for_writing") = documents;
+        self.clear_input_cache();
+        *self.inconsistent_revisions.lock().unwrap() = inconsistent;
+        self.clear_pruned();
+        self.notify_subscribers();
+        Ok(())
+    }
+
+    /// Drops uncommitted changes
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// replica.delete_object("myobject");
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", winner);
+    /// let value = replica.get_value("myobject", &winner);
+    /// assert!(value.is_ok());
+    /// assert!(value.unwrap().contains_key("_deleted"));
+    /// replica.unstage();
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// ```
+    pub fn unstage(&self) -> Result<()> {
+        self.unstage_reporting().map(|_| ())
+    }
+
+    /// Drops uncommitted changes (see unstage), returning the dropped changes as
+    /// (object, revision, parent revision) tuples, sorted by object and revision
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.create_object("myobject", json!({ "a" : 1 }).as_object().unwrap().clone());
+    /// let first = replica.get_winner("myobject").unwrap();
+    /// replica.commit(None);
+    /// replica.update_object("myobject", json!({ "a" : 2 }).as_object().unwrap().clone());
+    /// let second = replica.get_winner("myobject").unwrap();
+    /// replica.create_object("another", json!({ "b" : 1 }).as_object().unwrap().clone());
+    /// let another = replica.get_winner("another").unwrap();
+    /// let dropped = replica.unstage_reporting().unwrap();
+    /// assert_eq!(dropped, vec![("another".to_string(), another, None), ("myobject".to_string(), second, Some(first.clone()))]);
+    /// assert_eq!(replica.get_winner("myobject").unwrap(), first);
+    /// assert!(replica.unstage_reporting().unwrap().is_empty());
+    /// ```
+    pub fn unstage_reporting(&self) -> Result<Vec<BlockChange>> {
+        self.data
+            .write()
+            .expect("cannot_acquire_data_for_writing")
+            .unstage()?;
+        let mut stage = self.stage.write().unwrap();
+        let mut docs_w = self
+            .documents
+            .write()
+            .expect("failed_to_acquire_documents_for_writing");
+        let mut dropped = Vec::with_capacity(stage.len());
+        stage.iter().for_each(|Change(uuid, rev, prev)| {
+            if let Some(rt) = docs_w.get(uuid) {
+                let mut rt_w = rt
+                    .write()
+                    .expect("cannot_acquire_revision_tree_for_writing");
+                rt_w.remove(rev.clone(), prev.clone());
+                if rt_w.is_empty() {
+                    drop(rt_w);
+                    docs_w.remove(uuid);
+                }
+            }
+            dropped.push((
+                uuid.clone(),
+                rev.to_string(),
+                prev.as_ref().map(|p| p.to_string()),
+            ));
+        });
+        stage.clear();
+        drop(docs_w);
+        drop(stage);
+        self.clear_input_cache();
+        self.notify_subscribers();
+        dropped.sort();
+        Ok(dropped)
+    }
+
+    /// Merges changes from another Melda into this one (does not stage changes)
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// replica2.merge(&replica);
+    /// assert!(replica2.get_all_objects().contains("myobject"));
+    /// let winner = replica2.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// assert!(replica2.get_block(&block_id).unwrap().is_none());
+    pub fn merge(&self, other: &Melda) -> Result<()> {
+        for (uuid, rt) in other.documents.read().unwrap().iter() {
+            let rt_r = rt
+                .read()
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            let mut docs_w = self
+                .documents
+                .write()
+                .expect("cannot_acquire_documents_for_writing");
+            let mut rt_w = docs_w
+                .entry(uuid.to_string())
+                .or_insert_with(|| RwLock::new(RevisionTree::new()))
+                .write()
+                .expect("cannot_acquire_revision_tree_for_writing");
+            rt_w.merge(&rt_r);
+            drop(rt_r);
+            drop(rt_w);
+        }
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        let otherdata = &other.data.read().unwrap();
+        data.merge(otherdata)
+    }
+
+    /// Melds another Melda into this one
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// assert!(replica2.get_all_objects().contains("myobject"));
+    /// let winner = replica2.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block = replica2.get_block(&block_id).unwrap().unwrap();
+    /// assert_eq!(block_id, block.id);
+    pub fn meld(&self, other: &Melda) -> Result<Vec<String>> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("meld").entered();
+        #[cfg(feature = "tracing")]
+        let started = Instant::now();
+        let mut result = vec![];
+        let other_data = other.data.read().unwrap();
+        let other_items = other_data.list_raw_items("")?;
+        if !other_items.is_empty() {
+            let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+            let this_items = data.list_raw_items("")?;
+            let this_items: HashSet<String> = this_items.into_iter().collect();
+            // Reuse the same buffer for all items
+            let mut buf = vec![];
+            let progress = self.progress_reporter();
+            for (done, i) in other_items.iter().enumerate() {
+                if self.is_cancelled() {
+                    bail!("cancelled");
+                }
+                if !this_items.contains(i) {
+                    buf.clear();
+                    other_data.read_raw_bytes_into(i, 0, 0, &mut buf)?;
+                    data.write_raw_bytes(i, &buf)?;
+                    result.push(i.clone());
+                }
+                if let Some(progress) = &progress {
+                    progress.on_progress(ProgressOperation::Meld, done + 1, other_items.len());
+                }
+            }
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            copied = result.len(),
+            elapsed = ?started.elapsed(),
+            "melded items"
+        );
+        Ok(result)
+    }
+
+    /// Melds another Melda into this one (see meld), verifying each copied item before writing
+    /// it: the digest of packs, delta blocks, blobs and repack records must match their
+    /// identifier, indexes must be valid JSON objects. Items which do not pass the verification
+    /// are skipped. Returns the list of copied items and the list of rejected items.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : "somevalue" }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// replica.commit(None);
+    /// adapter.write().unwrap().write_object("0123456789.delta", "{}".as_bytes());
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// let (copied, rejected) = replica2.meld_verified(&replica).unwrap();
+    /// assert_eq!(rejected, vec!["0123456789.delta".to_string()]);
+    /// assert!(copied.iter().any(|i| i.ends_with(".pack")));
+    /// replica2.refresh();
+    /// assert_eq!(replica.read().unwrap(), replica2.read().unwrap());
+    /// ```
+    pub fn meld_verified(&self, other: &Melda) -> Result<(Vec<String>, Vec<String>)> {
+        let mut copied = vec![];
+        let mut rejected = vec![];
+        let other_data = other.data.read().unwrap();
+        let other_items = other_data.list_raw_items("")?;
+        if !other_items.is_empty() {
+            let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+            let this_items: HashSet<String> = data.list_raw_items("")?.into_iter().collect();
+            let mut buf = vec![];
+            let progress = self.progress_reporter();
+            let total = other_items.len();
+            for (done, i) in other_items.into_iter().enumerate() {
+                if self.is_cancelled() {
+                    bail!("cancelled");
+                }
+                if !this_items.contains(&i) {
+                    buf.clear();
+                    other_data.read_raw_bytes_into(&i, 0, 0, &mut buf)?;
+                    if DataStorage::is_valid_raw_item(&i, &buf) {
+                        data.write_raw_bytes(&i, &buf)?;
+                        copied.push(i);
+                    } else {
+                        rejected.push(i);
+                    }
+                }
+                if let Some(progress) = &progress {
+                    progress.on_progress(ProgressOperation::Meld, done + 1, total);
+                }
+            }
+        }
+        Ok((copied, rejected))
+    }
+
+    /// Copies from another Melda only the delta blocks reachable (through their parents) from the
+    /// given anchors, along with the packs (and indexes) referenced by those blocks: unrelated
+    /// history is left behind. Repack records are always copied. Binary blobs are not copied
+    /// (use meld to transfer them). After fetching, the state at each anchor can be rebuilt with
+    /// reload_until. Returns the list of copied items.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    /// * `anchors` - The anchor blocks (on the other Melda)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeSet;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : "first" }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// let first = replica.commit(None).unwrap().unwrap();
+    /// replica.update(json!({ "somekey" : "second" }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// replica2.fetch_subtree(&replica, &BTreeSet::from([first.clone()])).unwrap();
+    /// assert_eq!(adapter2.read().unwrap().list_objects(".delta").unwrap(), vec![first.clone()]);
+    /// replica2.reload_until(&first).unwrap();
+    /// assert_eq!(replica2.read().unwrap().get("somekey"), Some(&json!("first")));
+    /// assert!(replica2.fetch_subtree(&replica, &BTreeSet::from(["unknown".to_string()])).is_err());
+    /// ```
+    pub fn fetch_subtree(&self, other: &Melda, anchors: &BTreeSet<String>) -> Result<Vec<String>> {
+        // Determine the blocks reachable from the anchors
+        let other_blocks = other
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        let mut reachable = BTreeSet::new();
+        let mut packs = BTreeSet::new();
+        let mut to_visit: Vec<String> = anchors.iter().cloned().collect();
+        while let Some(bid) = to_visit.pop() {
+            if reachable.contains(&bid) {
+                continue;
+            }
+            let block = other_blocks
+                .get(&bid)
+                .ok_or_else(|| anyhow!("unknown_block: {}", bid))?
+                .read()
+                .expect("cannot_acquire_block_for_reading");
+            if let Some(parents) = &block.parents {
+                to_visit.extend(parents.iter().cloned());
+            }
+            if let Some(pks) = &block.packs {
+                packs.extend(pks.iter().cloned());
+            }
+            drop(block);
+            reachable.insert(bid);
+        }
+        drop(other_blocks);
+        // Determine the items to copy
+        let other_data = other.data.read().expect("cannot_acquire_data_for_reading");
+        let indexes: HashSet<String> = other_data
+            .list_raw_items(INDEX_EXTENSION)?
+            .into_iter()
+            .collect();
+        let mut items: Vec<String> = reachable
+            .into_iter()
+            .map(|bid| bid + DELTA_EXTENSION)
+            .collect();
+        for pack in packs {
+            for p in other_data.resolve_pack(&pack) {
+                if indexes.contains(&p) {
+                    items.push(p.clone() + INDEX_EXTENSION);
+                }
+                items.push(p + PACK_EXTENSION);
+            }
+        }
+        items.extend(
+            other_data
+                .list_raw_items(REPACK_EXTENSION)?
+                .into_iter()
+                .map(|r| r + REPACK_EXTENSION),
+        );
+        // Copy the missing items
+        let mut result = vec![];
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        let this_items: HashSet<String> = data.list_raw_items("")?.into_iter().collect();
+        let mut buf = vec![];
+        for i in items {
+            if !this_items.contains(&i) {
+                buf.clear();
+                other_data.read_raw_bytes_into(&i, 0, 0, &mut buf)?;
+                data.write_raw_bytes(&i, &buf)?;
+                result.push(i);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Replicate changes from another Melda into this one
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// replica2.replicate(&replica);
+    /// assert!(replica2.get_all_objects().contains("myobject"));
+    /// let winner = replica2.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// assert!(replica2.get_block(&block_id).unwrap().is_none());
+    pub fn replicate(&self, other: &Melda) -> Result<()> {
+        let other_data = other.data.read().unwrap();
+        let other_documents = other.documents.read().unwrap();
+        let other_stage = other.stage.read().unwrap();
+        if !self.stage.read().unwrap().is_empty() {
+            bail!("stage_not_empty")
+        }
+        if !other_stage.is_empty() {
+            bail!("other_stage_not_empty")
+        }
+        self.data
+            .write()
+            .expect("cannot_get_data_for_writing")
+            .replicate(&other_data)?;
+        for (uuid, other_rt) in other_documents.iter() {
+            let other_rt_r = other_rt
+                .read()
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            let mut docs_w = self
+                .documents
+                .write()
+                .expect("cannot_get_documents_for_writing");
+            let mut rt_w = docs_w
+                .entry(uuid.to_string())
+                .or_insert_with(|| RwLock::new(RevisionTree::new()))
+                .write()
+                .expect("cannot_acquire_revision_tree_for_writing");
+            for (rev, prev) in other_rt_r.get_revisions() {
+                if rt_w.add(rev.clone(), prev.clone()) {
+                    self.stage
+                        .write()
+                        .expect("cannot_get_stage_for_writing")
+                        .push(Change(uuid.clone(), rev.clone(), prev.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Imports the current state of an independent Melda (which does not share any history with
+    /// this one) by mounting its root object under a flattened field of the root object of
+    /// this replica (the change is staged).
+    ///
+    /// The imported root object is given a fresh identifier (see generate_id), the root object
+    /// identifier of the other replica is not used. Objects of the other replica are flattened
+    /// again under the new root object: objects with a user defined identifier (_id field) keep
+    /// it, other objects receive new identifiers derived from their new path. If an object with
+    /// the same identifier already exists in this replica the import only succeeds if both
+    /// objects have the same content (in which case the object is shared), otherwise the import
+    /// fails without changes (the conflicting identifiers must be renamed in one of the
+    /// replicas, for example with rename_object). The items of the other replica which are
+    /// missing in this one (packs, indexes and binary blobs) are copied as meld does, except
+    /// delta blocks and pins: the history (blocks) of the other replica is not imported, since
+    /// it would replace the root object of this replica. Only the current state of the other
+    /// replica is recorded, as new changes in this replica.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    /// * `mount_key` - The (flattened) field of the root object under which the other root object is mounted
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "items\u{266D}" : [ { "_id": "1", "key" : "alpha" } ] }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// replica.commit(None);
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut other = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "items\u{266D}" : [ { "_id": "1", "key" : "alpha" }, { "_id": "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// other.update(object);
+    /// other.commit(None);
+    /// assert!(replica.import(&other, "team").is_err());
+    /// let mounted = replica.import(&other, "team\u{266D}").unwrap();
+    /// replica.commit(None);
+    /// let readback = replica.read().unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!(format!("{{\"_id\":\"\u{221A}\",\"items\u{266D}\":[{{\"_id\":\"1\",\"key\":\"alpha\"}}],\"team\u{266D}\":{{\"_id\":\"{}\",\"items\u{266D}\":[{{\"_id\":\"1\",\"key\":\"alpha\"}},{{\"_id\":\"2\",\"key\":\"beta\"}}]}}}}", mounted), content);
+    /// // The blocks of the other replica are not copied
+    /// assert!(other.get_anchors().iter().all(|b| replica.get_block(b).unwrap().is_none()));
+    /// let object = json!({ "items\u{266D}" : [ { "_id": "2", "key" : "gamma" } ] }).as_object().unwrap().clone();
+    /// other.update(object);
+    /// assert!(replica.import(&other, "another\u{266D}").is_err());
+    /// ```
+    pub fn import(&self, other: &Melda, mount_key: &str) -> Result<String> {
+        self.autocommit(|| self.import_impl(other, mount_key))
+    }
+
+    // Imports another Melda (see import)
+    fn import_impl(&self, other: &Melda, mount_key: &str) -> Result<String> {
+        if std::ptr::eq(self, other) {
+            bail!("cannot_import_self");
+        }
+        if !is_flattened_field(mount_key) {
+            bail!("mount_key_not_flattened");
+        }
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let mut root = match docs_r.get(ROOT_ID) {
+            Some(rt) => {
+                let rt_r = rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                self.read_object(ROOT_ID, &rt_r)?
+            }
+            None => Map::new(),
+        };
+        if root.contains_key(mount_key) {
+            bail!("mount_key_already_exists");
+        }
+        // Flatten the other root object under the mount point (with a fresh identifier)
+        let mut other_root = other.read()?;
+        other_root.insert(ID_FIELD.to_string(), Value::from(self.generate_id()));
+        let mut extracted_objects = HashMap::<String, Map<String, Value>>::new();
+        let path = vec![ROOT_ID.to_string(), mount_key.to_string()];
+        let mounted = flatten(
+            &mut extracted_objects,
+            &Value::from(other_root),
+            &path,
+            self.id_strategy,
+        );
+        // Check for conflicting identifiers before recording any change
+        let mut new_objects = vec![];
+        for (uuid, obj) in extracted_objects {
+            if let Some(rt) = docs_r.get(&uuid) {
+                let rt_r = rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                match rt_r.get_winner() {
+                    Some(winner) if !winner.is_deleted() => {
+                        if self.read_object(&uuid, &rt_r)? != obj {
+                            bail!("conflicting_object_identifier: {}", uuid);
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            new_objects.push((uuid, obj));
+        }
+        drop(docs_r);
+        // Copy the missing items (except the history and the pins of the other replica)
+        let other_data = other.data.read().expect("cannot_acquire_data_for_reading");
+        let mut data_w = self.data.write().expect("cannot_acquire_data_for_writing");
+        let this_items: HashSet<String> = data_w.list_raw_items("")?.into_iter().collect();
+        let mut buf = vec![];
+        for i in other_data.list_raw_items("")? {
+            if !this_items.contains(&i)
+                && !i.ends_with(DELTA_EXTENSION)
+                && !i.ends_with(PINS_EXTENSION)
+            {
+                buf.clear();
+                other_data.read_raw_bytes_into(&i, 0, 0, &mut buf)?;
+                data_w.write_raw_bytes(&i, &buf)?;
+            }
+        }
+        drop(data_w);
+        drop(other_data);
+        // Record the imported objects and mount them
+        for (uuid, obj) in new_objects {
+            self.update_object(&uuid, obj)?;
+        }
+        root.insert(mount_key.to_string(), mounted.clone());
+        self.update_object(ROOT_ID, root)?;
+        Ok(mounted.as_str().unwrap_or_default().to_string())
+    }
+
+    /// Warms up the caches by reading the values of the winning revisions into the data cache
+    /// and rebuilding the order of array descriptors into the array descriptors cache, so that
+    /// subsequent reads do not need to access the packs. At most as many objects (and array
+    /// descriptors) as the capacity of the respective cache are loaded. Values are read in
+    /// parallel. Returns the number of objects and array descriptors which have been loaded.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1" }, { "_id" : "2" } ] }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// replica.commit(None);
+    /// let replica2 = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert_eq!(replica2.warm_cache().unwrap(), 4);
+    /// assert_eq!(replica.read().unwrap(), replica2.read().unwrap());
+    /// ```
+    pub fn warm_cache(&self) -> Result<usize> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let mut objects = vec![];
+        let mut descriptors = vec![];
+        for (uuid, rt) in docs_r.iter() {
+            let rt_r = rt
+                .read()
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            if let Some(winner) = rt_r.get_winner() {
+                if is_array_descriptor(uuid) {
+                    descriptors.push(rt);
+                } else if !winner.is_deleted() {
+                    objects.push(winner.clone());
+                }
+            }
+        }
+        // Load the values of the objects
+        let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
+        objects.truncate(data_r.cache_capacity());
+        objects
+            .par_iter()
+            .try_for_each(|revision| data_r.warm_object(revision))?;
+        drop(data_r);
+        // Rebuild the order of array descriptors
+        let capacity = self.array_descriptors_cache.lock().unwrap().cap().get();
+        descriptors.truncate(capacity);
+        descriptors.par_iter().try_for_each(|rt| -> Result<()> {
+            let rt_r = rt
+                .read()
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            self.get_merged_order(&rt_r)?;
+            Ok(())
+        })?;
+        Ok(objects.len() + descriptors.len())
+    }
+
+    /// Reads the data structure and unflattens to a JSON object. Each object is read at its
+    /// winning revision, including the root object: if the root object is in conflict (for
+    /// example after melding two replicas which independently created the document) only the
+    /// winning version is returned, use read_roots to obtain all the candidate versions. An
+    /// error is returned if the root object does not exist or has been deleted.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json,to_string};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// let readback = replica.read().unwrap();
+    /// assert!(readback.contains_key("somekey"));
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\":[\"somedata\",1,2,3,4]}", content);
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey\u{266D}" : [ { "_id": "1", "key" : "alpha" }, { "_id": "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// let readback = replica.read().unwrap();
+    /// assert!(!readback.contains_key("somekey"));
+    /// assert!(readback.contains_key("somekey\u{266D}"));
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"1\",\"key\":\"alpha\"},{\"_id\":\"2\",\"key\":\"beta\"}]}", content);
+    /// let info = json!({ "author" : "Some user", "date" : "2022-05-23 13:47:00CET" }).as_object().unwrap().clone();
+    /// replica.commit(Some(info));
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// // Continue editing on replica, removing one item
+    /// let object = json!({ "somekey\u{266D}" : [ { "_id": "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// let readback = replica.read().unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"2\",\"key\":\"beta\"}]}", content);
+    /// // Commit changes on replica
+    /// let info = json!({ "author" : "Some user", "date" : "2022-05-23 13:47:00CET" }).as_object().unwrap().clone();
+    /// replica.commit(Some(info));
+    /// let readback = replica.read().unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"2\",\"key\":\"beta\"}]}", content);
+    /// // Perform some changes on replica2 too
+    /// let object = json!({ "somekey\u{266D}" : [ { "_id": "1", "key" : "alpha" }, { "_id": "2", "key" : "beta" }, { "_id": "3", "key" : "gamma" } ] }).as_object().unwrap().clone();
+    /// replica2.update(object.clone());
+    /// let readback = replica2.read().unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"1\",\"key\":\"alpha\"},{\"_id\":\"2\",\"key\":\"beta\"},{\"_id\":\"3\",\"key\":\"gamma\"}]}", content);
+    /// // Commit changes on replica2
+    /// let info = json!({ "author" : "Another user", "date" : "2022-05-23 13:48:00CET" }).as_object().unwrap().clone();
+    /// replica2.commit(Some(info));
+    /// let readback = replica2.read().unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"1\",\"key\":\"alpha\"},{\"_id\":\"2\",\"key\":\"beta\"},{\"_id\":\"3\",\"key\":\"gamma\"}]}", content);
+    /// // Meld changes from replica2 back on replica
+    /// replica.meld(&replica2);
+    /// // Melding does not change the state of replica
+    /// let readback = replica.read().unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"2\",\"key\":\"beta\"}]}", content);
+    /// // Refresh the state of replica
+    /// replica.refresh();
+    /// let readback = replica.read().unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"2\",\"key\":\"beta\"},{\"_id\":\"3\",\"key\":\"gamma\"}]}", content);
+    /// // Empty arrays, empty objects and null values are read back as they are
+    /// let object = json!({ "a\u{266D}" : [], "b\u{266D}" : {}, "c\u{266D}" : null, "d\u{266D}" : [ null, 1, "x", [] ], "e" : {} }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// let mut readback = replica.read().unwrap();
+    /// readback.remove("_id");
+    /// readback.get_mut("b\u{266D}").unwrap().as_object_mut().unwrap().remove("_id");
+    /// assert_eq!(readback, object);
+    pub fn read(&self) -> Result<Map<String, Value>> {
+        self.read_impl(false, true)
+    }
+
+    /// Reads the current state of the CRDT like read, but objects whose winning revision is a
+    /// deletion are included as tombstones ({ "_deleted" : true, "_id" : uuid }) wherever they
+    /// are still referenced (read omits references to deleted objects, removing the field or
+    /// the array item). Deleted array descriptors are not included.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "item\u{266D}" : { "_id" : "1", "key" : "value" } }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// replica.delete_object("1");
+    /// let readback = replica.read().unwrap();
+    /// assert!(readback.get("item\u{266D}").is_none());
+    /// let readback = replica.read_with_tombstones().unwrap();
+    /// assert_eq!(readback.get("item\u{266D}").unwrap(), &json!({ "_deleted" : true, "_id" : "1" }));
+    /// ```
+    pub fn read_with_tombstones(&self) -> Result<Map<String, Value>> {
+        self.read_impl(true, true)
+    }
+
+    /// Reads the current state of the CRDT like read, but without the identifier field (_id)
+    /// which read adds to each object (including the root object and the objects in flattened
+    /// arrays). References and array descriptors are resolved as in read: the result only
+    /// differs from read in the missing identifiers.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(replica.read_clean().is_err());
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "key" : "alpha", "inner\u{266D}" : { "k" : [1, 2] } }, "text" ], "n" : 1 }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// let expected = json!({ "items\u{266D}" : [ { "key" : "alpha", "inner\u{266D}" : { "k" : [1, 2] } }, "text" ], "n" : 1 });
+    /// assert_eq!(Value::from(replica.read_clean().unwrap()), expected);
+    /// replica.commit(None).unwrap();
+    /// assert_eq!(Value::from(replica.read_clean().unwrap()), expected);
+    /// assert_eq!(replica.read().unwrap()["items\u{266D}"][0]["_id"], json!("1"));
+    /// ```
+    pub fn read_clean(&self) -> Result<Map<String, Value>> {
+        self.read_impl(false, false)
+    }
+
+    /// Reads the current state of the CRDT like read, but objects are read from the storage
+    /// only when they are reached while unflattening (instead of reading all the objects in
+    /// parallel first). This reduces the peak memory usage for large documents, since only
+    /// the objects being reconstructed are kept in memory alongside the result.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(replica.read_lazy().is_err());
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "inner\u{266D}" : { "_id" : "2", "key" : "value" } }, "text" ] }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// replica.commit(None);
+    /// replica.delete_object("2");
+    /// assert_eq!(replica.read().unwrap(), replica.read_lazy().unwrap());
+    /// ```
+    pub fn read_lazy(&self) -> Result<Map<String, Value>> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        if !docs_r.contains_key(ROOT_ID) {
+            bail!("no_root")
+        }
+        let timer = self.start_timer();
+        let count = Mutex::new(0);
+        let fetch = |uuid: &str| -> Result<Option<Map<String, Value>>> {
+            let rt_r = match docs_r.get(uuid) {
+                Some(rt) => rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading"),
+                None => return Ok(None),
+            };
+            if rt_r.get_winner().is_none_or(|w| w.is_deleted()) {
+                return Ok(None);
+            }
+            let mut obj = self.read_object(uuid, &rt_r)?;
+            obj.insert(ID_FIELD.to_string(), Value::from(uuid));
+            *count.lock().unwrap() += 1;
+            Ok(Some(obj))
+        };
+        let root = fetch(ROOT_ID)?.ok_or_else(|| anyhow!("root_object_not_found"))?;
+        let result = unflatten_with(&fetch, &Value::from(root), self.max_read_depth)?;
+        if let Some((metrics, start)) = timer {
+            metrics.on_read(start.elapsed(), count.into_inner().unwrap());
+        }
+        match result {
+            Some(Value::Object(result)) => Ok(result),
+            _ => bail!("not_an_object"),
+        }
+    }
+
+    /// Reads every candidate version of the document: one for each leaf revision of the root
+    /// object which is not a deletion, each paired with its revision. The winning revision (the
+    /// one used by read) comes first. More than one version is returned when the root object is
+    /// in conflict, for example after melding two replicas which independently created the
+    /// document. Objects referenced by the root are read at their winning revision.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(replica.read_roots().is_err());
+    /// replica.update(json!({ "title" : "first" }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// assert_eq!(replica.read_roots().unwrap().len(), 1);
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// replica2.update(json!({ "title" : "second" }).as_object().unwrap().clone());
+    /// replica2.commit(None);
+    /// replica.meld(&replica2).unwrap();
+    /// replica.refresh().unwrap();
+    /// // The root object is in conflict: read only returns the winning version
+    /// assert!(replica.in_conflict().contains("\u{221A}"));
+    /// let roots = replica.read_roots().unwrap();
+    /// assert_eq!(roots.len(), 2);
+    /// assert_eq!(roots[0].0, replica.get_winner("\u{221A}").unwrap());
+    /// assert_eq!(roots[0].1, replica.read().unwrap());
+    /// let titles: Vec<&Value> = roots.iter().map(|(_, root)| root.get("title").unwrap()).collect();
+    /// assert!(titles.contains(&&json!("first")) && titles.contains(&&json!("second")));
+    /// ```
+    pub fn read_roots(&self) -> Result<Vec<(String, Map<String, Value>)>> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let leafs: Vec<Revision> = {
+            let rt_r = docs_r
+                .get(ROOT_ID)
+                .ok_or_else(|| anyhow!("no_root"))?
+                .read()
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            // Winning revision first
+            rt_r.get_leafs()
+                .into_iter()
+                .rev()
+                .filter(|r| !r.is_deleted())
+                .cloned()
+                .collect()
+        };
+        let fetch = |uuid: &str| -> Result<Option<Map<String, Value>>> {
+            let rt_r = match docs_r.get(uuid) {
+                Some(rt) => rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading"),
+                None => return Ok(None),
+            };
+            if rt_r.get_winner().is_none_or(|w| w.is_deleted()) {
+                return Ok(None);
+            }
+            let mut obj = self.read_object(uuid, &rt_r)?;
+            obj.insert(ID_FIELD.to_string(), Value::from(uuid));
+            Ok(Some(obj))
+        };
+        let mut roots = vec![];
+        for revision in leafs {
+            let mut root = self
+                .data
+                .read()
+                .expect("cannot_acquire_data_for_reading")
+                .read_object(&revision)?;
+            root.insert(ID_FIELD.to_string(), Value::from(ROOT_ID));
+            let result = unflatten_with(&fetch, &Value::from(root), self.max_read_depth)?;
+            match result {
+                Some(Value::Object(result)) => roots.push((revision.to_string(), result)),
+                _ => bail!("not_an_object"),
+            }
+        }
+        Ok(roots)
+    }
+
+    fn read_impl(&self, tombstones: bool, ids: bool) -> Result<Map<String, Value>> {
+        if !self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .contains_key(ROOT_ID)
+        {
+            bail!("no_root")
+        } else {
+            let timer = self.start_timer();
+            let c = Mutex::new(HashMap::<String, Map<String, Value>>::new());
+            let docs_r = self
+                .documents
+                .read()
+                .expect("failed_to_acquire_documents_for_reading");
+            docs_r.par_iter().try_for_each(|(uuid, rt)| -> Result<()> {
+                let rt_r = rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                if let Some(winner) = rt_r.get_winner() {
+                    if !winner.is_deleted() {
+                        let mut obj = self.read_object(uuid, &rt_r)?;
+                        drop(rt_r);
+                        if ids {
+                            obj.insert(ID_FIELD.to_string(), Value::from(uuid.clone()));
+                        }
+                        let mut c_w = c.lock().unwrap();
+                        c_w.insert(uuid.clone(), obj);
+                        drop(c_w);
+                    } else if tombstones && !is_array_descriptor(uuid) {
+                        let mut obj = Map::new();
+                        obj.insert(DELETED_FIELD.to_string(), Value::from(true));
+                        obj.insert(ID_FIELD.to_string(), Value::from(uuid.clone()));
+                        let mut c_w = c.lock().unwrap();
+                        c_w.insert(uuid.clone(), obj);
+                        drop(c_w);
+                    }
+                }
+                Ok(())
+            })?;
+            let c_r = c.lock().unwrap();
+            let root = c_r
+                .get(ROOT_ID)
+                .ok_or_else(|| anyhow!("root_object_not_found"))?;
+            let root = Value::from(root.clone());
+            let result = unflatten(&c_r, &root, self.max_read_depth)?
+                .unwrap()
+                .as_object()
+                .expect("not_an_object")
+                .clone();
+            if let Some((metrics, start)) = timer {
+                metrics.on_read(start.elapsed(), c_r.len());
+            }
+            drop(c_r);
+            Ok(result)
+        }
+    }
+
+    /// Returns the stored (flattened) value of the winning revision of each object, without
+    /// unflattening it. This is a debugging API: references to other objects and to array
+    /// descriptors are not resolved, escaped strings are not unescaped, array descriptors
+    /// contain the stored (possibly delta) descriptor and deleted objects contain the _deleted field.
+    /// The format of the values is internal and may change between versions.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey\u{266D}" : [ { "_id": "1", "key" : "alpha" } ] }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// let dump = replica.dump_flat().unwrap();
+    /// assert_eq!(dump.get("1").unwrap().get("key"), Some(&json!("alpha")));
+    /// let root = dump.get("\u{221A}").unwrap();
+    /// let descriptor = root.get("somekey\u{266D}").unwrap().as_str().unwrap();
+    /// assert!(descriptor.starts_with("^"));
+    /// assert_eq!(dump.get(descriptor).unwrap().get("A"), Some(&json!(["1"])));
+    /// ```
+    pub fn dump_flat(&self) -> Result<BTreeMap<String, Map<String, Value>>> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
+        let mut result = BTreeMap::new();
+        for (uuid, rt) in docs_r.iter() {
+            let rt_r = rt
+                .read()
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            if let Some(winner) = rt_r.get_winner() {
+                result.insert(uuid.clone(), data_r.read_object(winner)?);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Updates the data structure with each of the given objects in turn (see update), committing
+    /// every commit_every objects (and after the last object) to bound the size of the stage.
+    /// Returns the anchors after each commit.
+    ///
+    /// # Arguments
+    ///
+    /// * `objects` - The input JSON objects
+    /// * `commit_every` - The number of objects applied between commits (at least one)
+    /// * `info_fn` - Returns the information associated with each commit, given the number of objects applied so far
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let objects = (0..5u32).map(|i| {
+    ///     let items: Vec<Value> = (0..=i).map(|j| json!({ "_id" : j.to_string() })).collect();
+    ///     json!({ "items\u{266D}" : items }).as_object().unwrap().clone()
+    /// });
+    /// let anchors = replica.update_streaming(objects, 2, |count| Some(json!({ "count" : count }).as_object().unwrap().clone())).unwrap();
+    /// assert_eq!(anchors.len(), 3);
+    /// assert_eq!(anchors.last().unwrap(), &replica.get_anchors());
+    /// assert!(!replica.has_staging());
+    /// assert_eq!(replica.read().unwrap().get("items\u{266D}").unwrap().as_array().unwrap().len(), 5);
+    /// let block = replica.get_block(anchors.last().unwrap().iter().next().unwrap()).unwrap().unwrap();
+    /// assert_eq!(block.info.unwrap().get("count").unwrap(), &json!(5));
+    /// ```
+    pub fn update_streaming<I, F>(
+        &self,
+        objects: I,
+        commit_every: usize,
+        mut info_fn: F,
+    ) -> Result<Vec<BTreeSet<String>>>
+    where
+        I: Iterator<Item = Map<String, Value>>,
+        F: FnMut(usize) -> Option<Map<String, Value>>,
+    {
+        if commit_every == 0 {
+            bail!("invalid_commit_interval");
+        }
+        // Updates are committed in batches of commit_every objects (even in autocommit mode)
+        self.autocommit(|| {
+            let mut anchors = vec![];
+            let mut count = 0;
+            for obj in objects {
+                self.update(obj)?;
+                count += 1;
+                if count % commit_every == 0 && self.commit(info_fn(count))?.is_some() {
+                    anchors.push(self.get_anchors());
+                }
+            }
+            if count % commit_every != 0 && self.commit(info_fn(count))?.is_some() {
+                anchors.push(self.get_anchors());
+            }
+            Ok(anchors)
+        })
+    }
+
+    /// Updates the data structure by serializing the input value (through serde) to a JSON
+    /// object and flattening it (see update). The same conventions apply: fields whose
+    /// name ends with \u{266D} are flattened and objects in flattened arrays are identified by
+    /// their _id field. Since such names are not valid Rust identifiers, use the serde rename
+    /// attribute on the corresponding struct fields, for example
+    /// #[serde(rename = "items\u{266D}")], and a field named _id (or renamed to "_id") on the
+    /// structs of array elements.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - input value (must serialize to a JSON object)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde::{Deserialize, Serialize};
+    /// #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    /// struct Item {
+    ///     #[serde(rename = "_id")]
+    ///     id: String,
+    ///     title: String,
+    /// }
+    /// #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    /// struct TodoList {
+    ///     name: String,
+    ///     #[serde(rename = "items\u{266D}")]
+    ///     items: Vec<Item>,
+    /// }
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let list = TodoList { name: "Shopping".to_string(), items: vec![Item { id: "1".to_string(), title: "Milk".to_string() }] };
+    /// replica.update_typed(&list).unwrap();
+    /// replica.commit(None);
+    /// let readback: TodoList = replica.read_typed().unwrap();
+    /// assert_eq!(list, readback);
+    /// assert!(replica.update_typed(&vec![1, 2, 3]).is_err());
+    /// ```
+    pub fn update_typed<T: Serialize>(&self, value: &T) -> Result<()> {
+        match serde_json::to_value(value)? {
+            Value::Object(obj) => self.update(obj),
+            _ => bail!("not_an_object"),
+        }
+    }
+
+    /// Reads the current state of the CRDT (see read) and deserializes it (through serde)
+    /// into the requested type. The _id field of the root object is included in the result
+    /// and is ignored unless the type has a corresponding field (see update_typed for the
+    /// conventions on field names).
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde::Deserialize;
+    /// use serde_json::json;
+    /// #[derive(Deserialize)]
+    /// struct Document {
+    ///     somekey: Vec<u32>,
+    /// }
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ 1u32, 2u32, 3u32 ] }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// let document: Document = replica.read_typed().unwrap();
+    /// assert_eq!(document.somekey, vec![1, 2, 3]);
+    /// ```
+    pub fn read_typed<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_value(Value::from(self.read()?))?)
+    }
+
+    /// Validates an object before it is used to update the state (see update), checking the
+    /// usage of reserved keys: identifiers (_id) must be strings which do not begin with the
+    /// array descriptor prefix and can only be used in the root object (with the root
+    /// identifier) or in objects within flattened fields, which must be arrays, objects,
+    /// blob references or null. The error includes the path (as a JSON pointer) of the
+    /// offending node.
+    ///
+    /// # Arguments
+    ///
+    /// * `obj` - The JSON object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "inner" : { "_id" : "2" } } ] }).as_object().unwrap().clone();
+    /// let error = Melda::validate_input(&object).unwrap_err();
+    /// assert_eq!(error.to_string(), "misplaced_identifier: /items\u{266D}/0/inner/_id");
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(replica.update(object).is_err());
+    /// assert!(replica.get_all_objects().is_empty());
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "inner" : { "a" : 1 } } ] }).as_object().unwrap().clone();
+    /// assert!(Melda::validate_input(&object).is_ok());
+    /// ```
+    pub fn validate_input(obj: &Map<String, Value>) -> Result<()> {
+        validate_object(obj)
+    }
+
+    /// Updates the data structure by flattening the input JSON object
+    ///
+    /// Objects are identified by the digest of their content, unless they provide their own
+    /// digest through the hash field (#) as a string or a number: this hash shortcut avoids
+    /// hashing large numbers of small objects, but objects with the same hash are considered
+    /// identical (only one of them is stored). Hashes of at most 8 hexadecimal digits denote
+    /// simple characters, which are not stored at all: they are read back as an object only
+    /// containing the hash field. In strict mode (see MeldaConfig::strict_hashes) an update
+    /// which would alias different objects fails with a hash_collision error. Updates are
+    /// atomic: the revision trees and the stage are only changed once all objects have been
+    /// updated, otherwise the changes of the update are dropped (previously staged changes are
+    /// kept) and the first error is returned. Objects which are unchanged since the previous
+    /// update can be skipped using the input cache (see set_input_cache).
+    ///
+    /// # Arguments
+    ///
+    /// * `obj` - input JSON object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.update(object.clone());
+    /// let readback = replica.read().unwrap();
+    /// assert!(readback.contains_key("somekey"));
+    /// // Arrays are stored as delta patches, unless the patch is not smaller than the full order
+    /// let items: Vec<Value> = (0..20).map(|j| json!({ "_id" : j.to_string() })).collect();
+    /// replica.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone());
+    /// let descriptor = replica.dump_flat().unwrap().get("\u{221A}").unwrap().get("items\u{266D}").unwrap().as_str().unwrap().to_string();
+    /// let items: Vec<Value> = (0..21).map(|j| json!({ "_id" : j.to_string() })).collect();
+    /// replica.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone());
+    /// assert!(replica.dump_flat().unwrap().get(&descriptor).unwrap().contains_key("a"));
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "x", "k" : 1 } ] }).as_object().unwrap().clone());
+    /// assert_eq!(replica.dump_flat().unwrap().get(&descriptor).unwrap().get("A"), Some(&json!(["x"])));
+    /// let readback = replica.read().unwrap();
+    /// assert_eq!(readback.get("items\u{266D}").unwrap(), &json!([ { "_id" : "x", "k" : 1 } ]));
+    /// // Hash shortcut
+    /// let object = json!({ "chars\u{266D}" : [ { "_id" : "c1", "#" : "41" }, { "_id" : "c2", "#" : "mytext", "text" : "a" } ] }).as_object().unwrap().clone();
+    /// replica.update(object.clone()).unwrap();
+    /// assert_eq!(replica.read().unwrap().get("chars\u{266D}").unwrap(), object.get("chars\u{266D}").unwrap());
+    /// let config = MeldaConfig { strict_hashes: true, ..MeldaConfig::default() };
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let strict = Melda::new_with_config(Arc::new(RwLock::new(adapter)), config).expect("cannot_initialize_crdt");
+    /// assert!(strict.update(object).is_ok());
+    /// let pending = strict.stage().unwrap();
+    /// let object = json!({ "chars\u{266D}" : [ { "_id" : "c1", "#" : "mytext", "text" : "a" }, { "_id" : "c2", "#" : "mytext", "text" : "b" } ] }).as_object().unwrap().clone();
+    /// assert_eq!(strict.update(object.clone()).unwrap_err().to_string(), "hash_collision: mytext");
+    /// // A failed update leaves the staged changes untouched
+    /// assert_eq!(strict.stage().unwrap(), pending);
+    /// assert!(replica.update(object).is_ok());
+    /// let object = json!({ "chars\u{266D}" : [ { "_id" : "c1", "#" : "41", "text" : "a" } ] }).as_object().unwrap().clone();
+    /// assert_eq!(strict.update(object).unwrap_err().to_string(), "hash_collision: 41");
+    pub fn update(&self, obj: Map<String, Value>) -> Result<()> {
+        self.autocommit(|| self.update_impl(obj))
+    }
+
+    // Updates the data structure (see update)
+    fn update_impl(&self, obj: Map<String, Value>) -> Result<()> {
+        Self::validate_input(&obj)?;
+        let mut extracted_objects = HashMap::<String, Map<String, Value>>::new();
+        let path = Vec::<String>::new();
+        let root = Value::from(obj);
+        // Flatten the structure
+        let root = flatten(&mut extracted_objects, &root, &path, self.id_strategy);
+        let root = root.as_str().expect("root_identifier_not_a_string");
+        if root != ROOT_ID {
+            bail!("invalid_root_id");
+        }
+        let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
+        if data_r.strict_hashes() {
+            // Reject objects which would alias each other before changing the state
+            let mut digests = HashMap::<String, &Map<String, Value>>::new();
+            for obj in extracted_objects.values() {
+                if obj.contains_key(HASH_FIELD) {
+                    let digest = digest_object(obj)?;
+                    data_r.check_hash(&digest, obj)?;
+                    if digests.get(&digest).is_some_and(|other| *other != obj) {
+                        bail!("hash_collision: {}", digest);
+                    }
+                    digests.insert(digest, obj);
+                }
+            }
+        }
+        drop(data_r);
+        // Changes are only applied if all objects can be updated
+        self.staged(|stage| self.update_extracted(extracted_objects, stage))
+    }
+
+    // Runs f recording the changes in a scratch stage: if f succeeds the changes are added to
+    // the revision trees and to the stage (at once), otherwise they are rolled back
+    fn staged<T, F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&RwLock<Vec<Change>>) -> Result<T>,
+    {
+        let scratch = RwLock::new(Vec::<Change>::new());
+        let result = f(&scratch);
+        let changes = scratch.into_inner().unwrap();
+        match result {
+            Ok(_) => {
+                self.apply_staged_changes(changes);
+                Ok(())
+            }
+            Err(e) => {
+                self.rollback_changes(changes);
+                Err(e)
+            }
+        }
+    }
+
+    // Adds the given changes to the revision trees and to the stage
+    fn apply_staged_changes(&self, changes: Vec<Change>) {
+        if changes.is_empty() {
+            return;
+        }
+        let mut docs_w = self
+            .documents
+            .write()
+            .expect("failed_to_acquire_documents_for_writing");
+        for Change(uuid, rev, prev) in &changes {
+            docs_w
+                .entry(uuid.to_string())
+                .or_insert_with(|| RwLock::new(RevisionTree::new()))
+                .write()
+                .expect("cannot_acquire_revision_tree_for_writing")
+                .add(rev.clone(), prev.clone());
+        }
+        self.stage
+            .write()
+            .expect("cannot_acquire_stage_for_writing")
+            .extend(changes);
+    }
+
+    // Deletes the objects which are not among the extracted objects, then creates or updates
+    // the extracted objects (recording the changes in the given stage)
+    fn update_extracted(
+        &self,
+        extracted_objects: HashMap<String, Map<String, Value>>,
+        stage: &RwLock<Vec<Change>>,
+    ) -> Result<()> {
+        // Check for objects that have disappeared
+        // i.e. objects that are found in the current state but are not within the extracted objects
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        docs_r
+            .par_iter()
+            .filter(|(uuid, _)| !extracted_objects.contains_key(*uuid))
+            .try_for_each(|(uuid, _)| self.delete_object_with_optional_meta(uuid, None, stage))?;
+        drop(docs_r);
+        if self.input_cache.lock().unwrap().is_some() {
+            return self.update_cached(extracted_objects, stage);
+        }
+        // Check for newly created and updated objects
+        extracted_objects
+            .into_par_iter()
+            .try_for_each(|(uuid, obj)| {
+                self.update_object_with_optional_digest(&uuid, obj, None, stage)
+                    .map(|_| ())
+            })
+    }
+
+    // Reverts the given changes (which have not been applied): their payloads (unless used by
+    // staged changes) are removed from the data stage
+    fn rollback_changes(&self, dropped: Vec<Change>) {
+        if dropped.is_empty() {
+            return;
+        }
+        let stage = self.stage.read().unwrap();
+        let kept: HashSet<&str> = stage
+            .iter()
+            .filter_map(|Change(_, rev, _)| DataStorage::payload_digest(rev))
+            .collect();
+        let mut data_w = self.data.write().expect("cannot_acquire_data_for_writing");
+        for Change(_, rev, _) in &dropped {
+            if let Some(digest) = DataStorage::payload_digest(rev) {
+                if !kept.contains(digest) {
+                    data_w.unstage_value(digest);
+                }
+            }
+        }
+    }
+
+    // Updates the extracted objects, skipping those which are unchanged since the last
+    // update according to the input cache (see set_input_cache)
+    fn update_cached(
+        &self,
+        mut extracted_objects: HashMap<String, Map<String, Value>>,
+        stage: &RwLock<Vec<Change>>,
+    ) -> Result<()> {
+        let winner = |uuid: &str| -> Option<Revision> {
+            self.documents
+                .read()
+                .expect("failed_to_acquire_documents_for_reading")
+                .get(uuid)
+                .and_then(|rt| {
+                    rt.read()
+                        .expect("failed_to_acquire_revision_tree_for_reading")
+                        .get_winner()
+                        .cloned()
+                })
+        };
+        if let Some(cache) = self.input_cache.lock().unwrap().as_ref() {
+            extracted_objects.retain(|uuid, obj| {
+                !cache
+                    .get(uuid)
+                    .is_some_and(|(rev, last)| last == obj && Some(rev) == winner(uuid).as_ref())
+            });
+        }
+        let updated: Vec<(String, Revision, Map<String, Value>)> = extracted_objects
+            .into_par_iter()
+            .map(|(uuid, obj)| {
+                let rev = match self.update_object_with_optional_digest(
+                    &uuid,
+                    obj.clone(),
+                    None,
+                    stage,
+                )? {
+                    Some(rev) => rev,
+                    None => winner(&uuid).ok_or_else(|| anyhow!("object_has_no_winner"))?,
+                };
+                Ok((uuid, rev, obj))
+            })
+            .collect::<Result<_>>()?;
+        if let Some(cache) = self.input_cache.lock().unwrap().as_mut() {
+            for (uuid, rev, obj) in updated {
+                cache.insert(uuid, (rev, obj));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a set of the object (identifiers) which have ongoing conflicts
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica2.create_object("myobject", object);  
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// let conflicting = replica2.in_conflict();
+    /// assert!(conflicting.contains("myobject"));
+    pub fn in_conflict(&self) -> BTreeSet<String> {
+        let mut result = BTreeSet::new();
+        // TODO: Make parallel
+        self.documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .iter()
+            .for_each(|(uuid, rt)| {
+                let rt_r = rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                let l = rt_r.get_leafs();
+                if l.len() > 1 {
+                    result.insert(uuid.clone());
+                }
+            });
+        result
+    }
+
+    /// Returns, for each object, the revisions which have been applied (from delta blocks) as
+    /// derived from a parent while already having another parent. Since a revision is derived
+    /// from a single parent, this denotes an inconsistency (for example a corrupted or forged
+    /// block) rather than a regular conflict. Blocks which re-introduce an existing revision with
+    /// the same parent, as well as merged revisions (see resolve_merged), are not reported.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter, revision::Revision};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use sha2::{Digest, Sha256};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// replica.create_object("myobject", json!({ "key" : 1 }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// replica.update_object("myobject", json!({ "key" : 2 }).as_object().unwrap().clone());
+    /// let anchor = replica.commit(None).unwrap().unwrap();
+    /// let revision = replica.get_winner("myobject").unwrap();
+    /// let digest = revision.split(['-', '_']).nth(1).unwrap();
+    /// assert!(replica.inconsistent_revisions().is_empty());
+    /// // A block introducing a revision with another parent, followed by a block deriving the
+    /// // same revision from the winner
+    /// let other = format!("1-{}", "0".repeat(64));
+    /// let forged = Revision::new_updated(digest, &Revision::from(&revision).unwrap()).to_string();
+    /// let block = json!({ "c" : [ [ "myobject", other, digest, forged ] ], "p" : [ anchor ], "v" : 2 }).to_string();
+    /// let id = hex::encode(Sha256::digest(block.as_bytes()));
+    /// adapter.write().unwrap().write_object(&(id.clone() + ".delta"), block.as_bytes()).unwrap();
+    /// let block = json!({ "c" : [ [ "myobject", revision, digest ] ], "p" : [ id ] }).to_string();
+    /// let id = hex::encode(Sha256::digest(block.as_bytes()));
+    /// adapter.write().unwrap().write_object(&(id + ".delta"), block.as_bytes()).unwrap();
+    /// replica.refresh().unwrap();
+    /// let inconsistent = replica.inconsistent_revisions();
+    /// assert!(inconsistent.get("myobject").unwrap().contains(&forged));
+    /// ```
+    pub fn inconsistent_revisions(&self) -> BTreeMap<String, BTreeSet<String>> {
+        self.inconsistent_revisions.lock().unwrap().clone()
+    }
+
+    /// Returns true if both replicas have applied the same blocks and have the same winning and
+    /// leaf revisions for all objects (hence they produce the same reads and conflicts)
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The other replica
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : "somevalue" }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// let block = replica.commit(None).unwrap().unwrap();
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let other = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(!replica.equivalent_to(&other));
+    /// assert_eq!(replica.difference_report(&other), Some(format!("extra_block: {}", block)));
+    /// assert_eq!(other.difference_report(&replica), Some(format!("missing_block: {}", block)));
+    /// other.meld(&replica);
+    /// other.refresh();
+    /// assert!(replica.equivalent_to(&other));
+    /// assert!(replica.difference_report(&other).is_none());
+    /// let object = json!({ "somekey" : "othervalue" }).as_object().unwrap().clone();
+    /// other.update(object);
+    /// assert!(replica.difference_report(&other).unwrap().starts_with("different_winner: \u{221A}"));
+    /// ```
+    pub fn equivalent_to(&self, other: &Melda) -> bool {
+        self.difference_report(other).is_none()
+    }
+
+    /// Compares two replicas (see equivalent_to), returns None if they are equivalent or a
+    /// description of the first difference otherwise: a block applied only by the other
+    /// replica (missing_block) or only by this replica (extra_block), an object only known
+    /// by the other replica (missing_object) or only by this replica (extra_object), an object
+    /// with a different winning revision (different_winner) or different leafs (different_leafs)
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The other replica
+    pub fn difference_report(&self, other: &Melda) -> Option<String> {
+        if std::ptr::eq(self, other) {
+            return None;
+        }
+        let blocks = self.applied_blocks();
+        let other_blocks = other.applied_blocks();
+        if let Some(b) = other_blocks.difference(&blocks).next() {
+            return Some(format!("missing_block: {}", b));
+        }
+        if let Some(b) = blocks.difference(&other_blocks).next() {
+            return Some(format!("extra_block: {}", b));
+        }
+        let states = self.revision_states();
+        let other_states = other.revision_states();
+        for (uuid, (winner, leafs)) in &states {
+            match other_states.get(uuid) {
+                Some((other_winner, other_leafs)) => {
+                    if winner != other_winner {
+                        return Some(format!(
+                            "different_winner: {} ({} != {})",
+                            uuid,
+                            winner.as_deref().unwrap_or("none"),
+                            other_winner.as_deref().unwrap_or("none")
+                        ));
+                    }
+                    if leafs != other_leafs {
+                        return Some(format!("different_leafs: {}", uuid));
+                    }
+                }
+                None => return Some(format!("extra_object: {}", uuid)),
+            }
+        }
+        other_states
+            .keys()
+            .find(|uuid| !states.contains_key(*uuid))
+            .map(|uuid| format!("missing_object: {}", uuid))
+    }
+
+    // Returns the identifiers of valid and applied blocks
+    fn applied_blocks(&self) -> BTreeSet<String> {
+        self.blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading")
+            .iter()
+            .filter(|(_, b)| b.read().unwrap().status == Status::ValidAndApplied)
+            .map(|(bid, _)| bid.clone())
+            .collect()
+    }
+
+    // Returns the winning and leaf revisions of each object
+    fn revision_states(&self) -> BTreeMap<String, (Option<String>, BTreeSet<String>)> {
+        self.documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .iter()
+            .map(|(uuid, rt)| {
+                let rt_r = rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                let winner = rt_r.get_winner().map(|r| r.to_string());
+                let leafs = rt_r.get_leafs().iter().map(|r| r.to_string()).collect();
+                (uuid.clone(), (winner, leafs))
+            })
+            .collect()
+    }
+
+    /// Returns the winning revision for the given object
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// replica.delete_object("myobject");
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", winner);
+    /// let value = replica.get_value("myobject", &winner);
+    /// assert!(value.is_ok());
+    /// assert!(value.unwrap().contains_key("_deleted"));
+    /// replica.unstage();
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// ```
+    pub fn get_winner<T>(&self, uuid: T) -> Result<String>
+    where
+        T: AsRef<str>,
+    {
+        match self
+            .documents
+            .read()
+            .expect("cannot_acquire_documents_for_reading")
+            .get(uuid.as_ref())
+        {
+            Some(rt) => {
+                let rt_r = rt.read().expect("cannot_acquire_revision_tree_for_reading");
+                match rt_r.get_winner() {
+                    Some(r) => Ok(r.to_string()),
+                    None => Err(anyhow!("no_winner")),
+                }
+            }
+            None => Err(anyhow!("unknown_document")),
+        }
+    }
+
+    /// Returns the winning revision of an object along with its value (array descriptors are
+    /// materialized), or None if the object does not exist or is deleted
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(replica.get_current("myobject").unwrap().is_none());
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object.clone());
+    /// let (winner, value) = replica.get_current("myobject").unwrap().unwrap();
+    /// assert_eq!(winner, replica.get_winner("myobject").unwrap());
+    /// assert_eq!(value, object);
+    /// replica.delete_object("myobject");
+    /// assert!(replica.get_current("myobject").unwrap().is_none());
+    /// ```
+    pub fn get_current(&self, uuid: &str) -> Result<Option<(String, Map<String, Value>)>> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("cannot_acquire_documents_for_reading");
+        match docs_r.get(uuid) {
+            Some(rt) => {
+                let rt_r = rt.read().expect("cannot_acquire_revision_tree_for_reading");
+                match rt_r.get_winner() {
+                    Some(r) if !r.is_deleted() => {
+                        Ok(Some((r.to_string(), self.read_object(uuid, &rt_r)?)))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a set of the conflicting revisions of the given object (the winning revision is not included!)
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica2.create_object("myobject", object);
+    /// let winner2 = replica2.get_winner("myobject").unwrap();
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// let conflicting = replica2.in_conflict();
+    /// assert!(conflicting.contains("myobject"));
+    /// let revs = replica2.get_conflicting("myobject").unwrap();
+    /// assert!(revs.contains(&winner2));
+    pub fn get_conflicting<T>(&self, uuid: T) -> Result<BTreeSet<String>>
+    where
+        T: AsRef<str>,
+    {
+        match self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .get(uuid.as_ref())
+        {
+            Some(rt) => {
+                let rt_r = rt
+                    .read()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                let w = rt_r.get_winner().ok_or_else(|| anyhow!("no_winner"))?;
+                let l = rt_r.get_leafs();
+                Ok(l.iter()
+                    .filter(|r| w.ne(r))
+                    .map(|r| r.to_string())
+                    .collect())
+            }
+            None => Err(anyhow!("unknown_document")),
         }
-        // Clear the documents
-        documents_w.clear();
-        drop(documents_w);
-        // Read block list
-        let data_r = self.data.write().expect("cannot_acquire_data_for_writing");
-        let list_str = data_r.list_raw_items(DELTA_EXTENSION)?;
-        drop(data_r);
-        // Reload data storage
-        let mut data_w = self.data.write().expect("cannot_acquire_data_for_writing");
-        data_w.reload()?;
-        drop(data_w);
-        // Clear the blocks
-        let mut blocks_w = self
-            .blocks
+    }
+
+    /// Resolves a conflict by choosing the new winning revision. All other conflicting revisions are marked as resolved.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    /// * `winner` - The revision that is to be made the winner
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica2.create_object("myobject", object);
+    /// let winner2 = replica2.get_winner("myobject").unwrap();
+    /// assert_eq!("1-255cc6219e48f526c04bc5af86439c34e4fe39fcdc611758ff833a2ff80583f0", winner2);
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// let conflicting = replica2.in_conflict();
+    /// assert!(conflicting.contains("myobject"));
+    /// let revs = replica2.get_conflicting("myobject").unwrap();
+    /// assert!(revs.contains(&winner2));
+    /// let winner3 = replica2.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner3);    
+    /// replica2.resolve_as("myobject", &winner2);
+    /// let winner = replica2.get_winner("myobject").unwrap();
+    /// assert_eq!("2-255cc6219e48f526c04bc5af86439c34e4fe39fcdc611758ff833a2ff80583f0_e5d1d20", winner);
+    /// assert!(replica2.in_conflict().is_empty());
+    pub fn resolve_as(&self, uuid: &str, winner: &str) -> Result<String> {
+        self.autocommit(|| self.resolve_as_impl(uuid, winner))
+    }
+
+    // Resolves a conflict (see resolve_as)
+    fn resolve_as_impl(&self, uuid: &str, winner: &str) -> Result<String> {
+        {
+            let winner = Revision::from(winner).expect("invalid_revision_string");
+            let docs_r = self
+                .documents
+                .read()
+                .expect("failed_to_acquire_documents_for_reading");
+            let rt = docs_r
+                .get(uuid)
+                .ok_or_else(|| anyhow!("unknown_document"))?;
+            let rt_r = rt
+                .read()
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            {
+                let leafs = rt_r.get_leafs();
+                // We can only resolve to a status revision
+                if !leafs.contains(&winner) {
+                    bail!("invalid_winner_revision");
+                }
+                // If there is only one leaf nothing needs to be resolved
+                if leafs.len() <= 1 {
+                    bail!("not_in_conflict");
+                }
+            }
+            // Update the winner to ensure that we do not change the view
+            let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
+            let merged = data_r.read_object(&winner)?;
+            drop(winner);
+            drop(rt_r);
+            drop(data_r);
+            drop(docs_r);
+            self.update_object(uuid, merged)?;
+        }
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let rt = docs_r
+            .get(uuid)
+            .ok_or_else(|| anyhow!("unknown_document"))?;
+        let rt_r = rt
+            .read()
+            .expect("failed_to_acquire_revision_tree_for_reading");
+        let winner = rt_r
+            .get_winner()
+            .expect("revision_tree_invalid_state")
+            .clone();
+        drop(rt_r);
+        drop(docs_r);
+        // Seal all other revisions as resolved
+        let mut docs_w = self
+            .documents
             .write()
-            .expect("cannot_acquire_blocks_for_writing");
-        blocks_w.clear();
-        // Fetch and parse blocks
-        if !list_str.is_empty() {
-            for i in &list_str {
-                if let Ok(block) = self.fetch_raw_block(i) {
-                    if let Ok(block) = self.parse_raw_block(i.to_string(), block) {
-                        blocks_w.insert(i.to_string(), RwLock::new(block));
-                    }
+            .expect("failed_to_acquire_documents_for_writing");
+        let rt = docs_w
+            .get_mut(uuid)
+            .ok_or_else(|| anyhow!("unknown_document"))?;
+        let rt_r = rt
+            .read()
+            .expect("failed_to_acquire_revision_tree_for_reading");
+        let leafs: Vec<Revision> = rt_r.get_leafs().iter().map(|r| (*r).clone()).collect();
+        drop(rt_r);
+        for r in leafs {
+            if r != winner {
+                let resolved = Revision::new_updated_with_tail_length(
+                    RESOLVED_HASH,
+                    &r,
+                    self.revision_tail_length,
+                );
+                let mut rt_w = rt
+                    .write()
+                    .expect("failed_to_acquire_revision_tree_for_writing");
+                if rt_w.add(resolved.clone(), Some(r.clone())) {
+                    self.stage.write().unwrap().push(Change(
+                        uuid.to_string(),
+                        resolved,
+                        Some(r.clone()),
+                    ));
                 }
             }
         }
-        drop(blocks_w);
-        // Mark valid blocks
-        self.mark_valid_blocks();
-        // Check if block is valid
-        let blocks_r = self
+        drop(docs_w);
+        self.notify_subscribers();
+        Ok(winner.to_string())
+    }
+
+    /// Resolves a conflict by recording an explicitly merged object as the new winning revision.
+    /// Since the revision tree stores (revision, parent) pairs, the merged revision is recorded
+    /// once for each conflicting leaf (as its parent): the merged-from revisions are therefore no
+    /// longer leafs, and the merged revision (whose index is greater than the index of any leaf)
+    /// becomes the winner. Array descriptors cannot be merged (use resolve_as instead).
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    /// * `merged` - The merged content of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "a" : 1u32, "b" : 1u32 }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// replica.commit(None);
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// let object = json!({ "a" : 2u32, "b" : 1u32 }).as_object().unwrap().clone();
+    /// replica.update_object("myobject", object);
+    /// replica.commit(None);
+    /// let object = json!({ "a" : 1u32, "b" : 3u32 }).as_object().unwrap().clone();
+    /// replica2.update_object("myobject", object);
+    /// replica2.commit(None);
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// assert!(replica2.in_conflict().contains("myobject"));
+    /// let merged = json!({ "a" : 2u32, "b" : 3u32 }).as_object().unwrap().clone();
+    /// let winner = replica2.resolve_merged("myobject", merged.clone()).unwrap();
+    /// assert!(winner.starts_with("3-"));
+    /// assert!(replica2.in_conflict().is_empty());
+    /// assert_eq!(replica2.get_winner("myobject").unwrap(), winner);
+    /// assert_eq!(replica2.get_value("myobject", &winner).unwrap(), merged);
+    /// let block = replica2.commit(None).unwrap().unwrap();
+    /// // Blocks containing merge records are marked with a newer format version
+    /// let raw: Value = serde_json::from_slice(&replica2.raw_read(&(block + ".delta")).unwrap()).unwrap();
+    /// assert_eq!(raw.get("v"), Some(&json!(2)));
+    /// replica.meld(&replica2);
+    /// replica.refresh();
+    /// assert!(replica.in_conflict().is_empty());
+    /// assert_eq!(replica.get_winner("myobject").unwrap(), winner);
+    /// // Merged revisions are not reported as inconsistent
+    /// assert!(replica.inconsistent_revisions().is_empty());
+    /// replica2.reload().unwrap();
+    /// assert_eq!(replica2.get_winner("myobject").unwrap(), winner);
+    /// assert!(replica2.inconsistent_revisions().is_empty());
+    /// ```
+    pub fn resolve_merged(&self, uuid: &str, merged: Map<String, Value>) -> Result<String> {
+        self.autocommit(|| self.resolve_merged_impl(uuid, merged))
+    }
+
+    // Resolves a conflict with a merged value (see resolve_merged)
+    fn resolve_merged_impl(&self, uuid: &str, merged: Map<String, Value>) -> Result<String> {
+        if is_array_descriptor(uuid) {
+            bail!("cannot_merge_array_descriptor");
+        }
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let rt = docs_r
+            .get(uuid)
+            .ok_or_else(|| anyhow!("unknown_document"))?;
+        let mut rt_w = rt
+            .write()
+            .expect("failed_to_acquire_revision_tree_for_writing");
+        let leafs: Vec<Revision> = rt_w.get_leafs().iter().map(|r| (*r).clone()).collect();
+        // If there is only one leaf nothing needs to be resolved
+        if leafs.len() <= 1 {
+            bail!("not_in_conflict");
+        }
+        let winner = rt_w
+            .get_winner()
+            .expect("revision_tree_invalid_state")
+            .clone();
+        let index = leafs.iter().map(|r| r.index).max().unwrap_or(0) + 1;
+        let merged_revision = Revision::new_with_tail_length(
+            index,
+            digest_object(&merged)?,
+            Some(&winner),
+            self.revision_tail_length,
+        );
+        self.data
+            .write()
+            .expect("cannot_acquire_data_for_writing")
+            .write_object(&merged_revision, merged)?;
+        for r in leafs {
+            if rt_w.add(merged_revision.clone(), Some(r.clone())) {
+                self.stage.write().unwrap().push(Change(
+                    uuid.to_string(),
+                    merged_revision.clone(),
+                    Some(r),
+                ));
+            }
+        }
+        drop(rt_w);
+        drop(docs_r);
+        self.notify_subscribers();
+        Ok(merged_revision.to_string())
+    }
+
+    /// Returns true if there are staged changes (which would be recorded by commit). The check
+    /// does not scan the objects (the stage only contains the changes made since the last commit)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(!replica.has_staging());
+    /// for i in 0..3 {
+    ///     let object = json!({ "items\u{266D}" : [ { "_id" : i.to_string() } ] }).as_object().unwrap().clone();
+    ///     replica.update(object);
+    ///     assert!(replica.has_staging());
+    ///     assert_eq!(replica.has_staging(), replica.stage().unwrap().is_some());
+    ///     let stage = replica.stage().unwrap();
+    ///     replica.unstage();
+    ///     assert!(!replica.has_staging());
+    ///     assert_eq!(replica.has_staging(), replica.stage().unwrap().is_some());
+    ///     replica.replay_stage(&stage);
+    ///     assert!(replica.has_staging());
+    ///     replica.commit(None);
+    ///     assert!(!replica.has_staging());
+    ///     assert_eq!(replica.has_staging(), replica.stage().unwrap().is_some());
+    /// }
+    /// replica.delete_object("2");
+    /// assert!(replica.has_staging());
+    /// ```
+    pub fn has_staging(&self) -> bool {
+        !self
+            .stage
+            .read()
+            .expect("cannot_acquire_stage_for_reading")
+            .is_empty()
+    }
+
+    /// Returns the number of staged revisions and an estimate of the size (in bytes) of the
+    /// staged values, which can be used to decide when to commit
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, StagingStats}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert_eq!(replica.staging_stats(), StagingStats::default());
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "value" : "somedata" } ] }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// let stats = replica.staging_stats();
+    /// assert_eq!(stats.object_count, 3);
+    /// assert!(stats.byte_estimate > "somedata".len());
+    /// replica.commit(None);
+    /// assert_eq!(replica.staging_stats(), StagingStats::default());
+    /// ```
+    pub fn staging_stats(&self) -> StagingStats {
+        let object_count = self
+            .stage
+            .read()
+            .expect("cannot_acquire_stage_for_reading")
+            .len();
+        let (_, byte_estimate) = self
+            .data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .stage_size();
+        StagingStats {
+            object_count,
+            byte_estimate,
+        }
+    }
+
+    /// Limits the memory used by the caches of the replica, trading I/O for memory. While a
+    /// budget is set:
+    /// - the objects read from the data packs are evicted from the data cache (least recently
+    ///   used first) as soon as the size of the cached objects (in bytes, as serialized) exceeds
+    ///   the part of the budget which is not used by the materialized orders of array
+    ///   descriptors; evicted objects are read again from the packs when needed and objects
+    ///   larger than the budget are not cached at all
+    /// - the materialized orders of array descriptors are evicted in the same way, they are
+    ///   rebuilt from the packs when needed
+    /// - delta blocks do not retain their changes once applied, even if configured to (see
+    ///   MeldaConfig::retain_block_changes): the changes retained so far are dropped and blocks
+    ///   are fetched again from the adapter when their changes are needed (reload thus streams
+    ///   the blocks, see also rebuild_from_blocks)
+    /// - the digest and input caches are disabled (see set_digest_cache and set_input_cache)
+    ///
+    /// The budget applies in addition to the capacities of the caches (see
+    /// MeldaConfig::data_cache). The state of the replica (revision trees, block headers and
+    /// pruned histories) is always kept in memory. The usage of the caches can be monitored
+    /// with memory_stats.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The maximum size of the cached objects and orders, None to remove the limit
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let items: Vec<Value> = (0..10).map(|j| json!({ "_id" : j.to_string(), "value" : format!("{}{}", j, "x".repeat(100)) })).collect();
+    /// replica.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// // The order of the array is then stored as a diff, which is materialized when read
+    /// replica.update(json!({ "items\u{266D}" : items[1..] }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// let config = MeldaConfig { retain_block_changes: true, ..MeldaConfig::default() };
+    /// let replica = Melda::new_with_config(adapter.clone(), config).expect("cannot_initialize_crdt");
+    /// let expected = replica.read().unwrap();
+    /// let stats = replica.memory_stats();
+    /// assert_eq!(stats.cached_orders, 1);
+    /// assert_eq!(stats.retained_blocks, 2);
+    /// replica.set_memory_budget(Some(300));
+    /// replica.warm_cache().unwrap();
+    /// let stats = replica.memory_stats();
+    /// assert_eq!(stats.budget, Some(300));
+    /// assert!(stats.order_bytes > 0);
+    /// assert!(stats.cached_bytes + stats.order_bytes <= 300);
+    /// assert!(stats.cached_objects < 10);
+    /// assert!(stats.evictions > 0);
+    /// assert_eq!(stats.retained_blocks, 0);
+    /// assert_eq!(replica.read().unwrap(), expected);
+    /// // Committed blocks do not retain their changes either
+    /// replica.update(json!({ "items\u{266D}" : items[2..] }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// assert_eq!(replica.memory_stats().retained_blocks, 0);
+    /// replica.reload().unwrap();
+    /// assert_eq!(replica.memory_stats().retained_blocks, 0);
+    /// replica.set_memory_budget(None);
+    /// let stats = replica.memory_stats();
+    /// assert_eq!(stats.cached_bytes + stats.order_bytes, 0);
+    /// ```
+    pub fn set_memory_budget(&self, bytes: Option<usize>) {
+        *self.memory_budget.lock().unwrap() = bytes;
+        self.data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .set_cache_budget(bytes);
+        let mut orders = self.array_descriptors_cache.lock().unwrap();
+        orders.set_measured(bytes.is_some());
+        self.enforce_memory_budget(&mut orders);
+        drop(orders);
+        if bytes.is_some() {
+            // Applied blocks are fetched again when their changes are needed
+            let blocks_r = self
+                .blocks
+                .read()
+                .expect("cannot_acquire_blocks_for_reading");
+            for block in blocks_r.values() {
+                let mut block_w = block.write().expect("cannot_acquire_block_for_writing");
+                if block_w.status == Status::ValidAndApplied {
+                    block_w.changes = None;
+                }
+            }
+            drop(blocks_r);
+            self.set_digest_cache(false);
+            self.set_input_cache(false);
+        }
+    }
+
+    // Keeps the materialized orders within the memory budget (if any) and reserves their size
+    // in the data cache, so that the cached objects are bounded by the rest of the budget
+    fn enforce_memory_budget(&self, orders: &mut OrderCache) {
+        let budget = *self.memory_budget.lock().unwrap();
+        if let Some(budget) = budget {
+            orders.shrink_to(budget);
+        }
+        self.data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .reserve_cache_bytes(orders.bytes);
+    }
+
+    // Returns true if applied blocks retain their changes (see MeldaConfig::retain_block_changes
+    // and set_memory_budget)
+    fn retains_block_changes(&self) -> bool {
+        self.retain_block_changes && self.memory_budget.lock().unwrap().is_none()
+    }
+
+    /// Returns the usage of the caches (see set_memory_budget)
+    pub fn memory_stats(&self) -> MemoryStats {
+        let (cached_objects, cached_bytes, budget, evictions) = self
+            .data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .cache_stats();
+        let orders = self.array_descriptors_cache.lock().unwrap();
+        let (cached_orders, order_bytes, order_evictions) =
+            (orders.descriptors.len(), orders.bytes, orders.evictions);
+        drop(orders);
+        let retained_blocks = self
             .blocks
             .read()
-            .expect("cannot_acquire_blocks_for_reading");
-        if !blocks_r.contains_key(block_id) {
-            bail!(
-                "reload_until_interrupted_block_not_found: {} {:?}",
-                block_id,
-                blocks_r.keys()
-            );
-        }
-        if blocks_r.get(block_id).unwrap().read().unwrap().status != Status::Valid {
-            bail!("reload_until_interrupted_invalid_block: {}", block_id);
+            .expect("cannot_acquire_blocks_for_reading")
+            .values()
+            .filter(|block| {
+                block
+                    .read()
+                    .expect("cannot_acquire_block_for_reading")
+                    .changes
+                    .is_some()
+            })
+            .count();
+        MemoryStats {
+            cached_objects,
+            cached_bytes,
+            cached_orders,
+            order_bytes,
+            budget,
+            evictions: evictions + order_evictions,
+            retained_blocks,
         }
-        // Apply block and parents
-        let mut to_apply = VecDeque::new();
-        to_apply.push_back(block_id.to_string());
-        while !to_apply.is_empty() {
-            let bid = to_apply.pop_front().unwrap();
-            let block_item = blocks_r.get(&bid).unwrap();
-            let block_r = block_item.read().expect("cannot_acquire_block_for_reading");
-            let status = block_r.status;
-            if status == Status::Valid && self.apply_block(&block_r).is_ok() {
-                if let Some(parents) = &block_r.parents {
-                    for b in parents {
-                        to_apply.push_back(b.to_string());
+    }
+
+    /// Returns how many revisions share the same committed payload (identified by the digest of
+    /// its content) and the storage saved by storing each payload only once. Revisions without
+    /// a payload (deletions, resolved revisions, simple characters) and uncommitted revisions
+    /// are not counted.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "key" : "somevalue" }).as_object().unwrap().clone();
+    /// replica.create_object("first", object.clone());
+    /// replica.create_object("second", object.clone());
+    /// replica.create_object("third", json!({ "key" : 1 }).as_object().unwrap().clone());
+    /// assert_eq!(replica.dedup_stats().total_references, 0);
+    /// replica.commit(None);
+    /// let stats = replica.dedup_stats();
+    /// assert_eq!(stats.unique_objects, 2);
+    /// assert_eq!(stats.total_references, 3);
+    /// assert_eq!(stats.bytes_saved, r#"{"key":"somevalue"}"#.len());
+    /// ```
+    pub fn dedup_stats(&self) -> DedupStats {
+        let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
+        let mut references = HashMap::<String, usize>::new();
+        for rt in self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .values()
+        {
+            let rt_r = rt
+                .read()
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            for rev in rt_r.get_all_revs() {
+                if let Some(digest) = DataStorage::payload_digest(rev) {
+                    if data_r.stored_length(digest).is_some() {
+                        *references.entry(digest.to_string()).or_default() += 1;
                     }
                 }
-                drop(block_r);
-                let mut block_w = block_item
-                    .write()
-                    .expect("cannot_acquire_block_for_writing");
-                block_w.status = Status::ValidAndApplied;
-                // We can drop the changes vector
-                block_w.changes = None;
             }
         }
-        Ok(())
+        let mut stats = DedupStats {
+            unique_objects: references.len(),
+            ..DedupStats::default()
+        };
+        for (digest, count) in references {
+            stats.total_references += count;
+            stats.bytes_saved += (count - 1) * data_r.stored_length(&digest).unwrap_or(0);
+        }
+        stats
     }
 
-    /// Drops uncommitted changes
+    /// Saves the current stage
     ///
     /// # Example
     /// ```
@@ -984,41 +6685,33 @@ impl Melda {
     /// let value = replica.get_value("myobject", &winner);
     /// assert!(value.is_ok());
     /// assert!(value.unwrap().contains_key("_deleted"));
+    /// let stage = replica.stage().unwrap();
     /// replica.unstage();
     /// let winner = replica.get_winner("myobject").unwrap();
     /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// replica.replay_stage(&stage);
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", winner);
     /// ```
-    pub fn unstage(&self) -> Result<()> {
-        self.data
-            .write()
-            .expect("cannot_acquire_data_for_writing")
-            .unstage()?;
-        let mut stage = self.stage.write().unwrap();
-        let mut docs_w = self
-            .documents
-            .write()
-            .expect("failed_to_acquire_documents_for_writing");
-        stage.iter().for_each(|Change(uuid, rev, prev)| {
-            if let Some(rt) = docs_w.get(uuid) {
-                let mut rt_w = rt
-                    .write()
-                    .expect("cannot_acquire_revision_tree_for_writing");
-                rt_w.remove(rev.clone(), prev.clone());
-                if rt_w.is_empty() {
-                    drop(rt_w);
-                    docs_w.remove(uuid);
-                }
-            }
-        });
-        stage.clear();
-        Ok(())
+    pub fn stage(&self) -> Result<Option<Value>> {
+        let mut r = Map::<String, Value>::new();
+        let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        let data_stage = data.stage()?;
+        r.insert(OBJECTS_FIELD.to_string(), data_stage);
+        let changes = self.stage_records();
+        if !changes.is_empty() {
+            r.insert(CHANGESETS_FIELD.to_string(), Value::from(changes));
+            Ok(Some(Value::from(r)))
+        } else {
+            Ok(None)
+        }
     }
 
-    /// Merges changes from another Melda into this one (does not stage changes)
+    /// Replays a stage
     ///
     /// # Arguments
     ///
-    /// * `other` - Another Melda instance
+    /// * `s` - The stage to be replayed
     ///
     /// # Example
     /// ```
@@ -1034,42 +6727,128 @@ impl Melda {
     /// let winner = replica.get_winner("myobject").unwrap();
     /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
     /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// replica2.merge(&replica);
-    /// assert!(replica2.get_all_objects().contains("myobject"));
-    /// let winner = replica2.get_winner("myobject").unwrap();
+    /// replica.delete_object("myobject");
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", winner);
+    /// let value = replica.get_value("myobject", &winner);
+    /// assert!(value.is_ok());
+    /// assert!(value.unwrap().contains_key("_deleted"));
+    /// let stage = replica.stage().unwrap();
+    /// replica.unstage();
+    /// let winner = replica.get_winner("myobject").unwrap();
     /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// assert!(replica2.get_block(&block_id).unwrap().is_none());
-    pub fn merge(&self, other: &Melda) -> Result<()> {
-        for (uuid, rt) in other.documents.read().unwrap().iter() {
-            let rt_r = rt
-                .read()
-                .expect("failed_to_acquire_revision_tree_for_reading");
-            let mut docs_w = self
+    /// replica.replay_stage(&stage);
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", winner);
+    /// ```
+    pub fn replay_stage(&self, s: &Option<Value>) -> Result<()> {
+        if let Some(s) = s {
+            if s.is_object() {
+                let s = s.as_object().unwrap();
+                if s.contains_key(OBJECTS_FIELD) {
+                    let o = s.get(OBJECTS_FIELD).unwrap();
+                    let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+                    data.replay_stage(o)?;
+                }
+                if let Some(changes) = s.get(CHANGESETS_FIELD) {
+                    self.replay_changes(changes, true)?;
+                }
+                Ok(())
+            } else {
+                Err(anyhow!("expecting_stage_object"))
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Applies a changeset produced by another replica (the objects and changes fields of the
+    /// value returned by stage), received through a transport other than the adapter. The objects
+    /// are written into the data storage and the revisions are added to the revision trees as
+    /// if they had been committed: unlike replay_stage they are not staged, hence they are not
+    /// included in the next delta block of this replica (however, subsequent local updates
+    /// of the same objects refer to them as parents). Unlike meld, no delta block is transferred:
+    /// the applied changes are only held in memory and are discarded by reload, unless the
+    /// corresponding delta block (committed by the originating replica) becomes available.
+    /// Like replay_stage, changes which are not an array and records which are not arrays are
+    /// skipped, while malformed records are rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `objects` - The objects (field "o" of the stage)
+    /// * `changes` - The changes (field "c" of the stage)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : "somevalue" }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object.clone());
+    /// let stage = replica.stage().unwrap().unwrap();
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let peer = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// peer.apply_changeset(stage.get("o").unwrap().clone(), stage.get("c").unwrap().clone()).unwrap();
+    /// assert!(!peer.has_staging());
+    /// let winner = peer.get_winner("myobject").unwrap();
+    /// assert_eq!(winner, replica.get_winner("myobject").unwrap());
+    /// assert_eq!(peer.get_value("myobject", &winner).unwrap(), object);
+    /// assert!(peer.apply_changeset(json!({}), json!([["myobject"]])).is_err());
+    /// // Entries which are not records are skipped
+    /// assert!(peer.apply_changeset(json!({}), json!({})).is_ok());
+    /// assert!(peer.apply_changeset(json!({}), json!([null, "myobject"])).is_ok());
+    /// assert_eq!(winner, peer.get_winner("myobject").unwrap());
+    /// ```
+    pub fn apply_changeset(&self, objects: Value, changes: Value) -> Result<()> {
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        data.replay_stage(&objects)?;
+        drop(data);
+        self.replay_changes(&changes, false)
+    }
+
+    /// Adds the revisions of the given changes records to the revision trees, recording
+    /// them in the stage if staging is true. Changes which are not an array, as well as
+    /// records which are not arrays, are skipped
+    fn replay_changes(&self, changes: &Value, staging: bool) -> Result<()> {
+        let changes = match changes.as_array() {
+            Some(changes) => changes,
+            None => return Ok(()),
+        };
+        for record in changes.iter().filter_map(|c| c.as_array()) {
+            let Change(uuid, r, prev) =
+                Change::from_record(record, self.revision_tail_length, true)?;
+            let uuid = uuid.as_str();
+            let mut docs = self
                 .documents
                 .write()
-                .expect("cannot_acquire_documents_for_writing");
-            let mut rt_w = docs_w
+                .expect("failed_to_acquire_documents_for_writing");
+            // FIXME: Should an update of an unknown object be allowed?
+            // This might happen if we save the stage, then reload to a previous block
+            // were an object did not yet exist and then try to re-apply the stage
+            let known = docs.contains_key(uuid);
+            let mut rt_w = docs
                 .entry(uuid.to_string())
                 .or_insert_with(|| RwLock::new(RevisionTree::new()))
                 .write()
-                .expect("cannot_acquire_revision_tree_for_writing");
-            rt_w.merge(&rt_r);
-            drop(rt_r);
-            drop(rt_w);
+                .expect("failed_to_acquire_revision_tree_for_writing");
+            if (rt_w.add(r.clone(), prev.clone()) || (!known && prev.is_some())) && staging {
+                self.stage
+                    .write()
+                    .unwrap()
+                    .push(Change(uuid.to_string(), r, prev));
+            }
         }
-        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
-        let otherdata = &other.data.read().unwrap();
-        data.merge(otherdata)
+        Ok(())
     }
 
-    /// Melds another Melda into this one
+    /// Returns a block, or None if the block does not exist.
     ///
     /// # Arguments
     ///
-    /// * `other` - Another Melda instance
+    /// * `block_id` - Block identifier
     ///
     /// # Example
     /// ```
@@ -1081,43 +6860,189 @@ impl Melda {
     /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
     /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let parent = replica.get_parent_revision("myobject", &winner).unwrap();
+    /// assert!(parent.is_none());
     /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// replica2.meld(&replica);
-    /// replica2.refresh();
-    /// assert!(replica2.get_all_objects().contains("myobject"));
-    /// let winner = replica2.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block = replica2.get_block(&block_id).unwrap().unwrap();
+    /// let block = replica.get_block(&block_id).unwrap().unwrap();
     /// assert_eq!(block_id, block.id);
-    pub fn meld(&self, other: &Melda) -> Result<Vec<String>> {
-        let mut result = vec![];
-        let other_data = other.data.read().unwrap();
-        let other_items = other_data.list_raw_items("")?;
-        if !other_items.is_empty() {
-            let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
-            let this_items = data.list_raw_items("")?;
-            let this_items: HashSet<String> = this_items.into_iter().collect();
-            for i in &other_items {
-                if !this_items.contains(i) {
-                    data.write_raw_bytes(i, other_data.read_raw_bytes(i, 0, 0)?.as_slice())?;
-                    result.push(i.clone());
-                }
+    pub fn get_block(&self, block_id: &str) -> Result<Option<Block>> {
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        match blocks_r.get(block_id) {
+            Some(b) => {
+                let block_r = b.read().expect("cannot_acquire_block_for_reading");
+                Ok(Some(block_r.clone()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the valid and applied blocks sorted by the key extracted by the given function (for
+    /// example, a timestamp stored in the information object of the block). Blocks for which no
+    /// key can be extracted are placed last, blocks with the same key are sorted by identifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_fn` - The function extracting the sort key from a block (None if missing)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "a" : 1 }).as_object().unwrap().clone());
+    /// let info = json!({ "date" : "2022-05-23" }).as_object().unwrap().clone();
+    /// let first = replica.commit(Some(info)).unwrap().unwrap();
+    /// replica.update(json!({ "a" : 2 }).as_object().unwrap().clone());
+    /// let untimed = replica.commit(None).unwrap().unwrap();
+    /// replica.update(json!({ "a" : 3 }).as_object().unwrap().clone());
+    /// let info = json!({ "date" : "2022-05-21" }).as_object().unwrap().clone();
+    /// let last = replica.commit(Some(info)).unwrap().unwrap();
+    /// let blocks = replica.blocks_sorted_by(|b| {
+    ///     b.info.as_ref().and_then(|i| i.get("date")).and_then(|d| d.as_str()).map(|d| d.to_string())
+    /// });
+    /// let ids : Vec<String> = blocks.into_iter().map(|b| b.id).collect();
+    /// assert_eq!(ids, vec![last, first, untimed]);
+    /// ```
+    pub fn blocks_sorted_by<F, K>(&self, key_fn: F) -> Vec<Block>
+    where
+        F: Fn(&Block) -> Option<K>,
+        K: Ord,
+    {
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        let mut blocks: Vec<(Option<K>, Block)> = blocks_r
+            .values()
+            .map(|b| b.read().expect("cannot_acquire_block_for_reading"))
+            .filter(|b| b.status == Status::ValidAndApplied)
+            .map(|b| (key_fn(&b), b.clone()))
+            .collect();
+        drop(blocks_r);
+        blocks.sort_by(|(k1, b1), (k2, b2)| match (k1, k2) {
+            (Some(k1), Some(k2)) => k1.cmp(k2).then_with(|| b1.id.cmp(&b2.id)),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => b1.id.cmp(&b2.id),
+        });
+        blocks.into_iter().map(|(_, b)| b).collect()
+    }
+
+    /// Returns the changes recorded in a block as (object, revision, parent revision) tuples,
+    /// or None if the block does not exist. If the changes have been dropped after applying
+    /// the block (see MeldaConfig::retain_block_changes) the block is fetched again from the
+    /// adapter and parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_id` - Block identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, MeldaConfig}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : "somevalue" }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let expected = vec![("myobject".to_string(), winner, None)];
+    /// let reloaded = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert_eq!(reloaded.get_block_changes(&block_id).unwrap().unwrap(), expected);
+    /// let config = MeldaConfig { retain_block_changes: true, ..MeldaConfig::default() };
+    /// let retained = Melda::new_with_config(adapter, config).expect("cannot_initialize_crdt");
+    /// assert_eq!(retained.get_block_changes(&block_id).unwrap().unwrap(), expected);
+    /// assert!(retained.get_block_changes("unknown").unwrap().is_none());
+    /// ```
+    pub fn get_block_changes(&self, block_id: &str) -> Result<Option<Vec<BlockChange>>> {
+        Ok(self.block_changes(block_id)?.map(|changes| {
+            changes
+                .into_iter()
+                .map(|Change(uuid, r, prev)| (uuid, r.to_string(), prev.map(|p| p.to_string())))
+                .collect()
+        }))
+    }
+
+    /// Returns the objects changed by each applied block, as (object, revision) pairs (see
+    /// get_block_changes), indexed by block identifier. Blocks whose changes have been dropped
+    /// after applying them (see MeldaConfig::retain_block_changes) are fetched again from the
+    /// adapter and parsed.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// use std::collections::BTreeMap;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(replica.changes_by_block().unwrap().is_empty());
+    /// replica.create_object("first", json!({ "a" : 1 }).as_object().unwrap().clone()).unwrap();
+    /// let first_revision = replica.get_winner("first").unwrap();
+    /// let first = replica.commit(None).unwrap().unwrap();
+    /// replica.create_object("second", json!({ "b" : 2 }).as_object().unwrap().clone()).unwrap();
+    /// let second_revision = replica.get_winner("second").unwrap();
+    /// let second = replica.commit(None).unwrap().unwrap();
+    /// let expected = BTreeMap::from([
+    ///     (first, vec![("first".to_string(), first_revision)]),
+    ///     (second, vec![("second".to_string(), second_revision)]),
+    /// ]);
+    /// assert_eq!(replica.changes_by_block().unwrap(), expected);
+    /// ```
+    pub fn changes_by_block(&self) -> Result<BTreeMap<String, Vec<(String, String)>>> {
+        let mut result = BTreeMap::new();
+        for (block_id, _) in self.applied_blocks_in_order() {
+            if let Some(changes) = self.block_changes(&block_id)? {
+                let changes = changes
+                    .into_iter()
+                    .map(|Change(uuid, r, _)| (uuid, r.to_string()))
+                    .collect();
+                result.insert(block_id, changes);
             }
         }
         Ok(result)
     }
 
-    /// Replicate changes from another Melda into this one
+    /// Returns the changes of a block, re-parsing the block if the changes have been dropped
+    fn block_changes(&self, block_id: &str) -> Result<Option<Vec<Change>>> {
+        let retained = match self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading")
+            .get(block_id)
+        {
+            Some(b) => b
+                .read()
+                .expect("cannot_acquire_block_for_reading")
+                .changes
+                .clone(),
+            None => return Ok(None),
+        };
+        let changes = match retained {
+            Some(changes) => changes,
+            None => self
+                .parse_block(block_id.to_string(), self.fetch_raw_block(block_id)?, false)?
+                .changes
+                .unwrap_or_default(),
+        };
+        Ok(Some(changes))
+    }
+
+    /// Returns the parent revision in the revision tree of the specified object, or None if there is no parent
     ///
     /// # Arguments
     ///
-    /// * `other` - Another Melda instance
+    /// * `uuid` - Object identifier
+    /// * `revision` - The revision
     ///
     /// # Example
     /// ```
@@ -1129,172 +7054,89 @@ impl Melda {
     /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
     /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let parent = replica.get_parent_revision("myobject", &winner).unwrap();
+    /// assert!(parent.is_none());
     /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// replica2.replicate(&replica);
-    /// assert!(replica2.get_all_objects().contains("myobject"));
-    /// let winner = replica2.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// assert!(replica2.get_block(&block_id).unwrap().is_none());
-    pub fn replicate(&self, other: &Melda) -> Result<()> {
-        let other_data = other.data.read().unwrap();
-        let other_documents = other.documents.read().unwrap();
-        let other_stage = other.stage.read().unwrap();
-        if !self.stage.read().unwrap().is_empty() {
-            bail!("stage_not_empty")
-        }
-        if !other_stage.is_empty() {
-            bail!("other_stage_not_empty")
-        }
-        self.data
-            .write()
-            .expect("cannot_get_data_for_writing")
-            .replicate(&other_data)?;
-        for (uuid, other_rt) in other_documents.iter() {
-            let other_rt_r = other_rt
-                .read()
-                .expect("failed_to_acquire_revision_tree_for_reading");
-            let mut docs_w = self
-                .documents
-                .write()
-                .expect("cannot_get_documents_for_writing");
-            let mut rt_w = docs_w
-                .entry(uuid.to_string())
-                .or_insert_with(|| RwLock::new(RevisionTree::new()))
-                .write()
-                .expect("cannot_acquire_revision_tree_for_writing");
-            for (rev, prev) in other_rt_r.get_revisions() {
-                if rt_w.add(rev.clone(), prev.clone()) {
-                    self.stage
-                        .write()
-                        .expect("cannot_get_stage_for_writing")
-                        .push(Change(uuid.clone(), rev.clone(), prev.clone()));
-                }
-            }
+    /// replica.delete_object("myobject");
+    /// let newrev = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", newrev);
+    /// let parent = replica.get_parent_revision("myobject", &newrev).unwrap().unwrap();
+    /// assert_eq!(&parent, &winner);
+    pub fn get_parent_revision(&self, uuid: &str, revision: &str) -> Result<Option<String>> {
+        let docs = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let rt = docs.get(uuid).ok_or_else(|| anyhow!("unknown_document"))?;
+        let revision = Revision::from(revision).expect("invalid_revision_string");
+        let rt_r = rt
+            .read()
+            .expect("failed_to_acquire_revision_tree_for_reading");
+        match rt_r.get_parent(&revision) {
+            Some(r) => Ok(Some(r.to_string())),
+            None => Ok(None),
         }
-        Ok(())
     }
 
-    /// Reads the data structure and unflattens to a JSON object
+    /// Returns, for each object, the revisions which are referenced as parents but are missing
+    /// from its revision tree (ghost parents), for example because the blocks defining them have
+    /// not been received yet after an incomplete meld. Revisions removed by prune_history are
+    /// not reported.
     ///
     /// # Example
     /// ```
     /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
     /// use std::sync::{Arc, Mutex, RwLock};
-    /// use serde_json::{Map, Value,json,to_string};
+    /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.update(object.clone());
-    /// let readback = replica.read().unwrap();
-    /// assert!(readback.contains_key("somekey"));
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\":[\"somedata\",1,2,3,4]}", content);
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : "first" }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);
+    /// let first = replica.get_winner("myobject").unwrap();
+    /// replica.commit(None);
+    /// let object = json!({ "somekey" : "second" }).as_object().unwrap().clone();
+    /// replica.update_object("myobject", object);
+    /// let stage = replica.stage().unwrap().unwrap();
+    /// // Apply the update on a replica which did not receive the first block
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey\u{266D}" : [ { "_id": "1", "key" : "alpha" }, { "_id": "2", "key" : "beta" } ] }).as_object().unwrap().clone();
-    /// replica.update(object.clone());
-    /// let readback = replica.read().unwrap();
-    /// assert!(!readback.contains_key("somekey"));
-    /// assert!(readback.contains_key("somekey\u{266D}"));
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"1\",\"key\":\"alpha\"},{\"_id\":\"2\",\"key\":\"beta\"}]}", content);
-    /// let info = json!({ "author" : "Some user", "date" : "2022-05-23 13:47:00CET" }).as_object().unwrap().clone();
-    /// replica.commit(Some(info));
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// replica2.meld(&replica);
-    /// replica2.refresh();
-    /// // Continue editing on replica, removing one item
-    /// let object = json!({ "somekey\u{266D}" : [ { "_id": "2", "key" : "beta" } ] }).as_object().unwrap().clone();
-    /// replica.update(object.clone());
-    /// let readback = replica.read().unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"2\",\"key\":\"beta\"}]}", content);
-    /// // Commit changes on replica
-    /// let info = json!({ "author" : "Some user", "date" : "2022-05-23 13:47:00CET" }).as_object().unwrap().clone();
-    /// replica.commit(Some(info));
-    /// let readback = replica.read().unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"2\",\"key\":\"beta\"}]}", content);
-    /// // Perform some changes on replica2 too
-    /// let object = json!({ "somekey\u{266D}" : [ { "_id": "1", "key" : "alpha" }, { "_id": "2", "key" : "beta" }, { "_id": "3", "key" : "gamma" } ] }).as_object().unwrap().clone();
-    /// replica2.update(object.clone());
-    /// let readback = replica2.read().unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"1\",\"key\":\"alpha\"},{\"_id\":\"2\",\"key\":\"beta\"},{\"_id\":\"3\",\"key\":\"gamma\"}]}", content);
-    /// // Commit changes on replica2
-    /// let info = json!({ "author" : "Another user", "date" : "2022-05-23 13:48:00CET" }).as_object().unwrap().clone();
-    /// replica2.commit(Some(info));
-    /// let readback = replica2.read().unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"1\",\"key\":\"alpha\"},{\"_id\":\"2\",\"key\":\"beta\"},{\"_id\":\"3\",\"key\":\"gamma\"}]}", content);
-    /// // Meld changes from replica2 back on replica
-    /// replica.meld(&replica2);
-    /// // Melding does not change the state of replica
-    /// let readback = replica.read().unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"2\",\"key\":\"beta\"}]}", content);
-    /// // Refresh the state of replica
-    /// replica.refresh();
-    /// let readback = replica.read().unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"2\",\"key\":\"beta\"},{\"_id\":\"3\",\"key\":\"gamma\"}]}", content);
-    pub fn read(&self) -> Result<Map<String, Value>> {
-        if !self
+    /// let peer = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(peer.missing_revisions().is_empty());
+    /// peer.apply_changeset(stage.get("o").unwrap().clone(), stage.get("c").unwrap().clone()).unwrap();
+    /// let missing = peer.missing_revisions();
+    /// assert_eq!(missing.get("myobject").unwrap().iter().next().unwrap(), &first);
+    /// assert!(replica.missing_revisions().is_empty());
+    /// ```
+    pub fn missing_revisions(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let docs_r = self
             .documents
             .read()
-            .expect("failed_to_acquire_documents_for_reading")
-            .contains_key(ROOT_ID)
-        {
-            bail!("no_root")
-        } else {
-            let c = Mutex::new(HashMap::<String, Map<String, Value>>::new());
-            let docs_r = self
-                .documents
+            .expect("failed_to_acquire_documents_for_reading");
+        let pruned = self.pruned_revisions.lock().unwrap();
+        let mut result = BTreeMap::new();
+        for (uuid, rt) in docs_r.iter() {
+            let rt_r = rt
                 .read()
-                .expect("failed_to_acquire_documents_for_reading");
-            docs_r.par_iter().for_each(|(uuid, rt)| {
-                let rt_r = rt
-                    .read()
-                    .expect("failed_to_acquire_revision_tree_for_reading");
-                if let Some(winner) = rt_r.get_winner() {
-                    if !winner.is_deleted() {
-                        let mut obj = self.read_object(uuid, &rt_r).unwrap();
-                        drop(rt_r);
-                        obj.insert(ID_FIELD.to_string(), Value::from(uuid.clone()));
-                        let mut c_w = c.lock().unwrap();
-                        c_w.insert(uuid.clone(), obj);
-                        drop(c_w);
-                    }
-                }
-            });
-            let c_r = c.lock().unwrap();
-            let root = c_r.get(ROOT_ID).expect("root_object_not_found");
-            let root = Value::from(root.clone());
-            let result = unflatten(&c_r, &root)
-                .unwrap()
-                .as_object()
-                .expect("not_an_object")
-                .clone();
-            drop(c_r);
-            Ok(result)
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            let missing: BTreeSet<String> = rt_r
+                .get_ghost_parents()
+                .into_iter()
+                .filter(|r| !pruned.get(uuid).is_some_and(|p| p.contains(r)))
+                .map(|r| r.to_string())
+                .collect();
+            if !missing.is_empty() {
+                result.insert(uuid.clone(), missing);
+            }
         }
+        result
     }
 
-    /// Updates the data structure by flattening the input JSON object
+    /// Returns the revision graph of the specified object: a node for each revision in the
+    /// revision tree, with its parent and its status
     ///
     /// # Arguments
     ///
-    /// * `obj` - input JSON object
+    /// * `uuid` - Object identifier
     ///
     /// # Example
     /// ```
@@ -1302,93 +7144,62 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.update(object.clone());
-    /// let readback = replica.read().unwrap();
-    /// assert!(readback.contains_key("somekey"));
-    pub fn update(&self, obj: Map<String, Value>) -> Result<()> {
-        let mut extracted_objects = HashMap::<String, Map<String, Value>>::new();
-        let path = Vec::<String>::new();
-        let root = Value::from(obj);
-        // Flatten the structure
-        let root = flatten(&mut extracted_objects, &root, &path);
-        let root = root.as_str().expect("root_identifier_not_a_string");
-        if root != ROOT_ID {
-            bail!("invalid_root_id");
-        }
-        // Check for objects that have disappeared
-        // i.e. objects that are found in the current state but are not within the extracted objects
-        let docs_r = self
+    /// replica.create_object("myobject", object);
+    /// let first = replica.get_winner("myobject").unwrap();
+    /// replica.commit(None);
+    /// replica.delete_object("myobject");
+    /// let graph = replica.revision_graph("myobject").unwrap();
+    /// assert_eq!(graph.len(), 2);
+    /// assert_eq!(graph[0].revision, first);
+    /// assert!(graph[0].parent.is_none() && !graph[0].is_staging && !graph[0].is_winner);
+    /// assert_eq!(graph[1].parent, Some(first));
+    /// assert!(graph[1].is_staging && graph[1].is_deleted && graph[1].is_winner);
+    /// assert!(replica.revision_graph("otherobject").is_err());
+    /// ```
+    pub fn revision_graph(&self, uuid: &str) -> Result<Vec<RevisionNode>> {
+        let docs = self
             .documents
             .read()
             .expect("failed_to_acquire_documents_for_reading");
-        docs_r
-            .par_iter()
-            .filter(|(uuid, _)| !extracted_objects.contains_key(*uuid))
-            .for_each(|(uuid, _)| {
-                self.delete_object(uuid).expect("unable_to_delete_object");
-            });
-        drop(docs_r);
-        // Check for newly created and updated objects
-        extracted_objects.into_par_iter().for_each(|(uuid, obj)| {
-            //for (uuid, obj) in extracted_objects {
-            self.update_object(&uuid, obj)
-                .expect("unable_to_update_object");
-        });
-        Ok(())
-    }
-
-    /// Returns a set of the object (identifiers) which have ongoing conflicts
-    ///
-    /// # Example
-    /// ```
-    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
-    /// use std::sync::{Arc, Mutex, RwLock};
-    /// use serde_json::{Map, Value,json};
-    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica2.create_object("myobject", object);  
-    /// replica2.meld(&replica);
-    /// replica2.refresh();
-    /// let conflicting = replica2.in_conflict();
-    /// assert!(conflicting.contains("myobject"));
-    pub fn in_conflict(&self) -> BTreeSet<String> {
-        let mut result = BTreeSet::new();
-        // TODO: Make parallel
-        self.documents
+        let rt = docs.get(uuid).ok_or_else(|| anyhow!("unknown_document"))?;
+        let rt_r = rt
             .read()
-            .expect("failed_to_acquire_documents_for_reading")
+            .expect("failed_to_acquire_revision_tree_for_reading");
+        let staged: HashSet<Revision> = self
+            .stage
+            .read()
+            .expect("cannot_acquire_stage_for_reading")
             .iter()
-            .for_each(|(uuid, rt)| {
-                let rt_r = rt
-                    .read()
-                    .expect("failed_to_acquire_revision_tree_for_reading");
-                let l = rt_r.get_leafs();
-                if l.len() > 1 {
-                    result.insert(uuid.clone());
-                }
-            });
-        result
+            .filter(|Change(id, _, _)| id == uuid)
+            .map(|Change(_, r, _)| r.clone())
+            .collect();
+        let winner = rt_r.get_winner();
+        Ok(rt_r
+            .get_revisions()
+            .iter()
+            .map(|(r, parent)| RevisionNode {
+                revision: r.to_string(),
+                parent: parent.as_ref().map(|p| p.to_string()),
+                is_staging: staged.contains(r),
+                is_deleted: r.is_deleted(),
+                is_resolved: r.is_resolved(),
+                is_winner: winner == Some(r),
+            })
+            .collect())
     }
 
-    /// Returns the winning revision for the given object
+    /// Exports the whole revision history of the specified object as a JSON array, with an
+    /// entry for each revision in the revision tree (in the same order as revision_graph):
+    /// an object with the revision, its parent (null for first revisions), its value (see
+    /// get_value: the values of array descriptors are materialized, deleted, resolved, empty
+    /// and simple character revisions have their synthetic values) and whether the revision is
+    /// the winning one.
     ///
     /// # Arguments
     ///
-    /// * `uuid` - The uuid of the object
+    /// * `uuid` - Object identifier
     ///
     /// # Example
     /// ```
@@ -1396,51 +7207,64 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// replica.delete_object("myobject");
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", winner);
-    /// let value = replica.get_value("myobject", &winner);
-    /// assert!(value.is_ok());
-    /// assert!(value.unwrap().contains_key("_deleted"));
-    /// replica.unstage();
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.create_object("myobject", json!({ "somekey" : "first" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.update_object("myobject", json!({ "somekey" : "second" }).as_object().unwrap().clone()).unwrap();
+    /// replica.delete_object("myobject").unwrap();
+    /// let history = replica.export_object("myobject").unwrap();
+    /// let history = history.as_array().unwrap();
+    /// assert_eq!(history.len(), 3);
+    /// assert_eq!(history[0]["parent"], Value::Null);
+    /// assert_eq!(history[0]["value"], json!({ "somekey" : "first" }));
+    /// assert_eq!(history[1]["parent"], history[0]["revision"]);
+    /// assert_eq!(history[1]["value"], json!({ "somekey" : "second" }));
+    /// assert_eq!(history[2]["value"], json!({ "_deleted" : true }));
+    /// assert_eq!(history[2]["is_winner"], json!(true));
+    /// // Array descriptors are materialized
+    /// replica.update(json!({ "items\u{266D}" : [ 1, 2 ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [ 1, 2, 3 ] }).as_object().unwrap().clone()).unwrap();
+    /// let array = replica.get_all_objects().into_iter().find(|uuid| uuid.starts_with('^')).unwrap();
+    /// let history = replica.export_object(&array).unwrap();
+    /// assert_eq!(history[1]["value"], json!({ "A" : [ 1, 2, 3 ] }));
+    /// assert!(replica.export_object("otherobject").is_err());
     /// ```
-    pub fn get_winner<T>(&self, uuid: T) -> Result<String>
-    where
-        T: AsRef<str>,
-    {
-        match self
+    pub fn export_object(&self, uuid: &str) -> Result<Value> {
+        let docs = self
             .documents
             .read()
-            .expect("cannot_acquire_documents_for_reading")
-            .get(uuid.as_ref())
-        {
-            Some(rt) => {
-                let rt_r = rt.read().expect("cannot_acquire_revision_tree_for_reading");
-                match rt_r.get_winner() {
-                    Some(r) => Ok(r.to_string()),
-                    None => Err(anyhow!("no_winner")),
-                }
-            }
-            None => Err(anyhow!("unknown_document")),
-        }
+            .expect("failed_to_acquire_documents_for_reading");
+        let rt = docs.get(uuid).ok_or_else(|| anyhow!("unknown_document"))?;
+        let rt_r = rt
+            .read()
+            .expect("failed_to_acquire_revision_tree_for_reading");
+        let winner = rt_r.get_winner();
+        let entries = rt_r
+            .get_revisions()
+            .iter()
+            .map(|(r, parent)| {
+                let mut entry = Map::new();
+                entry.insert("revision".to_string(), Value::from(r.to_string()));
+                entry.insert(
+                    "parent".to_string(),
+                    Value::from(parent.as_ref().map(|p| p.to_string())),
+                );
+                entry.insert(
+                    "value".to_string(),
+                    Value::from(self.read_value(uuid, &rt_r, r)?),
+                );
+                entry.insert("is_winner".to_string(), Value::from(winner == Some(r)));
+                Ok(Value::from(entry))
+            })
+            .collect::<Result<Vec<Value>>>()?;
+        Ok(Value::from(entries))
     }
 
-    /// Returns a set of the conflicting revisions of the given object (the winning revision is not included!)
-    ///
-    /// # Arguments
-    ///
-    /// * `uuid` - The uuid of the object
+    /// Verifies the integrity of the whole storage without modifying the current state: checks
+    /// that the digest of each pack and each block matches its identifier, that the packs and
+    /// parents referenced by each block exist and are valid, and that the payload of each revision
+    /// referenced in a changeset is available.
     ///
     /// # Example
     /// ```
@@ -1451,54 +7275,94 @@ impl Melda {
     /// let adapter = Arc::new(RwLock::new(adapter));
     /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica2.create_object("myobject", object);
-    /// let winner2 = replica2.get_winner("myobject").unwrap();
-    /// replica2.meld(&replica);
-    /// replica2.refresh();
-    /// let conflicting = replica2.in_conflict();
-    /// assert!(conflicting.contains("myobject"));
-    /// let revs = replica2.get_conflicting("myobject").unwrap();
-    /// assert!(revs.contains(&winner2));
-    pub fn get_conflicting<T>(&self, uuid: T) -> Result<BTreeSet<String>>
-    where
-        T: AsRef<str>,
-    {
-        match self
-            .documents
-            .read()
-            .expect("failed_to_acquire_documents_for_reading")
-            .get(uuid.as_ref())
-        {
-            Some(rt) => {
-                let rt_r = rt
-                    .read()
-                    .expect("failed_to_acquire_revision_tree_for_reading");
-                let w = rt_r.get_winner().ok_or_else(|| anyhow!("no_winner"))?;
-                let l = rt_r.get_leafs();
-                Ok(l.iter()
-                    .filter(|r| w.ne(r))
-                    .map(|r| r.to_string())
-                    .collect())
+    /// replica.create_object("myobject", object);
+    /// replica.commit(None);
+    /// let report = replica.verify().unwrap();
+    /// assert!(report.is_valid());
+    /// assert!(report.orphaned_objects.is_empty());
+    /// adapter.write().unwrap().write_object("0123456789.delta", "{}".as_bytes());
+    /// let report = replica.verify().unwrap();
+    /// assert!(!report.is_valid());
+    /// assert!(report.corrupt_blocks.contains("0123456789"));
+    /// ```
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
+        let (corrupt_packs, objects) = data_r.scan_packs()?;
+        let pack_list: HashSet<String> =
+            data_r.list_raw_items(PACK_EXTENSION)?.into_iter().collect();
+        let block_list = data_r.list_raw_items(DELTA_EXTENSION)?;
+        drop(data_r);
+        // Fetch and parse blocks (verifying the digest)
+        let mut blocks = BTreeMap::new();
+        let progress = self.progress_reporter();
+        let total = block_list.len();
+        for (done, bid) in block_list.into_iter().enumerate() {
+            match self
+                .fetch_raw_block(&bid)
+                .and_then(|raw_block| self.parse_block(bid.clone(), raw_block, false))
+            {
+                Ok(block) => {
+                    blocks.insert(bid, block);
+                }
+                Err(_) => {
+                    report.corrupt_blocks.insert(bid);
+                }
+            }
+            if let Some(progress) = &progress {
+                progress.on_progress(ProgressOperation::Verify, done + 1, total);
+            }
+        }
+        // Check references
+        let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
+        let mut referenced = HashSet::new();
+        for (bid, block) in &blocks {
+            if let Some(packs) = &block.packs {
+                for p in packs {
+                    if !data_r
+                        .resolve_pack(p)
+                        .iter()
+                        .all(|p| pack_list.contains(p) && !corrupt_packs.contains(p))
+                    {
+                        report.dangling_references.insert((bid.clone(), p.clone()));
+                    }
+                }
+            }
+            if let Some(parents) = &block.parents {
+                for p in parents {
+                    if !blocks.contains_key(p) {
+                        report.dangling_references.insert((bid.clone(), p.clone()));
+                    }
+                }
+            }
+            if let Some(changes) = &block.changes {
+                for Change(_, rev, _) in changes {
+                    if let Some(digest) = DataStorage::payload_digest(rev) {
+                        if !objects.contains_key(digest) {
+                            report
+                                .dangling_references
+                                .insert((bid.clone(), rev.to_string()));
+                        }
+                        referenced.insert(digest.to_string());
+                    }
+                }
             }
-            None => Err(anyhow!("unknown_document")),
         }
+        report.orphaned_objects = objects
+            .into_keys()
+            .filter(|digest| !referenced.contains(digest))
+            .collect();
+        report.corrupt_packs = corrupt_packs;
+        Ok(report)
     }
 
-    /// Resolves a conflict by choosing the new winning revision. All other conflicting revisions are marked as resolved.
-    ///
-    /// # Arguments
-    ///
-    /// * `uuid` - The uuid of the object
-    /// * `winner` - The revision that is to be made the winner
+    /// Re-validates the blocks against the packs currently available in the adapter: blocks whose
+    /// packs cannot be read or are not valid anymore (for example because a pack has been removed
+    /// after the CRDT was loaded), as well as their descendants, are demoted to invalid. The
+    /// revision trees are then rebuilt from the remaining valid blocks (staged changes are kept),
+    /// hence the changes recorded by the demoted blocks disappear: once the missing packs have
+    /// been restored (for example by melding another replica) they can be loaded again with
+    /// reload. Returns the identifiers of the demoted blocks.
     ///
     /// # Example
     /// ```
@@ -1509,111 +7373,156 @@ impl Melda {
     /// let adapter = Arc::new(RwLock::new(adapter));
     /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// replica.update(object);
+    /// let first = replica.commit(None).unwrap().unwrap();
+    /// let expected = replica.read().unwrap();
+    /// let object = json!({ "somekey" : [ "otherdata" ] }).as_object().unwrap().clone();
+    /// replica.update(object);
     /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica2.create_object("myobject", object);
-    /// let winner2 = replica2.get_winner("myobject").unwrap();
-    /// assert_eq!("1-255cc6219e48f526c04bc5af86439c34e4fe39fcdc611758ff833a2ff80583f0", winner2);
-    /// replica2.meld(&replica);
-    /// replica2.refresh();
-    /// let conflicting = replica2.in_conflict();
-    /// assert!(conflicting.contains("myobject"));
-    /// let revs = replica2.get_conflicting("myobject").unwrap();
-    /// assert!(revs.contains(&winner2));
-    /// let winner3 = replica2.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner3);    
-    /// replica2.resolve_as("myobject", &winner2);
-    /// let winner = replica2.get_winner("myobject").unwrap();
-    /// assert_eq!("2-255cc6219e48f526c04bc5af86439c34e4fe39fcdc611758ff833a2ff80583f0_e5d1d20", winner);
-    /// assert!(replica2.in_conflict().is_empty());
-    pub fn resolve_as(&self, uuid: &str, winner: &str) -> Result<String> {
-        {
-            let winner = Revision::from(winner).expect("invalid_revision_string");
-            let docs_r = self
-                .documents
-                .read()
-                .expect("failed_to_acquire_documents_for_reading");
-            let rt = docs_r
-                .get(uuid)
-                .ok_or_else(|| anyhow!("unknown_document"))?;
-            let rt_r = rt
-                .read()
-                .expect("failed_to_acquire_revision_tree_for_reading");
-            {
-                let leafs = rt_r.get_leafs();
-                // We can only resolve to a status revision
-                if !leafs.contains(&winner) {
-                    bail!("invalid_winner_revision");
+    /// let replica2 = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert!(replica2.revalidate_blocks().unwrap().is_empty());
+    /// // Remove the pack after the replica has been loaded
+    /// let block = replica2.get_block(&block_id).unwrap().unwrap();
+    /// for pack in block.packs.unwrap() {
+    ///     adapter.write().unwrap().delete_object(&(pack + ".pack")).unwrap();
+    /// }
+    /// assert!(replica2.read().is_err());
+    /// assert_eq!(replica2.revalidate_blocks().unwrap(), [block_id].into());
+    /// assert_eq!(replica2.get_anchors(), [first].into());
+    /// // The state is rebuilt from the remaining blocks
+    /// assert_eq!(replica2.read().unwrap(), expected);
+    /// ```
+    pub fn revalidate_blocks(&self) -> Result<BTreeSet<String>> {
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
+        let mut demoted = BTreeSet::new();
+        for (bid, block) in blocks_r.iter() {
+            let block_r = block.read().expect("cannot_acquire_block_for_reading");
+            if block_r.status == Status::Invalid {
+                continue;
+            }
+            if let Some(packs) = &block_r.packs {
+                if !packs
+                    .iter()
+                    .all(|p| data_r.is_readable_and_valid_pack(p).unwrap_or(false))
+                {
+                    demoted.insert(bid.clone());
                 }
-                // If there is only one leaf nothing needs to be resolved
-                if leafs.len() <= 1 {
-                    bail!("not_in_conflict");
+            }
+        }
+        drop(data_r);
+        // Demote the descendants of the demoted blocks
+        let mut changed = !demoted.is_empty();
+        while changed {
+            changed = false;
+            for (bid, block) in blocks_r.iter() {
+                let block_r = block.read().expect("cannot_acquire_block_for_reading");
+                if block_r.status == Status::Invalid || demoted.contains(bid) {
+                    continue;
+                }
+                if let Some(parents) = &block_r.parents {
+                    if parents.iter().any(|p| demoted.contains(p)) {
+                        demoted.insert(bid.clone());
+                        changed = true;
+                    }
                 }
             }
-            // Update the winner to ensure that we do not change the view
-            let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
-            let merged = data_r.read_object(&winner)?;
-            drop(winner);
-            drop(rt_r);
-            drop(data_r);
-            drop(docs_r);
-            self.update_object(uuid, merged)?;
         }
-        let docs_r = self
-            .documents
-            .read()
-            .expect("failed_to_acquire_documents_for_reading");
-        let rt = docs_r
-            .get(uuid)
-            .ok_or_else(|| anyhow!("unknown_document"))?;
-        let rt_r = rt
-            .read()
-            .expect("failed_to_acquire_revision_tree_for_reading");
-        let winner = rt_r
-            .get_winner()
-            .expect("revision_tree_invalid_state")
-            .clone();
-        drop(rt_r);
-        drop(docs_r);
-        // Seal all other revisions as resolved
-        let mut docs_w = self
-            .documents
-            .write()
-            .expect("failed_to_acquire_documents_for_writing");
-        let rt = docs_w
-            .get_mut(uuid)
-            .ok_or_else(|| anyhow!("unknown_document"))?;
-        let rt_r = rt
+        for bid in &demoted {
+            let mut block_w = blocks_r
+                .get(bid)
+                .unwrap()
+                .write()
+                .expect("cannot_acquire_block_for_writing");
+            if block_w.status == Status::ValidAndApplied {
+                self.unset_applied(&block_w);
+            }
+            block_w.status = Status::Invalid;
+        }
+        drop(blocks_r);
+        if !demoted.is_empty() {
+            self.rebuild_from_valid_blocks()?;
+        }
+        Ok(demoted)
+    }
+
+    // Rebuilds the revision trees from the changes of the applied blocks (including the retired
+    // blocks in shallow mode), then adds the staged changes
+    fn rebuild_from_valid_blocks(&self) -> Result<()> {
+        let retired: Vec<String> = self
+            .retired_blocks
             .read()
-            .expect("failed_to_acquire_revision_tree_for_reading");
-        let leafs: Vec<Revision> = rt_r.get_leafs().iter().map(|r| (*r).clone()).collect();
-        drop(rt_r);
-        for r in leafs {
-            if r != winner {
-                let resolved = Revision::new_resolved(&r);
-                let mut rt_w = rt
-                    .write()
-                    .expect("failed_to_acquire_revision_tree_for_writing");
-                if rt_w.add(resolved.clone(), Some(r.clone())) {
-                    self.stage.write().unwrap().push(Change(
-                        uuid.to_string(),
-                        resolved,
-                        Some(r.clone()),
-                    ));
-                }
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect();
+        let mut documents = BTreeMap::new();
+        let mut inconsistent = BTreeMap::new();
+        for bid in retired {
+            let block = self.parse_block(bid.clone(), self.fetch_raw_block(&bid)?, false)?;
+            if let Some(changes) = &block.changes {
+                Self::apply_changes(
+                    &bid,
+                    changes,
+                    self.revision_tail_length,
+                    &mut documents,
+                    &mut inconsistent,
+                );
             }
         }
-        Ok(winner.to_string())
+        for (bid, _) in self.applied_blocks_in_order() {
+            if let Some(changes) = self.block_changes(&bid)? {
+                Self::apply_changes(
+                    &bid,
+                    &changes,
+                    self.revision_tail_length,
+                    &mut documents,
+                    &mut inconsistent,
+                );
+            }
+        }
+        let mut docs_w = self
+            .documents
+            .write()
+            .expect("failed_to_acquire_documents_for_writing");
+        let stage_r = self.stage.read().expect("cannot_acquire_stage_for_reading");
+        for Change(uuid, rev, prev) in stage_r.iter() {
+            documents
+                .entry(uuid.clone())
+                .or_insert_with(|| RwLock::new(RevisionTree::new()))
+                .write()
+                .expect("cannot_acquire_revision_tree_for_writing")
+                .add(rev.clone(), prev.clone());
+        }
+        *docs_w = documents;
+        drop(stage_r);
+        drop(docs_w);
+        self.clear_input_cache();
+        *self.inconsistent_revisions.lock().unwrap() = inconsistent;
+        self.clear_pruned();
+        self.notify_subscribers();
+        Ok(())
     }
 
-    /// Saves the current stage
+    /// Compacts the data packs smaller than target_pack_bytes into fewer packs of roughly
+    /// target_pack_bytes bytes. Delta blocks are immutable and keep referencing the replaced
+    /// packs: a repack record stored alongside the packs maps them to the new packs, so block
+    /// identifiers remain stable and replicas (including those reloading the storage later)
+    /// still consider the blocks valid. All the stored payloads are copied to the new packs
+    /// (hence the payloads of pinned revisions are never reclaimed). Repacking requires that
+    /// there are no staged changes.
+    ///
+    /// Other replicas sharing the adapter may still read the replaced packs, hence these are
+    /// only deleted from the adapter by the next repack (which deletes the packs replaced by
+    /// the earlier ones). Replicas must therefore refresh (or reload) between two repacks,
+    /// otherwise reading a value of a deleted pack fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `target_pack_bytes` - The target size of the packs (packs of at least this size are not rewritten)
     ///
     /// # Example
     /// ```
@@ -1623,62 +7532,127 @@ impl Melda {
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
     /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// replica.delete_object("myobject");
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", winner);
-    /// let value = replica.get_value("myobject", &winner);
-    /// assert!(value.is_ok());
-    /// assert!(value.unwrap().contains_key("_deleted"));
-    /// let stage = replica.stage().unwrap();
-    /// replica.unstage();
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// replica.replay_stage(&stage);
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", winner);
+    /// for i in 0..5u32 {
+    ///     let items: Vec<Value> = (0..=i).map(|j| json!({ "_id" : j.to_string(), "value" : j })).collect();
+    ///     let object = json!({ "items\u{266D}" : items }).as_object().unwrap().clone();
+    ///     replica.update(object);
+    ///     replica.commit(None);
+    /// }
+    /// let before = replica.read().unwrap();
+    /// // Another replica opened before repacking
+    /// let other = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let stats = replica.repack(1 << 20).unwrap();
+    /// assert_eq!(stats.packs_before, 5);
+    /// assert_eq!(stats.packs_after, 1);
+    /// // The replaced packs are kept until the next repack
+    /// assert_eq!(adapter.read().unwrap().list_objects(".pack").unwrap().len(), 6);
+    /// assert_eq!(other.read().unwrap(), before);
+    /// assert!(replica.verify().unwrap().is_valid());
+    /// let reloaded = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert_eq!(before, reloaded.read().unwrap());
+    /// assert!(reloaded.verify().unwrap().is_valid());
+    /// // Once the other replicas have refreshed, the next repack deletes the replaced packs
+    /// other.refresh().unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [] }).as_object().unwrap().clone());
+    /// replica.commit(None);
+    /// let stats = replica.repack(1 << 20).unwrap();
+    /// assert_eq!((stats.packs_before, stats.packs_after), (2, 1));
+    /// assert_eq!(adapter.read().unwrap().list_objects(".pack").unwrap().len(), 3);
+    /// assert_eq!(other.read().unwrap(), before);
+    /// let reloaded = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// assert_eq!(replica.read().unwrap(), reloaded.read().unwrap());
+    /// assert!(reloaded.verify().unwrap().is_valid());
     /// ```
-    pub fn stage(&self) -> Result<Option<Value>> {
-        let mut r = Map::<String, Value>::new();
-        let data = self.data.read().expect("cannot_acquire_data_for_reading");
-        let data_stage = data.stage()?;
-        r.insert(OBJECTS_FIELD.to_string(), data_stage);
-        let rur = self.stage.read().unwrap();
-        if !rur.is_empty() {
-            let mut changes = Vec::<Value>::new();
-            for Change(uuid, rev, prev) in rur.iter() {
-                if prev.is_none() {
-                    // Creation record
-                    let tuple = vec![uuid.clone(), rev.digest.clone()];
-                    changes.push(Value::from(tuple));
-                } else {
-                    // Update record
-                    let triple = vec![
-                        uuid.clone(),
-                        prev.as_ref().unwrap().to_string(),
-                        rev.digest.clone(),
-                    ];
-                    changes.push(Value::from(triple));
-                }
-            }
-            r.insert(CHANGESETS_FIELD.to_string(), Value::from(changes));
-            Ok(Some(Value::from(r)))
-        } else {
-            Ok(None)
+    pub fn repack(&self, target_pack_bytes: usize) -> Result<RepackStats> {
+        if self.has_staging() {
+            bail!("non_empty_stage");
         }
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        let (packs_before, packs_after) = data.repack(target_pack_bytes)?;
+        Ok(RepackStats {
+            packs_before,
+            packs_after,
+        })
     }
 
-    /// Replays a stage
+    /// Stores a binary blob and returns a reference string that can be used as a value in
+    /// the JSON document. Blobs are stored immediately (they do not need to be committed)
+    /// and are deduplicated by digest. The reference is the blob digest prefixed with the
+    /// \u{25C9} character: flatten and unflatten keep such strings unchanged. Since blobs
+    /// are stored as raw items of the adapter, they are transferred along with delta blocks
+    /// and packs by meld.
     ///
     /// # Arguments
     ///
-    /// * `s` - The stage to be replayed
+    /// * `data` - The content of the blob
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let blob = replica.put_blob("hello world".as_bytes()).unwrap();
+    /// assert_eq!("\u{25C9}b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9", blob);
+    /// let object = json!({ "images\u{266D}" : [ blob.clone() ] }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// replica.commit(None);
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// let readback = replica2.read().unwrap();
+    /// let blob = readback.get("images\u{266D}").unwrap()[0].as_str().unwrap();
+    /// assert_eq!("hello world".as_bytes(), replica2.get_blob(blob).unwrap().as_slice());
+    /// ```
+    pub fn put_blob(&self, data: &[u8]) -> Result<String> {
+        let digest = self
+            .data
+            .write()
+            .expect("cannot_acquire_data_for_writing")
+            .write_blob(data)?;
+        Ok(BLOB_REFERENCE_PREFIX.to_string() + &digest)
+    }
+
+    /// Returns the content of a binary blob
+    ///
+    /// # Arguments
+    ///
+    /// * `reference` - The blob reference (as returned by put_blob)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let blob = replica.put_blob(&[0u8, 1u8, 2u8]).unwrap();
+    /// assert_eq!(vec![0u8, 1u8, 2u8], replica.get_blob(&blob).unwrap());
+    /// assert!(replica.get_blob("notablob").is_err());
+    /// ```
+    pub fn get_blob(&self, reference: &str) -> Result<Vec<u8>> {
+        let digest = reference
+            .strip_prefix(BLOB_REFERENCE_PREFIX)
+            .ok_or_else(|| anyhow!("invalid_blob_reference"))?;
+        self.data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .read_blob(digest)
+    }
+
+    /// Exports all the items stored by the adapter (delta blocks, packs, indexes and blobs) to a
+    /// portable archive, which can be imported on any adapter with import_archive. Staged changes
+    /// are not exported. Returns the number of exported items.
+    ///
+    /// The archive begins with the MELDAARC header followed by the format version (one byte),
+    /// each item is then stored as the length of its key (4 bytes, big endian), the key, the length
+    /// of its content (8 bytes, big endian) and the content.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The destination of the archive
     ///
     /// # Example
     /// ```
@@ -1686,200 +7660,121 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "items\u{266D}" : [ { "_id": "1", "key" : "alpha" } ] }).as_object().unwrap().clone();
+    /// replica.update(object);
+    /// replica.commit(None);
+    /// let mut archive = vec![];
+    /// assert_eq!(replica.export_archive(&mut archive).unwrap(), 2);
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// replica.delete_object("myobject");
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", winner);
-    /// let value = replica.get_value("myobject", &winner);
-    /// assert!(value.is_ok());
-    /// assert!(value.unwrap().contains_key("_deleted"));
-    /// let stage = replica.stage().unwrap();
-    /// replica.unstage();
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// replica.replay_stage(&stage);
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", winner);
+    /// let copy = Melda::import_archive(archive.as_slice(), adapter.clone()).unwrap();
+    /// assert_eq!(replica.read().unwrap(), copy.read().unwrap());
+    /// assert_eq!(adapter.read().unwrap().list_objects("").unwrap().len(), 2);
+    /// assert!(Melda::import_archive(&b"NOTANARCHIVE"[..], adapter).is_err());
+    /// // Truncated archives are rejected (even within the length of the next key)
+    /// for extra in [&[0u8][..], &[0, 0, 0], &[0, 0, 0, 4, b'k']] {
+    ///     let mut truncated = archive.clone();
+    ///     truncated.extend_from_slice(extra);
+    ///     let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    ///     let result = Melda::import_archive(truncated.as_slice(), Arc::new(RwLock::new(adapter)));
+    ///     assert_eq!(result.err().unwrap().to_string(), "truncated_archive");
+    /// }
     /// ```
-    pub fn replay_stage(&self, s: &Option<Value>) -> Result<()> {
-        if let Some(s) = s {
-            if s.is_object() {
-                let s = s.as_object().unwrap();
-                if s.contains_key(OBJECTS_FIELD) {
-                    let o = s.get(OBJECTS_FIELD).unwrap();
-                    let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
-                    data.replay_stage(o)?;
-                }
-                if s.contains_key(CHANGESETS_FIELD) {
-                    let changes = s.get(CHANGESETS_FIELD);
-                    if let Some(changes) = changes {
-                        if changes.is_array() {
-                            for c in changes.as_array().unwrap() {
-                                if c.is_array() {
-                                    let record = c.as_array().unwrap();
-                                    if record.len() == 2 {
-                                        let uuid = record[0]
-                                            .as_str()
-                                            .ok_or_else(|| anyhow!("expecting_uuid_string"))?;
-                                        let digest = record[1]
-                                            .as_str()
-                                            .ok_or_else(|| anyhow!("expecting_digest_string"))?;
-                                        let r = Revision::new(1, digest.to_string(), None);
-                                        if !self
-                                            .documents
-                                            .read()
-                                            .expect("failed_to_acquire_documents_for_reading")
-                                            .contains_key(uuid)
-                                        {
-                                            let mut rt = RevisionTree::new();
-                                            if rt.add(r.clone(), None) {
-                                                self.stage.write().unwrap().push(Change(
-                                                    uuid.to_string(),
-                                                    r,
-                                                    None,
-                                                ));
-                                            }
-                                            self.documents
-                                                .write()
-                                                .unwrap()
-                                                .insert(uuid.to_string(), RwLock::new(rt));
-                                        } else {
-                                            let mut docs = self
-                                                .documents
-                                                .write()
-                                                .expect("failed_to_acquire_documents_for_writing");
-                                            let rt = docs.get_mut(uuid).unwrap();
-                                            let mut rt_w = rt.write().expect(
-                                                "failed_to_acquire_revision_tree_for_writing",
-                                            );
-                                            if rt_w.add(r.clone(), None) {
-                                                self.stage.write().unwrap().push(Change(
-                                                    uuid.to_string(),
-                                                    r,
-                                                    None,
-                                                ));
-                                            }
-                                        }
-                                    } else if record.len() == 3 {
-                                        let uuid = record[0]
-                                            .as_str()
-                                            .ok_or_else(|| anyhow!("expecting_uuid_string"))?;
-                                        let prev = record[1]
-                                            .as_str()
-                                            .ok_or_else(|| anyhow!("expecting_revision_string"))?;
-                                        let digest = record[2]
-                                            .as_str()
-                                            .ok_or_else(|| anyhow!("expecting_digest_string"))?;
-                                        let prev = Revision::from(prev)?;
-                                        let r = Revision::new(
-                                            prev.index + 1,
-                                            digest.to_string(),
-                                            Some(&prev),
-                                        );
-                                        if !self
-                                            .documents
-                                            .read()
-                                            .expect("failed_to_acquire_documents_for_reading")
-                                            .contains_key(uuid)
-                                        {
-                                            // FIXME: Should this be allowed?
-                                            // This might happen if we save the stage, then reload to a previous block
-                                            // were an object did not yet exist and then try to re-apply the stage
-                                            let mut rt = RevisionTree::new();
-                                            self.stage.write().unwrap().push(Change(
-                                                uuid.to_string(),
-                                                r.clone(),
-                                                Some(prev.clone()),
-                                            ));
-                                            rt.add(r, Some(prev));
-                                            self.documents
-                                                .write()
-                                                .unwrap()
-                                                .insert(uuid.to_string(), RwLock::new(rt));
-                                        } else {
-                                            let mut docs = self
-                                                .documents
-                                                .write()
-                                                .expect("failed_to_acquire_documents_for_writing");
-                                            let rt = docs.get_mut(uuid).unwrap();
-                                            let mut rt_w = rt.write().expect(
-                                                "failed_to_acquire_revision_tree_for_writing",
-                                            );
-                                            if rt_w.add(r.clone(), Some(prev.clone())) {
-                                                self.stage.write().unwrap().push(Change(
-                                                    uuid.to_string(),
-                                                    r,
-                                                    Some(prev),
-                                                ));
-                                            }
-                                        }
-                                    } else {
-                                        bail!("invalid_changes_record")
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                Ok(())
-            } else {
-                Err(anyhow!("expecting_stage_object"))
+    pub fn export_archive(&self, mut writer: impl Write) -> Result<usize> {
+        let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        writer.write_all(ARCHIVE_MAGIC)?;
+        writer.write_all(&[ARCHIVE_VERSION])?;
+        let mut count = 0;
+        let mut cursor = None;
+        loop {
+            let (page, next) = data.list_raw_items_paged("", cursor.as_deref(), LIST_PAGE_SIZE)?;
+            for key in page {
+                let content = data.read_raw_bytes(&key, 0, 0)?;
+                writer.write_all(&(key.len() as u32).to_be_bytes())?;
+                writer.write_all(key.as_bytes())?;
+                writer.write_all(&(content.len() as u64).to_be_bytes())?;
+                writer.write_all(&content)?;
+                count += 1;
+            }
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
             }
-        } else {
-            Ok(())
         }
+        writer.flush()?;
+        Ok(count)
     }
 
-    /// Returns a block, or None if the block does not exist.
+    /// Imports a portable archive (see export_archive) into the adapter and initializes a new
+    /// Melda data structure using the adapter. Each item is verified before being written (see
+    /// meld_bundle): if an item does not pass the verification, an invalid_raw_item error is
+    /// returned.
     ///
     /// # Arguments
     ///
-    /// * `block_id` - Block identifier
+    /// * `reader` - The source of the archive
+    /// * `adapter` - The adapter where the items of the archive are written
     ///
     /// # Example
     /// ```
     /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
-    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// let parent = replica.get_parent_revision("myobject", &winner).unwrap();
-    /// assert!(parent.is_none());
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// let block = replica.get_block(&block_id).unwrap().unwrap();
-    /// assert_eq!(block_id, block.id);
-    pub fn get_block(&self, block_id: &str) -> Result<Option<Block>> {
-        let blocks_r = self
-            .blocks
-            .read()
-            .expect("cannot_acquire_blocks_for_reading");
-        match blocks_r.get(block_id) {
-            Some(b) => {
-                let block_r = b.read().expect("cannot_acquire_block_for_reading");
-                Ok(Some(block_r.clone()))
+    /// let archive_with = |key: &str, content: &[u8]| {
+    ///     let mut archive = b"MELDAARC\x01".to_vec();
+    ///     archive.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    ///     archive.extend_from_slice(key.as_bytes());
+    ///     archive.extend_from_slice(&(content.len() as u64).to_be_bytes());
+    ///     archive.extend_from_slice(content);
+    ///     archive
+    /// };
+    /// let import = |archive: Vec<u8>| {
+    ///     let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    ///     let adapter = Arc::new(RwLock::new(adapter));
+    ///     let result = Melda::import_archive(archive.as_slice(), adapter.clone());
+    ///     let items = adapter.read().unwrap().list_objects("").unwrap();
+    ///     (result.err().map(|e| e.to_string()), items)
+    /// };
+    /// // Keys escaping the adapter are rejected
+    /// let (error, items) = import(archive_with("../../escaped.delta", b"{}"));
+    /// assert_eq!(error.unwrap(), "invalid_raw_item: ../../escaped.delta");
+    /// assert!(items.is_empty());
+    /// // Payloads which do not match the digest in their key are rejected
+    /// let key = "0".repeat(64) + ".delta";
+    /// let (error, items) = import(archive_with(&key, b"{}"));
+    /// assert_eq!(error.unwrap(), format!("invalid_raw_item: {}", key));
+    /// assert!(items.is_empty());
+    /// ```
+    pub fn import_archive(
+        mut reader: impl Read,
+        adapter: Arc<RwLock<Box<dyn Adapter>>>,
+    ) -> Result<Melda> {
+        Melda::read_archive_header(&mut reader)?;
+        while let Some((key, content)) = Melda::read_archive_item(&mut reader)? {
+            if !DataStorage::is_valid_raw_item(&key, &content) {
+                bail!("invalid_raw_item: {}", key);
             }
-            None => Ok(None),
+            adapter
+                .write()
+                .expect("cannot_acquire_adapter_for_writing")
+                .write_object(&key, &content)?;
         }
+        Melda::new(adapter)
     }
 
-    /// Returns the parent revision in the revision tree of the specified object, or None if there is no parent
+    /// Melds a portable archive (see export_archive) into this Melda: the items of the archive
+    /// which are missing from the adapter are written, like meld does with the items of another
+    /// Melda instance. This allows melding the content received from a remote replica (for
+    /// example in a sync protocol) without opening its storage. Each item is verified before
+    /// being written (see meld_verified): if an item does not pass the verification, an
+    /// invalid_raw_item error is returned (items written before it are kept, since they are
+    /// valid). Copied items are not loaded: call refresh afterwards. Returns the list of copied
+    /// items.
     ///
     /// # Arguments
     ///
-    /// * `uuid` - Object identifier
-    /// * `revision` - The revision
+    /// * `reader` - The source of the archive
     ///
     /// # Example
     /// ```
@@ -1887,33 +7782,133 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// let parent = replica.get_parent_revision("myobject", &winner).unwrap();
-    /// assert!(parent.is_none());
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// replica.delete_object("myobject");
-    /// let newrev = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", newrev);
-    /// let parent = replica.get_parent_revision("myobject", &newrev).unwrap().unwrap();
-    /// assert_eq!(&parent, &winner);
-    pub fn get_parent_revision(&self, uuid: &str, revision: &str) -> Result<Option<String>> {
-        let docs = self
-            .documents
-            .read()
-            .expect("failed_to_acquire_documents_for_reading");
-        let rt = docs.get(uuid).ok_or_else(|| anyhow!("unknown_document"))?;
-        let revision = Revision::from(revision).expect("invalid_revision_string");
-        let rt_r = rt
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "somekey" : "somevalue" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let mut bundle = vec![];
+    /// replica.export_archive(&mut bundle).unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica2 = Melda::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// let copied = replica2.meld_bundle(bundle.as_slice()).unwrap();
+    /// assert_eq!(copied.len(), 2);
+    /// replica2.refresh().unwrap();
+    /// assert_eq!(replica.read().unwrap(), replica2.read().unwrap());
+    /// // Items already stored are not copied again
+    /// assert!(replica2.meld_bundle(bundle.as_slice()).unwrap().is_empty());
+    /// // Items which do not match their key are rejected
+    /// let adapter3 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let invalid = Melda::new(Arc::new(RwLock::new(adapter3))).expect("cannot_initialize_crdt");
+    /// invalid.update(json!({ "somekey" : "othervalue" }).as_object().unwrap().clone()).unwrap();
+    /// invalid.commit(None).unwrap();
+    /// let mut bundle = vec![];
+    /// invalid.export_archive(&mut bundle).unwrap();
+    /// let position = bundle.windows(10).position(|w| w == b"othervalue").unwrap();
+    /// bundle[position] = b'O';
+    /// assert!(replica2.meld_bundle(bundle.as_slice()).is_err());
+    /// assert!(replica2.meld_bundle(&b"NOTANARCHIVE"[..]).is_err());
+    /// // Only keys made of a digest and a known extension are accepted
+    /// let bundle_with = |key: &str, content: &[u8]| {
+    ///     let mut bundle = b"MELDAARC\x01".to_vec();
+    ///     bundle.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    ///     bundle.extend_from_slice(key.as_bytes());
+    ///     bundle.extend_from_slice(&(content.len() as u64).to_be_bytes());
+    ///     bundle.extend_from_slice(content);
+    ///     bundle
+    /// };
+    /// assert!(replica2.meld_bundle(bundle_with("../../escaped.index", b"{}").as_slice()).is_err());
+    /// assert!(replica2.meld_bundle(bundle_with(&"a".repeat(64), b"{}").as_slice()).is_err());
+    /// let index = "0".repeat(64) + ".index";
+    /// assert_eq!(replica2.meld_bundle(bundle_with(&index, b"{}").as_slice()).unwrap(), vec![index]);
+    /// // Keys longer than allowed are rejected before being read
+    /// let mut bundle = b"MELDAARC\x01".to_vec();
+    /// bundle.extend_from_slice(&u32::MAX.to_be_bytes());
+    /// assert!(replica2.meld_bundle(bundle.as_slice()).is_err());
+    /// ```
+    pub fn meld_bundle(&self, mut reader: impl Read) -> Result<Vec<String>> {
+        Melda::read_archive_header(&mut reader)?;
+        let mut result = vec![];
+        let this_items: HashSet<String> = self
+            .data
             .read()
-            .expect("failed_to_acquire_revision_tree_for_reading");
-        match rt_r.get_parent(&revision) {
-            Some(r) => Ok(Some(r.to_string())),
-            None => Ok(None),
+            .expect("cannot_acquire_data_for_reading")
+            .list_raw_items("")?
+            .into_iter()
+            .collect();
+        // Each item is read and verified before locking the storage to write it
+        while let Some((key, content)) = Melda::read_archive_item(&mut reader)? {
+            if self.is_cancelled() {
+                bail!("cancelled");
+            }
+            if this_items.contains(&key) {
+                continue;
+            }
+            if !DataStorage::is_valid_raw_item(&key, &content) {
+                bail!("invalid_raw_item: {}", key);
+            }
+            self.data
+                .write()
+                .expect("cannot_acquire_data_for_writing")
+                .write_raw_bytes(&key, &content)?;
+            result.push(key);
+        }
+        Ok(result)
+    }
+
+    // Reads and checks the header of a portable archive (see export_archive)
+    fn read_archive_header(reader: &mut impl Read) -> Result<()> {
+        let mut header = vec![0u8; ARCHIVE_MAGIC.len() + 1];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| anyhow!("invalid_archive"))?;
+        if !header.starts_with(ARCHIVE_MAGIC) {
+            bail!("invalid_archive");
+        }
+        if header[ARCHIVE_MAGIC.len()] != ARCHIVE_VERSION {
+            bail!("unsupported_archive_version");
+        }
+        Ok(())
+    }
+
+    // Reads the next item (key and content) of a portable archive, None at the end of the archive
+    fn read_archive_item(reader: &mut impl Read) -> Result<Option<(String, Vec<u8>)>> {
+        // The archive only ends cleanly if no byte of the next key length can be read
+        let mut key_len = [0u8; 4];
+        let mut read = 0;
+        while read < key_len.len() {
+            match reader.read(&mut key_len[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match read {
+            0 => return Ok(None),
+            4 => {}
+            _ => bail!("truncated_archive"),
+        }
+        let key_len = u32::from_be_bytes(key_len) as usize;
+        if key_len > MAX_ARCHIVE_KEY_LENGTH {
+            bail!("invalid_archive_key");
+        }
+        let truncated = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => anyhow!("truncated_archive"),
+            _ => e.into(),
+        };
+        let mut key = vec![0u8; key_len];
+        reader.read_exact(&mut key).map_err(truncated)?;
+        let key = String::from_utf8(key).map_err(|_| anyhow!("invalid_archive_key"))?;
+        let mut content_len = [0u8; 8];
+        reader.read_exact(&mut content_len).map_err(truncated)?;
+        let mut content = vec![];
+        reader
+            .by_ref()
+            .take(u64::from_be_bytes(content_len))
+            .read_to_end(&mut content)?;
+        if content.len() as u64 != u64::from_be_bytes(content_len) {
+            bail!("truncated_archive");
         }
+        Ok(Some((key, content)))
     }
 
     // **********************************************************************
@@ -1928,12 +7923,24 @@ impl Melda {
     fn fetch_raw_block(&self, blockid: &str) -> Result<Map<String, Value>> {
         let object = blockid.to_string() + DELTA_EXTENSION;
         let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        // Oversized blocks are rejected before being fetched
+        if data.raw_item_size(object.as_str())? > self.block_limits.max_block_bytes as u64 {
+            bail!("block_too_large");
+        }
         let data = data.read_raw_bytes(object.as_str(), 0, 0)?;
-        let digest = digest_bytes(data.as_slice());
+        self.decode_raw_block(blockid, &data)
+    }
+
+    /// Decodes the content of a block, verifying its digest and size
+    fn decode_raw_block(&self, blockid: &str, data: &[u8]) -> Result<Map<String, Value>> {
+        if data.len() > self.block_limits.max_block_bytes {
+            bail!("block_too_large");
+        }
+        let digest = digest_bytes(data);
         if !digest.eq(blockid) {
             bail!("mismatching_block_hash");
         }
-        let json = std::str::from_utf8(&data)?;
+        let json = std::str::from_utf8(data)?;
         let json: Value = serde_json::from_str(json)?;
         if !json.is_object() {
             bail!("invalid_block_format");
@@ -1942,13 +7949,49 @@ impl Melda {
         Ok(blockobj.clone())
     }
 
+    /// Returns true if all the given packs are readable and valid
+    fn are_valid_packs<'a>(&self, mut packs: impl Iterator<Item = &'a str>) -> bool {
+        let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        packs.all(|p| data.is_readable_and_valid_pack(p).unwrap_or(false))
+    }
+
     /// Parse a block
     fn parse_raw_block(&self, b_id: String, raw_block: Map<String, Value>) -> Result<Block> {
+        self.parse_block(b_id, raw_block, true)
+    }
+
+    /// Parse a block, optionally verifying that the referenced packs are readable and valid
+    fn parse_block(
+        &self,
+        b_id: String,
+        raw_block: Map<String, Value>,
+        check_packs: bool,
+    ) -> Result<Block> {
         // Block values
         let mut b_parents: Option<BTreeSet<String>> = None;
         let mut b_info: Option<Map<String, Value>> = None;
         let mut b_packs: Option<BTreeSet<String>> = None;
         let mut b_changes: Option<Vec<Change>> = None;
+        // Blocks must be committed with the same revision tail length
+        let tail_length = match raw_block.get(TAIL_LENGTH_FIELD) {
+            Some(t) => {
+                t.as_u64()
+                    .ok_or_else(|| anyhow!("tail_length_not_a_number"))? as usize
+            }
+            None => DEFAULT_REVISION_TAIL_LENGTH,
+        };
+        if tail_length != self.revision_tail_length {
+            bail!("mismatching_revision_tail_length");
+        }
+        let version = match raw_block.get(BLOCK_VERSION_FIELD) {
+            Some(v) => v
+                .as_u64()
+                .ok_or_else(|| anyhow!("block_version_not_a_number"))?,
+            None => 1,
+        };
+        if version > TAIL_LENGTH_BLOCK_VERSION {
+            bail!("unsupported_block_version");
+        }
         // Parse raw block fields
         if raw_block.contains_key(CHANGESETS_FIELD) {
             if raw_block.contains_key(PACK_FIELD) {
@@ -1958,15 +8001,10 @@ impl Melda {
                     .unwrap()
                     .as_array()
                     .ok_or_else(|| anyhow!("packs_not_an_array"))?;
-                if !packs.iter().all(|x| {
-                    if x.is_string() {
-                        let data = self.data.read().expect("cannot_acquire_data_for_reading");
-                        data.is_readable_and_valid_pack(x.as_str().unwrap())
-                            .unwrap_or(false)
-                    } else {
-                        false
-                    }
-                }) {
+                if check_packs
+                    && !(packs.iter().all(|x| x.is_string())
+                        && self.are_valid_packs(packs.iter().filter_map(|x| x.as_str())))
+                {
                     bail!("missing_packs");
                 }
                 // Collect identifiers
@@ -1974,8 +8012,12 @@ impl Melda {
                     b_packs = Some(
                         packs
                             .iter()
-                            .map(|p| p.as_str().unwrap().to_string())
-                            .collect(),
+                            .map(|p| {
+                                p.as_str()
+                                    .map(|p| p.to_string())
+                                    .ok_or_else(|| anyhow!("pack_not_a_string"))
+                            })
+                            .collect::<Result<BTreeSet<String>>>()?,
                     );
                 }
             }
@@ -1986,6 +8028,9 @@ impl Melda {
                 if !info.is_object() {
                     bail!("info_not_an_object");
                 }
+                if info.to_string().len() > self.block_limits.max_info_bytes {
+                    bail!("info_too_large");
+                }
                 // Save identifier
                 b_info = Some(info.as_object().unwrap().clone());
             }
@@ -1996,6 +8041,9 @@ impl Melda {
                 if !parents.is_array() {
                     bail!("parents_not_an_array");
                 }
+                if parents.as_array().unwrap().len() > self.block_limits.max_parents {
+                    bail!("too_many_parents");
+                }
                 let mut ps = BTreeSet::new();
                 for p in parents.as_array().unwrap() {
                     if p.is_string() {
@@ -2010,39 +8058,19 @@ impl Melda {
             let changes = raw_block.get(CHANGESETS_FIELD);
             if let Some(changes) = changes {
                 if changes.is_array() {
+                    if changes.as_array().unwrap().len() > self.block_limits.max_changesets {
+                        bail!("too_many_changesets");
+                    }
                     // Process changeset
                     let mut cs: Vec<Change> = vec![];
                     for c in changes.as_array().unwrap() {
                         if c.is_array() {
                             let record = c.as_array().unwrap();
-                            if record.len() == 2 {
-                                // Creation record
-                                let uuid = record[0]
-                                    .as_str()
-                                    .ok_or_else(|| anyhow!("expecting_uuid_string"))?;
-                                let digest = record[1]
-                                    .as_str()
-                                    .ok_or_else(|| anyhow!("expecting_digest_string"))?;
-                                let r = Revision::new(1, digest.to_string(), None);
-                                cs.push(Change(uuid.to_string(), r, None));
-                            } else if record.len() == 3 {
-                                // Update record
-                                let uuid = record[0]
-                                    .as_str()
-                                    .ok_or_else(|| anyhow!("expecting_uuid_string"))?;
-                                let prev = record[1]
-                                    .as_str()
-                                    .ok_or_else(|| anyhow!("expecting_revision_string"))?;
-                                let digest = record[2]
-                                    .as_str()
-                                    .ok_or_else(|| anyhow!("expecting_digest_string"))?;
-                                let prev = Revision::from(prev)?;
-                                let r =
-                                    Revision::new(prev.index + 1, digest.to_string(), Some(&prev));
-                                cs.push(Change(uuid.to_string(), r, Some(prev)));
-                            } else {
-                                bail!("invalid_changes_record")
-                            }
+                            cs.push(Change::from_record(
+                                record,
+                                self.revision_tail_length,
+                                version >= MERGE_RECORDS_BLOCK_VERSION,
+                            )?);
                         }
                     }
                     if !cs.is_empty() {
@@ -2064,7 +8092,6 @@ impl Melda {
     fn check_block(&self, bid: &str) -> Status {
         let blocks = self.blocks.read().unwrap();
         let data = self.data.read().expect("cannot_acquire_data_for_reading");
-        let packs = data.get_loaded_packs();
         if let Some(block) = blocks.get(bid) {
             // If the block status has been determined return the corresponding value
             let mut status = block.read().unwrap().status;
@@ -2074,7 +8101,9 @@ impl Melda {
             // Verify that all packs are available
             status = Status::Valid;
             if let Some(pks) = &block.read().unwrap().packs {
-                if !pks.par_iter().all(|pack| packs.contains(pack)) {
+                if !pks.par_iter().all(|pack| data.is_available_pack(pack)) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(block = %bid, reason = "missing_pack", "invalid block");
                     status = Status::Invalid;
                 }
             };
@@ -2085,18 +8114,24 @@ impl Melda {
                         .iter()
                         .all(|parent| self.check_block(parent) != Status::Invalid)
                     {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(block = %bid, reason = "invalid_parent", "invalid block");
                         status = Status::Invalid;
                     }
                 };
             }
             block.write().unwrap().status = status;
             status
+        } else if self.retired_blocks.read().unwrap().contains(bid) {
+            Status::ValidAndApplied
         } else {
             Status::Invalid
         }
     }
 
     fn mark_valid_blocks(&self) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("mark_valid_blocks").entered();
         let blocks = self.blocks.read().unwrap();
         blocks.iter().for_each(|(bid, block)| {
             let status = block.read().unwrap().status;
@@ -2107,24 +8142,86 @@ impl Melda {
     }
 
     fn apply_block(&self, block: &Block) -> Result<()> {
-        if let Some(changes) = &block.changes {
-            for change in changes {
-                let Change(uuid, r, prev) = change;
-                let mut docs_w = self
-                    .documents
-                    .write()
-                    .expect("cannot_acquire_documents_for_writing");
-                let mut rt_w = docs_w
-                    .entry(uuid.to_string())
-                    .or_insert_with(|| RwLock::new(RevisionTree::new()))
-                    .write()
-                    .expect("cannot_acquire_revision_tree_for_writing");
-                rt_w.add(r.clone(), prev.clone());
+        // In shallow mode only the headers of the blocks are loaded (see open_shallow)
+        let fetched;
+        let changes = match &block.changes {
+            None if self.shallow => {
+                let raw = self.fetch_raw_block(&block.id)?;
+                fetched = self.parse_block(block.id.clone(), raw, false)?.changes;
+                fetched.as_ref()
             }
+            changes => changes.as_ref(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            block = %block.id,
+            changes = changes.map_or(0, |c| c.len()),
+            "applying block"
+        );
+        if let Some(changes) = changes {
+            let mut docs_w = self
+                .documents
+                .write()
+                .expect("cannot_acquire_documents_for_writing");
+            let mut inconsistent = self.inconsistent_revisions.lock().unwrap();
+            Self::apply_changes(
+                &block.id,
+                changes,
+                self.revision_tail_length,
+                &mut docs_w,
+                &mut inconsistent,
+            );
         };
         Ok(())
     }
 
+    // Adds the revisions of the changes of a block to the given revision trees, recording the
+    // revisions with conflicting parents as inconsistent
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn apply_changes(
+        block_id: &str,
+        changes: &[Change],
+        tail_length: usize,
+        documents: &mut BTreeMap<String, RwLock<RevisionTree>>,
+        inconsistent: &mut BTreeMap<String, BTreeSet<String>>,
+    ) {
+        // Derived changes are applied first, so that merged revisions (whose merge records are
+        // committed in the same block) already have their parent when the merge records are
+        // applied
+        let (derived, merged): (Vec<&Change>, Vec<&Change>) =
+            changes.iter().partition(|c| c.is_derived(tail_length));
+        for change in derived.into_iter().chain(merged) {
+            let Change(uuid, r, prev) = change;
+            let mut rt_w = documents
+                .entry(uuid.to_string())
+                .or_insert_with(|| RwLock::new(RevisionTree::new()))
+                .write()
+                .expect("cannot_acquire_revision_tree_for_writing");
+            // Re-introducing a revision with the same parent is harmless, and merged revisions
+            // (see resolve_merged) legitimately have several parents: only a revision derived
+            // from a parent other than the ones it already has denotes an inconsistency (the
+            // revision is added anyway)
+            let parents = rt_w.get_parents(r);
+            if !parents.is_empty()
+                && !parents.contains(&prev.as_ref())
+                && change.is_derived(tail_length)
+            {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    block = %block_id,
+                    object = %uuid,
+                    revision = %r.to_string(),
+                    "revision with conflicting parent"
+                );
+                inconsistent
+                    .entry(uuid.clone())
+                    .or_default()
+                    .insert(r.to_string());
+            }
+            rt_w.add(r.clone(), prev.clone());
+        }
+    }
+
     // **********************************************************************
     // **********************************************************************
     //
@@ -2135,6 +8232,8 @@ impl Melda {
 
     // Creates a delta array descriptor from the current obj
     // Returns None if the delta is empty (i.e. the arrays are the same)
+    // If the delta is not smaller than the new order (for example for small arrays which
+    // change completely) a full order descriptor is returned instead
     fn create_delta_array_descriptor(
         &self,
         obj: Map<String, Value>,
@@ -2148,6 +8247,10 @@ impl Melda {
         let patch = make_diff_patch(&winning_order, new_order).expect("failed_diffing");
         if patch.is_empty() {
             Ok(None)
+        } else if serde_json::to_string(&patch)?.len() >= serde_json::to_string(new_order)?.len() {
+            Ok(Some(
+                ArrayDescriptor::new_from_order(new_order.clone()).to_json_object(),
+            ))
         } else {
             Ok(Some(
                 ArrayDescriptor::new_from_patch(patch).to_json_object(),
@@ -2157,9 +8260,7 @@ impl Melda {
 
     fn read_array_descriptor(&self, revision: &Revision) -> Result<ArrayDescriptor> {
         let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
-        let base_object = data_r
-            .read_object(revision)
-            .expect("cannot_read_base_array_descriptor");
+        let base_object = data_r.read_object(revision)?;
         drop(data_r);
         ArrayDescriptor::new_from_object(base_object)
     }
@@ -2171,8 +8272,12 @@ impl Melda {
         rt: &RevisionTree,
     ) -> Result<Vec<Value>> {
         let mut cache = self.array_descriptors_cache.lock().unwrap();
+        // Materialized orders of pruned histories
+        let bases = self.array_descriptor_bases.lock().unwrap();
         if let Some(descriptor) = cache.get(base_revision) {
             Ok(descriptor.get_order().as_ref().unwrap().clone())
+        } else if let Some(order) = bases.get(base_revision) {
+            Ok(order.clone())
         } else {
             let base_descriptor = self.read_array_descriptor(base_revision)?;
             if base_descriptor.is_diff() {
@@ -2183,7 +8288,7 @@ impl Melda {
                 while let Some(new_current) = rt.get_parent(current) {
                     history.push(new_current);
                     current = new_current;
-                    if cache.contains(current) {
+                    if cache.contains(current) || bases.contains_key(current) {
                         break; // Break at last cached descriptor
                     }
                 }
@@ -2196,6 +8301,10 @@ impl Melda {
                         order = descriptor.get_order().clone().unwrap();
                         break; // Break at last cached descriptor
                     }
+                    if let Some(base_order) = bases.get(revision) {
+                        order = base_order.clone();
+                        break;
+                    }
                     let descriptor = self.read_array_descriptor(revision)?;
                     if descriptor.is_diff() {
                         descriptors.push(descriptor);
@@ -2213,6 +8322,7 @@ impl Melda {
                     base_revision.clone(),
                     ArrayDescriptor::new_from_order(order.clone()),
                 ); // Only cache the full object
+                self.enforce_memory_budget(&mut cache);
                 Ok(order)
             } else {
                 Ok(base_descriptor.get_order().clone().unwrap())
@@ -2227,9 +8337,19 @@ impl Melda {
         let leafs = rt.get_leafs();
         if leafs.len() > 1 {
             let mut base_order = self.rebuild_array_order(base_revision, rt)?;
+            let identity = self
+                .element_identity
+                .read()
+                .expect("cannot_acquire_element_identity_for_reading")
+                .clone();
             for l in leafs {
                 let leaf_order = self.rebuild_array_order(l, rt)?;
-                merge_arrays(&leaf_order, &mut base_order);
+                match &identity {
+                    Some(identity) => {
+                        merge_arrays_by(&leaf_order, &mut base_order, |v| identity(v))
+                    }
+                    None => merge_arrays(&leaf_order, &mut base_order),
+                }
             }
             Ok(base_order)
         } else {
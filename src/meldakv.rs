@@ -0,0 +1,282 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2022 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::Adapter;
+use crate::constants::{
+    ARRAY_DESCRIPTOR_ORDER_FIELD, ARRAY_DESCRIPTOR_PREFIX, KV_ENTRIES_FIELD, KV_VALUE_FIELD,
+    ROOT_ID,
+};
+use crate::melda::Melda;
+use crate::revision::Revision;
+use crate::utils::{digest_string, is_array_descriptor};
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+
+/// A key-value store backed by a Melda document. Each entry is an object (whose identifier is
+/// the key) in a flattened array of the root object, hence entries set (or deleted) on
+/// different replicas are merged, whereas concurrent changes to the same key are resolved
+/// like any other conflict (the winning revision is used, see Melda::in_conflict).
+/// Changes are local until they are committed.
+///
+/// # Example
+/// ```
+/// use melda::{meldakv::MeldaKv, adapter::Adapter, memoryadapter::MemoryAdapter};
+/// use std::sync::{Arc, Mutex, RwLock};
+/// use serde_json::{Map, Value,json};
+/// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let kv = MeldaKv::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+/// kv.set("alpha", json!(1)).unwrap();
+/// kv.commit(None).unwrap();
+/// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let kv2 = MeldaKv::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+/// kv2.melda().meld(kv.melda()).unwrap();
+/// kv2.melda().refresh().unwrap();
+/// // Concurrent changes to different keys are merged
+/// kv.set("beta", json!({ "x" : [1, 2] })).unwrap();
+/// kv.commit(None).unwrap();
+/// kv2.set("gamma", json!("text")).unwrap();
+/// kv2.delete("alpha").unwrap();
+/// kv2.commit(None).unwrap();
+/// kv.melda().meld(kv2.melda()).unwrap();
+/// kv.melda().refresh().unwrap();
+/// assert_eq!(kv.keys().unwrap(), ["beta".to_string(), "gamma".to_string()].into());
+/// assert_eq!(kv.get("beta").unwrap(), Some(json!({ "x" : [1, 2] })));
+/// assert_eq!(kv.get("alpha").unwrap(), None);
+/// ```
+pub struct MeldaKv {
+    melda: Melda,
+}
+
+impl MeldaKv {
+    /// Initializes a new key-value store using the given adapter (see Melda::new)
+    ///
+    /// # Arguments
+    ///
+    /// * `adapter` - The storage adapter
+    pub fn new(adapter: Arc<RwLock<Box<dyn Adapter>>>) -> Result<MeldaKv> {
+        Ok(MeldaKv::with_melda(Melda::new(adapter)?))
+    }
+
+    /// Uses an existing Melda instance as a key-value store. The document must have been
+    /// created through MeldaKv (or be empty).
+    ///
+    /// # Arguments
+    ///
+    /// * `melda` - The Melda instance
+    pub fn with_melda(melda: Melda) -> MeldaKv {
+        MeldaKv { melda }
+    }
+
+    /// Returns the underlying Melda instance (for example to meld or refresh replicas)
+    pub fn melda(&self) -> &Melda {
+        &self.melda
+    }
+
+    /// Sets the value associated with the key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key (it cannot be the identifier of the root object, nor start with
+    ///   the prefix of array descriptors)
+    /// * `value` - The value
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{meldakv::MeldaKv, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let kv = MeldaKv::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// kv.set("key", json!([1, 2, 3])).unwrap();
+    /// assert_eq!(kv.get("key").unwrap(), Some(json!([1, 2, 3])));
+    /// kv.set("key", json!(null)).unwrap();
+    /// assert_eq!(kv.get("key").unwrap(), Some(json!(null)));
+    /// assert!(kv.set("^key", json!(1)).is_err());
+    /// ```
+    pub fn set(&self, key: &str, value: Value) -> Result<()> {
+        if !MeldaKv::is_valid_key(key) {
+            bail!("invalid_key");
+        }
+        self.melda.autocommit(|| {
+            let mut entry = Map::new();
+            entry.insert(KV_VALUE_FIELD.to_string(), value);
+            self.melda.update_object(key, entry)?;
+            let descriptor = self.create_entries()?;
+            let mut entries = self.entries(&descriptor)?;
+            if !entries.iter().any(|e| e.as_str() == Some(key)) {
+                entries.push(Value::from(key));
+                self.write_entries(&descriptor, entries)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the value associated with the key, None if the key does not exist
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{meldakv::MeldaKv, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let kv = MeldaKv::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert_eq!(kv.get("key").unwrap(), None);
+    /// // Values are stored as they are (identifiers included)
+    /// kv.set("key", json!({ "_id" : "other", "items\u{266D}" : [1] })).unwrap();
+    /// assert_eq!(kv.get("key").unwrap(), Some(json!({ "_id" : "other", "items\u{266D}" : [1] })));
+    /// ```
+    pub fn get(&self, key: &str) -> Result<Option<Value>> {
+        if !MeldaKv::is_valid_key(key) {
+            return Ok(None);
+        }
+        Ok(self
+            .melda
+            .get_current(key)?
+            .and_then(|(_, mut entry)| entry.remove(KV_VALUE_FIELD)))
+    }
+
+    /// Deletes the key, returns true if the key existed
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{meldakv::MeldaKv, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let kv = MeldaKv::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// assert!(!kv.delete("key").unwrap());
+    /// kv.set("key", json!(1)).unwrap();
+    /// assert!(kv.delete("key").unwrap());
+    /// assert!(kv.keys().unwrap().is_empty());
+    /// assert_eq!(kv.get("key").unwrap(), None);
+    /// ```
+    pub fn delete(&self, key: &str) -> Result<bool> {
+        if !MeldaKv::is_valid_key(key) || self.melda.get_current(key)?.is_none() {
+            return Ok(false);
+        }
+        self.melda.autocommit(|| {
+            self.melda.delete_object(key)?;
+            if let Some(descriptor) = self.entries_descriptor()? {
+                let mut entries = self.entries(&descriptor)?;
+                let count = entries.len();
+                entries.retain(|e| e.as_str() != Some(key));
+                if entries.len() != count {
+                    self.write_entries(&descriptor, entries)?;
+                }
+            }
+            Ok(true)
+        })
+    }
+
+    /// Returns the keys of the store
+    pub fn keys(&self) -> Result<BTreeSet<String>> {
+        let descriptor = match self.entries_descriptor()? {
+            Some(descriptor) => descriptor,
+            None => return Ok(BTreeSet::new()),
+        };
+        let mut keys = BTreeSet::new();
+        for key in self.entries(&descriptor)? {
+            if let Some(key) = key.as_str() {
+                // Skip the entries whose object has been deleted
+                let winner = self.melda.get_winner(key);
+                if winner.is_ok_and(|w| Revision::from(&w).is_ok_and(|r| !r.is_deleted())) {
+                    keys.insert(key.to_string());
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Commits the changes (see Melda::commit), returns the identifier of the new block (if
+    /// there were changes to commit)
+    ///
+    /// # Arguments
+    ///
+    /// * `information` - Optional information object associated with the commit
+    pub fn commit(&self, information: Option<Map<String, Value>>) -> Result<Option<String>> {
+        self.melda.commit(information)
+    }
+
+    /// Returns true if the key can be used as the identifier of an entry
+    fn is_valid_key(key: &str) -> bool {
+        key != ROOT_ID && !is_array_descriptor(key)
+    }
+
+    /// Returns the identifier of the array descriptor of the entries (None if the document
+    /// does not exist yet)
+    fn entries_descriptor(&self) -> Result<Option<String>> {
+        match self.melda.get_current(ROOT_ID)? {
+            Some((_, root)) => match root.get(KV_ENTRIES_FIELD) {
+                Some(Value::String(descriptor)) if is_array_descriptor(descriptor) => {
+                    Ok(Some(descriptor.clone()))
+                }
+                None => Ok(None),
+                _ => bail!("entries_not_an_array"),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the identifier of the array descriptor of the entries, creating the root
+    /// object (with the same descriptor identifier update would use) if missing
+    fn create_entries(&self) -> Result<String> {
+        if let Some(descriptor) = self.entries_descriptor()? {
+            return Ok(descriptor);
+        }
+        let mut root = match self.melda.get_current(ROOT_ID)? {
+            Some((_, root)) => root,
+            None => Map::new(),
+        };
+        let descriptor = ARRAY_DESCRIPTOR_PREFIX.to_string()
+            + &digest_string(&(ROOT_ID.to_string() + KV_ENTRIES_FIELD));
+        root.insert(
+            KV_ENTRIES_FIELD.to_string(),
+            Value::from(descriptor.clone()),
+        );
+        self.melda.update_object(ROOT_ID, root)?;
+        Ok(descriptor)
+    }
+
+    /// Returns the keys of the entries (in the order of the array)
+    fn entries(&self, descriptor: &str) -> Result<Vec<Value>> {
+        match self.melda.get_current(descriptor)? {
+            Some((_, mut order)) => match order.remove(ARRAY_DESCRIPTOR_ORDER_FIELD) {
+                Some(Value::Array(entries)) => Ok(entries),
+                _ => bail!("entries_not_an_array"),
+            },
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Replaces the keys of the entries
+    fn write_entries(&self, descriptor: &str, entries: Vec<Value>) -> Result<()> {
+        let mut order = Map::new();
+        order.insert(
+            ARRAY_DESCRIPTOR_ORDER_FIELD.to_string(),
+            Value::from(entries),
+        );
+        self.melda.update_object(descriptor, order)
+    }
+}
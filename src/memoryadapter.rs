@@ -13,9 +13,10 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use crate::adapter::Adapter;
-use anyhow::Result;
-use std::{cell::RefCell, collections::BTreeMap, sync::Mutex};
+use crate::adapter::{Adapter, Cursor, PagedObjects};
+use crate::constants::LIST_PAGE_SIZE;
+use anyhow::{anyhow, bail, Result};
+use std::{cell::RefCell, collections::BTreeMap, ops::Bound, sync::Mutex};
 
 /// Implements in-memory storage
 pub struct MemoryAdapter {
@@ -50,14 +51,49 @@ impl Adapter for MemoryAdapter {
     fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
         let mem = self.data.lock().unwrap();
         let d = mem.borrow();
-        let data = d.get(key).unwrap();
+        let data = d
+            .get(key)
+            .ok_or_else(|| anyhow!("object_not_found: {}", key))?;
         if offset == 0 && length == 0 {
             Ok(data.clone())
+        } else if offset + length > data.len() {
+            bail!("invalid_object_range: {}", key)
         } else {
             Ok(data.as_slice()[offset..offset + length].to_vec())
         }
     }
 
+    /// Reads an object or a sub-object appending its content to the given buffer (without
+    /// intermediate copies)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    /// * `buf` - The buffer the content is appended to
+    fn read_object_into(
+        &self,
+        key: &str,
+        offset: usize,
+        length: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        let mem = self.data.lock().unwrap();
+        let d = mem.borrow();
+        let data = d
+            .get(key)
+            .ok_or_else(|| anyhow!("object_not_found: {}", key))?;
+        if offset == 0 && length == 0 {
+            buf.extend_from_slice(data);
+        } else if offset + length > data.len() {
+            bail!("invalid_object_range: {}", key)
+        } else {
+            buf.extend_from_slice(&data[offset..offset + length]);
+        }
+        Ok(())
+    }
+
     /// Writes an object to the storage
     ///
     /// # Arguments
@@ -73,6 +109,42 @@ impl Adapter for MemoryAdapter {
         Ok(())
     }
 
+    /// Deletes an object from the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        self.data.lock().unwrap().borrow_mut().remove(key);
+        Ok(())
+    }
+
+    /// Returns the size (in bytes) of an object in the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        let mem = self.data.lock().unwrap();
+        let d = mem.borrow();
+        let data = d
+            .get(key)
+            .ok_or_else(|| anyhow!("object_not_found: {}", key))?;
+        Ok(data.len() as u64)
+    }
+
+    /// Returns the total size (in bytes) of the objects in the storage
+    fn total_size(&self) -> Result<u64> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .borrow()
+            .values()
+            .map(|v| v.len() as u64)
+            .sum())
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -90,6 +162,54 @@ impl Adapter for MemoryAdapter {
             .collect();
         Ok(list)
     }
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        if limit == 0 {
+            bail!("invalid_page_limit");
+        }
+        let mem = self.data.lock().unwrap();
+        let d = mem.borrow();
+        let lower = match cursor {
+            Some(c) => Bound::Excluded(c.to_string() + ext),
+            None => Bound::Unbounded,
+        };
+        let mut keys = d
+            .range::<String, _>((lower, Bound::Unbounded))
+            .map(|(k, _)| k)
+            .filter(|k| k.ends_with(ext));
+        let page: Vec<String> = keys
+            .by_ref()
+            .take(limit)
+            .map(|k| k.strip_suffix(ext).unwrap().to_string())
+            .collect();
+        let next = if keys.next().is_some() {
+            page.last().cloned()
+        } else {
+            None
+        };
+        Ok((page, next))
+    }
+
+    /// Iterates over the keys of the objects whose key ends with ext, fetching them one page at a time
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        Box::new(PagedObjects::new(self, ext, LIST_PAGE_SIZE))
+    }
 }
 
 #[cfg(test)]
@@ -283,4 +403,138 @@ mod tests {
         assert!(sqa.list_objects(".pack").unwrap().len() == 1);
         assert!(sqa.list_objects("").unwrap().len() == 2);
     }
+
+    #[test]
+    fn test_memory_list_objects_paged() {
+        let sqa = MemoryAdapter::new();
+        let (page, cursor) = sqa.list_objects_paged(".delta", None, 2).unwrap();
+        assert!(page.is_empty());
+        assert!(cursor.is_none());
+        for k in ["a", "b", "c", "d", "e"] {
+            assert!(sqa
+                .write_object(&(k.to_string() + ".delta"), "somedata".as_bytes())
+                .is_ok());
+        }
+        assert!(sqa.write_object("c.pack", "otherdata".as_bytes()).is_ok());
+        let mut all = vec![];
+        let mut cursor = None;
+        loop {
+            let (page, next) = sqa
+                .list_objects_paged(".delta", cursor.as_deref(), 2)
+                .unwrap();
+            assert!(page.len() <= 2);
+            all.extend(page);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+        assert_eq!(all, sqa.list_objects(".delta").unwrap());
+        let (page, cursor) = sqa.list_objects_paged("", None, 10).unwrap();
+        assert!(page.len() == 6);
+        assert!(cursor.is_none());
+        assert!(sqa.list_objects_paged(".delta", None, 0).is_err());
+    }
+
+    #[test]
+    fn test_memory_write_batch() {
+        let sqa = MemoryAdapter::new();
+        assert!(sqa
+            .write_batch(&[
+                ("somekey.pack", "somedata".as_bytes()),
+                ("somekey.delta", "otherdata".as_bytes()),
+            ])
+            .is_ok());
+        assert!(sqa.list_objects("").unwrap().len() == 2);
+        let ro = sqa.read_object("somekey.delta", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "otherdata");
+        assert!(sqa
+            .write_batch(&[("somekey.pack", "changed".as_bytes())])
+            .is_ok());
+        let ro = sqa.read_object("somekey.pack", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "somedata");
+    }
+
+    #[test]
+    fn test_memory_delete_object() {
+        let sqa = MemoryAdapter::new();
+        assert!(sqa
+            .write_object("somekey.pack", "somedata".as_bytes())
+            .is_ok());
+        assert!(sqa
+            .write_object("otherkey.pack", "otherdata".as_bytes())
+            .is_ok());
+        assert!(sqa.delete_object("somekey.pack").is_ok());
+        assert!(sqa.list_objects("").unwrap().len() == 1);
+        assert!(sqa.read_object("somekey.pack", 0, 0).is_err());
+        assert!(sqa.delete_object("somekey.pack").is_ok());
+        let ro = sqa.read_object("otherkey.pack", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "otherdata");
+    }
+
+    #[test]
+    fn test_memory_read_object_into() {
+        let sqa = MemoryAdapter::new();
+        assert!(sqa
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        let mut buf = "prefix".as_bytes().to_vec();
+        assert!(sqa
+            .read_object_into("somekey.delta", 0, 0, &mut buf)
+            .is_ok());
+        assert!(String::from_utf8(buf.clone()).unwrap() == "prefixsomedata");
+        buf.clear();
+        assert!(sqa
+            .read_object_into("somekey.delta", 1, 2, &mut buf)
+            .is_ok());
+        assert!(String::from_utf8(buf.clone()).unwrap() == "om");
+        assert!(sqa
+            .read_object_into("somekey.delta", 7, 2, &mut buf)
+            .is_err());
+        assert!(sqa
+            .read_object_into("otherkey.delta", 0, 0, &mut buf)
+            .is_err());
+    }
+
+    #[test]
+    fn test_memory_total_size() {
+        let sqa = MemoryAdapter::new();
+        assert!(sqa.total_size().unwrap() == 0);
+        assert!(sqa
+            .write_object("somekey.pack", "somedata".as_bytes())
+            .is_ok());
+        assert!(sqa
+            .write_object("otherkey.delta", "otherdata".as_bytes())
+            .is_ok());
+        assert!(sqa.total_size().unwrap() == 17);
+        assert!(sqa.object_size("otherkey.delta").unwrap() == 9);
+        assert!(sqa.object_size("missing.delta").is_err());
+        assert!(sqa.delete_object("somekey.pack").is_ok());
+        assert!(sqa.total_size().unwrap() == 9);
+    }
+
+    #[test]
+    fn test_memory_iter_objects() {
+        let sqa = MemoryAdapter::new();
+        assert!(sqa.iter_objects(".delta").next().is_none());
+        for k in ["a", "b", "c", "d", "e"] {
+            assert!(sqa
+                .write_object(&(k.to_string() + ".delta"), "somedata".as_bytes())
+                .is_ok());
+        }
+        assert!(sqa.write_object("c.pack", "otherdata".as_bytes()).is_ok());
+        let all: Vec<String> = sqa
+            .iter_objects(".delta")
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        assert!(all == vec!["a", "b", "c", "d", "e"]);
+        let paged: Vec<String> = crate::adapter::PagedObjects::new(&sqa, ".delta", 2)
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        assert!(paged == all);
+        assert!(crate::adapter::PagedObjects::new(&sqa, ".delta", 0)
+            .next()
+            .unwrap()
+            .is_err());
+    }
 }
@@ -0,0 +1,393 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2022 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::{Adapter, PagedObjects};
+use crate::constants::LIST_PAGE_SIZE;
+use anyhow::{bail, Result};
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// An error which must not be retried (for example an authentication failure). Adapters
+/// return it (wrapped in an anyhow::Error) to stop the RetryAdapter from repeating the operation
+///
+/// # Example
+/// ```
+/// use melda::retryadapter::{is_retryable, NonRetryableError};
+/// let e: anyhow::Error = NonRetryableError::new("cannot_authenticate").into();
+/// assert!(!is_retryable(&e));
+/// assert!(e.to_string() == "cannot_authenticate");
+/// assert!(is_retryable(&anyhow::anyhow!("cannot_read_object")));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonRetryableError {
+    message: String,
+}
+
+impl NonRetryableError {
+    /// Creates a new error with the given message
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The error message
+    pub fn new(message: &str) -> Self {
+        NonRetryableError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for NonRetryableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for NonRetryableError {}
+
+/// Returns true if the operation which failed with the given error can be retried
+///
+/// # Arguments
+///
+/// * `e` - The error
+pub fn is_retryable(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<NonRetryableError>().is_none()
+}
+
+/// The policy used by the RetryAdapter: a failed operation is attempted at most max_attempts
+/// times, waiting initial_backoff before the first retry and doubling the delay after each retry
+/// (up to max_backoff)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the given retry (the first retry is 1)
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The number of the retry
+    ///
+    /// # Example
+    /// ```
+    /// use melda::retryadapter::RetryPolicy;
+    /// use std::time::Duration;
+    /// let policy = RetryPolicy { max_attempts: 5, initial_backoff: Duration::from_millis(100), max_backoff: Duration::from_millis(300) };
+    /// assert!(policy.backoff(1) == Duration::from_millis(100));
+    /// assert!(policy.backoff(2) == Duration::from_millis(200));
+    /// assert!(policy.backoff(3) == Duration::from_millis(300));
+    /// ```
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32 << retry.saturating_sub(1).min(31);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Retries the operations of another adapter when they fail (for example because of a transient
+/// network error). Since keys are content-addressed and objects are never overwritten, writes
+/// can be safely repeated. Errors marked as NonRetryableError are returned immediately.
+/// Note that waiting between retries relies on std::thread::sleep.
+pub struct RetryAdapter {
+    backend: Arc<RwLock<Box<dyn Adapter>>>,
+    policy: RetryPolicy,
+}
+
+impl RetryAdapter {
+    /// Creates a new adapter wrapping the specified adapter, using the default policy
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The adapter to be wrapped
+    pub fn new(backend: Arc<RwLock<Box<dyn Adapter>>>) -> Self {
+        RetryAdapter::new_with_policy(backend, RetryPolicy::default())
+    }
+
+    /// Creates a new adapter wrapping the specified adapter, using the given policy
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The adapter to be wrapped
+    /// * `policy` - The retry policy
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{adapter::Adapter, memoryadapter::MemoryAdapter, retryadapter::{RetryAdapter, RetryPolicy}};
+    /// use std::{sync::{Arc, RwLock}, time::Duration};
+    /// let backend: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let policy = RetryPolicy { max_attempts: 5, initial_backoff: Duration::from_millis(10), max_backoff: Duration::from_secs(1) };
+    /// let adapter = RetryAdapter::new_with_policy(Arc::new(RwLock::new(backend)), policy);
+    /// assert!(adapter.write_object("somekey.delta", "somedata".as_bytes()).is_ok());
+    /// assert!(adapter.read_object("somekey.delta", 0, 0).unwrap() == "somedata".as_bytes());
+    /// ```
+    pub fn new_with_policy(backend: Arc<RwLock<Box<dyn Adapter>>>, policy: RetryPolicy) -> Self {
+        RetryAdapter { backend, policy }
+    }
+
+    /// Returns the retry policy
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    fn retry<T>(&self, op: impl Fn(&dyn Adapter) -> Result<T>) -> Result<T> {
+        if self.policy.max_attempts == 0 {
+            bail!("invalid_max_attempts");
+        }
+        let mut attempt = 1;
+        loop {
+            let result = op(self
+                .backend
+                .read()
+                .expect("cannot_acquire_backend_for_reading")
+                .as_ref());
+            match result {
+                Ok(r) => return Ok(r),
+                Err(e) if attempt >= self.policy.max_attempts || !is_retryable(&e) => {
+                    return Err(e)
+                }
+                Err(_) => {
+                    std::thread::sleep(self.policy.backoff(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Adapter for RetryAdapter {
+    /// Reads an object or a sub-object from the backend storage. When offset and length are both 0
+    /// the full object is returned, otherwise the sub-object is returned
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    ///
+    fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        self.retry(|b| b.read_object(key, offset, length))
+    }
+
+    /// Writes an object to the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.retry(|b| b.write_object(key, data))
+    }
+
+    /// Writes multiple objects to the storage (the whole batch is retried on failure)
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The keys associated with the objects along with their content
+    fn write_batch(&self, items: &[(&str, &[u8])]) -> Result<()> {
+        self.retry(|b| b.write_batch(items))
+    }
+
+    /// Deletes an object from the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        self.retry(|b| b.delete_object(key))
+    }
+
+    /// Flushes the backend storage
+    fn flush(&self) -> Result<()> {
+        self.retry(|b| b.flush())
+    }
+
+    /// Returns the size (in bytes) of an object in the backend storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        self.retry(|b| b.object_size(key))
+    }
+
+    /// Returns the total size (in bytes) of the backend storage
+    fn total_size(&self) -> Result<u64> {
+        self.retry(|b| b.total_size())
+    }
+
+    /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+        self.retry(|b| b.list_objects(ext))
+    }
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time (each page is retried on failure)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<crate::adapter::Cursor>)> {
+        self.retry(|b| b.list_objects_paged(ext, cursor, limit))
+    }
+
+    /// Iterates over the keys of the objects whose key ends with ext, fetching them one page at
+    /// a time (see list_objects_paged)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        Box::new(PagedObjects::new(self, ext, LIST_PAGE_SIZE))
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::{
+        adapter::Adapter,
+        memoryadapter::MemoryAdapter,
+        retryadapter::{NonRetryableError, RetryAdapter, RetryPolicy},
+    };
+    #[allow(unused_imports)]
+    use anyhow::{bail, Result};
+    #[allow(unused_imports)]
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc, RwLock,
+        },
+        time::Duration,
+    };
+
+    /// Fails the first operations (all of them if fatal)
+    #[allow(dead_code)]
+    struct FlakyAdapter {
+        inner: MemoryAdapter,
+        failures: AtomicU32,
+        calls: Arc<AtomicU32>,
+        fatal: bool,
+    }
+
+    #[allow(dead_code)]
+    impl FlakyAdapter {
+        fn check(&self) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fatal {
+                return Err(NonRetryableError::new("cannot_authenticate").into());
+            }
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                bail!("connection_reset");
+            }
+            Ok(())
+        }
+    }
+
+    impl Adapter for FlakyAdapter {
+        fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+            self.check()?;
+            self.inner.read_object(key, offset, length)
+        }
+
+        fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+            self.check()?;
+            self.inner.write_object(key, data)
+        }
+
+        fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+            self.check()?;
+            self.inner.list_objects(ext)
+        }
+    }
+
+    #[allow(dead_code)]
+    fn flaky(failures: u32, fatal: bool, max_attempts: u32) -> (RetryAdapter, Arc<AtomicU32>) {
+        let calls = Arc::new(AtomicU32::new(0));
+        let fa: Box<dyn Adapter> = Box::new(FlakyAdapter {
+            inner: MemoryAdapter::new(),
+            failures: AtomicU32::new(failures),
+            calls: calls.clone(),
+            fatal,
+        });
+        let policy = RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        };
+        (
+            RetryAdapter::new_with_policy(Arc::new(RwLock::new(fa)), policy),
+            calls,
+        )
+    }
+
+    #[test]
+    fn test_retry_transient_errors() {
+        let (ra, calls) = flaky(2, false, 3);
+        assert!(ra
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        assert!(calls.load(Ordering::SeqCst) == 3);
+        let ro = ra.read_object("somekey.delta", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "somedata");
+        assert!(ra.list_objects(".delta").unwrap().len() == 1);
+        assert!(calls.load(Ordering::SeqCst) == 5);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let (ra, calls) = flaky(3, false, 3);
+        let e = ra.list_objects(".delta").unwrap_err();
+        assert!(e.to_string() == "connection_reset");
+        assert!(calls.load(Ordering::SeqCst) == 3);
+        // The backend has recovered
+        assert!(ra.list_objects(".delta").unwrap().is_empty());
+        let (ra, _) = flaky(0, false, 0);
+        assert!(ra.list_objects(".delta").is_err());
+    }
+
+    #[test]
+    fn test_non_retryable_errors() {
+        let (ra, calls) = flaky(0, true, 5);
+        let e = ra.read_object("somekey.delta", 0, 0).unwrap_err();
+        assert!(e.downcast_ref::<NonRetryableError>().is_some());
+        assert!(calls.load(Ordering::SeqCst) == 1);
+    }
+}
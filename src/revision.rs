@@ -18,7 +18,10 @@ use lazy_static::lazy_static;
 use regex::Regex;
 use std::hash::Hash;
 
-use crate::constants::{DELETED_HASH, EMPTY_HASH, RESOLVED_HASH};
+use crate::constants::{
+    DEFAULT_REVISION_TAIL_LENGTH, DELETED_HASH, DELETED_META_PREFIX, EMPTY_HASH,
+    MAX_REVISION_TAIL_LENGTH, RESOLVED_HASH,
+};
 use crate::utils::digest_string;
 
 lazy_static! {
@@ -45,37 +48,72 @@ impl Revision {
         }
     }
 
-    /// Constructs a new revision
+    /// Constructs a new revision (using the default tail length)
     pub fn new<T>(index: u32, digest: T, parent: Option<&Revision>) -> Revision
+    where
+        T: Into<String>,
+    {
+        Revision::new_with_tail_length(index, digest, parent, DEFAULT_REVISION_TAIL_LENGTH)
+    }
+
+    /// Constructs a new revision whose tail consists of the first tail_length characters of the
+    /// digest of the parent revision
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the revision
+    /// * `digest` - The digest of the revision
+    /// * `parent` - The optional parent revision
+    /// * `tail_length` - The length of the tail (between DEFAULT_REVISION_TAIL_LENGTH and
+    ///   MAX_REVISION_TAIL_LENGTH)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::revision::Revision;
+    /// let parent = Revision::new(1, "alpha", None);
+    /// let revision = Revision::new_with_tail_length(2, "beta", Some(&parent), 12);
+    /// assert_eq!(revision.tail.as_ref().unwrap().len(), 12);
+    /// assert!(revision.tail.unwrap().starts_with(Revision::new(2, "beta", Some(&parent)).tail.unwrap().as_str()));
+    /// ```
+    pub fn new_with_tail_length<T>(
+        index: u32,
+        digest: T,
+        parent: Option<&Revision>,
+        tail_length: usize,
+    ) -> Revision
     where
         T: Into<String>,
     {
         Revision {
             index,
             digest: digest.into(),
-            tail: match parent {
-                Some(p) => {
-                    let fulltail = digest_string(&p.to_string());
-                    Some(fulltail[..7].to_string())
-                }
-                None => None,
-            },
+            tail: parent.map(|p| {
+                let fulltail = digest_string(&p.to_string());
+                fulltail
+                    [..tail_length.clamp(DEFAULT_REVISION_TAIL_LENGTH, MAX_REVISION_TAIL_LENGTH)]
+                    .to_string()
+            }),
         }
     }
 
-    /// Constructs a new revision
+    /// Constructs a new revision (using the default tail length)
     pub fn new_updated<T>(digest: T, parent: &Revision) -> Revision
     where
         T: Into<String>,
     {
-        Revision {
-            index: parent.index + 1,
-            digest: digest.into(),
-            tail: {
-                let fulltail = digest_string(&parent.to_string());
-                Some(fulltail[..7].to_string())
-            },
-        }
+        Revision::new_updated_with_tail_length(digest, parent, DEFAULT_REVISION_TAIL_LENGTH)
+    }
+
+    /// Constructs a new revision, child of the given parent, with a tail of the given length
+    pub fn new_updated_with_tail_length<T>(
+        digest: T,
+        parent: &Revision,
+        tail_length: usize,
+    ) -> Revision
+    where
+        T: Into<String>,
+    {
+        Revision::new_with_tail_length(parent.index + 1, digest, Some(parent), tail_length)
     }
 
     /// Constructs a new deleted revision
@@ -83,6 +121,15 @@ impl Revision {
         Revision::new(parent.index + 1, DELETED_HASH.to_string(), Some(parent))
     }
 
+    /// Constructs a new deleted revision, referencing the digest of a metadata object
+    pub fn new_deleted_with_meta(parent: &Revision, meta_digest: &str) -> Revision {
+        Revision::new(
+            parent.index + 1,
+            DELETED_META_PREFIX.to_string() + meta_digest,
+            Some(parent),
+        )
+    }
+
     /// Constructs a new empty revision
     #[allow(dead_code)]
     pub fn new_empty(parent: &Revision) -> Revision {
@@ -115,9 +162,68 @@ impl Revision {
         }
     }
 
+    /// Constructs a new revision from its components, validating them: the index must be at
+    /// least 1, the digest must be a non-empty alphanumeric string, and the tail (the first
+    /// characters of the digest of the parent revision) must consist of 7 (or, if a longer tail
+    /// length is used, up to 64) alphanumeric characters. First revisions (index 1) have no parent, hence they cannot have a tail.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the revision
+    /// * `digest` - The digest of the revision
+    /// * `tail` - The optional tail of the revision
+    ///
+    /// # Example
+    /// ```
+    /// use melda::revision::Revision;
+    /// let parent = Revision::from_parts(1, "alpha", None).unwrap();
+    /// let revision = Revision::new_updated("beta", &parent);
+    /// assert_eq!(revision, Revision::from_parts(2, "beta", revision.tail.clone()).unwrap());
+    /// assert!(Revision::from_parts(0, "alpha", None).is_err());
+    /// assert!(Revision::from_parts(1, "", None).is_err());
+    /// assert!(Revision::from_parts(1, "alpha", Some("abcdef0".to_string())).is_err());
+    /// assert!(Revision::from_parts(2, "beta", Some("abc".to_string())).is_err());
+    /// assert!(Revision::from_parts(2, "beta", Some("abcdef0123".to_string())).is_ok());
+    /// ```
+    pub fn from_parts<T>(index: u32, digest: T, tail: Option<String>) -> Result<Revision>
+    where
+        T: Into<String>,
+    {
+        let digest = digest.into();
+        if index == 0 {
+            bail!("invalid_revision_index");
+        }
+        if digest.is_empty() || !digest.chars().all(|c| c.is_ascii_alphanumeric()) {
+            bail!("invalid_revision_digest: {}", digest);
+        }
+        if let Some(tail) = &tail {
+            if index == 1 {
+                bail!("unexpected_revision_tail");
+            }
+            if !(DEFAULT_REVISION_TAIL_LENGTH..=MAX_REVISION_TAIL_LENGTH).contains(&tail.len())
+                || !tail.chars().all(|c| c.is_ascii_alphanumeric())
+            {
+                bail!("invalid_revision_tail: {}", tail);
+            }
+        }
+        Ok(Revision {
+            index,
+            digest,
+            tail,
+        })
+    }
+
     /// Returns true if the revision represents a deleted object
     pub fn is_deleted(&self) -> bool {
-        self.digest == DELETED_HASH
+        self.digest == DELETED_HASH || self.get_deletion_meta_digest().is_some()
+    }
+
+    /// Returns the digest of the metadata object of a deleted revision (if any)
+    pub fn get_deletion_meta_digest(&self) -> Option<&str> {
+        match self.digest.strip_prefix(DELETED_META_PREFIX) {
+            Some(d) if d.len() == 64 && d.chars().all(|c| c.is_ascii_hexdigit()) => Some(d),
+            _ => None,
+        }
     }
 
     /// Returns true if the revision represents a resolved object
@@ -210,4 +316,37 @@ mod tests {
         assert!(r1 < r2);
         assert!(r2 > r1);
     }
+
+    #[test]
+    fn test_deleted_with_meta() {
+        let r1 = crate::revision::Revision::from("1-alpha").unwrap();
+        let meta = crate::utils::digest_string("meta");
+        let r2 = crate::revision::Revision::new_deleted_with_meta(&r1, &meta);
+        assert!(r2.is_deleted());
+        assert!(r2.get_deletion_meta_digest() == Some(meta.as_str()));
+        let r3 = crate::revision::Revision::from(&r2.to_string()).unwrap();
+        assert!(r2 == r3);
+        assert!(crate::revision::Revision::new_deleted(&r1).is_deleted());
+        assert!(crate::revision::Revision::new_deleted(&r1)
+            .get_deletion_meta_digest()
+            .is_none());
+        assert!(!crate::revision::Revision::from("2-dmitri_abc")
+            .unwrap()
+            .is_deleted());
+    }
+
+    #[test]
+    fn test_tail_length() {
+        // Both parents have the same 7 characters tail (a602514)
+        let p1 = crate::revision::Revision::new(1, "p26420", None);
+        let p2 = crate::revision::Revision::new(1, "p35394", None);
+        let r1 = crate::revision::Revision::new_updated("beta", &p1);
+        let r2 = crate::revision::Revision::new_updated("beta", &p2);
+        assert!(r1 == r2);
+        let r1 = crate::revision::Revision::new_updated_with_tail_length("beta", &p1, 12);
+        let r2 = crate::revision::Revision::new_updated_with_tail_length("beta", &p2, 12);
+        assert!(r1 != r2);
+        let r3 = crate::revision::Revision::from(&r1.to_string()).unwrap();
+        assert!(r1 == r3);
+    }
 }
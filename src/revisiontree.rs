@@ -35,6 +35,16 @@ impl RevisionTree {
         self.revisions.insert((revision, parent))
     }
 
+    /// Returns the parents recorded for the given revision (more than one if the revision has
+    /// been added with different parents, which denotes an inconsistency)
+    pub fn get_parents(&self, revision: &Revision) -> Vec<Option<&Revision>> {
+        self.revisions
+            .iter()
+            .filter(|(r, _)| r == revision)
+            .map(|(_, parent)| parent.as_ref())
+            .collect()
+    }
+
     /// Removes an existing revision, parent tuple
     /// This methods returns true if the pair was present (and then removed), false otherwise
     pub fn remove(&mut self, revision: Revision, parent: Option<Revision>) -> bool {
@@ -76,6 +86,16 @@ impl RevisionTree {
         leafs
     }
 
+    /// Returns ghost parents (parent revisions which are referenced but not present in the tree)
+    pub fn get_ghost_parents(&self) -> BTreeSet<&Revision> {
+        let revs = self.get_all_revs();
+        self.revisions
+            .iter()
+            .filter_map(|(_, parent)| parent.as_ref())
+            .filter(|parent| !revs.contains(parent))
+            .collect()
+    }
+
     /// Merges from another Revision Tree
     pub fn merge(&mut self, other: &RevisionTree) {
         self.revisions = self.revisions.union(&other.revisions).cloned().collect();
@@ -164,4 +184,38 @@ mod tests {
         let w = rt.get_winner().unwrap();
         assert!(lvec[1] == w);
     }
+
+    #[test]
+    fn test_parents() {
+        let mut rt = super::RevisionTree::new();
+        let first = crate::revision::Revision::from("1-abc").unwrap();
+        let second = crate::revision::Revision::from("2-cde_abc").unwrap();
+        assert!(rt.get_parents(&second).is_empty());
+        rt.add(first.clone(), None);
+        rt.add(second.clone(), Some(first.clone()));
+        assert!(rt.get_parents(&first) == vec![None]);
+        assert!(rt.get_parents(&second) == vec![Some(&first)]);
+        let other = crate::revision::Revision::from("1-xyz").unwrap();
+        rt.add(second.clone(), Some(other.clone()));
+        assert!(rt.get_parents(&second).len() == 2);
+    }
+
+    #[test]
+    fn test_ghost_parents() {
+        let mut rt = super::RevisionTree::new();
+        rt.add(crate::revision::Revision::from("1-abc").unwrap(), None);
+        assert!(rt.get_ghost_parents().is_empty());
+        rt.add(
+            crate::revision::Revision::from("3-xyz_cde").unwrap(),
+            crate::revision::Revision::from("2-cde_abc").ok(),
+        );
+        let ghosts = rt.get_ghost_parents();
+        assert!(ghosts.len() == 1);
+        assert!(ghosts.iter().next().unwrap().to_string() == "2-cde_abc");
+        rt.add(
+            crate::revision::Revision::from("2-cde_abc").unwrap(),
+            crate::revision::Revision::from("1-abc").ok(),
+        );
+        assert!(rt.get_ghost_parents().is_empty());
+    }
 }
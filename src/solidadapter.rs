@@ -13,14 +13,17 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use crate::adapter::Adapter;
-use anyhow::{bail, Result};
+use crate::adapter::{list_folders_paged, Adapter, Cursor, PagedObjects};
+use crate::constants::{DELTA_EXTENSION, LIST_PAGE_SIZE};
+use crate::retryadapter::NonRetryableError;
+use anyhow::{anyhow, bail, Result};
 use cacache;
 use lru::LruCache;
 use oxiri::Iri;
+use rayon::prelude::*;
 use reqwest::blocking::Client;
-use reqwest::header::HeaderMap;
-use rio_api::model::NamedNode;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH};
+use rio_api::model::{Literal, NamedNode, Term};
 use rio_api::parser::TriplesParser;
 use rio_turtle::{TurtleError, TurtleParser};
 use std::cell::RefCell;
@@ -107,7 +110,7 @@ impl SolidAdapter {
         if response.status() == 200 {
             Ok(())
         } else {
-            bail!("cannot_authenticate");
+            Err(NonRetryableError::new("cannot_authenticate").into())
         }
     }
 
@@ -139,6 +142,55 @@ impl SolidAdapter {
         }
     }
 
+    /// Uploads an object (if not already present in the pod), returns true if the object was written
+    fn upload_object(&self, key: &str, data: &[u8]) -> Result<bool> {
+        let url = self.ensure_sub_container_exists(key)?;
+        let response = self.client.head(url.clone()).send()?;
+        if response.status().as_u16() != 200 {
+            let mut headers = HeaderMap::new();
+            headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
+            let response = self
+                .client
+                .put(url)
+                .headers(headers)
+                .body(data.to_vec())
+                .send()?;
+            if response.status().as_u16() >= 200 || response.status().as_u16() <= 204 {
+                cacache::write_sync(&self.disk_cache_dir, key, data)?;
+                Ok(true)
+            } else {
+                bail!("cannot_write_object");
+            }
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Uploads the objects which are not cached (optionally in parallel), stops at the first error
+    fn upload_batch(&self, items: &[&(&str, &[u8])], parallel: bool) -> Result<()> {
+        let cache = self.cache.lock().unwrap();
+        let missing: Vec<&(&str, &[u8])> = items
+            .iter()
+            .filter(|(key, _)| !cache.borrow().contains(&key.to_string()))
+            .copied()
+            .collect();
+        drop(cache);
+        let upload = |(key, data): &&(&str, &[u8])| self.upload_object(key, data);
+        let written = if parallel {
+            missing.par_iter().map(upload).collect::<Result<Vec<_>>>()?
+        } else {
+            missing.iter().map(upload).collect::<Result<Vec<_>>>()?
+        };
+        let cache = self.cache.lock().unwrap();
+        let mut cache = cache.borrow_mut();
+        for ((key, data), written) in missing.into_iter().zip(written) {
+            if written {
+                cache.put(key.to_string(), data.to_vec());
+            }
+        }
+        Ok(())
+    }
+
     fn ensure_container_exists(&self) -> Result<()> {
         let url = self.url.clone() + "/" + self.folder.as_str();
         let response = self.client.head(url).send()?;
@@ -216,6 +268,38 @@ impl SolidAdapter {
         Ok(object_url)
     }
 
+    /// Returns the total size of the resources of a container (from their stat:size in the
+    /// description of the container)
+    fn container_size(&self, target: &str) -> Result<u64> {
+        let response = self.client.get(target).send()?;
+        if response.status().as_u16() != 200 {
+            bail!("cannot_list_container");
+        }
+        let data = response.text()?;
+        let stat_size = NamedNode {
+            iri: "http://www.w3.org/ns/posix/stat#size",
+        };
+        let mut size = 0;
+        let base_iri = Iri::parse(target.to_string())?;
+        TurtleParser::new(data.as_bytes(), Some(base_iri)).parse_all(&mut |t| {
+            if t.predicate == stat_size {
+                if let rio_api::model::Subject::NamedNode(nn) = t.subject {
+                    // Sub-containers are skipped
+                    if !nn.iri.ends_with('/') {
+                        let value = match t.object {
+                            Term::Literal(Literal::Simple { value }) => value,
+                            Term::Literal(Literal::Typed { value, .. }) => value,
+                            _ => "",
+                        };
+                        size += value.parse::<u64>().unwrap_or(0);
+                    }
+                }
+            }
+            Ok(()) as Result<(), TurtleError>
+        })?;
+        Ok(size)
+    }
+
     fn list_container(
         &self,
         ext: &str,
@@ -299,29 +383,78 @@ impl Adapter for SolidAdapter {
     fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
         let cache = self.cache.lock().unwrap();
         let mut cache = cache.borrow_mut();
-        if !cache.contains(&key.to_string()) {
-            let url = self.ensure_sub_container_exists(key)?;
-            let response = self.client.head(url.clone()).send()?;
-            if response.status().as_u16() != 200 {
-                let mut headers = HeaderMap::new();
-                headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
-                let response = self
-                    .client
-                    .put(url)
-                    .headers(headers)
-                    .body(data.to_vec())
-                    .send()?;
-                if response.status().as_u16() >= 200 || response.status().as_u16() <= 204 {
-                    cache.put(key.to_string(), data.to_vec());
-                    cacache::write_sync(&self.disk_cache_dir, key, data)?;
-                } else {
-                    bail!("cannot_write_object");
-                }
-            }
+        if !cache.contains(&key.to_string()) && self.upload_object(key, data)? {
+            cache.put(key.to_string(), data.to_vec());
         }
         Ok(())
     }
 
+    /// Writes multiple objects to the storage. Data objects (packs and indexes) are uploaded
+    /// first (in parallel), delta blocks are uploaded last (sequentially) and only if all data
+    /// objects have been uploaded, so that a visible block never references missing data
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The keys associated with the objects along with their content
+    fn write_batch(&self, items: &[(&str, &[u8])]) -> Result<()> {
+        let (blocks, data): (Vec<_>, Vec<_>) = items
+            .iter()
+            .partition(|(key, _)| key.ends_with(DELTA_EXTENSION));
+        self.upload_batch(&data, true)?;
+        self.upload_batch(&blocks, false)
+    }
+
+    /// Deletes an object from the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let cache = self.cache.lock().unwrap();
+        cache.borrow_mut().pop(&key.to_string());
+        let _ = cacache::remove_sync(&self.disk_cache_dir, key);
+        let (_, url) = self.get_object_url(key)?;
+        let response = self.client.delete(url).send()?;
+        let status = response.status().as_u16();
+        if (200..=204).contains(&status) || status == 404 {
+            Ok(())
+        } else {
+            bail!("cannot_delete_object")
+        }
+    }
+
+    /// Returns the size (in bytes) of an object in the pod (obtained from its Content-Length,
+    /// without downloading it)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        let (_, url) = self.get_object_url(key)?;
+        let response = self.client.head(url).send()?;
+        if response.status().as_u16() != 200 {
+            bail!("cannot_read_object");
+        }
+        response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| anyhow!("missing_content_length"))
+    }
+
+    /// Returns the total size (in bytes) of the objects in the pod (obtained from the
+    /// description of each sub-container, without downloading the objects)
+    fn total_size(&self) -> Result<u64> {
+        let mut size = 0;
+        let target = self.url.clone() + "/" + self.folder.as_str();
+        for sub in self.list_container("", &target, ResourceType::Folder)? {
+            let target = self.url.clone() + "/" + self.folder.as_str() + "/" + &sub;
+            size += self.container_size(&target)?;
+        }
+        Ok(size)
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -339,6 +472,43 @@ impl Adapter for SolidAdapter {
         }
         Ok(list)
     }
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time (only the
+    /// sub-containers needed for the page are listed)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        let base = self.url.clone() + "/" + self.folder.as_str();
+        let folders = self
+            .list_container("", &base, ResourceType::Folder)?
+            .into_iter()
+            .map(|sub| sub.trim_end_matches('/').to_string())
+            .collect();
+        let list_folder = |folder: &str| {
+            let target = base.clone() + "/" + folder + "/";
+            self.list_container(ext, &target, ResourceType::File)
+        };
+        list_folders_paged(folders, list_folder, cursor, limit)
+    }
+
+    /// Iterates over the keys of the objects whose key ends with ext, fetching them one page at
+    /// a time (see list_objects_paged)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        Box::new(PagedObjects::new(self, ext, LIST_PAGE_SIZE))
+    }
 }
 
 mod tests {
@@ -13,8 +13,9 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use crate::adapter::Adapter;
-use anyhow::Result;
+use crate::adapter::{Adapter, Cursor, PagedObjects};
+use crate::constants::{LIST_PAGE_SIZE, SQLITE_MAX_DELETE_KEYS};
+use anyhow::{bail, Result};
 use base64::{engine::general_purpose, Engine as _};
 use std::{cell::RefCell, sync::Mutex};
 
@@ -118,6 +119,105 @@ impl Adapter for SqliteAdapter {
         }
     }
 
+    /// Writes multiple objects to the storage within a single transaction
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The keys associated with the objects along with their content
+    fn write_batch(&self, items: &[(&str, &[u8])]) -> Result<()> {
+        let mcn = self.cn.lock().unwrap();
+        let mut cn = mcn.borrow_mut();
+        let tx = cn.transaction()?;
+        for (key, data) in items {
+            let value = general_purpose::STANDARD.encode(data);
+            if tx
+                .execute(
+                    "INSERT OR IGNORE INTO entries (key, value) VALUES (?1,?2)",
+                    [key, &value.as_str()],
+                )
+                .is_err()
+            {
+                bail!("cannot_write_object");
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Deletes an object from the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let mcn = self.cn.lock().unwrap();
+        let cn = mcn.borrow_mut();
+        match cn.execute("DELETE FROM entries WHERE key = ?1", [&key]) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(anyhow::anyhow!("cannot_delete_object")),
+        }
+    }
+
+    /// Deletes multiple objects from the storage (with a single statement for each chunk of
+    /// keys)
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keys associated with the objects
+    fn delete_objects(&self, keys: &[&str]) -> Result<Vec<(String, Result<()>)>> {
+        let mcn = self.cn.lock().unwrap();
+        let cn = mcn.borrow_mut();
+        let mut results = vec![];
+        for chunk in keys.chunks(SQLITE_MAX_DELETE_KEYS) {
+            let placeholders = vec!["?"; chunk.len()].join(",");
+            let deleted = cn.execute(
+                &format!("DELETE FROM entries WHERE key IN ({})", placeholders),
+                rusqlite::params_from_iter(chunk.iter()),
+            );
+            for key in chunk {
+                let result = match deleted {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(anyhow::anyhow!("cannot_delete_object")),
+                };
+                results.push((key.to_string(), result));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Returns the size (in bytes) of an object in the storage (the size of the encoded value,
+    /// see total_size)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        let mcn = self.cn.lock().unwrap();
+        let cn = mcn.borrow();
+        let result: rusqlite::Result<i64> = cn.query_row(
+            "SELECT length(value) FROM entries WHERE key = ?1",
+            [&key],
+            |row| row.get(0),
+        );
+        match result {
+            Ok(size) => Ok(size as u64),
+            Err(_) => Err(anyhow::anyhow!("cannot_read_object")),
+        }
+    }
+
+    /// Returns the total size (in bytes) of the objects in the storage (objects are stored
+    /// encoded in base64, hence the size of the encoded values is returned)
+    fn total_size(&self) -> Result<u64> {
+        let mcn = self.cn.lock().unwrap();
+        let cn = mcn.borrow();
+        let size: i64 = cn.query_row(
+            "SELECT COALESCE(SUM(length(value)), 0) FROM entries",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(size as u64)
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -140,6 +240,51 @@ impl Adapter for SqliteAdapter {
             })
             .collect())
     }
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        if limit == 0 {
+            bail!("invalid_page_limit");
+        }
+        let mcn = self.cn.lock().unwrap();
+        let cn = mcn.borrow();
+        let mut stmt = cn.prepare(
+            "SELECT key FROM entries WHERE key > ?1 AND substr(key, length(key) - length(?2) + 1) = ?2 ORDER BY key LIMIT ?3",
+        )?;
+        // Fetch one more key to find out if there is a next page
+        let rows = stmt.query_map(
+            rusqlite::params![cursor.unwrap_or(""), ext, limit as i64 + 1],
+            |row| row.get(0),
+        )?;
+        let mut page = rows.collect::<rusqlite::Result<Vec<String>>>()?;
+        let next = if page.len() > limit {
+            page.truncate(limit);
+            page.last().cloned()
+        } else {
+            None
+        };
+        Ok((page, next))
+    }
+
+    /// Iterates over the keys of the objects whose key ends with ext, fetching them one page at a time
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        Box::new(PagedObjects::new(self, ext, LIST_PAGE_SIZE))
+    }
 }
 
 #[cfg(test)]
@@ -333,4 +478,107 @@ mod tests {
         assert!(sqa.list_objects(".pack").unwrap().len() == 1);
         assert!(sqa.list_objects("").unwrap().len() == 2);
     }
+
+    #[test]
+    fn test_sqlite_list_objects_paged() {
+        let sqa = SqliteAdapter::new_in_memory();
+        let (page, cursor) = sqa.list_objects_paged(".delta", None, 2).unwrap();
+        assert!(page.is_empty());
+        assert!(cursor.is_none());
+        for k in ["a", "b", "c", "d", "e"] {
+            assert!(sqa
+                .write_object(&(k.to_string() + ".delta"), "somedata".as_bytes())
+                .is_ok());
+        }
+        assert!(sqa.write_object("c.pack", "otherdata".as_bytes()).is_ok());
+        let mut all = vec![];
+        let mut cursor = None;
+        loop {
+            let (page, next) = sqa
+                .list_objects_paged(".delta", cursor.as_deref(), 2)
+                .unwrap();
+            assert!(page.len() <= 2);
+            all.extend(page);
+            match next {
+                Some(c) => cursor = Some(c),
+                None => break,
+            }
+        }
+        assert_eq!(all, sqa.list_objects(".delta").unwrap());
+        let (page, cursor) = sqa.list_objects_paged("", None, 10).unwrap();
+        assert!(page.len() == 6);
+        assert!(cursor.is_none());
+        assert!(sqa.list_objects_paged(".delta", None, 0).is_err());
+    }
+
+    #[test]
+    fn test_sqlite_write_batch() {
+        let sqa = SqliteAdapter::new_in_memory();
+        assert!(sqa
+            .write_batch(&[
+                ("somekey.pack", "somedata".as_bytes()),
+                ("somekey.delta", "otherdata".as_bytes()),
+            ])
+            .is_ok());
+        assert!(sqa.list_objects("").unwrap().len() == 2);
+        let ro = sqa.read_object("somekey.delta", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "otherdata");
+        assert!(sqa
+            .write_batch(&[("somekey.pack", "changed".as_bytes())])
+            .is_ok());
+        let ro = sqa.read_object("somekey.pack", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "somedata");
+    }
+
+    #[test]
+    fn test_sqlite_delete_object() {
+        let sqa = SqliteAdapter::new_in_memory();
+        assert!(sqa
+            .write_object("somekey.pack", "somedata".as_bytes())
+            .is_ok());
+        assert!(sqa
+            .write_object("otherkey.pack", "otherdata".as_bytes())
+            .is_ok());
+        assert!(sqa.delete_object("somekey.pack").is_ok());
+        assert!(sqa.list_objects("").unwrap().len() == 1);
+        assert!(sqa.read_object("somekey.pack", 0, 0).is_err());
+        assert!(sqa.delete_object("somekey.pack").is_ok());
+        let ro = sqa.read_object("otherkey.pack", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "otherdata");
+    }
+
+    #[test]
+    fn test_sqlite_total_size() {
+        let sqa = SqliteAdapter::new_in_memory();
+        assert!(sqa.total_size().unwrap() == 0);
+        assert!(sqa
+            .write_object("somekey.pack", "somedata".as_bytes())
+            .is_ok());
+        // Values are stored in base64
+        assert!(sqa.total_size().unwrap() == 12);
+        assert!(sqa.object_size("somekey.pack").unwrap() == 12);
+        assert!(sqa.object_size("otherkey.pack").is_err());
+    }
+
+    #[test]
+    fn test_sqlite_delete_objects() {
+        let sa = SqliteAdapter::new_in_memory();
+        let ma: Box<dyn Adapter> = Box::new(sa);
+        let sqa = Flate2Adapter::new(std::sync::Arc::new(std::sync::RwLock::new(ma)));
+        // More keys than those deleted with a single statement
+        let keys: Vec<String> = (0..1200).map(|i| format!("key{}.delta", i)).collect();
+        for key in &keys {
+            assert!(sqa.write_object(key, "somedata".as_bytes()).is_ok());
+        }
+        let mut to_delete: Vec<&str> = keys.iter().skip(1).map(|k| k.as_str()).collect();
+        to_delete.push("missing.delta");
+        let results = sqa.delete_objects(&to_delete).unwrap();
+        assert!(results.len() == 1200);
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        assert!(results
+            .iter()
+            .map(|(k, _)| k.as_str())
+            .eq(to_delete.into_iter()));
+        assert!(sqa.list_objects(".delta").unwrap() == vec!["key0.delta".to_string()]);
+    }
 }
@@ -0,0 +1,323 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2022 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::Adapter;
+use crate::constants::{
+    ARRAY_DESCRIPTOR_ORDER_FIELD, ARRAY_DESCRIPTOR_PREFIX, HASH_FIELD, ROOT_ID, TEXT_CHARS_FIELD,
+};
+use crate::melda::Melda;
+use crate::utils::{digest_string, is_array_descriptor};
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+use std::sync::{Arc, RwLock};
+
+/// A collaborative text backed by a Melda document. The text is a flattened array of the root
+/// object with an object for each character: the object has its own identifier (generated like
+/// Melda::generate_id, but also derived from the character and the preceding one) and uses the
+/// hash shortcut (see Melda::update) with the hexadecimal code of the character as hash, hence
+/// characters are never stored in data packs (only their identifiers are stored in the array).
+/// Characters inserted (or removed) on different replicas are merged like the elements of any
+/// flattened array. Changes are local until they are committed. Operations change the array
+/// descriptor and the objects of the characters directly, without reading the whole document.
+///
+/// # Example
+/// ```
+/// use melda::{text::MeldaText, adapter::Adapter, memoryadapter::MemoryAdapter};
+/// use std::sync::{Arc, Mutex, RwLock};
+/// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let text = MeldaText::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+/// text.insert(0, "Hello world").unwrap();
+/// text.commit(None).unwrap();
+/// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let text2 = MeldaText::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+/// text2.melda().meld(text.melda()).unwrap();
+/// text2.melda().refresh().unwrap();
+/// // Concurrent edits are merged
+/// text.insert(5, ",").unwrap();
+/// text.commit(None).unwrap();
+/// text2.insert(11, "!").unwrap();
+/// text2.remove(0, 1).unwrap();
+/// text2.insert(0, "h").unwrap();
+/// text2.commit(None).unwrap();
+/// text.melda().meld(text2.melda()).unwrap();
+/// text.melda().refresh().unwrap();
+/// assert_eq!(text.to_string().unwrap(), "hello, world!");
+/// ```
+pub struct MeldaText {
+    melda: Melda,
+}
+
+impl MeldaText {
+    /// Initializes a new text using the given adapter (see Melda::new)
+    ///
+    /// # Arguments
+    ///
+    /// * `adapter` - The storage adapter
+    pub fn new(adapter: Arc<RwLock<Box<dyn Adapter>>>) -> Result<MeldaText> {
+        Ok(MeldaText::with_melda(Melda::new(adapter)?))
+    }
+
+    /// Uses an existing Melda instance as a text. The document must have been created through
+    /// MeldaText (or be empty).
+    ///
+    /// # Arguments
+    ///
+    /// * `melda` - The Melda instance
+    pub fn with_melda(melda: Melda) -> MeldaText {
+        MeldaText { melda }
+    }
+
+    /// Returns the underlying Melda instance (for example to meld or refresh replicas)
+    pub fn melda(&self) -> &Melda {
+        &self.melda
+    }
+
+    /// Inserts a string at the given position (in characters)
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - The position (at most the length of the text)
+    /// * `s` - The string
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{text::MeldaText, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let text = MeldaText::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// text.insert(0, "ac").unwrap();
+    /// text.insert(1, "b\u{00E8}").unwrap();
+    /// assert_eq!(text.to_string().unwrap(), "ab\u{00E8}c");
+    /// assert_eq!(text.len().unwrap(), 4);
+    /// assert!(text.insert(5, "d").is_err());
+    /// ```
+    pub fn insert(&self, pos: usize, s: &str) -> Result<()> {
+        self.melda.autocommit(|| {
+            let descriptor = self.create_chars()?;
+            let mut chars = self.chars(&descriptor)?;
+            let visible = self.visible(&chars)?;
+            if pos > visible.len() {
+                bail!("invalid_position");
+            }
+            // The characters are inserted right after the preceding visible character
+            let index = match pos {
+                0 => 0,
+                pos => visible[pos - 1].0 + 1,
+            };
+            let mut inserted = vec![];
+            // Identifiers are derived from the character and the one preceding it, hence
+            // different characters inserted concurrently receive different identifiers
+            let mut previous = match index {
+                0 => String::new(),
+                index => chars[index - 1].as_str().unwrap_or_default().to_string(),
+            };
+            for c in s.chars() {
+                let uuid = self
+                    .melda
+                    .generate_id_for(&format!("{}:{:x}", previous, c as u32));
+                previous = uuid.clone();
+                let mut o = Map::new();
+                o.insert(
+                    HASH_FIELD.to_string(),
+                    Value::from(format!("{:x}", c as u32)),
+                );
+                self.melda.update_object(&uuid, o)?;
+                inserted.push(Value::from(uuid));
+            }
+            chars.splice(index..index, inserted);
+            self.write_chars(&descriptor, chars)
+        })
+    }
+
+    /// Removes len characters starting at the given position
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - The position of the first character
+    /// * `len` - The number of characters to be removed
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{text::MeldaText, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let text = MeldaText::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// text.insert(0, "abcdef").unwrap();
+    /// text.remove(1, 3).unwrap();
+    /// assert_eq!(text.to_string().unwrap(), "aef");
+    /// assert!(text.remove(2, 2).is_err());
+    /// ```
+    pub fn remove(&self, pos: usize, len: usize) -> Result<()> {
+        self.melda.autocommit(|| {
+            let descriptor = self.create_chars()?;
+            let mut chars = self.chars(&descriptor)?;
+            let visible = self.visible(&chars)?;
+            if pos.checked_add(len).is_none_or(|end| end > visible.len()) {
+                bail!("invalid_range");
+            }
+            // Removed in reverse order so that the remaining indices stay valid
+            for (index, _) in visible[pos..pos + len].iter().rev() {
+                if let Some(uuid) = chars.remove(*index).as_str() {
+                    self.melda.delete_object(uuid)?;
+                }
+            }
+            self.write_chars(&descriptor, chars)
+        })
+    }
+
+    /// Returns the text
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> Result<String> {
+        let descriptor = match self.chars_descriptor()? {
+            Some(descriptor) => descriptor,
+            None => return Ok(String::new()),
+        };
+        let chars = self.chars(&descriptor)?;
+        Ok(self.visible(&chars)?.into_iter().map(|(_, c)| c).collect())
+    }
+
+    /// Returns the length of the text (in characters). Characters which have been deleted
+    /// (for example concurrently on another replica) are not counted
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{text::MeldaText, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let text = MeldaText::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// text.insert(0, "abc").unwrap();
+    /// text.commit(None).unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let text2 = MeldaText::new(Arc::new(RwLock::new(adapter2))).expect("cannot_initialize_crdt");
+    /// text2.melda().meld(text.melda()).unwrap();
+    /// text2.melda().refresh().unwrap();
+    /// // Concurrent removal and insertion
+    /// text.remove(1, 1).unwrap();
+    /// text.commit(None).unwrap();
+    /// text2.insert(2, "x").unwrap();
+    /// text2.commit(None).unwrap();
+    /// text.melda().meld(text2.melda()).unwrap();
+    /// text.melda().refresh().unwrap();
+    /// assert_eq!(text.to_string().unwrap(), "axc");
+    /// assert_eq!(text.len().unwrap(), 3);
+    /// // Positions only count visible characters
+    /// text.insert(3, "d").unwrap();
+    /// text.remove(0, 1).unwrap();
+    /// assert_eq!(text.to_string().unwrap(), "xcd");
+    /// assert_eq!(text.len().unwrap(), 3);
+    /// assert!(text.insert(4, "e").is_err());
+    /// assert!(text.remove(1, 3).is_err());
+    /// ```
+    pub fn len(&self) -> Result<usize> {
+        match self.chars_descriptor()? {
+            Some(descriptor) => Ok(self.visible(&self.chars(&descriptor)?)?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns true if the text is empty
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Commits the changes (see Melda::commit), returns the identifier of the new block (if
+    /// there were changes to commit)
+    ///
+    /// # Arguments
+    ///
+    /// * `information` - Optional information object associated with the commit
+    pub fn commit(&self, information: Option<Map<String, Value>>) -> Result<Option<String>> {
+        self.melda.commit(information)
+    }
+
+    /// Returns the identifier of the array descriptor of the characters (None if the document
+    /// does not exist yet)
+    fn chars_descriptor(&self) -> Result<Option<String>> {
+        match self.melda.get_current(ROOT_ID)? {
+            Some((_, root)) => match root.get(TEXT_CHARS_FIELD) {
+                Some(Value::String(descriptor)) if is_array_descriptor(descriptor) => {
+                    Ok(Some(descriptor.clone()))
+                }
+                None => Ok(None),
+                _ => bail!("text_not_an_array"),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the identifier of the array descriptor of the characters, creating the root
+    /// object (with the same descriptor identifier update would use) if missing
+    fn create_chars(&self) -> Result<String> {
+        if let Some(descriptor) = self.chars_descriptor()? {
+            return Ok(descriptor);
+        }
+        let mut root = match self.melda.get_current(ROOT_ID)? {
+            Some((_, root)) => root,
+            None => Map::new(),
+        };
+        let descriptor = ARRAY_DESCRIPTOR_PREFIX.to_string()
+            + &digest_string(&(ROOT_ID.to_string() + TEXT_CHARS_FIELD));
+        root.insert(
+            TEXT_CHARS_FIELD.to_string(),
+            Value::from(descriptor.clone()),
+        );
+        self.melda.update_object(ROOT_ID, root)?;
+        Ok(descriptor)
+    }
+
+    /// Returns the identifiers of the characters
+    fn chars(&self, descriptor: &str) -> Result<Vec<Value>> {
+        match self.melda.get_current(descriptor)? {
+            Some((_, mut order)) => match order.remove(ARRAY_DESCRIPTOR_ORDER_FIELD) {
+                Some(Value::Array(chars)) => Ok(chars),
+                _ => bail!("text_not_an_array"),
+            },
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Returns the visible characters along with their index in the identifiers of the
+    /// characters (the array can contain identifiers of deleted characters)
+    fn visible(&self, chars: &[Value]) -> Result<Vec<(usize, char)>> {
+        let mut visible = vec![];
+        for (index, c) in chars.iter().enumerate() {
+            if let Some((_, o)) = c
+                .as_str()
+                .map(|uuid| self.melda.get_current(uuid))
+                .transpose()?
+                .flatten()
+            {
+                visible.push((index, MeldaText::char_of(&o)?));
+            }
+        }
+        Ok(visible)
+    }
+
+    /// Replaces the identifiers of the characters
+    fn write_chars(&self, descriptor: &str, chars: Vec<Value>) -> Result<()> {
+        let mut order = Map::new();
+        order.insert(ARRAY_DESCRIPTOR_ORDER_FIELD.to_string(), Value::from(chars));
+        self.melda.update_object(descriptor, order)
+    }
+
+    /// Returns the character represented by a character object
+    fn char_of(c: &Map<String, Value>) -> Result<char> {
+        c.get(HASH_FIELD)
+            .and_then(|h| h.as_str())
+            .and_then(|h| u32::from_str_radix(h, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or_else(|| anyhow!("invalid_character"))
+    }
+}
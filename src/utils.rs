@@ -15,13 +15,37 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 use anyhow::{anyhow, bail, Result};
 use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::io::Write;
 use yavomrs::yavom::{myers_unfilled, Move, Point};
 
 use crate::constants::{
-    ARRAY_DESCRIPTOR_ORDER_FIELD, ARRAY_DESCRIPTOR_PREFIX, EMPTY_HASH, FLATTEN_SUFFIX, HASH_FIELD,
-    ID_FIELD, PATCH_DELETE, PATCH_INSERT, ROOT_ID, STRING_ESCAPE_PREFIX,
+    ARRAY_DESCRIPTOR_ORDER_FIELD, ARRAY_DESCRIPTOR_PREFIX, BLOB_REFERENCE_PREFIX, EMPTY_HASH,
+    FLATTEN_SUFFIX, HASH_FIELD, ID_FIELD, MAX_READ_REFERENCES, PATCH_DELETE, PATCH_INSERT, ROOT_ID,
+    STRING_ESCAPE_PREFIX,
 };
+use crate::melda::IdStrategy;
+
+/// Clock used to time operations (see Metrics): std::time::Instant panics on
+/// wasm32-unknown-unknown, which has no clock, hence on that target all durations are zero
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Instant {
+    pub(crate) fn now() -> Instant {
+        Instant
+    }
+
+    pub(crate) fn elapsed(&self) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) use std::time::Instant;
 
 /// Returns true if the key matches a flattened field
 pub fn is_flattened_field(key: &str) -> bool {
@@ -33,6 +57,11 @@ pub fn is_array_descriptor(key: &str) -> bool {
     key.starts_with(ARRAY_DESCRIPTOR_PREFIX)
 }
 
+/// Returns true if the string is a reference to a binary blob
+pub fn is_blob_reference(s: &str) -> bool {
+    s.starts_with(BLOB_REFERENCE_PREFIX)
+}
+
 /// Escapes a string (add escape prefix)
 pub fn escape(s: &str) -> String {
     STRING_ESCAPE_PREFIX.to_string() + s
@@ -54,12 +83,57 @@ pub fn digest_string(content: &str) -> String {
 
 /// Computes the digest of a slice of bytes
 pub fn digest_bytes(content: &[u8]) -> String {
-    let mut hasher = openssl::sha::Sha256::new();
+    let mut hasher = Sha256::new();
     hasher.update(content);
-    hex::encode(hasher.finish())
+    hex::encode(hasher.finalize())
+}
+
+/// Computes the digest of content written incrementally (the result of finish is the
+/// same as digest_bytes on the whole content)
+#[derive(Default)]
+pub struct DigestWriter(Sha256);
+
+impl DigestWriter {
+    /// Returns the digest of the content written so far
+    pub fn finish(self) -> String {
+        hex::encode(self.0.finalize())
+    }
+}
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Counts the bytes written to it (used to measure serialized values without allocating them)
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
-/// Computes the digest of a JSON object
+/// Returns the length (in bytes) of the JSON serialization of the value, without allocating it
+pub fn serialized_size<T: serde::Serialize + ?Sized>(value: &T) -> usize {
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, value).unwrap();
+    counter.0
+}
+
+/// Computes the digest of a JSON object. If the object contains the hash field (a string
+/// or a number) its value is used as the digest instead (see Melda::update)
 pub fn digest_object(o: &Map<String, Value>) -> Result<String> {
     if o.is_empty() {
         return Ok(EMPTY_HASH.to_string());
@@ -79,14 +153,20 @@ pub fn digest_object(o: &Map<String, Value>) -> Result<String> {
             }
         }
         None => {
-            let content = serde_json::to_string(o).unwrap();
-            Ok(digest_string(&content))
+            // Serialize directly into the hasher, without allocating the serialized object
+            let mut writer = DigestWriter::default();
+            serde_json::to_writer(&mut writer, o)?;
+            Ok(writer.finish())
         }
     }
 }
 
 /// Returns the identifier of an object with path
-pub fn generate_identifier(value: &Map<String, Value>, path: &[String]) -> Result<String> {
+pub fn generate_identifier(
+    value: &Map<String, Value>,
+    path: &[String],
+    strategy: IdStrategy,
+) -> Result<String> {
     if value.contains_key(ID_FIELD) {
         let v = value.get(ID_FIELD).unwrap();
         if let Some(v) = v.as_str() {
@@ -107,12 +187,26 @@ pub fn generate_identifier(value: &Map<String, Value>, path: &[String]) -> Resul
         // the ARRAY_DESCRIPTOR_PREFIX (which is true,
         // since the prefix is the ^ character by default
         // and the digest is an hex string)
-        Ok(digest_string(&path.join("")))
+        match strategy {
+            IdStrategy::PathBased => Ok(digest_string(&path.join(""))),
+            IdStrategy::ContentBased => Ok(digest_string(&serde_json::to_string(value)?)),
+        }
     }
 }
 
-/// Merges an array M into another array N
+/// Merges an array M into another array N. Duplicate elements are matched positionally: the
+/// k-th occurrence of an element in M corresponds to the k-th occurrence of the same element in N
 pub fn merge_arrays(order_m: &[Value], order_n: &mut Vec<Value>) {
+    merge_arrays_by(order_m, order_n, |v| v.to_string())
+}
+
+/// Merges an array M into another array N (see merge_arrays), two elements being the same
+/// element if identity returns the same string for both. When an element of M is matched,
+/// the corresponding element of N is kept.
+pub fn merge_arrays_by<F>(order_m: &[Value], order_n: &mut Vec<Value>, identity: F)
+where
+    F: Fn(&Value) -> String,
+{
     if order_n.is_empty() {
         order_m.iter().for_each(|t| order_n.push(t.clone()));
         return;
@@ -120,12 +214,33 @@ pub fn merge_arrays(order_m: &[Value], order_n: &mut Vec<Value>) {
     if order_m.is_empty() {
         return;
     }
+    let keys_m: Vec<String> = order_m.iter().map(&identity).collect();
+    let mut keys_n: Vec<String> = order_n.iter().map(&identity).collect();
+    // Occurrence of each element of M (0 for the first occurrence of a value, 1 for the second...)
+    let mut seen = HashMap::<&str, usize>::new();
+    let occurrences: Vec<usize> = keys_m
+        .iter()
+        .map(|k| {
+            let count = seen.entry(k).or_insert(0);
+            *count += 1;
+            *count - 1
+        })
+        .collect();
+    // Elements inserted from M are never matched again
+    let mut original = vec![true; order_n.len()];
+    let find = |keys_n: &[String], original: &[bool], k: &str, occurrence: usize| {
+        keys_n
+            .iter()
+            .enumerate()
+            .filter(|(i, e)| original[*i] && *e == k)
+            .nth(occurrence)
+            .map(|(i, _)| i)
+    };
     // Find the pivot
     let mut ins_pos_in_n = 0;
     let mut pivot_pos_in_m: usize = 0;
-    for t in order_m {
-        let index = order_n.iter().position(|e| *e == *t);
-        match index {
+    for (k, occurrence) in keys_m.iter().zip(&occurrences) {
+        match find(&keys_n, &original, k, *occurrence) {
             Some(position) => {
                 ins_pos_in_n = position;
                 break;
@@ -133,39 +248,55 @@ pub fn merge_arrays(order_m: &[Value], order_n: &mut Vec<Value>) {
             None => pivot_pos_in_m += 1,
         }
     }
-    for (current_pos_in_m, t) in order_m.iter().enumerate() {
+    for (current_pos_in_m, ((t, k), occurrence)) in
+        order_m.iter().zip(&keys_m).zip(&occurrences).enumerate()
+    {
         // Search t in N
-        let it = order_n.iter().position(|e| *e == *t);
-        match it {
+        match find(&keys_n, &original, k, *occurrence) {
             // If found, update the insertion point to this new position
             Some(position) => ins_pos_in_n = position,
             None => {
                 // Is the current position (in M) before the pivot
                 if current_pos_in_m < pivot_pos_in_m {
                     // Insert at insertIt
-                    order_n.insert(ins_pos_in_n, t.clone());
                     pivot_pos_in_m = current_pos_in_m
                 } else {
                     ins_pos_in_n += 1;
-                    order_n.insert(ins_pos_in_n, t.clone());
                 }
+                order_n.insert(ins_pos_in_n, t.clone());
+                keys_n.insert(ins_pos_in_n, k.clone());
+                original.insert(ins_pos_in_n, false);
             }
         }
     }
 }
 
-/// Flattens a JSON value, stores promoted objects in c
+/// Flattens a JSON value, stores promoted objects in c. Objects within flattened fields are
+/// replaced by their identifier (empty objects included), arrays within flattened fields are
+/// replaced by the identifier of an array descriptor (empty arrays included), strings within
+/// flattened fields are escaped, other values (including null) are kept as they are.
 pub fn flatten(
     c: &mut HashMap<String, Map<String, Value>>,
     value: &Value,
     path: &[String],
+    strategy: IdStrategy,
 ) -> Value {
     match value {
+        Value::String(s) if is_blob_reference(s) => value.clone(),
         Value::String(s) => Value::from(escape(s)),
-        Value::Array(a) => Value::from(a.iter().map(|v| flatten(c, v, path)).collect::<Vec<_>>()),
+        Value::Array(a) => Value::from(
+            a.iter()
+                .map(|v| flatten(c, v, path, strategy))
+                .collect::<Vec<_>>(),
+        ),
         Value::Object(o) => {
-            let uuid = generate_identifier(o, path).unwrap();
-            let mut fpath = path.to_owned();
+            let uuid = generate_identifier(o, path, strategy).unwrap();
+            // With content-based identifiers the content of an object (including the identifiers
+            // of its array descriptors) must not depend on its path
+            let mut fpath = match strategy {
+                IdStrategy::PathBased => path.to_owned(),
+                IdStrategy::ContentBased => vec![],
+            };
             fpath.push(uuid.clone());
             let no: Map<String, Value> = o
                 .into_iter()
@@ -174,7 +305,7 @@ pub fn flatten(
                     if is_flattened_field(k) {
                         let mut fpath = fpath.clone();
                         fpath.push(k.clone());
-                        let flattened = flatten(c, v, &fpath);
+                        let flattened = flatten(c, v, &fpath, strategy);
                         if let Value::Array(_) = &flattened {
                             // We assume that all arrays will be stored as deltas from
                             // the previous version
@@ -200,62 +331,211 @@ pub fn flatten(
     }
 }
 
-/// Unflattens a collection of objects starting from an initial value
-pub fn unflatten(c: &HashMap<String, Map<String, Value>>, value: &Value) -> Option<Value> {
+/// Validates a JSON object before it is flattened, checking the usage of reserved keys: the
+/// identifier field must be a string (not beginning with the array descriptor prefix) and can
+/// only be used in objects which are promoted (the root object and objects within flattened
+/// fields), flattened fields of promoted objects must be arrays, objects, blob references or null.
+/// Errors include the path (as a JSON pointer) of the offending node.
+pub fn validate_object(o: &Map<String, Value>) -> Result<()> {
+    if let Some(id) = o.get(ID_FIELD) {
+        if id.as_str() != Some(ROOT_ID) {
+            bail!("invalid_root_id: /{}", ID_FIELD);
+        }
+    }
+    validate_promoted_object(o, "")
+}
+
+fn validate_promoted_object(o: &Map<String, Value>, path: &str) -> Result<()> {
+    if let Some(id) = o.get(ID_FIELD) {
+        match id.as_str() {
+            Some(id) if is_array_descriptor(id) => bail!(
+                "user_object_identifier_cannot_begin_with_array_descriptor_prefix: {}/{}",
+                path,
+                ID_FIELD
+            ),
+            Some(_) => (),
+            None => bail!("invalid_user_object_identifier: {}/{}", path, ID_FIELD),
+        }
+    }
+    for (k, v) in o {
+        let path = format!("{}/{}", path, k.replace('~', "~0").replace('/', "~1"));
+        if is_flattened_field(k) {
+            match v {
+                Value::Array(_) | Value::Object(_) => validate_value(v, &path, true)?,
+                Value::String(s) if is_blob_reference(s) => (),
+                Value::Null => (),
+                _ => bail!("flattened_field_not_an_array_or_object: {}", path),
+            }
+        } else {
+            validate_value(v, &path, false)?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_value(value: &Value, path: &str, promoted: bool) -> Result<()> {
+    match value {
+        Value::Array(a) => {
+            for (i, v) in a.iter().enumerate() {
+                validate_value(v, &format!("{}/{}", path, i), promoted)?;
+            }
+            Ok(())
+        }
+        Value::Object(o) if promoted => validate_promoted_object(o, path),
+        Value::Object(o) => {
+            if o.contains_key(ID_FIELD) {
+                bail!("misplaced_identifier: {}/{}", path, ID_FIELD);
+            }
+            for (k, v) in o {
+                let path = format!("{}/{}", path, k.replace('~', "~0").replace('/', "~1"));
+                validate_value(v, &path, false)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Unflattens a collection of objects starting from an initial value. Values round-trip
+/// through flatten as follows: empty arrays and empty objects are restored as such, explicit
+/// nulls and other scalar values (also within flattened arrays) are kept, escaped strings are
+/// unescaped, and references to objects and array descriptors which are not in the collection
+/// (for example deleted objects) are omitted (the field is removed, or the item is removed from
+/// the array). None is returned only if the initial value is such a reference. Objects referenced
+/// more than once (for example by two different parents) are reconstructed at each reference. A
+/// reference_cycle error is returned if an object references itself (directly or through other
+/// objects), and a max_depth_exceeded error if references to objects and array descriptors are
+/// nested more than max_depth levels below the initial value. Since shared objects are
+/// reconstructed at each reference, a too_many_references error is returned if more than
+/// MAX_READ_REFERENCES references are expanded. Array descriptors without an order array are
+/// rejected with an error.
+pub fn unflatten(
+    c: &HashMap<String, Map<String, Value>>,
+    value: &Value,
+    max_depth: usize,
+) -> Result<Option<Value>> {
+    unflatten_with(&|uuid: &str| Ok(c.get(uuid).cloned()), value, max_depth)
+}
+
+/// Unflattens like unflatten, but objects are pulled on demand using the fetch function
+/// (which returns None for objects which are not available). Only the objects being
+/// reconstructed are kept in memory, hence fetch can read them lazily from a storage.
+/// Unflattening stops at the first error returned by fetch.
+pub fn unflatten_with<F>(fetch: &F, value: &Value, max_depth: usize) -> Result<Option<Value>>
+where
+    F: Fn(&str) -> Result<Option<Map<String, Value>>>,
+{
+    let mut path = vec![];
+    if let Some(uuid) = value.get(ID_FIELD).and_then(|id| id.as_str()) {
+        // The initial value is an object being reconstructed
+        path.push(uuid.to_string());
+    }
+    let max_depth = max_depth.saturating_add(path.len());
+    unflatten_on_path(fetch, value, &mut path, max_depth, &mut 0)
+}
+
+// Unflattens a value, path contains the identifiers of the objects (and array descriptors)
+// being reconstructed (from the initial value to the current one), references counts the
+// references expanded so far
+fn unflatten_on_path<F>(
+    fetch: &F,
+    value: &Value,
+    path: &mut Vec<String>,
+    max_depth: usize,
+    references: &mut usize,
+) -> Result<Option<Value>>
+where
+    F: Fn(&str) -> Result<Option<Map<String, Value>>>,
+{
     match value {
         Value::String(s) => {
             if s.starts_with(STRING_ESCAPE_PREFIX) {
-                Some(Value::from(unescape(s)))
-            } else if is_array_descriptor(s) {
-                // Fetch corresponding descriptor
-                match c.get(s) {
-                    Some(v) => {
-                        if let Some(v) = v.get(ARRAY_DESCRIPTOR_ORDER_FIELD) {
-                            if let Some(order) = v.as_array() {
-                                let mut array: Vec<Value> = vec![];
-                                for uuid in order {
-                                    if let Some(uuid) = uuid.as_str() {
-                                        if let Some(o) = c.get(uuid) {
-                                            if let Some(item) =
-                                                unflatten(c, &Value::from(o.clone()))
-                                            {
-                                                array.push(item);
-                                            }
-                                        }
-                                    }
+                Ok(Some(Value::from(unescape(s))))
+            } else if is_blob_reference(s) {
+                Ok(Some(value.clone()))
+            } else {
+                let fetched = fetch(s)?;
+                if fetched.is_some() {
+                    if path.contains(s) {
+                        bail!("reference_cycle: {}", s);
+                    }
+                    if path.len() >= max_depth {
+                        bail!("max_depth_exceeded");
+                    }
+                    // Shared references are expanded each time, bound the total work
+                    *references += 1;
+                    if *references > MAX_READ_REFERENCES {
+                        bail!("too_many_references");
+                    }
+                }
+                if is_array_descriptor(s) {
+                    // Fetch corresponding descriptor (missing descriptors are omitted like
+                    // any other missing reference)
+                    match fetched {
+                        Some(v) => {
+                            let order = v
+                                .get(ARRAY_DESCRIPTOR_ORDER_FIELD)
+                                .ok_or_else(|| {
+                                    anyhow!("expecting_order_field_in_descriptor: {}", s)
+                                })?
+                                .as_array()
+                                .ok_or_else(|| {
+                                    anyhow!("expecting_order_field_in_descriptor_as_array: {}", s)
+                                })?;
+                            path.push(s.clone());
+                            let mut items = vec![];
+                            for item in order {
+                                if let Some(item) =
+                                    unflatten_on_path(fetch, item, path, max_depth, references)?
+                                {
+                                    items.push(item);
                                 }
-                                Some(Value::from(array))
-                            } else {
-                                panic!("expecting_order_field_in_descriptor_as_array")
                             }
-                        } else {
-                            panic!("expecting_order_field_in_descriptor")
+                            path.pop();
+                            Ok(Some(Value::from(items)))
+                        }
+                        None => Ok(None),
+                    }
+                } else {
+                    match fetched {
+                        Some(v) => {
+                            path.push(s.clone());
+                            let result = unflatten_on_path(
+                                fetch,
+                                &Value::from(v),
+                                path,
+                                max_depth,
+                                references,
+                            );
+                            path.pop();
+                            result
                         }
+                        None => Ok(None),
                     }
-                    None => panic!("unknown_descriptor_object"),
                 }
-            } else {
-                match c.get(s) {
-                    Some(v) => unflatten(c, &Value::from(v.clone())),
-                    None => Some(json!(null)),
+            }
+        }
+        Value::Array(a) => {
+            let mut items = vec![];
+            for v in a {
+                if let Some(v) = unflatten_on_path(fetch, v, path, max_depth, references)? {
+                    items.push(v);
                 }
             }
+            Ok(Some(Value::from(items)))
         }
-        Value::Array(a) => Some(Value::from(
-            a.iter().filter_map(|v| unflatten(c, v)).collect::<Vec<_>>(),
-        )),
-        Value::Object(o) => Some(Value::from(
-            o.iter()
-                .map(|(k, v)| {
-                    if !is_flattened_field(k) {
-                        (k.clone(), v.clone())
-                    } else {
-                        (k.clone(), unflatten(c, v).unwrap())
-                    }
-                })
-                .collect::<Map<String, Value>>(),
-        )),
-        _ => Some(value.clone()),
+        Value::Object(o) => {
+            let mut result = Map::new();
+            for (k, v) in o {
+                if !is_flattened_field(k) {
+                    result.insert(k.clone(), v.clone());
+                } else if let Some(v) = unflatten_on_path(fetch, v, path, max_depth, references)? {
+                    result.insert(k.clone(), v);
+                }
+            }
+            Ok(Some(Value::from(result)))
+        }
+        _ => Ok(Some(value.clone())),
     }
 }
 
@@ -364,6 +644,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_digest_writer() {
+        let mut writer = DigestWriter::default();
+        writer.write_all("hello ".as_bytes()).unwrap();
+        writer.write_all("world".as_bytes()).unwrap();
+        assert!(
+            writer.finish() == "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+    }
+
     #[test]
     fn test_digest_object() {
         assert!(
@@ -381,13 +671,25 @@ mod tests {
         assert!(digest_object(json!({}).as_object().unwrap()).unwrap() == EMPTY_HASH);
     }
 
+    #[test]
+    fn test_digest_object_large() {
+        let items: Vec<Value> = (0..10000)
+            .map(|i| json!({"key": i, "text": "\u{266D}\"escaped\"\n".repeat(i % 7)}))
+            .collect();
+        let o = json!({"items": items, "blob": "x".repeat(1 << 20), "n": 1.5});
+        let o = o.as_object().unwrap();
+        // The digest is the same as the digest of the serialized object
+        assert!(digest_object(o).unwrap() == digest_string(&serde_json::to_string(o).unwrap()));
+    }
+
     #[test]
     fn test_get_identifier() {
         let path = vec![];
         assert!(
             generate_identifier(
                 json!({"_id":"foo","alpha":1234}).as_object_mut().unwrap(),
-                &path
+                &path,
+                IdStrategy::PathBased
             )
             .unwrap()
                 == "foo"
@@ -397,9 +699,23 @@ mod tests {
             .map(|x| x.to_string())
             .collect();
         assert!(
-            generate_identifier(json!({"alpha":1234}).as_object_mut().unwrap(), &path).unwrap()
+            generate_identifier(
+                json!({"alpha":1234}).as_object_mut().unwrap(),
+                &path,
+                IdStrategy::PathBased
+            )
+            .unwrap()
                 == digest_string("foobarbaz")
         );
+        assert!(
+            generate_identifier(
+                json!({"alpha":1234}).as_object_mut().unwrap(),
+                &path,
+                IdStrategy::ContentBased
+            )
+            .unwrap()
+                == digest_string(r#"{"alpha":1234}"#)
+        );
     }
 
     #[test]
@@ -490,13 +806,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_merge_arrays_by() {
+        let identity = |v: &Value| v.as_str().unwrap_or_default().to_lowercase();
+        {
+            let mut a = string_value_vec!["A", "b", "C"];
+            let mut b = string_value_vec!["a", "B", "D"];
+            merge_arrays_by(&a, &mut b, identity);
+            merge_arrays_by(&b, &mut a, identity);
+            assert!(vec_equals(&b, &string_value_vec!["a", "B", "C", "D"]));
+            assert!(vec_equals(&a, &string_value_vec!["A", "b", "C", "D"]));
+        }
+        {
+            let a = string_value_vec!["A", "b", "C"];
+            let mut b = string_value_vec!["a", "B", "D"];
+            merge_arrays(&a, &mut b);
+            assert_eq!(b.len(), 6);
+        }
+    }
+
+    #[test]
+    fn test_merge_arrays_with_duplicates() {
+        {
+            let mut a = string_value_vec!["A", "A", "B"];
+            let mut b = string_value_vec!["A", "C"];
+            merge_arrays(&a, &mut b);
+            assert!(vec_equals(&b, &string_value_vec!["A", "A", "B", "C"]));
+            merge_arrays(&b, &mut a);
+            assert!(vec_equals(&a, &b));
+        }
+        {
+            let mut a = string_value_vec!["A", "A", "B"];
+            let mut b = string_value_vec!["A", "A", "B"];
+            merge_arrays(&a, &mut b);
+            merge_arrays(&b, &mut a);
+            assert!(vec_equals(&a, &string_value_vec!["A", "A", "B"]));
+            assert!(vec_equals(&a, &b));
+        }
+        {
+            let mut a = string_value_vec!["B", "A", "B"];
+            let mut b = string_value_vec!["B", "B", "C"];
+            merge_arrays(&a, &mut b);
+            assert!(vec_equals(&b, &string_value_vec!["B", "A", "B", "C"]));
+            merge_arrays(&b, &mut a);
+            assert!(vec_equals(&a, &b));
+        }
+        {
+            let mut a = json!([1, 1, 2, 1]).as_array().unwrap().clone();
+            let mut b = json!([1, 3, 1]).as_array().unwrap().clone();
+            merge_arrays(&a, &mut b);
+            assert!(vec_equals(&b, json!([1, 3, 1, 2, 1]).as_array().unwrap()));
+            merge_arrays(&b, &mut a);
+            assert!(vec_equals(&a, &b));
+        }
+    }
+
     #[test]
     fn test_flatten() {
         {
             let mut c = HashMap::<String, Map<String, Value>>::new();
             let v = json!({ID_FIELD: ROOT_ID, "data" : [{ID_FIELD: "foo", "value": 1.23}, {ID_FIELD: "bar"}]});
             let path = vec![];
-            let f = flatten(&mut c, &v, &path);
+            let f = flatten(&mut c, &v, &path, IdStrategy::PathBased);
             assert!(f.is_string());
             assert!(f.as_str().unwrap() == ROOT_ID);
             assert!(c.len() == 1);
@@ -505,7 +876,7 @@ mod tests {
             let mut c = HashMap::<String, Map<String, Value>>::new();
             let v = json!({ID_FIELD : ROOT_ID, "data\u{266D}" : [{ID_FIELD: "foo", "value": 1.23}, {ID_FIELD: "bar"}]});
             let path = vec![];
-            let f = flatten(&mut c, &v, &path);
+            let f = flatten(&mut c, &v, &path, IdStrategy::PathBased);
             assert!(f.is_string());
             assert!(f.as_str().unwrap() == ROOT_ID);
             assert!(c.len() == 4);
@@ -524,6 +895,303 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_flatten_content_based() {
+        let v = json!({"first\u{266D}" : {"value": 1, "inner\u{266D}": [{"a": 1}]}, "second\u{266D}" : {"value": 1, "inner\u{266D}": [{"a": 1}]}, "third\u{266D}" : {"value": 2}});
+        let path = vec![];
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        flatten(&mut c, &v, &path, IdStrategy::PathBased);
+        // Root, three objects, two of them with an inner array with one object
+        assert!(c.len() == 8);
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        flatten(&mut c, &v, &path, IdStrategy::ContentBased);
+        // Identical subtrees are deduplicated
+        assert!(c.len() == 5);
+        let root = c.get(ROOT_ID).unwrap();
+        assert!(root["first\u{266D}"] == root["second\u{266D}"]);
+        assert!(root["first\u{266D}"] != root["third\u{266D}"]);
+        let u = unflatten(
+            &c,
+            &Value::from(c.get(ROOT_ID).unwrap().clone()),
+            usize::MAX,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(u == v);
+    }
+
+    #[test]
+    fn test_flatten_round_trip() {
+        let cases = vec![
+            json!({}),
+            json!({"a\u{266D}": []}),
+            json!({"a\u{266D}": {}}),
+            json!({"a\u{266D}": null}),
+            json!({"a\u{266D}": "text", "b": null, "c": {}, "d": []}),
+            json!({"a\u{266D}": [null, 1, true, "x", "", [], [null, "y"]]}),
+            json!({"a\u{266D}": [{}], "b\u{266D}": [{"_id": "foo"}, {"_id": "bar", "c": null}]}),
+            json!({"a\u{266D}": {"b\u{266D}": {"c\u{266D}": []}}}),
+        ];
+        for v in cases {
+            for strategy in [IdStrategy::PathBased, IdStrategy::ContentBased] {
+                let mut c = HashMap::<String, Map<String, Value>>::new();
+                let f = flatten(&mut c, &v, &[], strategy);
+                let u = unflatten(
+                    &c,
+                    &Value::from(c.get(f.as_str().unwrap()).unwrap().clone()),
+                    usize::MAX,
+                )
+                .unwrap();
+                let mut u = u.unwrap();
+                // Identifiers are not restored by unflatten
+                let mut v = v.clone();
+                if let Some(items) = v.get_mut("b\u{266D}").and_then(|i| i.as_array_mut()) {
+                    items
+                        .iter_mut()
+                        .for_each(|i| drop(i.as_object_mut().unwrap().remove(ID_FIELD)));
+                }
+                if let Some(items) = u.get_mut("b\u{266D}").and_then(|i| i.as_array_mut()) {
+                    items
+                        .iter_mut()
+                        .for_each(|i| drop(i.as_object_mut().unwrap().remove(ID_FIELD)));
+                }
+                assert!(u == v, "{} != {}", u, v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unflatten_missing_references() {
+        let v = json!({"a\u{266D}": [{"_id": "foo"}, {"_id": "bar"}], "b\u{266D}": {"_id": "baz"}});
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        flatten(&mut c, &v, &[], IdStrategy::PathBased);
+        // References to missing (deleted) objects are omitted
+        c.remove("foo");
+        c.remove("baz");
+        let u = unflatten(
+            &c,
+            &Value::from(c.get(ROOT_ID).unwrap().clone()),
+            usize::MAX,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(u == json!({"a\u{266D}": [{}]}));
+        assert!(unflatten(&c, &Value::from("foo"), usize::MAX)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_unflatten_shared_references_chain() {
+        // Each level references the next one twice
+        let chain = |levels: usize| {
+            let mut c = HashMap::<String, Map<String, Value>>::new();
+            for i in 0..levels {
+                let next = format!("n{}", i + 1);
+                let v = json!({"_id": format!("n{}", i), "l\u{266D}": next, "r\u{266D}": next});
+                c.insert(format!("n{}", i), v.as_object().unwrap().clone());
+            }
+            let v = json!({"_id": format!("n{}", levels), "k": 1});
+            c.insert(format!("n{}", levels), v.as_object().unwrap().clone());
+            c
+        };
+        let c = chain(3);
+        let u = unflatten(&c, &Value::from("n0"), usize::MAX)
+            .unwrap()
+            .unwrap();
+        assert!(u["l\u{266D}"]["r\u{266D}"]["l\u{266D}"] == json!({"_id": "n3", "k": 1}));
+        // The number of expanded references doubles at each level
+        let c = chain(40);
+        let e = unflatten(&c, &Value::from("n0"), usize::MAX).unwrap_err();
+        assert_eq!(e.to_string(), "too_many_references");
+    }
+
+    #[test]
+    fn test_unflatten_missing_descriptor() {
+        let v = json!({"a\u{266D}": [{"_id": "foo"}], "b": 1});
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        flatten(&mut c, &v, &[], IdStrategy::PathBased);
+        let descriptor = c.keys().find(|k| is_array_descriptor(k)).unwrap().clone();
+        // References to missing (deleted) array descriptors are omitted
+        c.remove(&descriptor);
+        let root = Value::from(c.get(ROOT_ID).unwrap().clone());
+        let u = unflatten(&c, &root, usize::MAX).unwrap().unwrap();
+        assert!(u == json!({"b": 1}));
+        assert!(unflatten(&c, &Value::from(descriptor), usize::MAX)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_unflatten_malformed_descriptor() {
+        let v = json!({"a\u{266D}": [{"_id": "foo"}]});
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        flatten(&mut c, &v, &[], IdStrategy::PathBased);
+        let descriptor = c.keys().find(|k| is_array_descriptor(k)).unwrap().clone();
+        let root = Value::from(c.get(ROOT_ID).unwrap().clone());
+        // Descriptors without an order, or with an order which is not an array, are rejected
+        c.insert(descriptor.clone(), Map::new());
+        let e = unflatten(&c, &root, usize::MAX).unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("expecting_order_field_in_descriptor:"));
+        c.insert(
+            descriptor.clone(),
+            json!({ARRAY_DESCRIPTOR_ORDER_FIELD: "foo"})
+                .as_object()
+                .unwrap()
+                .clone(),
+        );
+        let e = unflatten(&c, &root, usize::MAX).unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("expecting_order_field_in_descriptor_as_array"));
+    }
+
+    #[test]
+    fn test_unflatten_with() {
+        let v = json!({"a\u{266D}": [{"_id": "foo", "b\u{266D}": {"_id": "bar"}}, "x"], "c": 1});
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        flatten(&mut c, &v, &[], IdStrategy::PathBased);
+        let fetched = std::cell::RefCell::new(vec![]);
+        let fetch = |uuid: &str| {
+            fetched.borrow_mut().push(uuid.to_string());
+            Ok(c.get(uuid).cloned())
+        };
+        let root = Value::from(c.get(ROOT_ID).unwrap().clone());
+        let u = unflatten_with(&fetch, &root, usize::MAX).unwrap().unwrap();
+        assert!(u == unflatten(&c, &root, usize::MAX).unwrap().unwrap());
+        // Each referenced object is fetched exactly once
+        let mut fetched = fetched.into_inner();
+        fetched.sort();
+        let mut expected: Vec<String> = c.keys().filter(|k| *k != ROOT_ID).cloned().collect();
+        expected.sort();
+        assert!(fetched == expected);
+    }
+
+    #[test]
+    fn test_unflatten_max_depth() {
+        // Each level is a reference to another object
+        let mut v = json!({});
+        for _ in 0..100 {
+            v = json!({ "n\u{266D}": v });
+        }
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        flatten(&mut c, &v, &[], IdStrategy::PathBased);
+        let fetch = |uuid: &str| Ok(c.get(uuid).cloned());
+        let root = Value::from(c.get(ROOT_ID).unwrap().clone());
+        let u = unflatten_with(&fetch, &root, 100).unwrap().unwrap();
+        assert!(u == v);
+        assert!(unflatten_with(&fetch, &root, 99).is_err());
+    }
+
+    #[test]
+    fn test_unflatten_with_fetch_error() {
+        let v = json!({"a\u{266D}": [{"_id": "foo", "b\u{266D}": {"_id": "bar"}}, "x"]});
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        flatten(&mut c, &v, &[], IdStrategy::PathBased);
+        // Errors are returned (and stop unflattening), also for array descriptors
+        let fetched = std::cell::RefCell::new(0);
+        let fetch = |_: &str| -> Result<Option<Map<String, Value>>> {
+            *fetched.borrow_mut() += 1;
+            bail!("unreadable")
+        };
+        let root = Value::from(c.get(ROOT_ID).unwrap().clone());
+        let e = unflatten_with(&fetch, &root, usize::MAX).unwrap_err();
+        assert_eq!(e.to_string(), "unreadable");
+        assert_eq!(fetched.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_unflatten_reference_cycles() {
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        let mut insert = |v: Value| {
+            let uuid = v[ID_FIELD].as_str().unwrap().to_string();
+            c.insert(uuid, v.as_object().unwrap().clone());
+        };
+        // Objects shared by two parents are reconstructed at each reference
+        insert(json!({"_id": "a", "x\u{266D}": "c", "y\u{266D}": ["b", "c"]}));
+        insert(json!({"_id": "b", "z\u{266D}": "c"}));
+        insert(json!({"_id": "c", "k": 1}));
+        // Cycles
+        insert(json!({"_id": "d", "n\u{266D}": "e"}));
+        insert(json!({"_id": "e", "n\u{266D}": ["f"]}));
+        insert(json!({"_id": "f", "n\u{266D}": "d"}));
+        insert(json!({"_id": "g", "n\u{266D}": "g"}));
+        let u = unflatten(&c, &Value::from("a"), usize::MAX)
+            .unwrap()
+            .unwrap();
+        let shared = json!({"_id": "c", "k": 1});
+        assert!(u["x\u{266D}"] == shared);
+        assert!(u["y\u{266D}"] == json!([{"_id": "b", "z\u{266D}": shared}, shared]));
+        for uuid in ["d", "e", "g"] {
+            let e = unflatten(&c, &Value::from(uuid), usize::MAX).unwrap_err();
+            assert!(e.to_string().starts_with("reference_cycle"));
+            // Also when starting from the object itself
+            let e =
+                unflatten(&c, &Value::from(c.get(uuid).unwrap().clone()), usize::MAX).unwrap_err();
+            assert!(e.to_string().starts_with("reference_cycle"));
+        }
+    }
+
+    #[test]
+    fn test_flatten_blob_reference() {
+        let mut c = HashMap::<String, Map<String, Value>>::new();
+        let blob = BLOB_REFERENCE_PREFIX.to_string() + &digest_string("hello world");
+        let v = json!({ID_FIELD : ROOT_ID, "data\u{266D}" : [{ID_FIELD: "foo", "image\u{266D}": blob.clone()}, blob.clone()]});
+        let path = vec![];
+        let f = flatten(&mut c, &v, &path, IdStrategy::PathBased);
+        let content = serde_json::to_string(&c.get("foo")).unwrap();
+        assert!(content == format!(r#"{{"image♭":"{}"}}"#, blob));
+        let u = unflatten(
+            &c,
+            &Value::from(c.get(ROOT_ID).unwrap().clone()),
+            usize::MAX,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(f.as_str().unwrap() == ROOT_ID);
+        assert!(u["data\u{266D}"][0]["image\u{266D}"] == blob.as_str());
+        assert!(u["data\u{266D}"][1] == blob.as_str());
+    }
+
+    #[test]
+    fn test_validate_object() {
+        let blob = BLOB_REFERENCE_PREFIX.to_string() + &digest_string("hello world");
+        let v = json!({ID_FIELD : ROOT_ID, "data\u{266D}" : [{ID_FIELD: "foo", "image\u{266D}": blob, "inner": {"a": [1, 2]}}, "x"]});
+        assert!(validate_object(v.as_object().unwrap()).is_ok());
+        let v = json!({ID_FIELD : "foo"});
+        assert!(
+            validate_object(v.as_object().unwrap())
+                .unwrap_err()
+                .to_string()
+                == "invalid_root_id: /_id"
+        );
+        let v = json!({"data\u{266D}" : [{ID_FIELD: 1}]});
+        assert!(
+            validate_object(v.as_object().unwrap())
+                .unwrap_err()
+                .to_string()
+                == "invalid_user_object_identifier: /data\u{266D}/0/_id"
+        );
+        let v = json!({"data\u{266D}" : {"items\u{266D}": [{ID_FIELD: "^foo"}]}});
+        assert!(validate_object(v.as_object().unwrap()).unwrap_err().to_string() == "user_object_identifier_cannot_begin_with_array_descriptor_prefix: /data\u{266D}/items\u{266D}/0/_id");
+        let v = json!({"data" : [{"a/b": {ID_FIELD: "foo"}}]});
+        assert!(
+            validate_object(v.as_object().unwrap())
+                .unwrap_err()
+                .to_string()
+                == "misplaced_identifier: /data/0/a~1b/_id"
+        );
+        let v = json!({"data\u{266D}" : [{"count\u{266D}": 3}]});
+        assert!(
+            validate_object(v.as_object().unwrap())
+                .unwrap_err()
+                .to_string()
+                == "flattened_field_not_an_array_or_object: /data\u{266D}/0/count\u{266D}"
+        );
+    }
+
     #[test]
     fn test_patch() {
         {
@@ -0,0 +1,599 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2022 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::{list_folders_paged, Adapter, Cursor, PagedObjects};
+use crate::constants::{DELTA_EXTENSION, LIST_PAGE_SIZE};
+use crate::retryadapter::NonRetryableError;
+use anyhow::{anyhow, bail, Result};
+use lazy_static::lazy_static;
+use lru::LruCache;
+use rayon::prelude::*;
+use regex::Regex;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::HeaderMap;
+use reqwest::Method;
+use std::cell::RefCell;
+use std::env;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref HREF_REGEX: Regex =
+        Regex::new(r"<(?:[A-Za-z][\w.-]*:)?href>\s*([^<]*?)\s*</(?:[A-Za-z][\w.-]*:)?href>")
+            .unwrap();
+    static ref CONTENT_LENGTH_REGEX: Regex = Regex::new(
+        r"<(?:[A-Za-z][\w.-]*:)?getcontentlength>\s*(\d+)\s*</(?:[A-Za-z][\w.-]*:)?getcontentlength>"
+    )
+    .unwrap();
+}
+
+/// Body of the PROPFIND requests (only the resource type is needed to list a collection)
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><propfind xmlns="DAV:"><prop><resourcetype/></prop></propfind>"#;
+
+/// Body of the PROPFIND requests used to obtain the size of the members of a collection
+const PROPFIND_SIZE_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><propfind xmlns="DAV:"><prop><getcontentlength/></prop></propfind>"#;
+
+/// Implements storage on a WebDAV server (for example Nextcloud). Objects are stored in
+/// sub-collections named after the first two characters of their key
+pub struct WebDavAdapter {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    client: Client,
+    cache: Mutex<RefCell<LruCache<String, Vec<u8>>>>,
+    disk_cache_dir: String,
+}
+
+impl WebDavAdapter {
+    /// Creates a new adapter to store data in the specified WebDAV collection (which is created if
+    /// needed). If no username or password are provided the MELDA_WEBDAV_USERNAME and
+    /// MELDA_WEBDAV_PASSWORD environment variables will be used (if set, otherwise requests
+    /// are not authenticated).
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - URL of the WebDAV collection
+    /// * `username` - Optional username (for basic authentication on the server)
+    /// * `password` - Optional password (for basic authentication on the server)
+    pub fn new(url: String, username: Option<String>, password: Option<String>) -> Result<Self> {
+        // On disk cache
+        let disk_cache_dir = std::env::temp_dir()
+            .join(".webdavcache")
+            .into_os_string()
+            .into_string()
+            .unwrap();
+        let username = username.or_else(|| env::var("MELDA_WEBDAV_USERNAME").ok());
+        let password = password.or_else(|| env::var("MELDA_WEBDAV_PASSWORD").ok());
+        let wa = WebDavAdapter {
+            url: url.trim_end_matches('/').to_string(),
+            username,
+            password,
+            client: Client::builder().build()?,
+            cache: Mutex::new(RefCell::new(LruCache::<String, Vec<u8>>::new(
+                NonZeroUsize::new(1024).unwrap(),
+            ))),
+            disk_cache_dir,
+        };
+        wa.ensure_collection_exists(&wa.url)?;
+        Ok(wa)
+    }
+
+    /// Prepares a request, adding the credentials (if any)
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.username {
+            Some(username) => request.basic_auth(username, self.password.as_ref()),
+            None => request,
+        }
+    }
+
+    /// Sends a request, authentication failures are not retryable
+    fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let response = request.send()?;
+        let status = response.status().as_u16();
+        if status == 401 || status == 403 {
+            return Err(NonRetryableError::new("cannot_authenticate").into());
+        }
+        Ok(response)
+    }
+
+    fn get_object_url(&self, key: &str) -> Result<(String, String)> {
+        // Keys shorter than 2 bytes, or whose first 2 bytes split a character, are rejected
+        let prefix = key
+            .get(..2)
+            .ok_or_else(|| anyhow!("invalid_key: {}", key))?;
+        let prefix = self.url.clone() + "/" + prefix;
+        let object_url = prefix.clone() + "/" + key;
+        Ok((prefix, object_url))
+    }
+
+    fn ensure_collection_exists(&self, url: &str) -> Result<()> {
+        let response = self.send(self.request(Method::from_bytes(b"MKCOL")?, url))?;
+        // 405 (Method Not Allowed) is returned if the collection already exists
+        match response.status().as_u16() {
+            200 | 201 | 405 => Ok(()),
+            _ => bail!("cannot_ensure_collection_exists"),
+        }
+    }
+
+    fn fetch_object(&self, key: &str) -> Result<Vec<u8>> {
+        // The cache is not locked during the request, so that reads can proceed in parallel
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .borrow_mut()
+            .get(&key.to_string())
+            .cloned();
+        if let Some(v) = cached {
+            return Ok(v);
+        }
+        // Try to read from disk cache
+        if let Ok(data) = cacache::read_sync(&self.disk_cache_dir, key) {
+            return Ok(data);
+        }
+        let (_, url) = self.get_object_url(key)?;
+        let response = self.send(self.request(Method::GET, &url))?;
+        if response.status().as_u16() == 200 {
+            let data = response.bytes()?.to_vec();
+            self.cache
+                .lock()
+                .unwrap()
+                .borrow_mut()
+                .put(key.to_string(), data.clone());
+            cacache::write_sync(&self.disk_cache_dir, key, &data)?;
+            Ok(data)
+        } else {
+            bail!("cannot_read_object")
+        }
+    }
+
+    /// Reads a range of an object (using a Range request if the object is not cached)
+    fn fetch_range(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        if length == 0 {
+            return Ok(vec![]);
+        }
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .borrow()
+            .contains(&key.to_string());
+        if cached || cacache::metadata_sync(&self.disk_cache_dir, key).is_ok_and(|m| m.is_some()) {
+            let data = self.fetch_object(key)?;
+            return slice(&data, offset, length);
+        }
+        let (_, url) = self.get_object_url(key)?;
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Range",
+            format!("bytes={}-{}", offset, offset + length - 1).parse()?,
+        );
+        let response = self.send(self.request(Method::GET, &url).headers(headers))?;
+        match response.status().as_u16() {
+            206 => Ok(response.bytes()?.to_vec()),
+            200 => {
+                // The server ignored the range, the full object has been returned
+                let data = response.bytes()?.to_vec();
+                let result = slice(&data, offset, length);
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .borrow_mut()
+                    .put(key.to_string(), data.clone());
+                cacache::write_sync(&self.disk_cache_dir, key, &data)?;
+                result
+            }
+            _ => bail!("cannot_read_object"),
+        }
+    }
+
+    /// Uploads an object (if not already present on the server), returns true if the object was written
+    fn upload_object(&self, key: &str, data: &[u8]) -> Result<bool> {
+        let (prefix, url) = self.get_object_url(key)?;
+        let response = self.send(self.request(Method::HEAD, &url))?;
+        if response.status().as_u16() == 200 {
+            return Ok(false);
+        }
+        self.ensure_collection_exists(&prefix)?;
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
+        let response = self.send(
+            self.request(Method::PUT, &url)
+                .headers(headers)
+                .body(data.to_vec()),
+        )?;
+        if (200..=204).contains(&response.status().as_u16()) {
+            cacache::write_sync(&self.disk_cache_dir, key, data)?;
+            Ok(true)
+        } else {
+            bail!("cannot_write_object");
+        }
+    }
+
+    /// Requests the given properties of a collection and of its members, returns the multistatus
+    /// response (None if the collection does not exist)
+    fn propfind(&self, url: &str, body: &'static str) -> Result<Option<String>> {
+        let mut headers = HeaderMap::new();
+        headers.insert("Depth", "1".parse().unwrap());
+        headers.insert("Content-Type", "application/xml".parse().unwrap());
+        let response = self.send(
+            self.request(Method::from_bytes(b"PROPFIND")?, url)
+                .headers(headers)
+                .body(body),
+        )?;
+        match response.status().as_u16() {
+            207 => Ok(Some(response.text()?)),
+            404 => Ok(None),
+            _ => bail!("cannot_list_collection"),
+        }
+    }
+
+    /// Uploads the objects which are not cached (optionally in parallel), stops at the first error
+    fn upload_batch(&self, items: &[&(&str, &[u8])], parallel: bool) -> Result<()> {
+        let cache = self.cache.lock().unwrap();
+        let missing: Vec<&(&str, &[u8])> = items
+            .iter()
+            .filter(|(key, _)| !cache.borrow().contains(&key.to_string()))
+            .copied()
+            .collect();
+        drop(cache);
+        let upload = |(key, data): &&(&str, &[u8])| self.upload_object(key, data);
+        let written = if parallel {
+            missing.par_iter().map(upload).collect::<Result<Vec<_>>>()?
+        } else {
+            missing.iter().map(upload).collect::<Result<Vec<_>>>()?
+        };
+        let cache = self.cache.lock().unwrap();
+        let mut cache = cache.borrow_mut();
+        for ((key, data), written) in missing.into_iter().zip(written) {
+            if written {
+                cache.put(key.to_string(), data.to_vec());
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the members of a collection, returning the last segment of their path (collections
+    /// end with a slash). The collection itself is not included
+    fn list_collection(&self, url: &str) -> Result<Vec<String>> {
+        Ok(self
+            .propfind(url, PROPFIND_BODY)?
+            .map(|body| parse_multistatus(&body))
+            .unwrap_or_default())
+    }
+
+    /// Recursively deletes the collection
+    ///
+    pub fn delete_collection(&self) -> Result<()> {
+        let response = self.send(self.request(Method::DELETE, &self.url))?;
+        let status = response.status().as_u16();
+        if (200..=204).contains(&status) || status == 404 {
+            Ok(())
+        } else {
+            bail!("cannot_delete_collection")
+        }
+    }
+
+    /// Recursively deletes the collection and then recreates it
+    ///
+    pub fn reset_collection(&self) -> Result<()> {
+        self.delete_collection()?;
+        self.ensure_collection_exists(&self.url)
+    }
+}
+
+/// Returns the requested range of the data
+fn slice(data: &[u8], offset: usize, length: usize) -> Result<Vec<u8>> {
+    match data.get(offset..offset + length) {
+        Some(s) => Ok(s.to_vec()),
+        None => bail!("invalid_object_range"),
+    }
+}
+
+/// Returns the sum of the content lengths of a multistatus response (collections have no
+/// content length)
+fn parse_content_lengths(body: &str) -> u64 {
+    CONTENT_LENGTH_REGEX
+        .captures_iter(body)
+        .filter_map(|c| c.get(1)?.as_str().parse::<u64>().ok())
+        .sum()
+}
+
+/// Extracts the last segment of each href of a multistatus response, skipping the first
+/// response (the listed collection itself). Collections keep their trailing slash
+fn parse_multistatus(body: &str) -> Vec<String> {
+    HREF_REGEX
+        .captures_iter(body)
+        .skip(1)
+        .filter_map(|c| {
+            let href = c.get(1)?.as_str();
+            let is_collection = href.ends_with('/');
+            let name = href.trim_end_matches('/').rsplit('/').next()?;
+            if name.is_empty() {
+                None
+            } else if is_collection {
+                Some(name.to_string() + "/")
+            } else {
+                Some(name.to_string())
+            }
+        })
+        .collect()
+}
+
+impl Adapter for WebDavAdapter {
+    /// Reads an object or a sub-object from the backend storage. When offset and length are both 0
+    /// the full object is returned, otherwise the sub-object is returned
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    ///
+    fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        if offset == 0 && length == 0 {
+            self.fetch_object(key)
+        } else {
+            self.fetch_range(key, offset, length)
+        }
+    }
+
+    /// Writes an object to the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        // The cache is not locked while uploading, so that other operations are not blocked
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .borrow()
+            .contains(&key.to_string());
+        if !cached && self.upload_object(key, data)? {
+            self.cache
+                .lock()
+                .unwrap()
+                .borrow_mut()
+                .put(key.to_string(), data.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Writes multiple objects to the storage. Data objects (packs and indexes) are uploaded
+    /// first (in parallel), delta blocks are uploaded last (sequentially) and only if all data
+    /// objects have been uploaded, so that a visible block never references missing data
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The keys associated with the objects along with their content
+    fn write_batch(&self, items: &[(&str, &[u8])]) -> Result<()> {
+        let (blocks, data): (Vec<_>, Vec<_>) = items
+            .iter()
+            .partition(|(key, _)| key.ends_with(DELTA_EXTENSION));
+        self.upload_batch(&data, true)?;
+        self.upload_batch(&blocks, false)
+    }
+
+    /// Deletes an object from the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        self.cache
+            .lock()
+            .unwrap()
+            .borrow_mut()
+            .pop(&key.to_string());
+        let _ = cacache::remove_sync(&self.disk_cache_dir, key);
+        let (_, url) = self.get_object_url(key)?;
+        let response = self.send(self.request(Method::DELETE, &url))?;
+        let status = response.status().as_u16();
+        if (200..=204).contains(&status) || status == 404 {
+            Ok(())
+        } else {
+            bail!("cannot_delete_object")
+        }
+    }
+
+    /// Returns the size (in bytes) of an object in the storage (obtained from its content
+    /// length, without downloading it)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<u64> {
+        let (_, url) = self.get_object_url(key)?;
+        match self.propfind(&url, PROPFIND_SIZE_BODY)? {
+            Some(body) => Ok(parse_content_lengths(&body)),
+            None => bail!("cannot_read_object"),
+        }
+    }
+
+    /// Returns the total size (in bytes) of the objects in the storage (obtained from the
+    /// content length of the members of each sub-collection, without downloading them)
+    fn total_size(&self) -> Result<u64> {
+        let mut size = 0;
+        for sub in self.list_collection(&self.url)? {
+            if let Some(sub) = sub.strip_suffix('/') {
+                let target = self.url.clone() + "/" + sub;
+                if let Some(body) = self.propfind(&target, PROPFIND_SIZE_BODY)? {
+                    size += parse_content_lengths(&body);
+                }
+            }
+        }
+        Ok(size)
+    }
+
+    /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+        let mut list = vec![];
+        for sub in self.list_collection(&self.url)? {
+            if let Some(sub) = sub.strip_suffix('/') {
+                let target = self.url.clone() + "/" + sub;
+                for name in self.list_collection(&target)? {
+                    if !name.ends_with('/') {
+                        if let Some(key) = name.strip_suffix(ext) {
+                            list.push(key.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    /// Lists the keys of the objects whose key ends with ext, one page at a time (only the
+    /// sub-collections needed for the page are listed)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `cursor` - The cursor returned by the previous call (None to start from the first page)
+    /// * `limit` - The maximum number of keys in the page
+    fn list_objects_paged(
+        &self,
+        ext: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<String>, Option<Cursor>)> {
+        let folders = self
+            .list_collection(&self.url)?
+            .into_iter()
+            .filter_map(|sub| sub.strip_suffix('/').map(|s| s.to_string()))
+            .collect();
+        let list_folder = |folder: &str| -> Result<Vec<String>> {
+            let target = self.url.clone() + "/" + folder;
+            Ok(self
+                .list_collection(&target)?
+                .into_iter()
+                .filter(|name| !name.ends_with('/'))
+                .filter_map(|name| name.strip_suffix(ext).map(|key| key.to_string()))
+                .collect())
+        };
+        list_folders_paged(folders, list_folder, cursor, limit)
+    }
+
+    /// Iterates over the keys of the objects whose key ends with ext, fetching them one page at
+    /// a time (see list_objects_paged)
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn iter_objects(&self, ext: &str) -> Box<dyn Iterator<Item = Result<String>> + '_> {
+        Box::new(PagedObjects::new(self, ext, LIST_PAGE_SIZE))
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use serial_test::serial;
+
+    #[allow(unused_imports)]
+    use crate::{
+        adapter::Adapter,
+        flate2adapter::Flate2Adapter,
+        webdavadapter::{parse_content_lengths, parse_multistatus, WebDavAdapter},
+    };
+
+    #[test]
+    fn test_parse_multistatus() {
+        let body = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:">
+            <d:response><d:href>/dav/doc/</d:href></d:response>
+            <d:response><d:href>/dav/doc/ab/</d:href></d:response>
+            <d:response><d:href>/dav/doc/abcd.delta</d:href></d:response>
+            </d:multistatus>"#;
+        assert_eq!(parse_multistatus(body), vec!["ab/", "abcd.delta"]);
+        let body = r#"<multistatus xmlns="DAV:"><response><href>/doc/</href></response><response><href>https://host/doc/ab/</href></response></multistatus>"#;
+        assert_eq!(parse_multistatus(body), vec!["ab/"]);
+    }
+
+    #[test]
+    fn test_parse_content_lengths() {
+        let body = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:">
+            <d:response><d:href>/dav/doc/ab/</d:href><d:propstat><d:prop/></d:propstat></d:response>
+            <d:response><d:href>/dav/doc/ab/abcd.delta</d:href><d:propstat><d:prop><d:getcontentlength>12</d:getcontentlength></d:prop></d:propstat></d:response>
+            <d:response><d:href>/dav/doc/ab/abef.pack</d:href><d:propstat><d:prop><d:getcontentlength> 30 </d:getcontentlength></d:prop></d:propstat></d:response>
+            </d:multistatus>"#;
+        assert_eq!(parse_content_lengths(body), 42);
+        let body =
+            r#"<multistatus xmlns="DAV:"><response><href>/doc/ab/</href></response></multistatus>"#;
+        assert_eq!(parse_content_lengths(body), 0);
+    }
+
+    #[test]
+    fn test_get_object_url() {
+        // Built without contacting the server (new ensures the collection exists)
+        let wa = WebDavAdapter {
+            url: "https://host/dav".to_string(),
+            username: None,
+            password: None,
+            client: reqwest::blocking::Client::builder().build().unwrap(),
+            cache: std::sync::Mutex::new(std::cell::RefCell::new(lru::LruCache::new(
+                std::num::NonZeroUsize::new(1).unwrap(),
+            ))),
+            disk_cache_dir: String::new(),
+        };
+        assert_eq!(
+            wa.get_object_url("abcd.delta").unwrap(),
+            (
+                "https://host/dav/ab".to_string(),
+                "https://host/dav/ab/abcd.delta".to_string()
+            )
+        );
+        assert!(wa.get_object_url("a").is_err());
+        assert!(wa.get_object_url("").is_err());
+        assert!(wa.get_object_url("a\u{e9}.delta").is_err());
+        assert!(wa.get_object_url("\u{e9}a.delta").is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn test_webdav_read_write_object_flate() {
+        let url = match std::env::var("MELDA_WEBDAV_URL") {
+            Ok(url) => url,
+            Err(_) => return, // No server available
+        };
+        let wa = WebDavAdapter::new(url, None, None).expect("Failed to create adapter");
+        wa.reset_collection().expect("Failed to reset collection");
+        let ma: Box<dyn Adapter> = Box::new(wa);
+        let sqa = Flate2Adapter::new(std::sync::Arc::new(std::sync::RwLock::new(ma)));
+        assert!(sqa.list_objects(".delta").unwrap().is_empty());
+        assert!(sqa
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        assert!(sqa
+            .write_object("otherkey.pack", "otherdata".as_bytes())
+            .is_ok());
+        assert!(sqa.list_objects(".delta").unwrap().len() == 1);
+        assert!(sqa.list_objects("").unwrap().len() == 2);
+        let ro = sqa.read_object("somekey.delta", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "somedata");
+        let ro = sqa.read_object("somekey.delta", 1, 2).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "om");
+        assert!(sqa.delete_object("somekey.delta").is_ok());
+        assert!(sqa.list_objects(".delta").unwrap().is_empty());
+    }
+}